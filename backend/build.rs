@@ -0,0 +1,9 @@
+// gRPC のコードを proto/badbit.proto から生成する（grpc.rs で include_proto! する）
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc をインストールしていなくてもビルドできるよう、同梱のバイナリを使う
+    let mut config = tonic_prost_build::Config::new();
+    config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+    tonic_prost_build::configure().compile_with_config(config, &["proto/badbit.proto"], &["proto"])?;
+    Ok(())
+}
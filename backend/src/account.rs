@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
 use rust_decimal::Decimal;
 use uuid::Uuid;
+use crate::db::Balance;
 use crate::models::Side;
 
 /// ユーザーごとの残高状態
@@ -40,6 +42,24 @@ impl AccountManager {
         (Decimal::ZERO, Decimal::ZERO)
     }
 
+    /// ユーザーの全資産の残高（資産名の順）
+    pub fn user_balances(&self, user_id: &Uuid) -> Vec<Balance> {
+        let mut balances: Vec<Balance> = self
+            .balances
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .map(|(asset, b)| Balance { user_id: *user_id, asset: asset.clone(), available: b.available, locked: b.locked })
+            .collect();
+        balances.sort_by(|a, b| a.asset.cmp(&b.asset));
+        balances
+    }
+
+    /// 全ユーザーの残高（順不同）
+    pub fn all_balances(&self) -> Vec<Balance> {
+        self.balances.keys().flat_map(|user_id| self.user_balances(user_id)).collect()
+    }
+
     /// 注文前の残高チェックとロック（仮押さえ）
     /// 
     /// - 買い注文: (価格 * 数量) 分のUSDCをロック
@@ -99,6 +119,22 @@ impl AccountManager {
         }
     }
 
+    /// Availableから引き落とす（証拠金口座への振替用）
+    pub fn debit_available(&mut self, user_id: &Uuid, asset: &str, amount: Decimal) -> Result<(), &'static str> {
+        let balance = self.balances.entry(*user_id).or_default().entry(asset.to_string()).or_default();
+        if balance.available < amount {
+            return Err("残高不足");
+        }
+        balance.available -= amount;
+        Ok(())
+    }
+
+    /// Availableに入金する（証拠金口座からの振替用）
+    pub fn credit_available(&mut self, user_id: &Uuid, asset: &str, amount: Decimal) {
+        let balance = self.balances.entry(*user_id).or_default().entry(asset.to_string()).or_default();
+        balance.available += amount;
+    }
+
     /// 注文キャンセル時のロック解除
     /// 
     /// 指定された注文分のロックを解除し、Availableに戻します。
@@ -116,3 +152,49 @@ impl AccountManager {
         balance.available += amount_to_unlock;
     }
 }
+
+/// エンジンの残高の読み取り用コピー（リードレプリカ）
+///
+/// エンジンは残高を変えるたびに、応答を返す前にここへ書き込む。
+/// APIはエンジンに問い合わせずにここから読むので、発注の直後でもその結果が反映されている
+/// （DBは run_db_writer が非同期に書くので遅れることがある）
+#[derive(Debug, Clone, Default)]
+pub struct BalanceReplica {
+    balances: Arc<RwLock<HashMap<Uuid, ReplicaBalances>>>, // ユーザーID -> そのユーザーの残高
+}
+
+/// 資産名 -> (available, locked)
+type ReplicaBalances = BTreeMap<String, (Decimal, Decimal)>;
+
+impl BalanceReplica {
+    /// エンジンの残高で丸ごと置き換える（エンジンの起動時用）
+    pub fn load(&self, account_manager: &AccountManager) {
+        let mut balances = self.balances.write().unwrap();
+        balances.clear();
+        for b in account_manager.all_balances() {
+            balances.entry(b.user_id).or_default().insert(b.asset, (b.available, b.locked));
+        }
+    }
+
+    /// 1資産分の残高を書き込む
+    pub fn set(&self, user_id: Uuid, asset: &str, available: Decimal, locked: Decimal) {
+        let mut balances = self.balances.write().unwrap();
+        balances.entry(user_id).or_default().insert(asset.to_string(), (available, locked));
+    }
+
+    /// ユーザーの全資産の残高（資産名の順）
+    pub fn get(&self, user_id: Uuid) -> Vec<Balance> {
+        let balances = self.balances.read().unwrap();
+        balances
+            .get(&user_id)
+            .into_iter()
+            .flatten()
+            .map(|(asset, (available, locked))| Balance {
+                user_id,
+                asset: asset.clone(),
+                available: *available,
+                locked: *locked,
+            })
+            .collect()
+    }
+}
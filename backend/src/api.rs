@@ -0,0 +1,1196 @@
+// =============================================================================
+// REST API
+// =============================================================================
+//
+// axumのハンドラーとルーター定義です。
+// ハンドラーはエンジン（mpsc経由）とストレージにしかアクセスしないので、
+// main.rs からもテストからも `router()` で同じAPIを組み立てられます。
+//
+// ログインが必要なAPIは引数に `AuthUser` を取ります（auth.rs 参照）。
+// APIキーで署名されたリクエストは、ルーター全体にかけたミドルウェアで先に検証されます。
+// =============================================================================
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::middleware;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tower_http::cors::CorsLayer;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::auth::{self, AuthUser, SessionUser, SESSION_TTL_MS};
+use crate::db::DbWriterStatus;
+use crate::engine::{EngineMessage, OrderIdGenerator};
+use crate::feeds::MarketFeeds;
+use crate::margin::{MarginAccountSummary, MarginRequest, QUOTE_ASSET};
+use crate::perp::{PerpAccountSummary, PerpMarket, PerpRequest, SETTLEMENT_ASSET};
+use crate::models::{ApiKey, ApiScope, Asset, AssetPrecisions, Candle, CandleInterval, Order, OrderRecord, OrderType, Side, Trade};
+use crate::openapi;
+use crate::orderbook::{BookSnapshot, DepthSnapshot, L3Snapshot};
+use crate::ratelimit::{self, RateLimiter};
+use crate::storage::{to_scaled, SharedStorage, StorageError, TradeQuery, DECIMAL_SCALE};
+use crate::ticker::Ticker;
+use crate::ws::{self, WsConfig};
+
+// =============================================================================
+// Webサーバーの状態
+// =============================================================================
+
+/// APIハンドラーが持つ共有状態
+#[derive(Clone)]
+pub struct AppState {
+    pub sender: mpsc::Sender<EngineMessage>,
+    pub storage: SharedStorage, // 永続化バックエンド（SQLite / インメモリ）
+    pub feeds: MarketFeeds,     // 板情報・ローソク足の配信チャンネル
+    pub rate_limiter: Arc<RateLimiter>, // REST・WebSocketのレート制限
+    pub order_ids: Arc<OrderIdGenerator>, // 注文IDの採番器（シミュレータと共有）
+    pub ws: WsConfig,           // /ws のハートビート設定
+    pub db_status: Arc<DbWriterStatus>, // DB Writer の書き込み失敗の記録
+    pub assets: AssetPrecisions, // 資産ごとの桁数（価格・金額の検証用）
+}
+
+// =============================================================================
+// エラーレスポンス
+// =============================================================================
+
+/// APIのエラー
+///
+/// `{"error": "..."}`（ErrorResponse）のJSONとステータスコードで返す
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "内部エラーが発生しました")
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Conflict(message) => Self::new(StatusCode::CONFLICT, message),
+            e => {
+                // 詳細はログにだけ残し、クライアントには返さない
+                eprintln!("Storage Error: {}", e);
+                Self::internal()
+            }
+        }
+    }
+}
+
+/// エラー時のレスポンスボディ
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorResponse { error: self.message })).into_response()
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+// =============================================================================
+// 認証API
+// =============================================================================
+
+/// 登録・ログインのリクエストボディ
+#[derive(Deserialize, ToSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// 登録結果
+#[derive(Serialize, ToSchema)]
+pub struct RegisterResponse {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+/// ログイン結果
+#[derive(Serialize, ToSchema)]
+pub struct LoginResponse {
+    pub token: String, // 以降のリクエストで `Authorization: Bearer <token>` に使う
+    pub user_id: Uuid,
+    pub username: String,
+    pub expires_at: u128, // セッションの有効期限（ミリ秒）
+}
+
+/// POST /auth/register - ユーザー登録
+///
+/// 初期残高つきでユーザーを作成し、エンジンの残高管理にも読み込ませる
+#[utoipa::path(
+    post, path = "/auth/register", tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 201, body = RegisterResponse),
+        (status = 400, description = "ユーザー名・パスワードの形式が不正", body = ErrorResponse),
+        (status = 409, description = "ユーザー名が使用済み", body = ErrorResponse),
+    ),
+)]
+async fn register(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<Credentials>,
+) -> ApiResult<(StatusCode, Json<RegisterResponse>)> {
+    auth::validate_username(&payload.username).map_err(ApiError::bad_request)?;
+    auth::validate_password(&payload.password).map_err(ApiError::bad_request)?;
+
+    // Argon2は重いので非同期ランタイムのスレッドを塞がないようにする
+    let password = payload.password;
+    let password_hash = tokio::task::spawn_blocking(move || auth::hash_password(&password))
+        .await
+        .map_err(|_| ApiError::internal())?
+        .map_err(|e| {
+            eprintln!("Password Hash Error: {}", e);
+            ApiError::internal()
+        })?;
+
+    let user_id = state.storage.create_user(&payload.username, &password_hash).await?;
+
+    // 作成した残高をエンジンに読み込ませる（読み込み前の注文は残高不足で弾かれる）
+    let balances = state.storage.get_balances(user_id).await?;
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::LoadAccount { balances, respond_to: resp_tx }).await;
+    resp_rx.await.map_err(|_| ApiError::internal())?;
+
+    Ok((StatusCode::CREATED, Json(RegisterResponse { user_id, username: payload.username })))
+}
+
+/// POST /auth/login - ログインしてセッショントークンを発行
+///
+/// ユーザーが存在しない場合もパスワード違いと同じエラーを返す
+#[utoipa::path(
+    post, path = "/auth/login", tag = "auth",
+    request_body = Credentials,
+    responses(
+        (status = 200, body = LoginResponse),
+        (status = 401, description = "ユーザー名またはパスワードが違う", body = ErrorResponse),
+    ),
+)]
+async fn login(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<Credentials>,
+) -> ApiResult<Json<LoginResponse>> {
+    let invalid = || ApiError::unauthorized("ユーザー名またはパスワードが違います");
+
+    let user = state.storage.get_user_by_username(&payload.username).await?.ok_or_else(invalid)?;
+    let password_hash = user.password_hash.ok_or_else(invalid)?;
+
+    let password = payload.password;
+    let verified = tokio::task::spawn_blocking(move || auth::verify_password(&password, &password_hash))
+        .await
+        .map_err(|_| ApiError::internal())?;
+    if !verified {
+        return Err(invalid());
+    }
+
+    let token = auth::generate_token();
+    let expires_at = auth::now_millis() + SESSION_TTL_MS;
+    state.storage.create_session(&auth::hash_token(&token), user.id, expires_at).await?;
+
+    Ok(Json(LoginResponse { token, user_id: user.id, username: user.username, expires_at }))
+}
+
+/// POST /auth/logout - 現在のセッションを破棄
+#[utoipa::path(
+    post, path = "/auth/logout", tag = "auth",
+    security(("session" = [])),
+    responses(
+        (status = 204, description = "破棄した"),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    _user: SessionUser,
+    headers: HeaderMap,
+) -> ApiResult<StatusCode> {
+    // SessionUserの検証を通っているので、ヘッダーには必ずトークンがある
+    let token = auth::bearer_token(&headers).unwrap_or_default();
+    state.storage.delete_session(&auth::hash_token(token)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// =============================================================================
+// APIキー管理（ブラウザのセッションのみ）
+// =============================================================================
+
+/// 1ユーザーが同時に持てる有効なAPIキーの上限
+const MAX_ACTIVE_API_KEYS: usize = 20;
+
+/// APIキー発行のリクエストボディ
+#[derive(Deserialize, ToSchema)]
+struct CreateApiKeyPayload {
+    #[serde(default)]
+    label: String,
+    scopes: Vec<ApiScope>,
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    ip_allowlist: Vec<IpAddr>, // 省略時は全IPを許可
+}
+
+/// 発行結果（secret はこのレスポンスでしか返さない）
+#[derive(Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub secret: String,
+}
+
+/// POST /api-keys - APIキーを発行
+#[utoipa::path(
+    post, path = "/api-keys", tag = "api-keys",
+    security(("session" = [])),
+    request_body = CreateApiKeyPayload,
+    responses(
+        (status = 201, body = CreatedApiKey),
+        (status = 400, body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    SessionUser(user_id): SessionUser,
+    Json(payload): Json<CreateApiKeyPayload>,
+) -> ApiResult<(StatusCode, Json<CreatedApiKey>)> {
+    if payload.scopes.is_empty() {
+        return Err(ApiError::bad_request("scopes を1つ以上指定してください"));
+    }
+    if payload.label.chars().count() > 64 {
+        return Err(ApiError::bad_request("label は64文字以内で指定してください"));
+    }
+    let active = state
+        .storage
+        .list_api_keys(user_id)
+        .await?
+        .iter()
+        .filter(|k| k.revoked_at.is_none())
+        .count();
+    if active >= MAX_ACTIVE_API_KEYS {
+        return Err(ApiError::bad_request("有効なAPIキーが上限に達しています"));
+    }
+
+    let mut scopes = payload.scopes;
+    scopes.sort_by_key(|s| s.as_str());
+    scopes.dedup();
+    let api_key = ApiKey {
+        key: auth::generate_api_key(),
+        secret: auth::generate_token(),
+        user_id,
+        label: payload.label,
+        scopes,
+        ip_allowlist: payload.ip_allowlist,
+        created_at: auth::now_millis(),
+        revoked_at: None,
+    };
+    state.storage.create_api_key(&api_key).await?;
+
+    let secret = api_key.secret.clone();
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { api_key, secret })))
+}
+
+/// GET /api-keys - 自分のAPIキー一覧（失効済みも含む、secret は返さない）
+#[utoipa::path(
+    get, path = "/api-keys", tag = "api-keys",
+    security(("session" = [])),
+    responses(
+        (status = 200, body = Vec<ApiKey>),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn list_api_keys(
+    State(state): State<Arc<AppState>>,
+    SessionUser(user_id): SessionUser,
+) -> ApiResult<Json<Vec<ApiKey>>> {
+    Ok(Json(state.storage.list_api_keys(user_id).await?))
+}
+
+/// DELETE /api-keys/{key} - APIキーを失効させる
+#[utoipa::path(
+    delete, path = "/api-keys/{key}", tag = "api-keys",
+    security(("session" = [])),
+    params(("key" = String, Path, description = "APIキーの公開ID")),
+    responses(
+        (status = 204, description = "失効させた"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+async fn revoke_api_key(
+    State(state): State<Arc<AppState>>,
+    SessionUser(user_id): SessionUser,
+    Path(key): Path<String>,
+) -> ApiResult<StatusCode> {
+    if state.storage.revoke_api_key(user_id, &key, auth::now_millis()).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found("APIキーが見つかりません"))
+    }
+}
+
+// =============================================================================
+// マーケットAPI
+// =============================================================================
+
+/// GET /orderbook - 現在の板情報を価格帯ごとに集計して取得
+#[utoipa::path(get, path = "/orderbook", tag = "market", responses((status = 200, body = BookSnapshot)))]
+async fn get_orderbook(State(state): State<Arc<AppState>>) -> Json<BookSnapshot> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetOrderBook { respond_to: resp_tx }).await;
+    let book = resp_rx.await.unwrap();
+    Json(book)
+}
+
+/// GET /depth のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DepthQuery {
+    limit: Option<usize>,   // 片側の段数（省略時20、上限1000）
+    group: Option<Decimal>, // 価格をまとめる刻み（例: 0.1、価格と同じく USDC の桁数まで）。省略時はまとめない
+}
+
+/// GET /depth - 上位N段の板の深さ（累計数量・シーケンス番号付き）
+#[utoipa::path(
+    get, path = "/depth", tag = "market",
+    params(DepthQuery),
+    responses(
+        (status = 200, body = DepthSnapshot),
+        (status = 400, body = ErrorResponse),
+    ),
+)]
+async fn get_depth(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DepthQuery>,
+) -> ApiResult<Json<DepthSnapshot>> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 1000);
+    // 価格は決済資産の桁数（保存形式の8桁以内）までなので、それより細かい刻みは受け付けない
+    let precision = state.assets.precision(QUOTE_ASSET).unwrap_or(DECIMAL_SCALE).min(DECIMAL_SCALE);
+    if query.group.is_some_and(|g| g <= Decimal::ZERO || g.normalize().scale() > precision) {
+        return Err(ApiError::bad_request(format!("group は小数点以下{}桁までの正の値を指定してください", precision)));
+    }
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetDepth { limit, group: query.group, respond_to: resp_tx }).await;
+    let depth = resp_rx.await.map_err(|_| ApiError::internal())?;
+    Ok(Json(depth))
+}
+
+/// GET /orderbook/l3 - 現在の板情報を注文単位で取得（注文IDのみで所有者は含まない）
+#[utoipa::path(get, path = "/orderbook/l3", tag = "market", responses((status = 200, body = L3Snapshot)))]
+async fn get_orderbook_l3(State(state): State<Arc<AppState>>) -> Json<L3Snapshot> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetOrderBookL3 { respond_to: resp_tx }).await;
+    let book = resp_rx.await.unwrap();
+    Json(book)
+}
+
+/// GET /trades・GET /my-trades のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TradeQueryParams {
+    from_id: Option<u64>,    // この約定ID以上（続きは「最後のid + 1」）
+    start_time: Option<u64>, // 約定時刻（ミリ秒）の下限
+    end_time: Option<u64>,   // 約定時刻（ミリ秒）の上限
+    limit: Option<u32>,      // 最大件数（省略時500、上限1000）
+}
+
+impl TradeQueryParams {
+    fn into_query(self, user_id: Option<Uuid>) -> TradeQuery {
+        TradeQuery {
+            user_id,
+            from_id: self.from_id,
+            start_time: self.start_time.map(u128::from),
+            end_time: self.end_time.map(u128::from),
+            limit: self.limit.unwrap_or(500).clamp(1, 1000),
+        }
+    }
+}
+
+/// GET /trades - 公開の約定履歴を取得（約定IDの古い順）
+///
+/// ページング: from_id / start_time を省略すると最新の約定から limit 件
+#[utoipa::path(
+    get, path = "/trades", tag = "market",
+    params(TradeQueryParams),
+    responses((status = 200, body = Vec<Trade>)),
+)]
+async fn get_trades(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TradeQueryParams>,
+) -> ApiResult<Json<Vec<Trade>>> {
+    Ok(Json(state.storage.get_trades(&params.into_query(None)).await?))
+}
+
+/// GET /my-trades - 自分がMaker/Takerになった約定履歴を取得（約定IDの古い順）
+#[utoipa::path(
+    get, path = "/my-trades", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    params(TradeQueryParams),
+    responses(
+        (status = 200, body = Vec<Trade>),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_my_trades(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(params): Query<TradeQueryParams>,
+) -> ApiResult<Json<Vec<Trade>>> {
+    Ok(Json(state.storage.get_trades(&params.into_query(Some(user_id))).await?))
+}
+
+/// GET /candles のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CandleQuery {
+    interval: Option<CandleInterval>, // "1m" / "5m" / "15m" / "1h" / "1d"（省略時は1m）
+    from: Option<u64>,                // 足の開始時刻（ミリ秒）の下限
+    to: Option<u64>,                  // 足の開始時刻（ミリ秒）の上限
+    limit: Option<u32>,               // 最大本数（省略時500、上限1000）
+}
+
+/// GET /candles - ローソク足を取得（古い順）
+///
+/// ページング: from を省略すると最新の足から limit 本、
+/// 続きは「最後の足の open_time + 1」を from に指定して取得する
+#[utoipa::path(
+    get, path = "/candles", tag = "market",
+    params(CandleQuery),
+    responses((status = 200, body = Vec<Candle>)),
+)]
+async fn get_candles(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CandleQuery>,
+) -> ApiResult<Json<Vec<Candle>>> {
+    let interval = query.interval.unwrap_or(CandleInterval::OneMinute);
+    let limit = query.limit.unwrap_or(500).clamp(1, 1000);
+    let candles = state
+        .storage
+        .get_candles(interval, query.from.map(u128::from), query.to.map(u128::from), limit)
+        .await?;
+    Ok(Json(candles))
+}
+
+/// GET /assets - 登録されている資産（表示名・小数点以下の桁数）の一覧
+#[utoipa::path(get, path = "/assets", tag = "market", responses((status = 200, body = Vec<Asset>)))]
+async fn get_assets(State(state): State<Arc<AppState>>) -> ApiResult<Json<Vec<Asset>>> {
+    Ok(Json(state.storage.get_assets().await?))
+}
+
+/// GET /ticker - 直近24時間の統計と最良気配を取得
+#[utoipa::path(get, path = "/ticker", tag = "market", responses((status = 200, body = Ticker)))]
+async fn get_ticker(State(state): State<Arc<AppState>>) -> Json<Ticker> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetTicker { respond_to: resp_tx }).await;
+    let ticker = resp_rx.await.unwrap();
+    Json(ticker)
+}
+
+/// GET /health のレスポンス
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    pub status: String,           // "ok"（DBへの書き込みの失敗なし）/ "degraded"
+    pub db_write_failures: u64,   // 起動してから失敗したDBへの書き込みの数
+    pub last_db_error: Option<String>, // 最後に失敗した書き込み
+}
+
+/// GET /health - サーバーの状態
+///
+/// DBへの書き込みが1回でも失敗していれば、エンジンと DB がずれているので 503 を返す
+#[utoipa::path(
+    get, path = "/health", tag = "market",
+    responses(
+        (status = 200, body = HealthResponse),
+        (status = 503, description = "DBへの書き込みに失敗している", body = HealthResponse),
+    ),
+)]
+async fn get_health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<HealthResponse>) {
+    let failures = state.db_status.failures();
+    let (status, code) = match failures {
+        0 => ("ok", StatusCode::OK),
+        _ => ("degraded", StatusCode::SERVICE_UNAVAILABLE),
+    };
+    let health = HealthResponse {
+        status: status.to_string(),
+        db_write_failures: failures,
+        last_db_error: state.db_status.last_error(),
+    };
+    (code, Json(health))
+}
+
+// =============================================================================
+// アカウントAPI（ログイン必須）
+// =============================================================================
+
+/// 残高レスポンス用の構造体（GET /balance）
+///
+/// USDC・BAD以外の資産は含まない。全資産が必要なら GET /balances を使う
+#[derive(Serialize, ToSchema)]
+pub struct BalanceResponse {
+    pub usdc_available: String,
+    pub usdc_locked: String,
+    pub bad_available: String,
+    pub bad_locked: String,
+}
+
+/// GET /balance - ログイン中ユーザーの残高を取得
+///
+/// 残高はエンジンの読み取り用コピーから読む（DBへの書き込みを待たずに、発注・約定の結果が反映されている）
+#[utoipa::path(
+    get, path = "/balance", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    responses(
+        (status = 200, body = BalanceResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_balance(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> Json<BalanceResponse> {
+    let balances = state.feeds.balances.get(user_id);
+
+    let mut response = BalanceResponse {
+        usdc_available: "0".to_string(),
+        usdc_locked: "0".to_string(),
+        bad_available: "0".to_string(),
+        bad_locked: "0".to_string(),
+    };
+
+    for balance in balances {
+        match balance.asset.as_str() {
+            "USDC" => {
+                response.usdc_available = balance.available.to_string();
+                response.usdc_locked = balance.locked.to_string();
+            }
+            "BAD" => {
+                response.bad_available = balance.available.to_string();
+                response.bad_locked = balance.locked.to_string();
+            }
+            _ => {}
+        }
+    }
+
+    Json(response)
+}
+
+/// GET /balances の1資産分
+#[derive(Serialize, ToSchema)]
+pub struct AssetBalance {
+    pub asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub locked: Decimal, // 注文に拘束されている分
+    #[serde(with = "rust_decimal::serde::str")]
+    pub total: Decimal, // available + locked
+}
+
+/// GET /balances - ログイン中ユーザーが持つ全資産の残高（資産名の順）
+///
+/// GET /balance と同じく、エンジンの読み取り用コピーから読む
+#[utoipa::path(
+    get, path = "/balances", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    responses(
+        (status = 200, body = Vec<AssetBalance>),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_balances(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> Json<Vec<AssetBalance>> {
+    let balances = state.feeds.balances.get(user_id);
+    Json(
+        balances
+            .into_iter()
+            .map(|b| AssetBalance { total: b.available + b.locked, asset: b.asset, available: b.available, locked: b.locked })
+            .collect(),
+    )
+}
+
+/// 新規注文APIのリクエストボディ
+#[derive(Deserialize, ToSchema)]
+struct CreateOrderPayload {
+    #[serde(with = "rust_decimal::serde::str")] // JSONから文字列として受け取る
+    price: Decimal,
+    quantity: u64,
+    side: Side,
+    #[serde(default = "default_order_type")]
+    order_type: OrderType,
+    #[serde(default)]
+    reduce_only: bool, // 保有量（ポジション）を減らす向き・数量でしか出せない
+    #[serde(default)]
+    close_position: bool, // 保有量（ポジション）全体を閉じる。quantity は無視される
+}
+
+fn default_order_type() -> OrderType {
+    OrderType::Limit
+}
+
+impl CreateOrderPayload {
+    /// ログイン中ユーザーの注文にする（IDはここで採番する）
+    ///
+    /// 指値の価格が正でない注文や、価格・約定代金が保存形式で表せない注文は 400
+    fn into_order(self, state: &AppState, user_id: Uuid) -> ApiResult<Order> {
+        if self.order_type == OrderType::Limit && self.price <= Decimal::ZERO {
+            return Err(ApiError::bad_request("指値の price は正の値を指定してください"));
+        }
+        let mut order = Order {
+            id: 0,
+            price: self.price, // 成行の場合は0などの値が入ってくる想定
+            quantity: self.quantity,
+            side: self.side,
+            user_id: Some(user_id), // 注文者のIDを設定
+            order_type: self.order_type,
+            reduce_only: self.reduce_only,
+            close_position: self.close_position,
+        };
+        order.check_representable(&state.assets).map_err(|e| ApiError::bad_request(e.to_string()))?;
+        order.id = state.order_ids.next_id();
+        Ok(order)
+    }
+}
+
+/// 振替・借入・返済の金額が資産の桁数に収まり、保存形式で表せるか（そうでなければ 400）
+fn check_amount(state: &AppState, asset: &str, amount: Decimal) -> ApiResult<Decimal> {
+    if !state.assets.allows(asset, amount) {
+        let precision = state.assets.precision(asset).unwrap_or_default();
+        return Err(ApiError::bad_request(format!("{} の amount は小数点以下{}桁までで指定してください", asset, precision)));
+    }
+    to_scaled(amount)
+        .map(|_| amount)
+        .map_err(|_| ApiError::bad_request("amount は小数点以下8桁まで・保存できる範囲で指定してください"))
+}
+
+/// POST /order - ログイン中ユーザーの新規注文を作成
+#[utoipa::path(
+    post, path = "/order", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    request_body = CreateOrderPayload,
+    responses(
+        (status = 200, description = "発注と同時に成立した約定", body = Vec<Trade>),
+        (status = 400, description = "価格・約定代金が不正", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn create_order(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<CreateOrderPayload>,
+) -> ApiResult<Json<Vec<Trade>>> {
+    // 注文IDを生成
+    let new_order = payload.into_order(&state, user_id)?;
+
+    let (resp_tx, resp_rx) = oneshot::channel();
+
+    // エンジンに注文処理を依頼
+    let _ = state.sender.send(EngineMessage::PlaceOrder {
+        order: new_order,
+        respond_to: resp_tx
+    }).await;
+
+    // 約定結果を受け取って返す
+    let new_trades = resp_rx.await.map_err(|_| ApiError::internal())?;
+    Ok(Json(new_trades))
+}
+
+/// DELETE /order/:id - ログイン中ユーザーの注文をキャンセル
+#[utoipa::path(
+    delete, path = "/order/{id}", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    params(("id" = u64, Path, description = "注文ID")),
+    responses(
+        (status = 200, description = "取り消した注文（残数量）", body = Order),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+async fn cancel_order(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Path(order_id): Path<u64>,
+) -> ApiResult<Json<Order>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+
+    // エンジンにキャンセルを依頼
+    let _ = state.sender.send(EngineMessage::CancelOrder {
+        order_id,
+        user_id, // 自分の注文しかキャンセルできない
+        respond_to: resp_tx
+    }).await;
+
+    // 結果待機
+    match resp_rx.await {
+        // キャンセル成功: 削除された注文を返す
+        Ok(Some(order)) => Ok(Json(order)),
+        // 注文が見つからない (404 Not Found)
+        Ok(None) => Err(ApiError::not_found("注文が見つかりません")),
+        // エンジンとの通信エラー (500)
+        Err(_) => Err(ApiError::internal()),
+    }
+}
+
+/// 注文照会のレスポンス（注文記録 + 平均約定価格）
+#[derive(Serialize, ToSchema)]
+pub struct OrderResponse {
+    #[serde(flatten)]
+    pub order: OrderRecord,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub avg_fill_price: Option<Decimal>, // 未約定ならnull
+}
+
+impl From<OrderRecord> for OrderResponse {
+    fn from(order: OrderRecord) -> Self {
+        let avg_fill_price = order.avg_fill_price();
+        Self { order, avg_fill_price }
+    }
+}
+
+/// GET /orders/open - ログイン中ユーザーの板に残っている注文（注文ID順）
+#[utoipa::path(
+    get, path = "/orders/open", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    responses(
+        (status = 200, body = Vec<OrderResponse>),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_open_orders(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> ApiResult<Json<Vec<OrderResponse>>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetOpenOrders { user_id, respond_to: resp_tx }).await;
+    let orders = resp_rx.await.map_err(|_| ApiError::internal())?;
+    Ok(Json(orders.into_iter().map(OrderResponse::from).collect()))
+}
+
+/// GET /orders/{id} - ログイン中ユーザーの注文を1件取得
+///
+/// 板に残っていればエンジンの最新状態、なければ保存済みの記録を返す。
+/// 他人の注文は存在しないものとして 404 を返す。
+#[utoipa::path(
+    get, path = "/orders/{id}", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    params(("id" = u64, Path, description = "注文ID")),
+    responses(
+        (status = 200, body = OrderResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    ),
+)]
+async fn get_order(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Path(order_id): Path<u64>,
+) -> ApiResult<Json<OrderResponse>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetOrder { order_id, respond_to: resp_tx }).await;
+    let order = match resp_rx.await.map_err(|_| ApiError::internal())? {
+        Some(order) => Some(order),
+        None => state.storage.get_order(order_id).await?,
+    };
+    match order {
+        Some(order) if order.user_id == user_id => Ok(Json(order.into())),
+        _ => Err(ApiError::not_found("注文が見つかりません")),
+    }
+}
+
+/// GET /orders/history のクエリパラメータ
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OrderHistoryQuery {
+    limit: Option<u32>, // 最大件数（省略時100、上限1000）
+}
+
+/// GET /orders/history - ログイン中ユーザーの注文履歴（新しい順、約定・キャンセル済みも含む）
+#[utoipa::path(
+    get, path = "/orders/history", tag = "account",
+    security(("session" = []), ("api_key" = [])),
+    params(OrderHistoryQuery),
+    responses(
+        (status = 200, body = Vec<OrderResponse>),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_order_history(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<OrderHistoryQuery>,
+) -> ApiResult<Json<Vec<OrderResponse>>> {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let orders = state.storage.get_user_orders(user_id, limit).await?;
+    Ok(Json(orders.into_iter().map(OrderResponse::from).collect()))
+}
+
+// =============================================================================
+// 証拠金取引API（ログイン必須・プロトタイプ）
+// =============================================================================
+//
+// 口座は DB に保存され、再起動しても残る（証拠金注文は消え、ロックは available に戻る。margin.rs 参照）。
+// 証拠金注文のキャンセル・照会は通常の注文と同じAPIで行う
+
+/// 振替の向き
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum TransferDirection {
+    ToMargin, // 現物 → 証拠金口座（先物ならウォレット）
+    ToSpot,   // 証拠金口座（先物ならウォレット） → 現物
+}
+
+/// POST /margin/transfer のリクエストボディ
+#[derive(Deserialize, ToSchema)]
+struct MarginTransferPayload {
+    asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    amount: Decimal,
+    direction: TransferDirection,
+}
+
+/// POST /margin/borrow・/margin/repay のリクエストボディ
+#[derive(Deserialize, ToSchema)]
+struct MarginLoanPayload {
+    asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    amount: Decimal,
+}
+
+/// エンジンに証拠金口座の操作を依頼し、処理後の口座の状態を返す（拒否されたら 400）
+async fn margin_request(state: &AppState, user_id: Uuid, request: MarginRequest) -> ApiResult<Json<MarginAccountSummary>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::Margin { user_id, request, respond_to: resp_tx }).await;
+    match resp_rx.await {
+        Ok(Ok(summary)) => Ok(Json(summary)),
+        Ok(Err(e)) => Err(ApiError::bad_request(e.to_string())),
+        Err(_) => Err(ApiError::internal()),
+    }
+}
+
+/// GET /margin/account - ログイン中ユーザーの証拠金口座（残高・借入・証拠金率）
+#[utoipa::path(
+    get, path = "/margin/account", tag = "margin",
+    security(("session" = []), ("api_key" = [])),
+    responses(
+        (status = 200, body = MarginAccountSummary),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_margin_account(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> ApiResult<Json<MarginAccountSummary>> {
+    margin_request(&state, user_id, MarginRequest::Get).await
+}
+
+/// POST /margin/transfer - 現物の残高と証拠金口座の間で振り替える
+///
+/// 借入があるときに現物へ戻せるのは、戻した後も証拠金率が initial_margin を満たす分まで
+#[utoipa::path(
+    post, path = "/margin/transfer", tag = "margin",
+    security(("session" = []), ("api_key" = [])),
+    request_body = MarginTransferPayload,
+    responses(
+        (status = 200, description = "振替後の証拠金口座", body = MarginAccountSummary),
+        (status = 400, description = "残高不足・証拠金率不足など", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn margin_transfer(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<MarginTransferPayload>,
+) -> ApiResult<Json<MarginAccountSummary>> {
+    let MarginTransferPayload { asset, amount, direction } = payload;
+    let amount = check_amount(&state, &asset, amount)?;
+    let request = match direction {
+        TransferDirection::ToMargin => MarginRequest::TransferIn { asset, amount },
+        TransferDirection::ToSpot => MarginRequest::TransferOut { asset, amount },
+    };
+    margin_request(&state, user_id, request).await
+}
+
+/// POST /margin/borrow - 証拠金口座に借り入れる
+///
+/// 借りた後の証拠金率が initial_margin を下回るなら拒否する。利息は日利で毎ミリ秒按分して付く
+#[utoipa::path(
+    post, path = "/margin/borrow", tag = "margin",
+    security(("session" = []), ("api_key" = [])),
+    request_body = MarginLoanPayload,
+    responses(
+        (status = 200, description = "借入後の証拠金口座", body = MarginAccountSummary),
+        (status = 400, description = "借りられない資産・証拠金率不足など", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn margin_borrow(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<MarginLoanPayload>,
+) -> ApiResult<Json<MarginAccountSummary>> {
+    let amount = check_amount(&state, &payload.asset, payload.amount)?;
+    margin_request(&state, user_id, MarginRequest::Borrow { asset: payload.asset, amount }).await
+}
+
+/// POST /margin/repay - 証拠金口座の残高で返済する（利息から先に充てる）
+#[utoipa::path(
+    post, path = "/margin/repay", tag = "margin",
+    security(("session" = []), ("api_key" = [])),
+    request_body = MarginLoanPayload,
+    responses(
+        (status = 200, description = "返済後の証拠金口座", body = MarginAccountSummary),
+        (status = 400, description = "残高不足など", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn margin_repay(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<MarginLoanPayload>,
+) -> ApiResult<Json<MarginAccountSummary>> {
+    let amount = check_amount(&state, &payload.asset, payload.amount)?;
+    margin_request(&state, user_id, MarginRequest::Repay { asset: payload.asset, amount }).await
+}
+
+/// POST /margin/order - 証拠金口座の資産で新規注文を作成
+///
+/// 残高不足なら POST /order と同じく拒否され、空の約定リストが返る
+#[utoipa::path(
+    post, path = "/margin/order", tag = "margin",
+    security(("session" = []), ("api_key" = [])),
+    request_body = CreateOrderPayload,
+    responses(
+        (status = 200, description = "発注と同時に成立した約定", body = Vec<Trade>),
+        (status = 400, description = "価格・約定代金が不正", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn create_margin_order(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<CreateOrderPayload>,
+) -> ApiResult<Json<Vec<Trade>>> {
+    let order = payload.into_order(&state, user_id)?;
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::PlaceMarginOrder { order, respond_to: resp_tx }).await;
+    let trades = resp_rx.await.map_err(|_| ApiError::internal())?;
+    Ok(Json(trades))
+}
+
+// =============================================================================
+// 無期限先物API（プロトタイプ）
+// =============================================================================
+//
+// BAD-PERP の板・口座・ポジション（perp.rs 参照）。ウォレット・ポジションは DB に保存され、先物の注文は再起動すると消える。
+// 先物の注文のキャンセルは DELETE /order/{id} で行う
+
+/// GET /perp/market - 先物のインデックス価格・マーク価格・資金調達率
+#[utoipa::path(
+    get, path = "/perp/market", tag = "perp",
+    responses((status = 200, body = PerpMarket)),
+)]
+async fn get_perp_market(State(state): State<Arc<AppState>>) -> ApiResult<Json<PerpMarket>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetPerpMarket { respond_to: resp_tx }).await;
+    Ok(Json(resp_rx.await.map_err(|_| ApiError::internal())?))
+}
+
+/// GET /perp/orderbook - 先物の板（価格帯ごとの集計）
+#[utoipa::path(
+    get, path = "/perp/orderbook", tag = "perp",
+    responses((status = 200, body = BookSnapshot)),
+)]
+async fn get_perp_orderbook(State(state): State<Arc<AppState>>) -> ApiResult<Json<BookSnapshot>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetPerpOrderBook { respond_to: resp_tx }).await;
+    Ok(Json(resp_rx.await.map_err(|_| ApiError::internal())?))
+}
+
+/// エンジンに先物口座の操作を依頼し、処理後の口座の状態を返す（拒否されたら 400）
+async fn perp_request(state: &AppState, user_id: Uuid, request: PerpRequest) -> ApiResult<Json<PerpAccountSummary>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::Perp { user_id, request, respond_to: resp_tx }).await;
+    match resp_rx.await {
+        Ok(Ok(summary)) => Ok(Json(summary)),
+        Ok(Err(e)) => Err(ApiError::bad_request(e.to_string())),
+        Err(_) => Err(ApiError::internal()),
+    }
+}
+
+/// GET /perp/account - ログイン中ユーザーの先物口座（ウォレット・ポジション・損益）
+#[utoipa::path(
+    get, path = "/perp/account", tag = "perp",
+    security(("session" = []), ("api_key" = [])),
+    responses(
+        (status = 200, body = PerpAccountSummary),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_perp_account(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> ApiResult<Json<PerpAccountSummary>> {
+    perp_request(&state, user_id, PerpRequest::Get).await
+}
+
+/// POST /perp/transfer のリクエストボディ（資産は USDC のみ）
+#[derive(Deserialize, ToSchema)]
+struct PerpTransferPayload {
+    #[serde(with = "rust_decimal::serde::str")]
+    amount: Decimal,
+    direction: TransferDirection, // to_margin で現物 → 先物ウォレット
+}
+
+/// POST /perp/transfer - 現物の USDC と先物ウォレットの間で振り替える
+///
+/// ポジションがあるときに現物へ戻せるのは、戻した後も証拠金が足りる分まで
+#[utoipa::path(
+    post, path = "/perp/transfer", tag = "perp",
+    security(("session" = []), ("api_key" = [])),
+    request_body = PerpTransferPayload,
+    responses(
+        (status = 200, description = "振替後の先物口座", body = PerpAccountSummary),
+        (status = 400, description = "残高不足・証拠金不足など", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn perp_transfer(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<PerpTransferPayload>,
+) -> ApiResult<Json<PerpAccountSummary>> {
+    let amount = check_amount(&state, SETTLEMENT_ASSET, payload.amount)?;
+    let request = match payload.direction {
+        TransferDirection::ToMargin => PerpRequest::TransferIn { amount },
+        TransferDirection::ToSpot => PerpRequest::TransferOut { amount },
+    };
+    perp_request(&state, user_id, request).await
+}
+
+/// POST /perp/order - ログイン中ユーザーの先物の新規注文を作成
+///
+/// 数量は枚数（1枚 = 1 BAD）。約定しても資産は動かず、ポジションが変わる
+#[utoipa::path(
+    post, path = "/perp/order", tag = "perp",
+    security(("session" = []), ("api_key" = [])),
+    request_body = CreateOrderPayload,
+    responses(
+        (status = 200, description = "発注と同時に成立した約定", body = Vec<Trade>),
+        (status = 400, description = "価格が不正・証拠金不足・reduce_only の条件を満たさないなど", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn create_perp_order(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+    Json(payload): Json<CreateOrderPayload>,
+) -> ApiResult<Json<Vec<Trade>>> {
+    let order = payload.into_order(&state, user_id)?;
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::PlacePerpOrder { order, respond_to: resp_tx }).await;
+    match resp_rx.await {
+        Ok(Ok(trades)) => Ok(Json(trades)),
+        Ok(Err(e)) => Err(ApiError::bad_request(e.to_string())),
+        Err(_) => Err(ApiError::internal()),
+    }
+}
+
+/// GET /perp/orders/open - ログイン中ユーザーの先物の板に残っている注文（注文ID順）
+#[utoipa::path(
+    get, path = "/perp/orders/open", tag = "perp",
+    security(("session" = []), ("api_key" = [])),
+    responses(
+        (status = 200, body = Vec<OrderResponse>),
+        (status = 401, body = ErrorResponse),
+    ),
+)]
+async fn get_perp_open_orders(
+    State(state): State<Arc<AppState>>,
+    AuthUser(user_id): AuthUser,
+) -> ApiResult<Json<Vec<OrderResponse>>> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let _ = state.sender.send(EngineMessage::GetPerpOpenOrders { user_id, respond_to: resp_tx }).await;
+    let orders = resp_rx.await.map_err(|_| ApiError::internal())?;
+    Ok(Json(orders.into_iter().map(OrderResponse::from).collect()))
+}
+
+// =============================================================================
+// ルーター
+// =============================================================================
+
+/// 全APIのルーターを組み立てる
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/auth/register", post(register)) // POST /auth/register (ユーザー登録)
+        .route("/auth/login", post(login))       // POST /auth/login (トークン発行)
+        .route("/auth/logout", post(logout))     // POST /auth/logout (トークン破棄)
+        .route("/api-keys", get(list_api_keys).post(create_api_key)) // APIキーの一覧・発行
+        .route("/api-keys/{key}", delete(revoke_api_key)) // DELETE /api-keys/{key} (失効)
+        .route("/orderbook", get(get_orderbook)) // GET /orderbook (価格帯ごとの集計)
+        .route("/orderbook/l3", get(get_orderbook_l3)) // GET /orderbook/l3 (注文単位)
+        .route("/depth", get(get_depth))         // GET /depth (上位N段・価格のまとめ)
+        .route("/trades", get(get_trades))       // GET /trades
+        .route("/order", post(create_order))     // POST /order
+        .route("/order/{id}", delete(cancel_order)) // DELETE /order/{id}
+        .route("/orders/open", get(get_open_orders)) // GET /orders/open (自分の未約定注文)
+        .route("/orders/history", get(get_order_history)) // GET /orders/history (自分の注文履歴)
+        .route("/orders/{id}", get(get_order))   // GET /orders/{id} (自分の注文の状態)
+        .route("/my-trades", get(get_my_trades)) // GET /my-trades (自分の履歴)
+        .route("/balance", get(get_balance))     // GET /balance
+        .route("/balances", get(get_balances))   // GET /balances (全資産の残高)
+        .route("/assets", get(get_assets))       // GET /assets (資産の登録情報)
+        .route("/margin/account", get(get_margin_account)) // GET /margin/account (証拠金口座)
+        .route("/margin/transfer", post(margin_transfer)) // POST /margin/transfer (現物との振替)
+        .route("/margin/borrow", post(margin_borrow)) // POST /margin/borrow (借入)
+        .route("/margin/repay", post(margin_repay)) // POST /margin/repay (返済)
+        .route("/margin/order", post(create_margin_order)) // POST /margin/order (証拠金注文)
+        .route("/perp/market", get(get_perp_market)) // GET /perp/market (先物の価格・資金調達率)
+        .route("/perp/orderbook", get(get_perp_orderbook)) // GET /perp/orderbook (先物の板)
+        .route("/perp/account", get(get_perp_account)) // GET /perp/account (先物口座・ポジション)
+        .route("/perp/transfer", post(perp_transfer)) // POST /perp/transfer (現物との振替)
+        .route("/perp/order", post(create_perp_order)) // POST /perp/order (先物の注文)
+        .route("/perp/orders/open", get(get_perp_open_orders)) // GET /perp/orders/open (先物の未約定注文)
+        .route("/candles", get(get_candles))     // GET /candles (ローソク足)
+        .route("/ticker", get(get_ticker))       // GET /ticker (24時間統計)
+        .route("/health", get(get_health))       // GET /health (DBへの書き込みの失敗)
+        .route("/ws", get(ws::ws_handler))       // WebSocket
+        .route("/ws/l3", get(ws::ws_l3_handler)) // WebSocket (注文単位の板)
+        .route("/ws/candles", get(ws::ws_candles_handler)) // WebSocket (形成中のローソク足)
+        .route("/openapi.json", get(openapi::openapi_json)) // OpenAPI 3 のスキーマ
+        .route("/docs", get(openapi::swagger_ui)) // Swagger UI
+        .layer(middleware::from_fn_with_state(state.clone(), ratelimit::user_rate_limit)) // ユーザーごとのレート制限
+        .layer(middleware::from_fn_with_state(state.clone(), auth::api_key_auth)) // APIキー署名の検証
+        .layer(middleware::from_fn_with_state(state.clone(), ratelimit::ip_rate_limit)) // IPごとのレート制限（認証より前）
+        .layer(CorsLayer::permissive())          // CORS許可（開発用に全許可）
+        .with_state(state)                       // ハンドラーに状態を渡す
+}
@@ -0,0 +1,366 @@
+// =============================================================================
+// 認証モジュール
+// =============================================================================
+//
+// ユーザー登録・ログインと、リクエストごとのユーザー特定を担当します。
+//
+// - パスワード: Argon2id でハッシュ化して保存（PHC文字列形式）
+// - セッション: ランダムな256bitトークンを発行し、DBには SHA-256 ハッシュだけを保存
+// - リクエスト: `Authorization: Bearer <token>` を AuthUser エクストラクタが検証する
+// - ボット: APIキーで HMAC-SHA256 署名したリクエストを api_key_auth ミドルウェアが検証する
+//
+// ハンドラーは引数に `AuthUser` を書くだけで、ログイン必須のAPIになります。
+// （セッション・APIキーのどちらでも通る。APIキーの管理など、ブラウザからだけ
+//   許可したいAPIは `SessionUser` を使う）
+//
+// 【署名の作り方】
+//   payload   = timestamp + HTTPメソッド + パス（クエリ込み）+ ボディ
+//   signature = hex(HMAC-SHA256(secret, payload))
+// を X-API-KEY / X-TIMESTAMP / X-SIGNATURE（任意で X-RECV-WINDOW）ヘッダーで送ります。
+// サーバー時刻との差が recv-window を超えたリクエストは、署名が正しくても拒否します。
+// =============================================================================
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::body::{to_bytes, Body};
+use axum::extract::{ConnectInfo, FromRequestParts, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::api::{ApiError, AppState};
+use crate::models::{ApiKey, ApiScope};
+use crate::storage::StorageResult;
+
+/// セッションの有効期間（7日、ミリ秒）
+pub const SESSION_TTL_MS: u128 = 7 * 24 * 60 * 60 * 1000;
+
+/// 現在時刻（ミリ秒）
+pub(crate) fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+// =============================================================================
+// パスワード
+// =============================================================================
+
+/// パスワードをArgon2idでハッシュ化する
+///
+/// 意図的に重い処理なので、非同期ハンドラーからは spawn_blocking で呼ぶこと
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt_bytes: [u8; 16] = rand::rng().random();
+    let salt = SaltString::encode_b64(&salt_bytes)?;
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// パスワードがハッシュと一致するか（ハッシュが壊れていれば不一致扱い）
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// ユーザー名の形式チェック（3〜32文字の英数字・`_`・`-`）
+pub fn validate_username(username: &str) -> Result<(), &'static str> {
+    let valid_chars = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !(3..=32).contains(&username.len()) || !valid_chars {
+        return Err("ユーザー名は3〜32文字の英数字・_・- で指定してください");
+    }
+    Ok(())
+}
+
+/// パスワードの長さチェック（8〜128文字）
+pub fn validate_password(password: &str) -> Result<(), &'static str> {
+    if !(8..=128).contains(&password.chars().count()) {
+        return Err("パスワードは8〜128文字で指定してください");
+    }
+    Ok(())
+}
+
+// =============================================================================
+// セッショントークン
+// =============================================================================
+
+/// 新しいセッショントークンを生成する（32バイトの乱数を16進数に）
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    hex::encode(bytes)
+}
+
+/// DB保存用のトークンハッシュ
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// 新しいAPIキーの公開IDを生成する（16バイトの乱数を16進数に）
+pub fn generate_api_key() -> String {
+    let bytes: [u8; 16] = rand::rng().random();
+    hex::encode(bytes)
+}
+
+/// Authorizationヘッダーから Bearer トークンを取り出す
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+// =============================================================================
+// エクストラクタ
+// =============================================================================
+
+/// ログイン中のユーザー
+///
+/// ハンドラーの引数に置くと、有効なセッションも検証済みのAPIキーもなければ 401 を返す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthUser(pub Uuid);
+
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        // api_key_auth ミドルウェアで検証済みならそのユーザー
+        if let Some(auth) = parts.extensions.get::<ApiKeyAuth>() {
+            return Ok(AuthUser(auth.user_id));
+        }
+        let SessionUser(user_id) = SessionUser::from_request_parts(parts, state).await?;
+        Ok(AuthUser(user_id))
+    }
+}
+
+/// ブラウザのログインセッションでのみ認証されたユーザー
+///
+/// APIキーの発行など、APIキー自身には許可しない操作に使う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionUser(pub Uuid);
+
+impl FromRequestParts<Arc<AppState>> for SessionUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if parts.extensions.get::<ApiKeyAuth>().is_some() {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "このAPIはAPIキーでは利用できません"));
+        }
+        if bearer_token(&parts.headers).is_none() {
+            return Err(ApiError::unauthorized("ログインが必要です"));
+        }
+        // user_rate_limit ミドルウェアが照会済みならその結果を使う
+        let user_id = match parts.extensions.get::<ResolvedSession>() {
+            Some(ResolvedSession(user_id)) => *user_id,
+            None => session_user(state, &parts.headers).await?,
+        };
+        user_id.map(SessionUser).ok_or_else(|| ApiError::unauthorized("セッションが無効か期限切れです"))
+    }
+}
+
+/// Bearerトークンのセッションを照会した結果（リクエストのextensionsに入る）
+///
+/// user_rate_limit ミドルウェアが付け、SessionUser が同じリクエストで照会し直さないようにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedSession(pub Option<Uuid>);
+
+/// Bearerトークンのセッションが有効ならそのユーザー
+pub async fn session_user(state: &AppState, headers: &HeaderMap) -> StorageResult<Option<Uuid>> {
+    match bearer_token(headers) {
+        Some(token) => token_user(state, token).await,
+        None => Ok(None),
+    }
+}
+
+/// セッショントークンが有効ならそのユーザー
+pub async fn token_user(state: &AppState, token: &str) -> StorageResult<Option<Uuid>> {
+    state.storage.get_session_user(&hash_token(token), now_millis()).await
+}
+
+// =============================================================================
+// APIキー署名
+// =============================================================================
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const RECV_WINDOW_HEADER: &str = "x-recv-window";
+
+/// recv-window の既定値と上限（ミリ秒）
+pub const DEFAULT_RECV_WINDOW_MS: u128 = 5_000;
+pub const MAX_RECV_WINDOW_MS: u128 = 60_000;
+
+/// クライアントの時計が進んでいる場合の許容幅（ミリ秒）
+const CLOCK_SKEW_MS: u128 = 1_000;
+
+/// 署名対象にするボディの上限（これより大きいリクエストは受け付けない）
+const MAX_SIGNED_BODY_BYTES: usize = 64 * 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// APIキーで認証されたリクエストに付く情報（リクエストのextensionsに入る）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyAuth {
+    pub key: String,
+    pub user_id: Uuid,
+}
+
+/// 署名対象の文字列を組み立てる
+fn signing_payload(timestamp: &str, method: &Method, path_and_query: &str, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{}{}{}", timestamp, method.as_str(), path_and_query).into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+/// リクエストに署名する（クライアント側と同じ計算。テストやボットの実装用）
+pub fn sign_request(secret: &str, timestamp: &str, method: &Method, path_and_query: &str, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMACは任意長の鍵を受け付ける");
+    mac.update(&signing_payload(timestamp, method, path_and_query, body));
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// 署名が正しいか（比較は定数時間）
+fn verify_signature(secret: &str, payload: &[u8], signature: &str) -> bool {
+    let Ok(expected) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMACは任意長の鍵を受け付ける");
+    mac.update(payload);
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// HTTPメソッドとパスから必要な権限を決める
+///
+/// 参照系は read、現物の残高から資金を移す振替は withdraw、それ以外は trade
+fn required_scope(method: &Method, path: &str) -> ApiScope {
+    if method == Method::GET || method == Method::HEAD {
+        ApiScope::Read
+    } else if matches!(path, "/margin/transfer" | "/perp/transfer") {
+        ApiScope::Withdraw
+    } else {
+        ApiScope::Trade
+    }
+}
+
+pub(crate) fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// 署名の検証待ちのAPIキー（キー・接続元IP・タイムスタンプは確認済み）
+///
+/// 署名対象のボディは読み方が呼び出し側（REST・gRPC）で違うので、検証を2段階に分けている
+pub struct UnverifiedApiKey {
+    api_key: ApiKey,
+    timestamp: String,
+    signature: String,
+}
+
+impl UnverifiedApiKey {
+    /// X-API-KEY のキーを読み込み、接続元IPとタイムスタンプを確かめる
+    pub async fn check(state: &AppState, key: &str, headers: &HeaderMap, ip: Option<IpAddr>) -> Result<Self, ApiError> {
+        let api_key = state
+            .storage
+            .get_api_key(key)
+            .await?
+            .filter(|k| k.revoked_at.is_none())
+            .ok_or_else(|| ApiError::unauthorized("APIキーが無効です"))?;
+
+        // 1. 接続元IP
+        if !api_key.allows_ip(ip) {
+            return Err(ApiError::new(StatusCode::FORBIDDEN, "このIPアドレスからは利用できません"));
+        }
+
+        // 2. タイムスタンプと recv-window（リプレイ対策）
+        let timestamp = header_str(headers, TIMESTAMP_HEADER)
+            .ok_or_else(|| ApiError::bad_request("X-TIMESTAMP ヘッダーが必要です"))?
+            .to_string();
+        let sent_at: u128 = timestamp
+            .parse()
+            .map_err(|_| ApiError::bad_request("X-TIMESTAMP はミリ秒の整数で指定してください"))?;
+        let recv_window = match header_str(headers, RECV_WINDOW_HEADER) {
+            Some(v) => v
+                .parse::<u128>()
+                .ok()
+                .filter(|w| (1..=MAX_RECV_WINDOW_MS).contains(w))
+                .ok_or_else(|| ApiError::bad_request("X-RECV-WINDOW は1〜60000ミリ秒で指定してください"))?,
+            None => DEFAULT_RECV_WINDOW_MS,
+        };
+        let now = now_millis();
+        if sent_at > now + CLOCK_SKEW_MS || now.saturating_sub(sent_at) > recv_window {
+            return Err(ApiError::unauthorized("タイムスタンプが受付時間外です"));
+        }
+
+        let signature = header_str(headers, SIGNATURE_HEADER)
+            .ok_or_else(|| ApiError::bad_request("X-SIGNATURE ヘッダーが必要です"))?
+            .to_string();
+        Ok(Self { api_key, timestamp, signature })
+    }
+
+    /// 署名と権限を確かめる
+    pub fn verify(self, method: &Method, path_and_query: &str, body: &[u8], scope: ApiScope) -> Result<ApiKeyAuth, ApiError> {
+        // 3. 署名
+        let payload = signing_payload(&self.timestamp, method, path_and_query, body);
+        if !verify_signature(&self.api_key.secret, &payload, &self.signature) {
+            return Err(ApiError::unauthorized("署名が一致しません"));
+        }
+
+        // 4. 権限
+        if !self.api_key.has_scope(scope) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("このAPIキーには {} 権限がありません", scope.as_str()),
+            ));
+        }
+        Ok(ApiKeyAuth { key: self.api_key.key, user_id: self.api_key.user_id })
+    }
+}
+
+/// APIキー認証ミドルウェア
+///
+/// X-API-KEY ヘッダーがないリクエストはそのまま通す（セッション認証・公開API）。
+/// ある場合は、キー・接続元IP・タイムスタンプ・署名・権限をすべて検証し、
+/// 通ったら `ApiKeyAuth` を付けて後ろのハンドラーに渡す。
+pub async fn api_key_auth(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(key) = header_str(req.headers(), API_KEY_HEADER).map(str::to_string) else {
+        return Ok(next.run(req).await);
+    };
+
+    // ConnectInfo がない環境では接続元IPは不明扱い
+    let ip = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip());
+    let unverified = UnverifiedApiKey::check(&state, &key, req.headers(), ip).await?;
+
+    // ボディも署名対象なので一度読み切ってから組み直す
+    let (mut parts, body) = req.into_parts();
+    let body = to_bytes(body, MAX_SIGNED_BODY_BYTES)
+        .await
+        .map_err(|_| ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "リクエストボディが大きすぎます"))?;
+    let path_and_query = parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let auth = unverified.verify(&parts.method, path_and_query, &body, required_scope(&parts.method, parts.uri.path()))?;
+
+    parts.extensions.insert(auth);
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
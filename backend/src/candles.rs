@@ -0,0 +1,115 @@
+// =============================================================================
+// ローソク足（OHLCV）集計モジュール
+// =============================================================================
+//
+// 約定が発生するたびに、各時間足（1m/5m/15m/1h/1d）の「形成中の足」を更新します。
+//
+// - エンジンアクターが保持し、process_order の結果をそのまま流し込む
+// - 更新された足は DB Writer で保存され、WebSocketで配信される
+// - 起動時は DB から形成中の足を読み戻すので、再起動しても足が途切れない
+// =============================================================================
+
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::models::{Candle, CandleInterval, Trade};
+use crate::storage::{SharedStorage, StorageResult};
+
+/// タイムスタンプが属する足の開始時刻（ミリ秒）
+pub fn open_time_for(timestamp: u128, interval: CandleInterval) -> u128 {
+    timestamp - timestamp % interval.duration_ms()
+}
+
+/// 全時間足の形成中ローソク足を管理する
+#[derive(Debug, Clone, Default)]
+pub struct CandleAggregator {
+    current: HashMap<CandleInterval, Candle>,
+}
+
+impl CandleAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// DBに保存されている形成中の足を読み込んで復元する
+    ///
+    /// now の時点でまだ閉じていない足だけを対象にする
+    pub async fn load(storage: &SharedStorage, now: u128) -> StorageResult<Self> {
+        let mut aggregator = Self::new();
+        for interval in CandleInterval::ALL {
+            let open_time = open_time_for(now, interval);
+            let candles = storage
+                .get_candles(interval, Some(open_time), Some(open_time), 1)
+                .await?;
+            if let Some(candle) = candles.into_iter().next() {
+                aggregator.restore(candle);
+            }
+        }
+        Ok(aggregator)
+    }
+
+    /// 形成中の足を直接セットする（起動時の復元用）
+    pub fn restore(&mut self, candle: Candle) {
+        self.current.insert(candle.interval, candle);
+    }
+
+    /// 指定した時間足の形成中の足
+    pub fn current(&self, interval: CandleInterval) -> Option<&Candle> {
+        self.current.get(&interval)
+    }
+
+    /// 約定を反映し、時間足ごとに最終状態の足だけを返す
+    ///
+    /// 1回の注文で複数の約定が出ても、保存・配信は時間足ごとに1回で済む
+    pub fn apply_trades(&mut self, trades: &[Trade]) -> Vec<Candle> {
+        if trades.is_empty() {
+            return Vec::new();
+        }
+        for trade in trades {
+            for interval in CandleInterval::ALL {
+                self.apply_to_interval(interval, trade);
+            }
+        }
+        CandleInterval::ALL
+            .into_iter()
+            .filter_map(|interval| self.current.get(&interval).cloned())
+            .collect()
+    }
+
+    fn apply_to_interval(&mut self, interval: CandleInterval, trade: &Trade) {
+        let open_time = open_time_for(trade.timestamp, interval);
+
+        match self.current.get_mut(&interval) {
+            // 同じ期間（時計が巻き戻った約定も形成中の足に含める）
+            Some(candle) if open_time <= candle.open_time => {
+                candle.high = candle.high.max(trade.price);
+                candle.low = candle.low.min(trade.price);
+                candle.close = trade.price;
+                candle.volume += trade.quantity;
+                candle.quote_volume += trade.price * Decimal::from(trade.quantity);
+                candle.trade_count += 1;
+            }
+            // 最初の約定、または新しい期間に入った
+            // → 前の足は最後の更新時に保存済みなので置き換えてよい
+            _ => {
+                self.current.insert(interval, new_candle(interval, open_time, trade));
+            }
+        }
+    }
+}
+
+/// 1件の約定から新しい足を作る
+fn new_candle(interval: CandleInterval, open_time: u128, trade: &Trade) -> Candle {
+    Candle {
+        interval,
+        open_time,
+        open: trade.price,
+        high: trade.price,
+        low: trade.price,
+        close: trade.price,
+        volume: trade.quantity,
+        quote_volume: trade.price * Decimal::from(trade.quantity),
+        trade_count: 1,
+    }
+}
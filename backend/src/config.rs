@@ -0,0 +1,235 @@
+// =============================================================================
+// 設定モジュール
+// =============================================================================
+//
+// サーバーの設定を環境変数から読み込みます。
+// 未設定の項目は開発用のデフォルト値になります。
+//
+// | 環境変数          | 内容                               | デフォルト |
+// |-------------------|------------------------------------|------------|
+// | BADBIT_STORAGE    | ストレージ種別 (sqlite / memory)   | sqlite     |
+// | BADBIT_DB_PATH    | SQLiteファイルのパス               | data.db    |
+// | BADBIT_RATE_USER_BURST    | ユーザーごとのバケット容量    | 100 |
+// | BADBIT_RATE_USER_PER_SEC  | ユーザーごとの毎秒の回復量    | 20  |
+// | BADBIT_RATE_IP_BURST      | IPごとのバケット容量          | 200 |
+// | BADBIT_RATE_IP_PER_SEC    | IPごとの毎秒の回復量          | 40  |
+// | BADBIT_WS_MAX_PER_USER    | ユーザーごとのWebSocket同時接続数 | 5  |
+// | BADBIT_WS_MAX_PER_IP      | IPごとのWebSocket同時接続数   | 20  |
+// | BADBIT_WS_PING_SECS       | /ws でPingを送る間隔（秒）    | 20  |
+// | BADBIT_WS_IDLE_SECS       | /ws で無応答の接続を切るまでの秒数 | 60 |
+// | BADBIT_PUBLISH_BOOK_MS    | 板の配信間隔（ミリ秒）        | 50  |
+// | BADBIT_PUBLISH_TICKER_MS  | ティッカーの配信間隔（ミリ秒）| 250 |
+// | BADBIT_FIX_ADDR           | FIXゲートウェイの待ち受けアドレス（off で無効） | 0.0.0.0:9878 |
+// | BADBIT_FIX_COMP_ID        | FIXゲートウェイの CompID      | BADBIT |
+// | BADBIT_GRPC_ADDR          | gRPCサーバーの待ち受けアドレス（off で無効） | 0.0.0.0:50051 |
+// | BADBIT_MARGIN_INITIAL     | 借入・引き出しの後に必要な証拠金率 | 0.25 |
+// | BADBIT_MARGIN_MAINTENANCE | これを下回ると清算する証拠金率 | 0.1 |
+// | BADBIT_MARGIN_DAILY_RATES | 借りられる資産と日利（例: USDC=0.0003,BAD=0.0005） | USDC=0.0003,BAD=0.0005 |
+// | BADBIT_PERP_FUNDING_SECS  | 無期限先物の資金調達の間隔（秒） | 28800 |
+// | BADBIT_PERP_MAX_FUNDING_RATE | 資金調達率の上限（絶対値） | 0.0075 |
+// | BADBIT_PERP_MAX_BASIS     | マーク価格とインデックス価格の差の上限（インデックス価格に対する比率） | 0.005 |
+// | BADBIT_PERP_INITIAL_MARGIN | 先物の建玉に必要な証拠金の比率 | 0.1 |
+// | BADBIT_PERP_MAINTENANCE_MARGIN | これを下回ると先物のポジションを清算する証拠金の比率 | 0.05 |
+// =============================================================================
+
+use std::collections::BTreeMap;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use crate::fix_gateway::FixConfig;
+use crate::grpc::GrpcConfig;
+use crate::margin::MarginConfig;
+use crate::perp::PerpConfig;
+use crate::publisher::PublishIntervals;
+use crate::ratelimit::RateLimitConfig;
+use crate::ws::WsConfig;
+
+/// 永続化バックエンドの種類
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageBackend {
+    /// SQLiteファイルに保存
+    Sqlite { path: String },
+    /// プロセス内メモリに保存（再起動で消える）
+    Memory,
+}
+
+/// サーバー全体の設定
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub storage: StorageBackend,
+    pub rate_limit: RateLimitConfig,
+    pub ws: WsConfig,
+    pub publish: PublishIntervals,
+    pub fix: FixConfig,
+    pub grpc: GrpcConfig,
+    pub margin: MarginConfig,
+    pub perp: PerpConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            storage: StorageBackend::Sqlite { path: "data.db".to_string() },
+            rate_limit: RateLimitConfig::default(),
+            ws: WsConfig::default(),
+            publish: PublishIntervals::default(),
+            fix: FixConfig::default(),
+            grpc: GrpcConfig::default(),
+            margin: MarginConfig::default(),
+            perp: PerpConfig::default(),
+        }
+    }
+}
+
+/// "USDC=0.0003,BAD=0.0005" を 資産 -> 日利 にする（空なら借入なし）
+fn parse_daily_rates(value: &str) -> Option<BTreeMap<String, Decimal>> {
+    let mut rates = BTreeMap::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (asset, rate) = entry.split_once('=')?;
+        let rate: Decimal = rate.trim().parse().ok()?;
+        if asset.trim().is_empty() || rate < Decimal::ZERO {
+            return None;
+        }
+        rates.insert(asset.trim().to_string(), rate);
+    }
+    Some(rates)
+}
+
+/// 数値の環境変数を読む（未設定ならNone、解釈できなければエラー）
+fn env_number<T: FromStr>(name: &str) -> Result<Option<T>, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} の値が不正です: {}", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    /// 環境変数から設定を読み込む
+    ///
+    /// 不正な値はデフォルトにフォールバックせず、起動時にエラーとして扱う
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Config::default();
+
+        let db_path = env::var("BADBIT_DB_PATH").unwrap_or_else(|_| "data.db".to_string());
+        config.storage = match env::var("BADBIT_STORAGE").as_deref() {
+            Ok("sqlite") | Err(_) => StorageBackend::Sqlite { path: db_path },
+            Ok("memory") => StorageBackend::Memory,
+            Ok(other) => return Err(format!("BADBIT_STORAGE の値が不正です: {}", other)),
+        };
+
+        let limits = &mut config.rate_limit;
+        for (name, field) in [
+            ("BADBIT_RATE_USER_BURST", &mut limits.user.burst),
+            ("BADBIT_RATE_USER_PER_SEC", &mut limits.user.refill_per_sec),
+            ("BADBIT_RATE_IP_BURST", &mut limits.ip.burst),
+            ("BADBIT_RATE_IP_PER_SEC", &mut limits.ip.refill_per_sec),
+        ] {
+            if let Some(value) = env_number(name)? {
+                *field = value;
+            }
+        }
+        for (name, field) in [
+            ("BADBIT_WS_MAX_PER_USER", &mut limits.ws_connections_per_user),
+            ("BADBIT_WS_MAX_PER_IP", &mut limits.ws_connections_per_ip),
+        ] {
+            if let Some(value) = env_number(name)? {
+                *field = value;
+            }
+        }
+
+        if let Some(secs) = env_number("BADBIT_WS_PING_SECS")? {
+            config.ws.ping_interval = Duration::from_secs(secs);
+        }
+        if let Some(secs) = env_number("BADBIT_WS_IDLE_SECS")? {
+            config.ws.idle_timeout = Duration::from_secs(secs);
+        }
+        // Pingへの応答が届く前に切断しないよう、Ping間隔より長くする
+        if config.ws.idle_timeout <= config.ws.ping_interval {
+            return Err("BADBIT_WS_IDLE_SECS は BADBIT_WS_PING_SECS より大きくしてください".to_string());
+        }
+
+        for (name, field) in [
+            ("BADBIT_PUBLISH_BOOK_MS", &mut config.publish.book),
+            ("BADBIT_PUBLISH_TICKER_MS", &mut config.publish.ticker),
+        ] {
+            if let Some(ms) = env_number(name)? {
+                *field = Duration::from_millis(ms);
+            }
+        }
+
+        match env::var("BADBIT_FIX_ADDR").as_deref() {
+            Ok("off") | Ok("") => config.fix.addr = None,
+            Ok(addr) => config.fix.addr = Some(addr.to_string()),
+            Err(_) => {}
+        }
+        match env::var("BADBIT_GRPC_ADDR").as_deref() {
+            Ok("off") | Ok("") => config.grpc.addr = None,
+            Ok(addr) => config.grpc.addr = Some(addr.to_string()),
+            Err(_) => {}
+        }
+        if let Ok(comp_id) = env::var("BADBIT_FIX_COMP_ID") {
+            if comp_id.is_empty() || comp_id.contains(['\x01', '=']) {
+                return Err(format!("BADBIT_FIX_COMP_ID の値が不正です: {}", comp_id));
+            }
+            config.fix.comp_id = comp_id;
+        }
+
+        for (name, field) in [
+            ("BADBIT_MARGIN_INITIAL", &mut config.margin.initial_margin),
+            ("BADBIT_MARGIN_MAINTENANCE", &mut config.margin.maintenance_margin),
+        ] {
+            if let Some(ratio) = env_number(name)? {
+                *field = ratio;
+            }
+        }
+        if let Ok(rates) = env::var("BADBIT_MARGIN_DAILY_RATES") {
+            config.margin.daily_rates = parse_daily_rates(&rates)
+                .ok_or_else(|| format!("BADBIT_MARGIN_DAILY_RATES の値が不正です: {}", rates))?;
+        }
+        // 清算の水準が借入できる水準より上だと、借りた直後に清算されてしまう
+        let margin = &config.margin;
+        if margin.maintenance_margin <= Decimal::ZERO || margin.initial_margin <= margin.maintenance_margin {
+            return Err("BADBIT_MARGIN_INITIAL は BADBIT_MARGIN_MAINTENANCE（0より大きい）より大きくしてください".to_string());
+        }
+
+        if let Some(secs) = env_number("BADBIT_PERP_FUNDING_SECS")? {
+            config.perp.funding_interval = Duration::from_secs(secs);
+        }
+        for (name, field) in [
+            ("BADBIT_PERP_MAX_FUNDING_RATE", &mut config.perp.max_funding_rate),
+            ("BADBIT_PERP_MAX_BASIS", &mut config.perp.max_basis),
+            ("BADBIT_PERP_INITIAL_MARGIN", &mut config.perp.initial_margin),
+            ("BADBIT_PERP_MAINTENANCE_MARGIN", &mut config.perp.maintenance_margin),
+        ] {
+            if let Some(value) = env_number(name)? {
+                *field = value;
+            }
+        }
+        let perp = &config.perp;
+        if perp.funding_interval.is_zero()
+            || perp.max_funding_rate < Decimal::ZERO
+            || perp.max_basis < Decimal::ZERO
+            || perp.initial_margin <= Decimal::ZERO
+        {
+            return Err("BADBIT_PERP_FUNDING_SECS・BADBIT_PERP_INITIAL_MARGIN は0より大きく、BADBIT_PERP_MAX_FUNDING_RATE・BADBIT_PERP_MAX_BASIS は0以上にしてください".to_string());
+        }
+        // 清算の水準が建てられる水準より上だと、建てた直後に清算されてしまう
+        if perp.maintenance_margin <= Decimal::ZERO || perp.initial_margin <= perp.maintenance_margin {
+            return Err("BADBIT_PERP_INITIAL_MARGIN は BADBIT_PERP_MAINTENANCE_MARGIN（0より大きい）より大きくしてください".to_string());
+        }
+
+        // 最も重いリクエストがバケットに収まらないと、二度と通らなくなる
+        let max_weight = limits.order_weight.max(limits.cancel_weight).max(limits.read_weight);
+        if limits.user.burst < max_weight || limits.ip.burst < max_weight {
+            return Err(format!("レート制限のバケット容量は {} 以上にしてください", max_weight));
+        }
+
+        Ok(config)
+    }
+}
@@ -13,12 +13,26 @@
 // - セットアップ不要（ファイル1つで完結）
 // - Rustとの相性が良い（sqlxが優秀）
 // - 開発・学習に最適（本番ならPostgreSQLに移行も容易）
+//
+// 【数値の保存形式】
+// - 金額・価格は10^8倍した整数（INTEGER）で保存する（storage::to_scaled）
+// - 時刻はすべてミリ秒単位のUNIXタイムスタンプ（INTEGER）
 // =============================================================================
 
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
 use rust_decimal::Decimal;
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+use sqlx::{sqlite::SqlitePoolOptions, Acquire, Pool, Sqlite, SqliteConnection};
 use uuid::Uuid;
 
+use crate::models::{
+    ApiKey, ApiScope, Asset, Candle, CandleInterval, FixSequence, MarginAccountRecord, MarginAssetRecord, OrderRecord, OrderStatus,
+    OrderType, PerpAccountRecord, Side, Trade,
+};
+use crate::storage::{from_scaled, to_scaled, SharedStorage, Storage, StorageError, StorageResult, TradeQuery};
+
 /// データベース接続プール
 /// 
 /// 複数の接続を効率的に管理し、並行リクエストを捌けるようにする
@@ -26,11 +40,11 @@ pub type DbPool = Pool<Sqlite>;
 
 /// ユーザー情報
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
-    pub created_at: i64,
+    pub password_hash: Option<String>, // PHC形式のArgon2ハッシュ（パスワード未設定のユーザーはNone）
+    pub created_at: i64, // ミリ秒
 }
 
 /// 残高情報
@@ -42,276 +56,1777 @@ pub struct Balance {
     pub locked: Decimal,
 }
 
+/// デフォルトユーザーのユーザー名
+pub const DEFAULT_USERNAME: &str = "trader";
+
+/// デフォルトユーザーの初期残高（資産名, 数量）
+pub const DEFAULT_BALANCES: &[(&str, i64)] = &[("USDC", 10000), ("BAD", 0)];
+
+/// 最初から登録しておく資産（資産名, 表示名, 小数点以下の桁数）
+///
+/// BADの数量は整数なので桁数0
+pub const DEFAULT_ASSETS: &[(&str, &str, u32)] = &[("USDC", "USD Coin", 6), ("BAD", "Badbit", 0)];
+
+/// 現在のスキーマバージョン（`PRAGMA user_version` に保存）
+/// 
+/// - 0: 初期版。金額・価格をTEXTで保存していた
+/// - 1: 金額・価格を10^8倍の整数（INTEGER）で保存。時刻はすべてミリ秒
+/// - 2: trades にエンジン採番の約定ID・taker_side・Maker/Taker両方のユーザーIDを保存
+/// - 3: users.password_hash と sessions テーブルを追加（複数ユーザー・ログイン対応）
+/// - 4: api_keys テーブルを追加（ボット用のAPIキー）
+/// - 5: orders.filled_quote を追加（平均約定価格の計算用）
+/// - 6: fix_sessions / fix_messages テーブルを追加（FIXゲートウェイのシーケンス番号と再送用）
+/// - 7: assets テーブルを追加（資産の表示名・桁数）
+/// - 8: perp_trades テーブルを追加（無期限先物 BAD-PERP の約定）
+/// - 9: margin_accounts / margin_assets テーブルを追加（証拠金口座）
+/// - 10: perp_accounts テーブルを追加（先物のウォレット・ポジション）
+pub const SCHEMA_VERSION: i64 = 10;
+
 /// データベースを初期化する
 /// 
-/// 1. SQLiteファイルに接続（なければ作成）
-/// 2. テーブルを作成（なければ作成）
-/// 3. デフォルトユーザーを作成（いなければ作成）
+/// 1. SQLiteファイルに接続してスキーマを最新にする（`connect`）
+/// 2. デフォルトユーザーを作成（いなければ作成）
 /// 
 /// # 引数
-/// - db_path: SQLiteファイルのパス（例: "data.db"）
+/// - db_path: SQLiteファイルのパス（例: "data.db"）。":memory:" ならインメモリDB
 /// 
 /// # 戻り値
 /// - 接続プールと、デフォルトユーザーのID
-pub async fn init_database(db_path: &str) -> Result<(DbPool, Uuid), sqlx::Error> {
+pub async fn init_database(db_path: &str) -> StorageResult<(DbPool, Uuid)> {
+    let pool = connect(db_path).await?;
+
+    // デフォルトユーザーを取得または作成
+    let default_user_id = ensure_default_user(&pool).await?;
+
+    println!("✅ データベース初期化完了: {}", db_path);
+    println!("   デフォルトユーザーID: {}", default_user_id);
+
+    Ok((pool, default_user_id))
+}
+
+/// SQLiteに接続し、スキーマを最新バージョンにする
+/// 
+/// ":memory:" の場合は接続ごとに別々のDBになってしまうため、
+/// 接続を1本に固定し、アイドル切断もしないようにする。
+pub async fn connect(db_path: &str) -> StorageResult<DbPool> {
     // 接続プールを作成
-    // create_if_missing: ファイルがなければ作成
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&format!("sqlite:{}?mode=rwc", db_path))
-        .await?;
+    // mode=rwc: ファイルがなければ作成
+    let pool = if db_path == ":memory:" {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?
+    } else {
+        SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect(&format!("sqlite:{}?mode=rwc", db_path))
+            .await?
+    };
+
+    migrate(&pool).await?;
+
+    Ok(pool)
+}
+
+// =============================================================================
+// スキーマ & マイグレーション
+// =============================================================================
 
-    // テーブル作成（IF NOT EXISTSで冪等性を保証）
+/// 最新スキーマのテーブルを作成する（IF NOT EXISTSで冪等性を保証）
+/// 
+/// 金額・価格のカラムはすべて「10^8倍した整数」（storage::to_scaled を参照）
+async fn create_schema(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS users (
             id TEXT PRIMARY KEY,
             username TEXT UNIQUE NOT NULL,
-            created_at INTEGER NOT NULL
+            created_at INTEGER NOT NULL,
+            password_hash TEXT
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // トークンそのものではなくSHA-256ハッシュを保存する（DBが漏れてもなりすませない）
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            token_hash TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // 署名の検証に secret そのものが必要なので、sessions と違ってハッシュ化しない
+    // scopes・ip_allowlist はカンマ区切り（ip_allowlist が空文字なら全IP許可）
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_keys (
+            key TEXT PRIMARY KEY,
+            secret TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            label TEXT NOT NULL,
+            scopes TEXT NOT NULL,
+            ip_allowlist TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            revoked_at INTEGER
         )
         "#,
     )
-    .execute(&pool)
+    .execute(&mut *conn)
     .await?;
 
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_api_keys_user ON api_keys (user_id, created_at)")
+        .execute(&mut *conn)
+        .await?;
+
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS balances (
             user_id TEXT NOT NULL,
             asset TEXT NOT NULL,
-            available TEXT NOT NULL,
-            locked TEXT NOT NULL,
+            available INTEGER NOT NULL,
+            locked INTEGER NOT NULL,
+            PRIMARY KEY (user_id, asset)
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // 資産の登録（残高の asset はここになくてもよい）
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS assets (
+            asset TEXT PRIMARY KEY,
+            display_name TEXT NOT NULL,
+            precision INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // 既に登録があれば（表示名を変えていても）そのまま残す
+    for (asset, display_name, precision) in DEFAULT_ASSETS {
+        sqlx::query("INSERT OR IGNORE INTO assets (asset, display_name, precision) VALUES (?, ?, ?)")
+            .bind(*asset)
+            .bind(*display_name)
+            .bind(*precision)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    // 証拠金口座（口座全体の状態と、資産ごとの残高・借入・貸し倒れ）
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS margin_accounts (
+            user_id TEXT PRIMARY KEY,
+            accrued_at INTEGER NOT NULL,
+            liquidated INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS margin_assets (
+            user_id TEXT NOT NULL,
+            asset TEXT NOT NULL,
+            available INTEGER NOT NULL,
+            locked INTEGER NOT NULL,
+            principal INTEGER NOT NULL,
+            interest INTEGER NOT NULL,
+            bad_debt INTEGER NOT NULL,
             PRIMARY KEY (user_id, asset)
         )
         "#,
     )
-    .execute(&pool)
+    .execute(&mut *conn)
     .await?;
 
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS trades (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            id INTEGER PRIMARY KEY,
             maker_order_id INTEGER NOT NULL,
             taker_order_id INTEGER NOT NULL,
-            price TEXT NOT NULL,
+            price INTEGER NOT NULL,
             quantity INTEGER NOT NULL,
             timestamp INTEGER NOT NULL,
-            user_id TEXT
+            taker_user_id TEXT,
+            taker_side TEXT NOT NULL,
+            maker_user_id TEXT
         )
         "#,
     )
-    .execute(&pool)
+    .execute(&mut *conn)
     .await?;
 
-    // デフォルトユーザーを取得または作成
-    let default_user_id = ensure_default_user(&pool).await?;
-
-    println!("✅ データベース初期化完了: {}", db_path);
-    println!("   デフォルトユーザーID: {}", default_user_id);
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_trades_timestamp ON trades (timestamp)")
+        .execute(&mut *conn)
+        .await?;
 
-    Ok((pool, default_user_id))
-}
+    // 自分の約定（Maker/Takerどちらでも）を約定ID順に引くためのインデックス
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_trades_taker_user ON trades (taker_user_id, id)")
+        .execute(&mut *conn)
+        .await?;
 
-/// デフォルトユーザーを確保する
-/// 
-/// - 既に存在すれば、そのIDを返す
-/// - 存在しなければ、新規作成して初期残高を設定
-async fn ensure_default_user(pool: &DbPool) -> Result<Uuid, sqlx::Error> {
-    const DEFAULT_USERNAME: &str = "trader";
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_trades_maker_user ON trades (maker_user_id, id)")
+        .execute(&mut *conn)
+        .await?;
 
-    // 既存ユーザーを検索
-    let existing: Option<(String,)> = sqlx::query_as(
-        "SELECT id FROM users WHERE username = ?"
+    // 無期限先物の約定（現物とは板も約定IDの採番も別なので、テーブルを分ける）
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS perp_trades (
+            id INTEGER PRIMARY KEY,
+            maker_order_id INTEGER NOT NULL,
+            taker_order_id INTEGER NOT NULL,
+            price INTEGER NOT NULL,
+            quantity INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL,
+            taker_user_id TEXT,
+            taker_side TEXT NOT NULL,
+            maker_user_id TEXT
+        )
+        "#,
     )
-    .bind(DEFAULT_USERNAME)
-    .fetch_optional(pool)
+    .execute(&mut *conn)
     .await?;
 
-    if let Some((id_str,)) = existing {
-        // 既存ユーザーが見つかった
-        let user_id = Uuid::parse_str(&id_str).expect("Invalid UUID in database");
-        println!("   既存ユーザー発見: {}", DEFAULT_USERNAME);
-        return Ok(user_id);
-    }
+    // 先物のウォレット・ポジション（ユーザーごとに1行）
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS perp_accounts (
+            user_id TEXT PRIMARY KEY,
+            wallet_balance INTEGER NOT NULL,
+            size INTEGER NOT NULL,
+            entry_price INTEGER NOT NULL,
+            realized_pnl INTEGER NOT NULL,
+            funding INTEGER NOT NULL,
+            bad_debt INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
 
-    // 新規ユーザーを作成
-    let user_id = Uuid::new_v4();
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS orders (
+            id INTEGER PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            side TEXT NOT NULL,
+            order_type TEXT NOT NULL,
+            price INTEGER NOT NULL,
+            quantity INTEGER NOT NULL,
+            filled_quantity INTEGER NOT NULL,
+            filled_quote INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
 
-    sqlx::query("INSERT INTO users (id, username, created_at) VALUES (?, ?, ?)")
-        .bind(user_id.to_string())
-        .bind(DEFAULT_USERNAME)
-        .bind(now)
-        .execute(pool)
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_orders_user ON orders (user_id, created_at)")
+        .execute(&mut *conn)
         .await?;
 
-    // 初期残高を設定: 10,000 USDC
     sqlx::query(
-        "INSERT INTO balances (user_id, asset, available, locked) VALUES (?, ?, ?, ?)"
+        r#"
+        CREATE TABLE IF NOT EXISTS candles (
+            interval TEXT NOT NULL,
+            open_time INTEGER NOT NULL,
+            open INTEGER NOT NULL,
+            high INTEGER NOT NULL,
+            low INTEGER NOT NULL,
+            close INTEGER NOT NULL,
+            volume INTEGER NOT NULL,
+            quote_volume INTEGER NOT NULL,
+            trade_count INTEGER NOT NULL,
+            PRIMARY KEY (interval, open_time)
+        )
+        "#,
     )
-    .bind(user_id.to_string())
-    .bind("USDC")
-    .bind("10000")  // Decimalは文字列で保存
-    .bind("0")
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
-    // BADトークンも初期化（0から開始）
+    // FIXセッション（"FIX.4.4:<相手>-><自分>"）ごとのシーケンス番号
     sqlx::query(
-        "INSERT INTO balances (user_id, asset, available, locked) VALUES (?, ?, ?, ?)"
+        r#"
+        CREATE TABLE IF NOT EXISTS fix_sessions (
+            session_id TEXT PRIMARY KEY,
+            next_outgoing INTEGER NOT NULL,
+            next_incoming INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )
+        "#,
     )
-    .bind(user_id.to_string())
-    .bind("BAD")
-    .bind("0")
-    .bind("0")
-    .execute(pool)
+    .execute(&mut *conn)
     .await?;
 
-    println!("   新規ユーザー作成: {} (初期残高: 10,000 USDC)", DEFAULT_USERNAME);
+    // ResendRequest で送り直すため、送信したアプリケーションメッセージをそのまま残す
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS fix_messages (
+            session_id TEXT NOT NULL,
+            seq INTEGER NOT NULL,
+            message BLOB NOT NULL,
+            PRIMARY KEY (session_id, seq)
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
 
-    Ok(user_id)
+    Ok(())
 }
 
-/// ユーザーの残高を取得する
-pub async fn get_balances(pool: &DbPool, user_id: Uuid) -> Result<Vec<Balance>, sqlx::Error> {
-    let rows: Vec<(String, String, String, String)> = sqlx::query_as(
-        "SELECT user_id, asset, available, locked FROM balances WHERE user_id = ?"
+/// スキーマを最新バージョンにする
+/// 
+/// - 新規DB: 最新スキーマをそのまま作成
+/// - 旧バージョンのDB: トランザクション内でデータを変換して移行
+/// 
+/// 変換できない値（壊れた文字列など）があれば移行全体をロールバックし、
+/// どのテーブル・カラムの値かを含むエラーを返す。
+pub async fn migrate(pool: &DbPool) -> StorageResult<()> {
+    let mut conn = pool.acquire().await?;
+    let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    let mut tx = conn.begin().await?;
+
+    let (existing_tables,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'balances'"
     )
-    .bind(user_id.to_string())
-    .fetch_all(pool)
+    .fetch_one(&mut *tx)
     .await?;
 
-    let balances = rows
-        .into_iter()
-        .map(|(uid, asset, available, locked)| Balance {
-            user_id: Uuid::parse_str(&uid).unwrap(),
-            asset,
-            available: available.parse().unwrap_or_default(),
-            locked: locked.parse().unwrap_or_default(),
-        })
-        .collect();
+    if existing_tables == 0 {
+        create_schema(&mut tx).await?;
+    } else {
+        match version {
+            // バージョン0（TEXT保存）は丸ごと作り直すので、直接最新にする
+            0 => migrate_from_v0(&mut tx).await?,
+            _ => {
+                if version < 2 {
+                    migrate_v1_to_v2(&mut tx).await?;
+                }
+                if version < 3 {
+                    migrate_v2_to_v3(&mut tx).await?;
+                }
+                if version < 5 {
+                    migrate_v4_to_v5(&mut tx).await?;
+                }
+                // v4・v6〜v10 はテーブルの追加だけなので作成し直すだけでよい
+                create_schema(&mut tx).await?;
+            }
+        }
+        println!("✅ DBスキーマを v{} から v{} に移行しました", version, SCHEMA_VERSION);
+    }
+
+    // PRAGMAはバインド変数を使えないので値を埋め込む（定数なので安全）
+    sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
 
-    Ok(balances)
+    Ok(())
 }
 
-/// 残高を更新する
-pub async fn update_balance(
-    pool: &DbPool,
-    user_id: Uuid,
-    asset: &str,
-    available: Decimal,
-    locked: Decimal,
-) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        "UPDATE balances SET available = ?, locked = ? WHERE user_id = ? AND asset = ?"
+// v0のテーブルの行（金額・価格がTEXT）
+type LegacyTradeRow = (i64, i64, i64, String, i64, i64, Option<String>);
+type LegacyOrderRow = (i64, String, String, String, String, i64, i64, String, i64, i64);
+type LegacyCandleRow = (String, i64, String, String, String, String, i64, String, i64);
+
+/// v0 -> 最新: TEXTの金額をスケール済み整数に、users.created_at を秒からミリ秒に変換
+async fn migrate_from_v0(conn: &mut SqliteConnection) -> StorageResult<()> {
+    // orders / candles は後から追加されたテーブルなので、存在しない場合もある
+    let legacy: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT name FROM sqlite_master
+        WHERE type = 'table' AND name IN ('users', 'balances', 'trades', 'orders', 'candles')
+        "#
     )
-    .bind(available.to_string())
-    .bind(locked.to_string())
-    .bind(user_id.to_string())
-    .bind(asset)
-    .execute(pool)
+    .fetch_all(&mut *conn)
     .await?;
+    let legacy: Vec<String> = legacy.into_iter().map(|(name,)| name).collect();
+
+    for table in &legacy {
+        sqlx::query(&format!("ALTER TABLE {table} RENAME TO {table}_v0"))
+            .execute(&mut *conn)
+            .await?;
+    }
+    // 旧テーブルのインデックスは名前が衝突するので先に消す
+    sqlx::query("DROP INDEX IF EXISTS idx_orders_user")
+        .execute(&mut *conn)
+        .await?;
+
+    create_schema(conn).await?;
+
+    if legacy.iter().any(|t| t == "users") {
+        sqlx::query("INSERT INTO users (id, username, created_at) SELECT id, username, created_at * 1000 FROM users_v0")
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    if legacy.iter().any(|t| t == "balances") {
+        let rows: Vec<(String, String, String, String)> =
+            sqlx::query_as("SELECT user_id, asset, available, locked FROM balances_v0")
+                .fetch_all(&mut *conn)
+                .await?;
+        for (user_id, asset, available, locked) in rows {
+            sqlx::query("INSERT INTO balances (user_id, asset, available, locked) VALUES (?, ?, ?, ?)")
+                .bind(&user_id)
+                .bind(&asset)
+                .bind(to_scaled(parse_decimal("balances", "available", &available)?)?)
+                .bind(to_scaled(parse_decimal("balances", "locked", &locked)?)?)
+                .execute(&mut *conn)
+                .await?;
+        }
+    }
+
+    if legacy.iter().any(|t| t == "trades") {
+        let rows: Vec<LegacyTradeRow> = sqlx::query_as(
+            "SELECT id, maker_order_id, taker_order_id, price, quantity, timestamp, user_id FROM trades_v0"
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        for (id, maker_order_id, taker_order_id, price, quantity, timestamp, user_id) in rows {
+            sqlx::query(
+                r#"
+                INSERT INTO trades (id, maker_order_id, taker_order_id, price, quantity, timestamp, taker_user_id, taker_side)
+                VALUES (?, ?, ?, ?, ?, ?, ?, 'Buy')
+                "#
+            )
+            .bind(id)
+            .bind(maker_order_id)
+            .bind(taker_order_id)
+            .bind(to_scaled(parse_decimal("trades", "price", &price)?)?)
+            .bind(quantity)
+            .bind(timestamp)
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    if legacy.iter().any(|t| t == "orders") {
+        let rows: Vec<LegacyOrderRow> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, side, order_type, price, quantity, filled_quantity, status, created_at, updated_at
+            FROM orders_v0
+            "#
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        for (id, user_id, side, order_type, price, quantity, filled_quantity, status, created_at, updated_at) in rows {
+            sqlx::query(
+                r#"
+                INSERT INTO orders (id, user_id, side, order_type, price, quantity, filled_quantity, status, created_at, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(side)
+            .bind(order_type)
+            .bind(to_scaled(parse_decimal("orders", "price", &price)?)?)
+            .bind(quantity)
+            .bind(filled_quantity)
+            .bind(status)
+            .bind(created_at)
+            .bind(updated_at)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    if legacy.iter().any(|t| t == "candles") {
+        let rows: Vec<LegacyCandleRow> = sqlx::query_as(
+            "SELECT interval, open_time, open, high, low, close, volume, quote_volume, trade_count FROM candles_v0"
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        for (interval, open_time, open, high, low, close, volume, quote_volume, trade_count) in rows {
+            sqlx::query(
+                r#"
+                INSERT INTO candles (interval, open_time, open, high, low, close, volume, quote_volume, trade_count)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#
+            )
+            .bind(interval)
+            .bind(open_time)
+            .bind(to_scaled(parse_decimal("candles", "open", &open)?)?)
+            .bind(to_scaled(parse_decimal("candles", "high", &high)?)?)
+            .bind(to_scaled(parse_decimal("candles", "low", &low)?)?)
+            .bind(to_scaled(parse_decimal("candles", "close", &close)?)?)
+            .bind(volume)
+            .bind(to_scaled(parse_decimal("candles", "quote_volume", &quote_volume)?)?)
+            .bind(trade_count)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    for table in &legacy {
+        sqlx::query(&format!("DROP TABLE {table}_v0"))
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    backfill_taker_side(conn).await?;
 
     Ok(())
 }
 
-/// 約定をDBに保存する
-pub async fn save_trade(
-    pool: &DbPool,
-    maker_order_id: u64,
-    taker_order_id: u64,
-    price: Decimal,
-    quantity: u64,
-    timestamp: u128,
-    user_id: Option<Uuid>,
-) -> Result<(), sqlx::Error> {
-    sqlx::query(
+/// v1 -> v2: trades にテイカーの売買方向とMakerのユーザーIDを追加する
+/// 
+/// v1までは自分がテイカーの約定だけを user_id 付きで保存していたので、
+/// user_id はそのまま taker_user_id になる。Makerの所有者は記録がないので NULL。
+async fn migrate_v1_to_v2(conn: &mut SqliteConnection) -> StorageResult<()> {
+    sqlx::query("DROP INDEX IF EXISTS idx_trades_user")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("ALTER TABLE trades RENAME COLUMN user_id TO taker_user_id")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("ALTER TABLE trades ADD COLUMN taker_side TEXT NOT NULL DEFAULT 'Buy'")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("ALTER TABLE trades ADD COLUMN maker_user_id TEXT")
+        .execute(&mut *conn)
+        .await?;
+
+    // 新しいインデックスを作る（既存のテーブルはIF NOT EXISTSでそのまま）
+    create_schema(conn).await?;
+    backfill_taker_side(conn).await?;
+
+    Ok(())
+}
+
+/// v2 -> v3: ログイン用のパスワードハッシュとセッションを追加する
+/// 
+/// 既存ユーザーはパスワード未設定（NULL）になり、ログインはできない
+async fn migrate_v2_to_v3(conn: &mut SqliteConnection) -> StorageResult<()> {
+    sqlx::query("ALTER TABLE users ADD COLUMN password_hash TEXT")
+        .execute(&mut *conn)
+        .await?;
+
+    // sessions テーブルを作る
+    create_schema(conn).await?;
+
+    Ok(())
+}
+
+/// v4 -> v5: 注文の約定代金の合計を追加する
+/// 
+/// 既存の注文は約定価格が分からないので 0（平均約定価格なし）になる。
+/// orders テーブルがまだない（または途中の移行で最新の形で作られた）場合は何もしない
+async fn migrate_v4_to_v5(conn: &mut SqliteConnection) -> StorageResult<()> {
+    let (columns, has_filled_quote): (i64, i64) = sqlx::query_as(
         r#"
-        INSERT INTO trades (maker_order_id, taker_order_id, price, quantity, timestamp, user_id)
-        VALUES (?, ?, ?, ?, ?, ?)
+        SELECT
+            (SELECT COUNT(*) FROM pragma_table_info('orders')),
+            (SELECT COUNT(*) FROM pragma_table_info('orders') WHERE name = 'filled_quote')
         "#
     )
-    .bind(maker_order_id as i64)
-    .bind(taker_order_id as i64)
-    .bind(price.to_string())
-    .bind(quantity as i64)
-    .bind(timestamp as i64)
-    .bind(user_id.map(|u| u.to_string()))
-    .execute(pool)
+    .fetch_one(&mut *conn)
     .await?;
 
+    if columns > 0 && has_filled_quote == 0 {
+        sqlx::query("ALTER TABLE orders ADD COLUMN filled_quote INTEGER NOT NULL DEFAULT 0")
+            .execute(&mut *conn)
+            .await?;
+    }
+
     Ok(())
 }
 
-/// ユーザーごとの約定履歴を取得する
-pub async fn get_user_trades(pool: &DbPool, user_id: Uuid) -> Result<Vec<crate::models::Trade>, sqlx::Error> {
-    let rows: Vec<(i64, i64, String, i64, i64)> = sqlx::query_as(
+/// 移行した約定の taker_side を、テイカー注文の記録から埋める
+/// 
+/// 注文記録が残っていない約定は判別できないので Buy のままになる
+async fn backfill_taker_side(conn: &mut SqliteConnection) -> StorageResult<()> {
+    sqlx::query(
         r#"
-        SELECT maker_order_id, taker_order_id, price, quantity, timestamp 
-        FROM trades 
-        WHERE user_id = ? 
-        ORDER BY timestamp DESC 
-        LIMIT 50
+        UPDATE trades
+        SET taker_side = (SELECT side FROM orders WHERE orders.id = trades.taker_order_id)
+        WHERE taker_order_id IN (SELECT id FROM orders)
         "#
     )
-    .bind(user_id.to_string())
-    .fetch_all(pool)
+    .execute(&mut *conn)
     .await?;
 
-    let trades = rows
-        .into_iter()
-        .map(|(maker_id, taker_id, price, quantity, timestamp)| crate::models::Trade {
-            maker_id: maker_id as u64,
-            taker_id: taker_id as u64,
-            price: price.parse().unwrap_or_default(),
-            quantity: quantity as u64,
-            timestamp: timestamp as u128,
-        })
-        .collect();
+    Ok(())
+}
+
+// =============================================================================
+// ユーザー
+// =============================================================================
 
-    Ok(trades)
+fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
 }
 
-/// DBタスクへの非同期メッセージ
-#[derive(Debug)]
-pub enum DbMessage {
-    /// 残高が変化したことを通知
-    UpdateBalance {
-        user_id: Uuid,
-        asset: String,
-        available: Decimal,
-        locked: Decimal,
-    },
-    /// 約定履歴を保存
-    SaveTrade {
-        maker_order_id: u64,
-        taker_order_id: u64,
-        price: Decimal,
-        quantity: u64,
-        timestamp: u128,
-        user_id: Option<Uuid>, // 約定したユーザー（Maker/Taker両方送る）
+/// デフォルトユーザーを確保する
+/// 
+/// - 既に存在すれば、そのIDを返す
+/// - 存在しなければ、新規作成して初期残高を設定
+pub async fn ensure_default_user(pool: &DbPool) -> StorageResult<Uuid> {
+    // 既存ユーザーを検索
+    if let Some(user) = get_user_by_username(pool, DEFAULT_USERNAME).await? {
+        println!("   既存ユーザー発見: {}", DEFAULT_USERNAME);
+        return Ok(user.id);
     }
+
+    // 新規ユーザーを作成（パスワードなし = ログインはできない）
+    let user_id = create_user(pool, DEFAULT_USERNAME, None).await?;
+    println!("   新規ユーザー作成: {} (初期残高: 10,000 USDC)", DEFAULT_USERNAME);
+
+    Ok(user_id)
 }
 
-pub async fn run_db_writer(mut rx: tokio::sync::mpsc::Receiver<DbMessage>, pool: DbPool) {
-    // メッセージが来るたびにDBに書き込む
-    // エラーが出てもログに出すだけでクラッシュさせない
-    while let Some(msg) = rx.recv().await {
-        match msg {
+/// ユーザーを作成し、初期残高（DEFAULT_BALANCES）を付与する
+/// 
+/// ユーザー名が使われていれば StorageError::Conflict を返す
+pub async fn create_user(pool: &DbPool, username: &str, password_hash: Option<&str>) -> StorageResult<Uuid> {
+    let user_id = Uuid::new_v4();
+    // ユーザーと初期残高はまとめて作る（片方だけ残らないように）
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("INSERT INTO users (id, username, password_hash, created_at) VALUES (?, ?, ?, ?)")
+        .bind(user_id.to_string())
+        .bind(username)
+        .bind(password_hash)
+        .bind(now_millis())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+                StorageError::Conflict(format!("ユーザー名 {:?} は既に使われています", username))
+            }
+            e => e.into(),
+        })?;
+
+    for (asset, amount) in DEFAULT_BALANCES {
+        sqlx::query(
+            "INSERT INTO balances (user_id, asset, available, locked) VALUES (?, ?, ?, ?)"
+        )
+        .bind(user_id.to_string())
+        .bind(*asset)
+        .bind(to_scaled(Decimal::from(*amount))?)
+        .bind(0i64)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(user_id)
+}
+
+/// ユーザー名でユーザーを取得する
+pub async fn get_user_by_username(pool: &DbPool, username: &str) -> StorageResult<Option<User>> {
+    let row: Option<(String, String, Option<String>, i64)> = sqlx::query_as(
+        "SELECT id, username, password_hash, created_at FROM users WHERE username = ?"
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(|(id, username, password_hash, created_at)| {
+        Ok(User {
+            id: parse_uuid("users", "id", &id)?,
+            username,
+            password_hash,
+            created_at,
+        })
+    })
+    .transpose()
+}
+
+// =============================================================================
+// セッション
+// =============================================================================
+
+/// セッションを保存する
+pub async fn create_session(pool: &DbPool, token_hash: &str, user_id: Uuid, expires_at: u128) -> StorageResult<()> {
+    sqlx::query("INSERT INTO sessions (token_hash, user_id, created_at, expires_at) VALUES (?, ?, ?, ?)")
+        .bind(token_hash)
+        .bind(user_id.to_string())
+        .bind(now_millis())
+        .bind(expires_at as i64)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 有効期限内のセッションからユーザーIDを引く
+pub async fn get_session_user(pool: &DbPool, token_hash: &str, now: u128) -> StorageResult<Option<Uuid>> {
+    let row: Option<(String,)> = sqlx::query_as(
+        "SELECT user_id FROM sessions WHERE token_hash = ? AND expires_at > ?"
+    )
+    .bind(token_hash)
+    .bind(now as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(|(user_id,)| parse_uuid("sessions", "user_id", &user_id))
+        .transpose()
+}
+
+/// セッションを削除する（ログアウト）
+pub async fn delete_session(pool: &DbPool, token_hash: &str) -> StorageResult<()> {
+    sqlx::query("DELETE FROM sessions WHERE token_hash = ?")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// =============================================================================
+// APIキー
+// =============================================================================
+
+/// api_keys テーブルの1行
+type ApiKeyRow = (String, String, String, String, String, String, i64, Option<i64>);
+
+fn api_key_from_row(row: ApiKeyRow) -> StorageResult<ApiKey> {
+    let (key, secret, user_id, label, scopes, ip_allowlist, created_at, revoked_at) = row;
+    let scopes = scopes
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| ApiScope::parse(s).ok_or_else(|| corrupt("api_keys", "scopes", s)))
+        .collect::<StorageResult<Vec<_>>>()?;
+    let ip_allowlist = ip_allowlist
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| corrupt("api_keys", "ip_allowlist", s)))
+        .collect::<StorageResult<Vec<_>>>()?;
+    Ok(ApiKey {
+        user_id: parse_uuid("api_keys", "user_id", &user_id)?,
+        key,
+        secret,
+        label,
+        scopes,
+        ip_allowlist,
+        created_at: created_at as u128,
+        revoked_at: revoked_at.map(|t| t as u128),
+    })
+}
+
+/// カンマ区切りで保存する
+fn join_csv<T: ToString>(items: &[T]) -> String {
+    items.iter().map(T::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// APIキーを保存する
+pub async fn create_api_key(pool: &DbPool, api_key: &ApiKey) -> StorageResult<()> {
+    let scopes: Vec<&str> = api_key.scopes.iter().map(ApiScope::as_str).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO api_keys (key, secret, user_id, label, scopes, ip_allowlist, created_at, revoked_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        "#
+    )
+    .bind(&api_key.key)
+    .bind(&api_key.secret)
+    .bind(api_key.user_id.to_string())
+    .bind(&api_key.label)
+    .bind(join_csv(&scopes))
+    .bind(join_csv(&api_key.ip_allowlist))
+    .bind(api_key.created_at as i64)
+    .bind(api_key.revoked_at.map(|t| t as i64))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// APIキーを取得する（失効済みも含む）
+pub async fn get_api_key(pool: &DbPool, key: &str) -> StorageResult<Option<ApiKey>> {
+    let row: Option<ApiKeyRow> = sqlx::query_as(
+        "SELECT key, secret, user_id, label, scopes, ip_allowlist, created_at, revoked_at FROM api_keys WHERE key = ?"
+    )
+    .bind(key)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(api_key_from_row).transpose()
+}
+
+/// ユーザーのAPIキーを発行順に取得する（失効済みも含む）
+pub async fn list_api_keys(pool: &DbPool, user_id: Uuid) -> StorageResult<Vec<ApiKey>> {
+    let rows: Vec<ApiKeyRow> = sqlx::query_as(
+        r#"
+        SELECT key, secret, user_id, label, scopes, ip_allowlist, created_at, revoked_at
+        FROM api_keys WHERE user_id = ? ORDER BY created_at, key
+        "#
+    )
+    .bind(user_id.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter().map(api_key_from_row).collect()
+}
+
+/// APIキーを失効させる
+///
+/// 本人の有効なキーでなければ何もせず false を返す
+pub async fn revoke_api_key(pool: &DbPool, user_id: Uuid, key: &str, now: u128) -> StorageResult<bool> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = ? WHERE key = ? AND user_id = ? AND revoked_at IS NULL"
+    )
+    .bind(now as i64)
+    .bind(key)
+    .bind(user_id.to_string())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// =============================================================================
+// 残高
+// =============================================================================
+
+/// ユーザーの残高を取得する
+/// 
+/// 壊れた行があれば0として扱わず、エラーを返す
+pub async fn get_balances(pool: &DbPool, user_id: Uuid) -> StorageResult<Vec<Balance>> {
+    let rows: Vec<BalanceRow> = sqlx::query_as(
+        "SELECT user_id, asset, available, locked FROM balances WHERE user_id = ? ORDER BY asset"
+    )
+    .bind(user_id.to_string())
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter().map(balance_from_row).collect()
+}
+
+/// 全ユーザーの残高を取得する（起動時にエンジンへ読み込む用）
+pub async fn get_all_balances(pool: &DbPool) -> StorageResult<Vec<Balance>> {
+    let rows: Vec<BalanceRow> = sqlx::query_as(
+        "SELECT user_id, asset, available, locked FROM balances ORDER BY user_id, asset"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter().map(balance_from_row).collect()
+}
+
+type BalanceRow = (String, String, i64, i64);
+
+fn balance_from_row((user_id, asset, available, locked): BalanceRow) -> StorageResult<Balance> {
+    Ok(Balance {
+        user_id: parse_uuid("balances", "user_id", &user_id)?,
+        asset,
+        available: from_scaled(available),
+        locked: from_scaled(locked),
+    })
+}
+
+/// 残高を更新する（行がなければ作成）
+pub async fn update_balance(
+    pool: &DbPool,
+    user_id: Uuid,
+    asset: &str,
+    available: Decimal,
+    locked: Decimal,
+) -> StorageResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO balances (user_id, asset, available, locked) VALUES (?, ?, ?, ?)
+        ON CONFLICT (user_id, asset) DO UPDATE SET available = excluded.available, locked = excluded.locked
+        "#
+    )
+    .bind(user_id.to_string())
+    .bind(asset)
+    .bind(to_scaled(available)?)
+    .bind(to_scaled(locked)?)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// =============================================================================
+// 資産
+// =============================================================================
+
+/// 登録されている資産を資産名の順に取得する
+pub async fn get_assets(pool: &DbPool) -> StorageResult<Vec<Asset>> {
+    let rows: Vec<(String, String, u32)> =
+        sqlx::query_as("SELECT asset, display_name, precision FROM assets ORDER BY asset")
+            .fetch_all(pool)
+            .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(asset, display_name, precision)| Asset { asset, display_name, precision })
+        .collect())
+}
+
+/// 資産を登録する（登録済みなら表示名・桁数を上書き）
+pub async fn save_asset(pool: &DbPool, asset: &Asset) -> StorageResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO assets (asset, display_name, precision) VALUES (?, ?, ?)
+        ON CONFLICT (asset) DO UPDATE SET display_name = excluded.display_name, precision = excluded.precision
+        "#
+    )
+    .bind(&asset.asset)
+    .bind(&asset.display_name)
+    .bind(asset.precision)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// =============================================================================
+// 証拠金口座
+// =============================================================================
+
+/// 証拠金口座を保存する（その口座の資産の行は消してから入れ直す）
+pub async fn save_margin_account(pool: &DbPool, account: &MarginAccountRecord) -> StorageResult<()> {
+    let user_id = account.user_id.to_string();
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        INSERT INTO margin_accounts (user_id, accrued_at, liquidated) VALUES (?, ?, ?)
+        ON CONFLICT (user_id) DO UPDATE SET accrued_at = excluded.accrued_at, liquidated = excluded.liquidated
+        "#
+    )
+    .bind(&user_id)
+    .bind(account.accrued_at as i64)
+    .bind(account.liquidated)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM margin_assets WHERE user_id = ?")
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+    for a in &account.assets {
+        sqlx::query(
+            r#"
+            INSERT INTO margin_assets (user_id, asset, available, locked, principal, interest, bad_debt)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#
+        )
+        .bind(&user_id)
+        .bind(&a.asset)
+        .bind(to_scaled(a.available)?)
+        .bind(to_scaled(a.locked)?)
+        .bind(to_scaled(a.principal)?)
+        .bind(to_scaled(a.interest)?)
+        .bind(to_scaled(a.bad_debt)?)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+type MarginAssetRow = (String, String, i64, i64, i64, i64, i64);
+
+/// 全ユーザーの証拠金口座をユーザーID順に取得する
+pub async fn get_margin_accounts(pool: &DbPool) -> StorageResult<Vec<MarginAccountRecord>> {
+    let rows: Vec<(String, i64, bool)> =
+        sqlx::query_as("SELECT user_id, accrued_at, liquidated FROM margin_accounts ORDER BY user_id")
+            .fetch_all(pool)
+            .await?;
+    let mut accounts: Vec<MarginAccountRecord> = Vec::new();
+    for (user_id, accrued_at, liquidated) in rows {
+        accounts.push(MarginAccountRecord {
+            user_id: parse_uuid("margin_accounts", "user_id", &user_id)?,
+            accrued_at: accrued_at as u128,
+            liquidated,
+            assets: Vec::new(),
+        });
+    }
+
+    let rows: Vec<MarginAssetRow> = sqlx::query_as(
+        "SELECT user_id, asset, available, locked, principal, interest, bad_debt FROM margin_assets ORDER BY user_id, asset"
+    )
+    .fetch_all(pool)
+    .await?;
+    for (user_id, asset, available, locked, principal, interest, bad_debt) in rows {
+        let user_id = parse_uuid("margin_assets", "user_id", &user_id)?;
+        let account = accounts
+            .iter_mut()
+            .find(|a| a.user_id == user_id)
+            .ok_or_else(|| corrupt("margin_assets", "user_id", &user_id.to_string()))?;
+        account.assets.push(MarginAssetRecord {
+            asset,
+            available: from_scaled(available),
+            locked: from_scaled(locked),
+            principal: from_scaled(principal),
+            interest: from_scaled(interest),
+            bad_debt: from_scaled(bad_debt),
+        });
+    }
+
+    Ok(accounts)
+}
+
+// =============================================================================
+// 先物口座
+// =============================================================================
+
+/// 先物口座を保存する（行がなければ作成）
+pub async fn save_perp_account(pool: &DbPool, account: &PerpAccountRecord) -> StorageResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO perp_accounts (user_id, wallet_balance, size, entry_price, realized_pnl, funding, bad_debt)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (user_id) DO UPDATE SET
+            wallet_balance = excluded.wallet_balance,
+            size = excluded.size,
+            entry_price = excluded.entry_price,
+            realized_pnl = excluded.realized_pnl,
+            funding = excluded.funding,
+            bad_debt = excluded.bad_debt
+        "#
+    )
+    .bind(account.user_id.to_string())
+    .bind(to_scaled(account.wallet_balance)?)
+    .bind(account.size)
+    .bind(to_scaled(account.entry_price)?)
+    .bind(to_scaled(account.realized_pnl)?)
+    .bind(to_scaled(account.funding)?)
+    .bind(to_scaled(account.bad_debt)?)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+type PerpAccountRow = (String, i64, i64, i64, i64, i64, i64);
+
+/// 全ユーザーの先物口座をユーザーID順に取得する
+pub async fn get_perp_accounts(pool: &DbPool) -> StorageResult<Vec<PerpAccountRecord>> {
+    let rows: Vec<PerpAccountRow> = sqlx::query_as(
+        "SELECT user_id, wallet_balance, size, entry_price, realized_pnl, funding, bad_debt FROM perp_accounts ORDER BY user_id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter()
+        .map(|(user_id, wallet_balance, size, entry_price, realized_pnl, funding, bad_debt)| {
+            Ok(PerpAccountRecord {
+                user_id: parse_uuid("perp_accounts", "user_id", &user_id)?,
+                wallet_balance: from_scaled(wallet_balance),
+                size,
+                entry_price: from_scaled(entry_price),
+                realized_pnl: from_scaled(realized_pnl),
+                funding: from_scaled(funding),
+                bad_debt: from_scaled(bad_debt),
+            })
+        })
+        .collect()
+}
+
+// =============================================================================
+// 約定
+// =============================================================================
+
+/// 約定をDBに保存する
+pub async fn save_trade(pool: &DbPool, trade: &Trade) -> StorageResult<()> {
+    insert_trade(pool, "trades", trade).await
+}
+
+/// 無期限先物の約定をDBに保存する
+pub async fn save_perp_trade(pool: &DbPool, trade: &Trade) -> StorageResult<()> {
+    insert_trade(pool, "perp_trades", trade).await
+}
+
+/// 約定を trades / perp_trades に保存する（テーブル名は定数だけを渡す）
+async fn insert_trade(pool: &DbPool, table: &str, trade: &Trade) -> StorageResult<()> {
+    sqlx::query(&format!(
+        r#"
+        INSERT INTO {table} (id, maker_order_id, taker_order_id, price, quantity, taker_side, timestamp, maker_user_id, taker_user_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#
+    ))
+    .bind(trade.id as i64)
+    .bind(trade.maker_id as i64)
+    .bind(trade.taker_id as i64)
+    .bind(to_scaled(trade.price)?)
+    .bind(trade.quantity as i64)
+    .bind(side_to_str(trade.taker_side))
+    .bind(trade.timestamp as i64)
+    .bind(trade.maker_user_id.map(|u| u.to_string()))
+    .bind(trade.taker_user_id.map(|u| u.to_string()))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+type TradeRow = (i64, i64, i64, i64, i64, String, i64, Option<String>, Option<String>);
+
+fn trade_from_row(row: TradeRow) -> StorageResult<Trade> {
+    let (id, maker_id, taker_id, price, quantity, taker_side, timestamp, maker_user_id, taker_user_id) = row;
+    Ok(Trade {
+        id: id as u64,
+        maker_id: maker_id as u64,
+        taker_id: taker_id as u64,
+        price: from_scaled(price),
+        quantity: quantity as u64,
+        taker_side: parse_side("trades", "taker_side", &taker_side)?,
+        timestamp: timestamp as u128,
+        maker_user_id: maker_user_id
+            .map(|u| parse_uuid("trades", "maker_user_id", &u))
+            .transpose()?,
+        taker_user_id: taker_user_id
+            .map(|u| parse_uuid("trades", "taker_user_id", &u))
+            .transpose()?,
+    })
+}
+
+/// 条件に合う約定を約定IDの古い順に取得する
+/// 
+/// from_id も start_time も省略した場合は最新の limit 件を返す
+pub async fn get_trades(pool: &DbPool, query: &TradeQuery) -> StorageResult<Vec<Trade>> {
+    // カーソルがなければ新しい方から limit 件取って、後で古い順に並べ直す
+    let order = if query.is_forward() { "ASC" } else { "DESC" };
+    let user_filter = if query.user_id.is_some() {
+        "AND (maker_user_id = ? OR taker_user_id = ?)"
+    } else {
+        ""
+    };
+    let sql = format!(
+        r#"
+        SELECT id, maker_order_id, taker_order_id, price, quantity, taker_side, timestamp, maker_user_id, taker_user_id
+        FROM trades
+        WHERE id >= ? AND timestamp >= ? AND timestamp <= ? {}
+        ORDER BY id {}
+        LIMIT ?
+        "#,
+        user_filter, order
+    );
+
+    let mut q = sqlx::query_as::<_, TradeRow>(&sql)
+        .bind(query.from_id.map(|id| id as i64).unwrap_or(0))
+        .bind(query.start_time.map(|t| t as i64).unwrap_or(0))
+        .bind(query.end_time.map(|t| t as i64).unwrap_or(i64::MAX));
+    if let Some(user_id) = query.user_id {
+        q = q.bind(user_id.to_string()).bind(user_id.to_string());
+    }
+    let mut rows = q.bind(query.limit as i64).fetch_all(pool).await?;
+    if !query.is_forward() {
+        rows.reverse();
+    }
+
+    rows.into_iter().map(trade_from_row).collect()
+}
+
+/// 保存済みの最大の約定ID（約定がなければ0）
+pub async fn last_trade_id(pool: &DbPool) -> StorageResult<u64> {
+    let (id,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(id), 0) FROM trades")
+        .fetch_one(pool)
+        .await?;
+    Ok(id as u64)
+}
+
+/// 保存済みの最大の先物の約定ID（まだ約定がなければ0）
+pub async fn last_perp_trade_id(pool: &DbPool) -> StorageResult<u64> {
+    let (id,): (i64,) = sqlx::query_as("SELECT COALESCE(MAX(id), 0) FROM perp_trades")
+        .fetch_one(pool)
+        .await?;
+    Ok(id as u64)
+}
+
+/// 使用済みの最大の注文ID（注文記録と約定の両方から探す）
+/// 
+/// シミュレータの注文は注文記録を残さないが、約定していれば約定側にIDが残る。
+/// 先物の注文も注文記録を残さないので、先物の約定からも探す
+pub async fn last_order_id(pool: &DbPool) -> StorageResult<u64> {
+    let (id,): (i64,) = sqlx::query_as(
+        r#"
+        SELECT MAX(
+            (SELECT COALESCE(MAX(id), 0) FROM orders),
+            (SELECT COALESCE(MAX(maker_order_id), 0) FROM trades),
+            (SELECT COALESCE(MAX(taker_order_id), 0) FROM trades),
+            (SELECT COALESCE(MAX(maker_order_id), 0) FROM perp_trades),
+            (SELECT COALESCE(MAX(taker_order_id), 0) FROM perp_trades)
+        )
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(id as u64)
+}
+
+// =============================================================================
+// 注文 & ローソク足
+// =============================================================================
+
+fn side_to_str(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "Buy",
+        Side::Sell => "Sell",
+    }
+}
+
+fn order_type_to_str(order_type: OrderType) -> &'static str {
+    match order_type {
+        OrderType::Limit => "Limit",
+        OrderType::Market => "Market",
+    }
+}
+
+fn corrupt(table: &str, column: &str, value: &str) -> StorageError {
+    StorageError::Corrupt(format!("{}.{} = {:?}", table, column, value))
+}
+
+fn parse_decimal(table: &str, column: &str, value: &str) -> StorageResult<Decimal> {
+    value.parse().map_err(|_| corrupt(table, column, value))
+}
+
+fn parse_uuid(table: &str, column: &str, value: &str) -> StorageResult<Uuid> {
+    Uuid::parse_str(value).map_err(|_| corrupt(table, column, value))
+}
+
+fn parse_side(table: &str, column: &str, value: &str) -> StorageResult<Side> {
+    match value {
+        "Buy" => Ok(Side::Buy),
+        "Sell" => Ok(Side::Sell),
+        _ => Err(corrupt(table, column, value)),
+    }
+}
+
+/// 注文記録を保存する（同じIDがあれば上書き）
+pub async fn save_order(pool: &DbPool, order: &OrderRecord) -> StorageResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO orders (id, user_id, side, order_type, price, quantity, filled_quantity, filled_quote, status, created_at, updated_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (id) DO UPDATE SET
+            filled_quantity = excluded.filled_quantity,
+            filled_quote = excluded.filled_quote,
+            status = excluded.status,
+            updated_at = excluded.updated_at
+        "#
+    )
+    .bind(order.id as i64)
+    .bind(order.user_id.to_string())
+    .bind(side_to_str(order.side))
+    .bind(order_type_to_str(order.order_type))
+    .bind(to_scaled(order.price)?)
+    .bind(order.quantity as i64)
+    .bind(order.filled_quantity as i64)
+    .bind(to_scaled(order.filled_quote)?)
+    .bind(order.status.as_str())
+    .bind(order.created_at as i64)
+    .bind(order.updated_at as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+type OrderRow = (i64, String, String, String, i64, i64, i64, i64, String, i64, i64);
+
+fn order_from_row(row: OrderRow) -> StorageResult<OrderRecord> {
+    let (id, user_id, side, order_type, price, quantity, filled_quantity, filled_quote, status, created_at, updated_at) = row;
+    Ok(OrderRecord {
+        id: id as u64,
+        user_id: parse_uuid("orders", "user_id", &user_id)?,
+        side: parse_side("orders", "side", &side)?,
+        order_type: match order_type.as_str() {
+            "Limit" => OrderType::Limit,
+            "Market" => OrderType::Market,
+            _ => return Err(corrupt("orders", "order_type", &order_type)),
+        },
+        price: from_scaled(price),
+        quantity: quantity as u64,
+        filled_quantity: filled_quantity as u64,
+        filled_quote: from_scaled(filled_quote),
+        status: OrderStatus::parse(&status).ok_or_else(|| corrupt("orders", "status", &status))?,
+        created_at: created_at as u128,
+        updated_at: updated_at as u128,
+    })
+}
+
+/// 注文IDで注文記録を取得する
+pub async fn get_order(pool: &DbPool, order_id: u64) -> StorageResult<Option<OrderRecord>> {
+    let row: Option<OrderRow> = sqlx::query_as(
+        r#"
+        SELECT id, user_id, side, order_type, price, quantity, filled_quantity, filled_quote, status, created_at, updated_at
+        FROM orders
+        WHERE id = ?
+        "#
+    )
+    .bind(order_id as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    row.map(order_from_row).transpose()
+}
+
+/// ユーザーの注文記録を新しい順に取得する
+pub async fn get_user_orders(pool: &DbPool, user_id: Uuid, limit: u32) -> StorageResult<Vec<OrderRecord>> {
+    let rows: Vec<OrderRow> = sqlx::query_as(
+        r#"
+        SELECT id, user_id, side, order_type, price, quantity, filled_quantity, filled_quote, status, created_at, updated_at
+        FROM orders
+        WHERE user_id = ?
+        ORDER BY created_at DESC, id DESC
+        LIMIT ?
+        "#
+    )
+    .bind(user_id.to_string())
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+
+    rows.into_iter().map(order_from_row).collect()
+}
+
+/// ローソク足を保存する（同じ時間足・開始時刻があれば上書き）
+pub async fn save_candle(pool: &DbPool, candle: &Candle) -> StorageResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO candles (interval, open_time, open, high, low, close, volume, quote_volume, trade_count)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (interval, open_time) DO UPDATE SET
+            high = excluded.high,
+            low = excluded.low,
+            close = excluded.close,
+            volume = excluded.volume,
+            quote_volume = excluded.quote_volume,
+            trade_count = excluded.trade_count
+        "#
+    )
+    .bind(candle.interval.as_str())
+    .bind(candle.open_time as i64)
+    .bind(to_scaled(candle.open)?)
+    .bind(to_scaled(candle.high)?)
+    .bind(to_scaled(candle.low)?)
+    .bind(to_scaled(candle.close)?)
+    .bind(candle.volume as i64)
+    .bind(to_scaled(candle.quote_volume)?)
+    .bind(candle.trade_count as i64)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+type CandleRow = (i64, i64, i64, i64, i64, i64, i64, i64);
+
+/// 指定期間のローソク足を古い順に取得する
+/// 
+/// from を省略した場合は to 以前の最新 limit 本を返す
+pub async fn get_candles(
+    pool: &DbPool,
+    interval: CandleInterval,
+    from: Option<u128>,
+    to: Option<u128>,
+    limit: u32,
+) -> StorageResult<Vec<Candle>> {
+    // from がなければ新しい方から limit 本取って、後で古い順に並べ直す
+    let order = if from.is_some() { "ASC" } else { "DESC" };
+    let mut rows: Vec<CandleRow> = sqlx::query_as(&format!(
+        r#"
+        SELECT open_time, open, high, low, close, volume, quote_volume, trade_count
+        FROM candles
+        WHERE interval = ? AND open_time >= ? AND open_time <= ?
+        ORDER BY open_time {}
+        LIMIT ?
+        "#,
+        order
+    ))
+    .bind(interval.as_str())
+    .bind(from.map(|t| t as i64).unwrap_or(0))
+    .bind(to.map(|t| t as i64).unwrap_or(i64::MAX))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    if from.is_none() {
+        rows.reverse();
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(|(open_time, open, high, low, close, volume, quote_volume, trade_count)| Candle {
+            interval,
+            open_time: open_time as u128,
+            open: from_scaled(open),
+            high: from_scaled(high),
+            low: from_scaled(low),
+            close: from_scaled(close),
+            volume: volume as u64,
+            quote_volume: from_scaled(quote_volume),
+            trade_count: trade_count as u64,
+        })
+        .collect())
+}
+
+// =============================================================================
+// FIXセッション
+// =============================================================================
+
+/// FIXセッションのシーケンス番号を取得する（初めてのセッションなら None）
+pub async fn get_fix_sequence(pool: &DbPool, session_id: &str) -> StorageResult<Option<FixSequence>> {
+    let row: Option<(i64, i64)> = sqlx::query_as(
+        "SELECT next_outgoing, next_incoming FROM fix_sessions WHERE session_id = ?"
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|(next_outgoing, next_incoming)| FixSequence {
+        next_outgoing: next_outgoing as u64,
+        next_incoming: next_incoming as u64,
+    }))
+}
+
+/// FIXセッションのシーケンス番号を保存する
+pub async fn save_fix_sequence(pool: &DbPool, session_id: &str, sequence: &FixSequence) -> StorageResult<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    sqlx::query(
+        r#"
+        INSERT INTO fix_sessions (session_id, next_outgoing, next_incoming, updated_at)
+        VALUES (?, ?, ?, ?)
+        ON CONFLICT(session_id) DO UPDATE SET
+            next_outgoing = excluded.next_outgoing,
+            next_incoming = excluded.next_incoming,
+            updated_at = excluded.updated_at
+        "#
+    )
+    .bind(session_id)
+    .bind(sequence.next_outgoing as i64)
+    .bind(sequence.next_incoming as i64)
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// 送信したメッセージを保存する
+pub async fn save_fix_message(pool: &DbPool, session_id: &str, seq: u64, message: &[u8]) -> StorageResult<()> {
+    sqlx::query("INSERT OR REPLACE INTO fix_messages (session_id, seq, message) VALUES (?, ?, ?)")
+        .bind(session_id)
+        .bind(seq as i64)
+        .bind(message)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// 送信済みメッセージを seq の範囲（閉区間）で古い順に取得する
+pub async fn get_fix_messages(pool: &DbPool, session_id: &str, from: u64, to: u64) -> StorageResult<Vec<(u64, Vec<u8>)>> {
+    let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as(
+        "SELECT seq, message FROM fix_messages WHERE session_id = ? AND seq BETWEEN ? AND ? ORDER BY seq"
+    )
+    .bind(session_id)
+    .bind(from as i64)
+    .bind(to.min(i64::MAX as u64) as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(seq, message)| (seq as u64, message)).collect())
+}
+
+/// シーケンス番号を1に戻し、送信済みメッセージを消す
+pub async fn reset_fix_session(pool: &DbPool, session_id: &str) -> StorageResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM fix_messages WHERE session_id = ?")
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM fix_sessions WHERE session_id = ?")
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+// =============================================================================
+// Storageトレイトの実装（SQLite）
+// =============================================================================
+
+/// SQLiteをバックエンドとするストレージ
+///
+/// 上の関数群に接続プールを渡すだけの薄いラッパー
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    pool: DbPool,
+}
+
+impl SqliteStorage {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// 内部の接続プール（直接SQLを投げたいテスト・分析用）
+    pub fn pool(&self) -> &DbPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn ensure_default_user(&self) -> StorageResult<Uuid> {
+        ensure_default_user(&self.pool).await
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Uuid> {
+        create_user(&self.pool, username, Some(password_hash)).await
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> StorageResult<Option<User>> {
+        get_user_by_username(&self.pool, username).await
+    }
+
+    async fn create_session(&self, token_hash: &str, user_id: Uuid, expires_at: u128) -> StorageResult<()> {
+        create_session(&self.pool, token_hash, user_id, expires_at).await
+    }
+
+    async fn get_session_user(&self, token_hash: &str, now: u128) -> StorageResult<Option<Uuid>> {
+        get_session_user(&self.pool, token_hash, now).await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        delete_session(&self.pool, token_hash).await
+    }
+
+    async fn create_api_key(&self, api_key: &ApiKey) -> StorageResult<()> {
+        create_api_key(&self.pool, api_key).await
+    }
+
+    async fn get_api_key(&self, key: &str) -> StorageResult<Option<ApiKey>> {
+        get_api_key(&self.pool, key).await
+    }
+
+    async fn list_api_keys(&self, user_id: Uuid) -> StorageResult<Vec<ApiKey>> {
+        list_api_keys(&self.pool, user_id).await
+    }
+
+    async fn revoke_api_key(&self, user_id: Uuid, key: &str, now: u128) -> StorageResult<bool> {
+        revoke_api_key(&self.pool, user_id, key, now).await
+    }
+
+    async fn get_balances(&self, user_id: Uuid) -> StorageResult<Vec<Balance>> {
+        get_balances(&self.pool, user_id).await
+    }
+
+    async fn get_all_balances(&self) -> StorageResult<Vec<Balance>> {
+        get_all_balances(&self.pool).await
+    }
+
+    async fn update_balance(
+        &self,
+        user_id: Uuid,
+        asset: &str,
+        available: Decimal,
+        locked: Decimal,
+    ) -> StorageResult<()> {
+        update_balance(&self.pool, user_id, asset, available, locked).await
+    }
+
+    async fn get_assets(&self) -> StorageResult<Vec<Asset>> {
+        get_assets(&self.pool).await
+    }
+
+    async fn save_asset(&self, asset: &Asset) -> StorageResult<()> {
+        save_asset(&self.pool, asset).await
+    }
+
+    async fn save_margin_account(&self, account: &MarginAccountRecord) -> StorageResult<()> {
+        save_margin_account(&self.pool, account).await
+    }
+
+    async fn get_margin_accounts(&self) -> StorageResult<Vec<MarginAccountRecord>> {
+        get_margin_accounts(&self.pool).await
+    }
+
+    async fn save_perp_account(&self, account: &PerpAccountRecord) -> StorageResult<()> {
+        save_perp_account(&self.pool, account).await
+    }
+
+    async fn get_perp_accounts(&self) -> StorageResult<Vec<PerpAccountRecord>> {
+        get_perp_accounts(&self.pool).await
+    }
+
+    async fn save_trade(&self, trade: &Trade) -> StorageResult<()> {
+        save_trade(&self.pool, trade).await
+    }
+
+    async fn get_trades(&self, query: &TradeQuery) -> StorageResult<Vec<Trade>> {
+        get_trades(&self.pool, query).await
+    }
+
+    async fn last_trade_id(&self) -> StorageResult<u64> {
+        last_trade_id(&self.pool).await
+    }
+
+    async fn save_perp_trade(&self, trade: &Trade) -> StorageResult<()> {
+        save_perp_trade(&self.pool, trade).await
+    }
+
+    async fn last_perp_trade_id(&self) -> StorageResult<u64> {
+        last_perp_trade_id(&self.pool).await
+    }
+
+    async fn last_order_id(&self) -> StorageResult<u64> {
+        last_order_id(&self.pool).await
+    }
+
+    async fn save_order(&self, order: &OrderRecord) -> StorageResult<()> {
+        save_order(&self.pool, order).await
+    }
+
+    async fn get_order(&self, order_id: u64) -> StorageResult<Option<OrderRecord>> {
+        get_order(&self.pool, order_id).await
+    }
+
+    async fn get_user_orders(&self, user_id: Uuid, limit: u32) -> StorageResult<Vec<OrderRecord>> {
+        get_user_orders(&self.pool, user_id, limit).await
+    }
+
+    async fn save_candle(&self, candle: &Candle) -> StorageResult<()> {
+        save_candle(&self.pool, candle).await
+    }
+
+    async fn get_candles(
+        &self,
+        interval: CandleInterval,
+        from: Option<u128>,
+        to: Option<u128>,
+        limit: u32,
+    ) -> StorageResult<Vec<Candle>> {
+        get_candles(&self.pool, interval, from, to, limit).await
+    }
+
+    async fn get_fix_sequence(&self, session_id: &str) -> StorageResult<Option<FixSequence>> {
+        get_fix_sequence(&self.pool, session_id).await
+    }
+
+    async fn save_fix_sequence(&self, session_id: &str, sequence: &FixSequence) -> StorageResult<()> {
+        save_fix_sequence(&self.pool, session_id, sequence).await
+    }
+
+    async fn save_fix_message(&self, session_id: &str, seq: u64, message: &[u8]) -> StorageResult<()> {
+        save_fix_message(&self.pool, session_id, seq, message).await
+    }
+
+    async fn get_fix_messages(&self, session_id: &str, from: u64, to: u64) -> StorageResult<Vec<(u64, Vec<u8>)>> {
+        get_fix_messages(&self.pool, session_id, from, to).await
+    }
+
+    async fn reset_fix_session(&self, session_id: &str) -> StorageResult<()> {
+        reset_fix_session(&self.pool, session_id).await
+    }
+}
+
+/// DBタスクへの非同期メッセージ
+#[derive(Debug)]
+pub enum DbMessage {
+    /// 残高が変化したことを通知
+    UpdateBalance {
+        user_id: Uuid,
+        asset: String,
+        available: Decimal,
+        locked: Decimal,
+    },
+    /// 約定履歴を保存（シミュレータ同士の約定も含む全約定）
+    SaveTrade(Trade),
+    /// 無期限先物の約定を保存
+    SavePerpTrade(Trade),
+    /// 形成中のローソク足を保存（同じ足は上書き）
+    SaveCandle(Candle),
+    /// ユーザーの注文記録を保存（状態が変わるたびに上書き）
+    SaveOrder(OrderRecord),
+    /// 証拠金口座を保存（口座が変わるたびに上書き）
+    SaveMarginAccount(MarginAccountRecord),
+    /// 先物口座を保存（ウォレット・ポジションが変わるたびに上書き）
+    SavePerpAccount(PerpAccountRecord),
+}
+
+/// DB Writer の書き込み失敗の記録（GET /health で返す）
+///
+/// 書き込みに失敗するとエンジン（メモリ）と DB がずれたままになるので、ログに出すだけでなく数えておく
+#[derive(Debug, Default)]
+pub struct DbWriterStatus {
+    failures: AtomicU64,
+    last_error: Mutex<Option<String>>,
+}
+
+impl DbWriterStatus {
+    /// 書き込みの失敗を記録する
+    fn record(&self, kind: &str, e: StorageError) {
+        eprintln!("DB Error ({}): {}", kind, e);
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().unwrap() = Some(format!("{}: {}", kind, e));
+    }
+
+    /// 起動してから失敗した書き込みの数
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// 最後に失敗した書き込み（"種類: エラー"）
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+/// DB Writer Actor
+/// 
+/// どのバックエンドに書くかは `storage` 次第（SQLite / インメモリ）
+pub async fn run_db_writer(mut rx: tokio::sync::mpsc::Receiver<DbMessage>, storage: SharedStorage, status: Arc<DbWriterStatus>) {
+    // メッセージが来るたびにDBに書き込む
+    // エラーが出てもクラッシュさせず、ログに出して status に記録する
+    while let Some(msg) = rx.recv().await {
+        let result = match msg {
             DbMessage::UpdateBalance { user_id, asset, available, locked } => {
-                if let Err(e) = update_balance(&pool, user_id, &asset, available, locked).await {
-                    eprintln!("DB Error (UpdateBalance): {}", e);
-                }
+                storage.update_balance(user_id, &asset, available, locked).await.map_err(|e| ("UpdateBalance", e))
             }
-            DbMessage::SaveTrade { maker_order_id, taker_order_id, price, quantity, timestamp, user_id } => {
-                if let Err(e) = save_trade(&pool, maker_order_id, taker_order_id, price, quantity, timestamp, user_id).await {
-                    eprintln!("DB Error (SaveTrade): {}", e);
-                }
+            DbMessage::SaveTrade(trade) => storage.save_trade(&trade).await.map_err(|e| ("SaveTrade", e)),
+            DbMessage::SavePerpTrade(trade) => storage.save_perp_trade(&trade).await.map_err(|e| ("SavePerpTrade", e)),
+            DbMessage::SaveCandle(candle) => storage.save_candle(&candle).await.map_err(|e| ("SaveCandle", e)),
+            DbMessage::SaveOrder(order) => storage.save_order(&order).await.map_err(|e| ("SaveOrder", e)),
+            DbMessage::SaveMarginAccount(account) => {
+                storage.save_margin_account(&account).await.map_err(|e| ("SaveMarginAccount", e))
             }
+            DbMessage::SavePerpAccount(account) => storage.save_perp_account(&account).await.map_err(|e| ("SavePerpAccount", e)),
+        };
+        if let Err((kind, e)) = result {
+            status.record(kind, e);
         }
     }
 }
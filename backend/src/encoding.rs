@@ -0,0 +1,118 @@
+// =============================================================================
+// WebSocket配信のエンコード形式
+// =============================================================================
+//
+// depth / diff_depth / trades は、JSONの代わりにMessagePackでも受け取れます。
+// MessagePackは配信を軽くするための形式なので、JSONとは次の点が違います。
+//
+// - 構造体はキー名なしの配列にする（フィールドの宣言順。先頭は "type"）
+//     JSON:        {"type":"delta","prev_sequence":1,"sequence":2,"bids":[],"asks":[{"price":"101","quantity":3,"order_count":1}]}
+//     MessagePack: ["delta",1,2,[],[[10100000000,3,1]]]
+// - 価格は 10^8 倍した整数（DBと同じ固定小数点。storage::to_scaled 参照）。
+//   小数点以下8桁を超えるなど整数にできない価格だけは文字列のまま送る
+//
+// MessagePackは Binary フレーム、JSONは Text フレームで送ります。
+//
+// 形式は接続時のサブプロトコル（Sec-WebSocket-Protocol: badbit.msgpack）で
+// 接続全体の既定を決めるか、購読ごとに {"encoding": "msgpack"} で指定します。
+// 購読・エラーなどの応答は、形式によらず常にJSONです。
+//
+// 配信は1回分ごとに FeedFrame にまとめ、形式ごとに最初に必要になった時に1回だけ
+// エンコードして、同じ形式の購読者で共有します。
+// =============================================================================
+
+use std::sync::{Arc, OnceLock};
+
+use axum::extract::ws::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::feeds::MarketEvent;
+
+/// 配信する価格のシリアライズ（`#[serde(serialize_with = "crate::encoding::price::serialize")]`）
+///
+/// JSON（human readable な形式）では精度を保つために文字列、
+/// MessagePackでは 10^8 倍した整数にする
+pub mod price {
+    use rust_decimal::Decimal;
+    use serde::Serializer;
+
+    use crate::storage::to_scaled;
+
+    pub fn serialize<S: Serializer>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return rust_decimal::serde::str::serialize(value, serializer);
+        }
+        match to_scaled(*value) {
+            Ok(scaled) => serializer.serialize_i64(scaled),
+            Err(_) => rust_decimal::serde::str::serialize(value, serializer),
+        }
+    }
+}
+
+/// JSONを既定にするサブプロトコル
+pub const JSON_PROTOCOL: &str = "badbit.json";
+/// MessagePackを既定にするサブプロトコル
+pub const MSGPACK_PROTOCOL: &str = "badbit.msgpack";
+
+/// 配信メッセージのエンコード形式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl Encoding {
+    /// サブプロトコル名から形式を決める（対応していなければ None）
+    pub fn from_protocol(protocol: &str) -> Option<Self> {
+        match protocol {
+            JSON_PROTOCOL => Some(Self::Json),
+            MSGPACK_PROTOCOL => Some(Self::Msgpack),
+            _ => None,
+        }
+    }
+
+    /// 1件を WebSocket のフレームにする（失敗しない型だけなので、失敗したら None）
+    ///
+    /// MessagePackは構造体をキー名なしの配列にする（ヘッダーの説明を参照）
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Option<Message> {
+        match self {
+            Self::Json => serde_json::to_string(value).ok().map(|json| Message::Text(json.into())),
+            Self::Msgpack => rmp_serde::to_vec(value).ok().map(|bytes| Message::Binary(bytes.into())),
+        }
+    }
+}
+
+/// 形式を選べるチャネルの1回分の配信
+///
+/// clone しても中身は共有され、エンコード結果も形式ごとに1回だけ作られる
+#[derive(Debug, Clone)]
+pub struct FeedFrame(Arc<FrameInner>);
+
+#[derive(Debug)]
+struct FrameInner {
+    event: MarketEvent,
+    json: OnceLock<Option<Message>>,
+    msgpack: OnceLock<Option<Message>>,
+}
+
+impl FeedFrame {
+    pub fn new(event: MarketEvent) -> Self {
+        Self(Arc::new(FrameInner { event, json: OnceLock::new(), msgpack: OnceLock::new() }))
+    }
+
+    /// 配信する内容
+    pub fn event(&self) -> &MarketEvent {
+        &self.0.event
+    }
+
+    /// 指定した形式のフレームを返す（初回だけエンコードする）
+    pub fn encode(&self, encoding: Encoding) -> Option<Message> {
+        let cache = match encoding {
+            Encoding::Json => &self.0.json,
+            Encoding::Msgpack => &self.0.msgpack,
+        };
+        cache.get_or_init(|| encoding.encode(&self.0.event.message())).clone()
+    }
+}
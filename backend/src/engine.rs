@@ -1,10 +1,21 @@
-use tokio::sync::{mpsc, oneshot, broadcast};
-use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
 use uuid::Uuid;
-use crate::models::{Order, Trade, Side};
-use crate::orderbook::OrderBook;
+use crate::models::{Order, OrderRecord, OrderStatus, OrderType, Trade, Side};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use crate::orderbook::{BookSnapshot, DepthSnapshot, L3Snapshot, OrderBook};
 use crate::account::AccountManager;
-use crate::db::DbMessage;
+use crate::candles::CandleAggregator;
+use crate::db::{Balance, DbMessage};
+use crate::feeds::MarketFeeds;
+use crate::margin::{MarginAccountSummary, MarginError, MarginManager, MarginRequest};
+use crate::perp::{PerpAccountSummary, PerpError, PerpManager, PerpMarket, PerpRequest, PERP_SYMBOL, SETTLEMENT_ASSET};
+use crate::publisher::CoalescingPublisher;
+use crate::storage::{SharedStorage, StorageResult};
+use crate::ticker::{Ticker, TickerTracker};
 
 // =============================================================================
 // Actorパターンのメッセージ定義
@@ -28,13 +39,75 @@ pub enum EngineMessage {
         order: Order,                          // 処理してほしい注文
         respond_to: oneshot::Sender<Vec<Trade>>, // 約定リストを返信する先
     },
-    /// 現在のオーダーブックを見せてください
+    /// 証拠金口座の資産で新規注文を処理してください（margin.rs 参照）
+    ///
+    /// キャンセルは通常の注文と同じく CancelOrder で行う
+    PlaceMarginOrder {
+        order: Order,
+        respond_to: oneshot::Sender<Vec<Trade>>,
+    },
+    /// 証拠金口座の振替・借入・返済・照会をしてください
+    Margin {
+        user_id: Uuid,
+        request: MarginRequest,
+        respond_to: oneshot::Sender<Result<MarginAccountSummary, MarginError>>, // 処理後の口座の状態
+    },
+    /// 無期限先物（BAD-PERP）の新規注文を処理してください（perp.rs 参照）
+    ///
+    /// キャンセルは通常の注文と同じく CancelOrder で行う
+    PlacePerpOrder {
+        order: Order,
+        respond_to: oneshot::Sender<Result<Vec<Trade>, PerpError>>,
+    },
+    /// 先物口座の振替・照会をしてください
+    Perp {
+        user_id: Uuid,
+        request: PerpRequest,
+        respond_to: oneshot::Sender<Result<PerpAccountSummary, PerpError>>, // 処理後の口座の状態
+    },
+    /// 先物の板（価格帯ごとの集計）を見せてください
+    GetPerpOrderBook {
+        respond_to: oneshot::Sender<BookSnapshot>,
+    },
+    /// 先物のインデックス価格・マーク価格・資金調達率を見せてください
+    GetPerpMarket {
+        respond_to: oneshot::Sender<PerpMarket>,
+    },
+    /// ユーザーの先物の板に残っている注文を見せてください
+    GetPerpOpenOrders {
+        user_id: Uuid,
+        respond_to: oneshot::Sender<Vec<OrderRecord>>, // 注文ID順
+    },
+    /// 現在のオーダーブック（価格帯ごとの集計）を見せてください
     GetOrderBook {
-        respond_to: oneshot::Sender<OrderBook>,
+        respond_to: oneshot::Sender<BookSnapshot>,
     },
-    /// 取引履歴を見せてください
-    GetTrades {
-        respond_to: oneshot::Sender<Vec<Trade>>,
+    /// 上位N段の板の深さを見せてください（板全体はコピーしない）
+    GetDepth {
+        limit: usize,
+        group: Option<Decimal>, // 価格をまとめる刻み
+        respond_to: oneshot::Sender<DepthSnapshot>,
+    },
+    /// 現在のオーダーブックを注文単位で見せてください（所有者は含まない）
+    GetOrderBookL3 {
+        respond_to: oneshot::Sender<L3Snapshot>,
+    },
+    /// 24時間ティッカー統計を見せてください
+    GetTicker {
+        respond_to: oneshot::Sender<Ticker>,
+    },
+    /// ユーザーの全資産の残高を見せてください（資産名の順）
+    ///
+    /// 読むだけなら MarketFeeds::balances のほうが安い。
+    /// 他のメッセージとの前後関係まで揃えたい場合に使う
+    GetBalances {
+        user_id: Uuid,
+        respond_to: oneshot::Sender<Vec<Balance>>,
+    },
+    /// 新しく登録されたユーザーの残高を読み込んでください
+    LoadAccount {
+        balances: Vec<Balance>,
+        respond_to: oneshot::Sender<()>,
     },
     /// 注文をキャンセルしてください
     CancelOrder {
@@ -42,162 +115,926 @@ pub enum EngineMessage {
         user_id: Uuid, // セキュリティのため、誰の注文かを確認する
         respond_to: oneshot::Sender<Option<Order>>, // 削除された注文を返す（なければNone）
     },
+    /// ユーザーの板に残っている注文を見せてください
+    GetOpenOrders {
+        user_id: Uuid,
+        respond_to: oneshot::Sender<Vec<OrderRecord>>, // 注文ID順
+    },
+    /// 板に残っている注文の記録を見せてください（約定・キャンセル済みならNone）
+    GetOrder {
+        order_id: u64,
+        respond_to: oneshot::Sender<Option<OrderRecord>>,
+    },
 }
 
-/// マッチングエンジンを実行する（Actor Loop）
-pub async fn run_matching_engine(
-    mut rx: mpsc::Receiver<EngineMessage>,
-    db_tx: mpsc::Sender<DbMessage>,
-    mut account_manager: AccountManager,
-    broadcast_tx: broadcast::Sender<OrderBook>, // 板情報の配信チャンネル
+/// 注文IDの採番器
+/// 
+/// API・シミュレータなど注文を出す全員で共有し、IDが重複しないようにする
+#[derive(Debug, Default)]
+pub struct OrderIdGenerator {
+    last: AtomicU64,
+}
+
+impl OrderIdGenerator {
+    /// last_id の次から採番する
+    pub fn new(last_id: u64) -> Self {
+        Self { last: AtomicU64::new(last_id) }
+    }
+
+    /// 新しい注文IDを払い出す
+    pub fn next_id(&self) -> u64 {
+        self.last.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// 約定から差分更新されるマーケットデータ
+/// 
+/// 再起動しても途切れないよう、起動時にストレージから復元してエンジンに渡す
+#[derive(Debug, Clone, Default)]
+pub struct MarketData {
+    pub candles: CandleAggregator, // 形成中のローソク足
+    pub ticker: TickerTracker,     // 24時間統計
+    pub last_trade_id: u64,        // 保存済みの最大の約定ID（採番の続きに使う）
+    pub last_perp_trade_id: u64,   // 保存済みの最大の先物の約定ID（同上）
+    pub last_order_id: u64,        // 使用済みの最大の注文ID（同上）
+    pub margin: MarginManager,     // 証拠金口座
+    pub perp: PerpManager,         // 無期限先物の口座・ポジション
+}
+
+impl MarketData {
+    /// 保存済みのローソク足・約定・証拠金口座・先物口座から復元する
+    ///
+    /// 証拠金・先物の設定と採番器は既定値なので、MarginManager::configure・PerpManager::configure で差し替える
+    pub async fn load(storage: &SharedStorage, now: u128) -> StorageResult<Self> {
+        let mut margin = MarginManager::default();
+        for account in storage.get_margin_accounts().await? {
+            margin.load_account(account);
+        }
+        let mut perp = PerpManager::default();
+        for account in storage.get_perp_accounts().await? {
+            perp.load_account(account);
+        }
+        Ok(Self {
+            candles: CandleAggregator::load(storage, now).await?,
+            ticker: TickerTracker::load(storage, now).await?,
+            last_trade_id: storage.last_trade_id().await?,
+            last_perp_trade_id: storage.last_perp_trade_id().await?,
+            last_order_id: storage.last_order_id().await?,
+            margin,
+            perp,
+        })
+    }
+}
+
+/// 現在時刻（ミリ秒）
+fn now_millis() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+/// 残高を読み取り用コピーに反映し、DBへ保存してそのユーザーへ配信する
+async fn update_balance(
+    db_tx: &mpsc::Sender<DbMessage>,
+    feeds: &MarketFeeds,
+    account_manager: &AccountManager,
+    user_id: Uuid,
+    asset: &str,
 ) {
-    let mut orderbook = OrderBook::new();
-    let mut trades_history: Vec<Trade> = Vec::new();
-    // account_managerはmoveされる（所有権がこのタスクに移る）
+    let (available, locked) = account_manager.get_balance(&user_id, asset);
+    feeds.balances.set(user_id, asset, available, locked);
+    feeds.publish_balance(user_id, asset, available, locked);
+    let _ = db_tx.send(DbMessage::UpdateBalance { user_id, asset: asset.to_string(), available, locked }).await;
+}
 
-    // 配信頻度制限用: 前回の配信時刻
-    let mut last_broadcast_time = Instant::now();
-    // 50msに1回（20fps）以上は配信しない
-    let broadcast_interval = Duration::from_millis(50);
+/// 現在のティッカー（最良気配は板から取る）
+fn ticker_snapshot(orderbook: &OrderBook, market: &mut MarketData) -> Ticker {
+    let best_bid = orderbook.bids.keys().next_back().copied();
+    let best_ask = orderbook.asks.keys().next().copied();
+    market.ticker.snapshot(now_millis(), best_bid, best_ask)
+}
 
-    while let Some(msg) = rx.recv().await {
-        match msg {
-            EngineMessage::PlaceOrder { order, respond_to } => {
-                // 1. 残高チェック & ロック
-                if let Some(uid) = order.user_id {
-                    if let Err(e) = account_manager.try_lock_balance(&uid, order.side, order.price, order.quantity) {
-                        eprintln!("Order Rejected: {}", e);
-                        // エラー時は空のトレードリストを返して終了
-                        let _ = respond_to.send(vec![]);
-                        continue;
+/// 約定したユーザー（Taker・Maker。シミュレータの注文は除く。重複なし）
+fn trade_users(trades: &[Trade]) -> Vec<Uuid> {
+    let mut users: Vec<Uuid> = Vec::new();
+    for trade in trades {
+        for uid in [trade.taker_user_id, trade.maker_user_id].into_iter().flatten() {
+            if !users.contains(&uid) {
+                users.push(uid);
+            }
+        }
+    }
+    users
+}
+
+/// 板の変化を拾い、配信間隔が空いたチャネルを配信する
+fn publish_due(
+    publisher: &mut CoalescingPublisher,
+    feeds: &MarketFeeds,
+    orderbook: &mut OrderBook,
+    market: &mut MarketData,
+) {
+    publisher.observe(orderbook.sequence);
+    let due = publisher.take_due(Instant::now());
+    if due.book {
+        feeds.publish_book(orderbook);
+    }
+    if due.ticker && feeds.ticker.receiver_count() > 0 {
+        feeds.publish_ticker(&ticker_snapshot(orderbook, market));
+    }
+}
+
+/// 注文を出した場（どの残高・ポジションで精算するか）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Venue {
+    Spot,   // 現物（BAD の保有量がポジション）
+    Margin, // 証拠金口座（BAD の保有 - 借入がポジション）
+    Perp,   // 無期限先物
+}
+
+/// エンジンアクターが持つ状態
+///
+/// 注文の受付・約定の精算・キャンセルは、通常の注文・証拠金注文・清算注文で共通なのでメソッドにまとめる
+struct Engine {
+    orderbook: OrderBook,
+    account_manager: AccountManager,
+    market: MarketData,
+    feeds: MarketFeeds,
+    db_tx: mpsc::Sender<DbMessage>,
+    // 板に残っているユーザー注文の記録（注文ID -> 記録）
+    // 約定・キャンセルで状態が変わるたびにDBへ保存し、終わった注文はここから外す
+    open_orders: HashMap<u64, OrderRecord>,
+    // 板に残っている注文のうち証拠金口座のもの（約定・キャンセルを証拠金口座で精算する）
+    margin_orders: HashSet<u64>,
+    // 無期限先物の板と、そこに残っている注文の記録（保存・配信はしない）
+    perp_book: OrderBook,
+    perp_orders: HashMap<u64, OrderRecord>,
+    // 板に残っている注文のうち reduce_only のもの（約定のたびにポジションと突き合わせる）
+    reduce_only_orders: HashSet<u64>,
+}
+
+impl Engine {
+    /// 注文を受け付ける（残高をロックしてからマッチングする）
+    ///
+    /// margin なら証拠金口座の残高を使う。残高不足・reduce_only の条件を満たさない場合は拒否して空の約定リストを返す
+    async fn place_order(&mut self, mut order: Order, margin: bool) -> Vec<Trade> {
+        let now = now_millis();
+        // 1. reduce_only の確認 → 残高チェック & ロック
+        if let Some(uid) = order.user_id {
+            let venue = if margin { Venue::Margin } else { Venue::Spot };
+            let locked = if !self.prepare_reduce_only(&mut order, venue) {
+                Err("reduce_only の注文がポジションを増やす".to_string())
+            } else if margin {
+                self.market.margin.try_lock(&uid, order.side, order.price, order.quantity, now).map_err(|e| e.to_string())
+            } else {
+                self.account_manager.try_lock_balance(&uid, order.side, order.price, order.quantity).map_err(str::to_string)
+            };
+            if let Err(e) = locked {
+                eprintln!("Order Rejected: {}", e);
+                // 拒否された注文も履歴に残す
+                let mut record = OrderRecord::new(&order, uid, now);
+                record.close(OrderStatus::Rejected, now);
+                self.feeds.publish_execution(&record, None);
+                let _ = self.db_tx.send(DbMessage::SaveOrder(record)).await;
+                // エラー時は空のトレードリストを返して終了
+                return vec![];
+            }
+            // ロック成功 → DBに通知
+            // 注意: ここのロック状態も永続化すべきだが、厳密には「注文ID」と紐づける必要がある。
+            // 今回は簡易的に残高だけ更新通知を送る。
+            if margin {
+                self.save_margin_account(uid).await;
+            } else {
+                let asset = if order.side == Side::Buy { "USDC" } else { "BAD" };
+                update_balance(&self.db_tx, &self.feeds, &self.account_manager, uid, asset).await;
+            }
+        }
+
+        self.execute(order, margin, now).await
+    }
+
+    /// マッチングし、約定を配信・精算・記録する（残高のロックは済んでいる前提）
+    async fn execute(&mut self, order: Order, margin: bool, now: u128) -> Vec<Trade> {
+        if margin && order.user_id.is_some() {
+            self.margin_orders.insert(order.id);
+        }
+
+        // 2. マッチング実行
+        let new_trades = self.orderbook.process_order(order.clone());
+
+        // 3. 全約定を配信・保存（シミュレータ同士の約定も公開履歴に残す）
+        // 約定は板と違って間引かず、発生した時点で1件ずつ流す
+        for trade in &new_trades {
+            self.feeds.publish_trade(trade);
+            let _ = self.db_tx.send(DbMessage::SaveTrade(trade.clone())).await;
+        }
+
+        // 4. 約定処理 (残高移動)
+        // Maker・Takerのうちユーザーの注文だけを精算する（シミュレータの注文は user_id = None）
+        // 証拠金注文は証拠金口座で精算する
+        let mut settled_users: Vec<Uuid> = Vec::new();
+        let mut margin_users: Vec<Uuid> = Vec::new();
+        for trade in &new_trades {
+            let parties = [
+                (trade.taker_user_id, trade.taker_side, trade.taker_id),
+                (trade.maker_user_id, trade.taker_side.opposite(), trade.maker_id),
+            ];
+            for (user_id, side, order_id) in parties {
+                let Some(uid) = user_id else { continue };
+                if self.margin_orders.contains(&order_id) {
+                    self.market.margin.on_trade_match(&uid, side, trade.price, trade.quantity);
+                    if !margin_users.contains(&uid) {
+                        margin_users.push(uid);
                     }
-                    // ロック成功 → DBに通知
-                    // 注意: ここのロック状態も永続化すべきだが、厳密には「注文ID」と紐づける必要がある。
-                    // 今回は簡易的に残高だけ更新通知を送る。
-                    let (avail, locked) = account_manager.get_balance(&uid, if order.side == Side::Buy { "USDC" } else { "BAD" });
-                    let _ = db_tx.send(DbMessage::UpdateBalance { 
-                        user_id: uid, 
-                        asset: (if order.side == Side::Buy { "USDC" } else { "BAD" }).to_string(), 
-                        available: avail, 
-                        locked 
-                    }).await;
+                    continue;
                 }
+                self.account_manager.on_trade_match(&uid, side, trade.price, trade.quantity);
+                if !settled_users.contains(&uid) {
+                    settled_users.push(uid);
+                }
+            }
+        }
 
-                // 2. マッチング実行
-                let new_trades = orderbook.process_order(order.clone());
-                
-                // 3. 約定処理 (残高移動)
-                for _trade in &new_trades {
-                    // Maker（板にいた人）の処理
-                    // シミュレータの注文(user_id=None)は無視する
-                    // しかし、注文IDから元のUserを探す仕組みがまだないため、
-                    // ここでは「今回のTaker」がユーザーの場合のみ処理する簡易実装とする
-                    // ★ 本来は OrderBook内の Order に user_id が入っているので、それを使うべき
-                    // process_order の返り値 Trade には user_id がない。これが必要。
+        // 残高変更をDBに通知 (USDCとBAD両方)
+        for uid in settled_users {
+            for asset in ["USDC", "BAD"] {
+                update_balance(&self.db_tx, &self.feeds, &self.account_manager, uid, asset).await;
+            }
+        }
+        for uid in margin_users {
+            self.save_margin_account(uid).await;
+        }
+
+        // 5. 注文記録を更新して保存（Taker自身と、約定したユーザーのMaker注文）
+        // 約定レポートは 受付 → 約定ごと → 成行の残りの失効 の順に送る
+        if let Some(uid) = order.user_id {
+            let mut record = OrderRecord::new(&order, uid, now);
+            self.feeds.publish_execution(&record, None);
+            for trade in &new_trades {
+                record.apply_fill(trade.price, trade.quantity, now);
+                self.feeds.publish_execution(&record, Some(trade));
+            }
+            if record.status.is_open() && order.order_type == OrderType::Market {
+                // 成行の未約定分は板に載らずに捨てられる
+                record.close(OrderStatus::Expired, now);
+                self.feeds.publish_execution(&record, None);
+            }
+            if record.status.is_open() {
+                self.open_orders.insert(record.id, record.clone());
+                if order.reduce_only {
+                    self.reduce_only_orders.insert(record.id);
                 }
-                
-                // ★ Trade構造体に user_id を持たせていないため、ここで詰まる。
-                // 修正: Trade構造体に user_id はあるが、maker/takerのどちらか不明確。
-                // 正しい実装: process_order が返す Trade には maker_order と taker_order の情報が必要。
-                // ここでロジックを修正する必要がある。
-                
-                // 今回は Taker (注文を出した人) の残高更新だけを行う（Makerはシミュレータと仮定）
-                    if let Some(taker_uid) = order.user_id {
-                    for trade in &new_trades {
-                        // Takerの残高更新
-                        account_manager.on_trade_match(&taker_uid, order.side, trade.price, trade.quantity);
-                        
-                        // DBに保存
-                        let _ = db_tx.send(DbMessage::SaveTrade {
-                            maker_order_id: trade.maker_id,
-                            taker_order_id: trade.taker_id,
-                            price: trade.price,
-                            quantity: trade.quantity,
-                            timestamp: trade.timestamp,
-                            user_id: Some(taker_uid),
-                        }).await;
-                    }
-                    
-                    if !new_trades.is_empty() {
-                        // 残高変更をDBに通知 (USDCとBAD両方)
-                        let (usdc_av, usdc_lk) = account_manager.get_balance(&taker_uid, "USDC");
-                        let _ = db_tx.send(DbMessage::UpdateBalance { user_id: taker_uid, asset: "USDC".to_string(), available: usdc_av, locked: usdc_lk }).await;
-                        
-                        let (bad_av, bad_lk) = account_manager.get_balance(&taker_uid, "BAD");
-                        let _ = db_tx.send(DbMessage::UpdateBalance { user_id: taker_uid, asset: "BAD".to_string(), available: bad_av, locked: bad_lk }).await;
-                    }
+            } else {
+                self.margin_orders.remove(&record.id);
+            }
+            let _ = self.db_tx.send(DbMessage::SaveOrder(record)).await;
+        }
+        let mut filled_makers: Vec<u64> = Vec::new();
+        for trade in &new_trades {
+            if let Some(maker) = self.open_orders.get_mut(&trade.maker_id) {
+                maker.apply_fill(trade.price, trade.quantity, now);
+                self.feeds.publish_execution(maker, Some(trade));
+                if !filled_makers.contains(&maker.id) {
+                    filled_makers.push(maker.id);
+                }
+            }
+        }
+        for maker_id in filled_makers {
+            let record = match self.open_orders.get(&maker_id) {
+                Some(record) if record.status.is_open() => record.clone(),
+                _ => {
+                    self.margin_orders.remove(&maker_id);
+                    self.reduce_only_orders.remove(&maker_id);
+                    self.open_orders.remove(&maker_id).expect("上で更新済み")
+                }
+            };
+            let _ = self.db_tx.send(DbMessage::SaveOrder(record)).await;
+        }
+
+        // 6. ポジションが変わったユーザーの reduce_only 注文を、ポジションを反転させない数量に揃える
+        if !self.reduce_only_orders.is_empty() {
+            for uid in trade_users(&new_trades) {
+                self.enforce_reduce_only(uid, Venue::Spot).await;
+                self.enforce_reduce_only(uid, Venue::Margin).await;
+            }
+        }
+
+        // 7. ローソク足・ティッカーを更新し、足は保存・配信
+        // シミュレータの約定も含めた全約定を集計する
+        self.market.ticker.on_trades(&new_trades);
+        self.market.margin.on_trades(&new_trades);
+        for candle in self.market.candles.apply_trades(&new_trades) {
+            let _ = self.feeds.candles.send(candle.clone());
+            let _ = self.db_tx.send(DbMessage::SaveCandle(candle)).await;
+        }
+
+        // 板情報・ティッカーの配信は、ループの先頭でまとめて行う
+        new_trades
+    }
+
+    /// 注文を板から外してロックを解除し、記録を閉じる（所有者の確認は呼び出し側で行う）
+    async fn cancel_order(&mut self, order_id: u64, user_id: Uuid) -> Option<Order> {
+        // 1. OrderBookから削除
+        let order = self.orderbook.cancel_order(order_id)?;
+        self.reduce_only_orders.remove(&order_id);
+
+        // 2. ロック解除 (返金) → 残高更新をDBへ通知
+        if self.margin_orders.remove(&order_id) {
+            self.market.margin.unlock(&user_id, order.side, order.price, order.quantity);
+            self.save_margin_account(user_id).await;
+        } else {
+            self.account_manager.unlock_balance(&user_id, order.side, order.price, order.quantity);
+            let asset = if order.side == Side::Buy { "USDC" } else { "BAD" };
+            update_balance(&self.db_tx, &self.feeds, &self.account_manager, user_id, asset).await;
+        }
+
+        // 3. 注文記録をDBへ通知
+        if let Some(mut record) = self.open_orders.remove(&order_id) {
+            record.close(OrderStatus::Cancelled, now_millis());
+            self.feeds.publish_execution(&record, None);
+            let _ = self.db_tx.send(DbMessage::SaveOrder(record)).await;
+        }
+        Some(order)
+    }
+
+    /// ユーザーのポジション（符号付きの枚数。正なら買い持ち、負なら売り持ち）
+    ///
+    /// 現物・証拠金では、reduce_only でない売り注文にロックした BAD は売る予定の分なので数えない
+    /// （reduce_only の売りはその残りでしか出せないので、ロックに使える分を超えない）。
+    /// 整数単位に切り捨て、i64 に収まらなければ i64 の上限・下限にする
+    fn position(&mut self, user_id: &Uuid, venue: Venue) -> i64 {
+        let held = match venue {
+            Venue::Spot => {
+                let (available, locked) = self.account_manager.get_balance(user_id, "BAD");
+                available + locked
+            }
+            Venue::Margin => self.market.margin.base_position(user_id, now_millis()),
+            Venue::Perp => return self.market.perp.position_size(user_id),
+        };
+        let committed: u64 = self
+            .open_orders
+            .values()
+            .filter(|o| o.user_id == *user_id && o.side == Side::Sell && !self.reduce_only_orders.contains(&o.id))
+            .filter(|o| self.margin_orders.contains(&o.id) == (venue == Venue::Margin))
+            .map(|o| o.quantity - o.filled_quantity)
+            .sum();
+        let position = (held - Decimal::from(committed)).trunc();
+        position.to_i64().unwrap_or(if position.is_sign_negative() { i64::MIN } else { i64::MAX })
+    }
+
+    /// その場の板に残っているユーザーの reduce_only 注文の記録（古い順）
+    fn resting_reduce_only(&self, user_id: &Uuid, venue: Venue) -> Vec<OrderRecord> {
+        let mut orders: Vec<OrderRecord> = self
+            .reduce_only_orders
+            .iter()
+            .filter_map(|id| match venue {
+                Venue::Spot => self.open_orders.get(id).filter(|_| !self.margin_orders.contains(id)),
+                Venue::Margin => self.open_orders.get(id).filter(|_| self.margin_orders.contains(id)),
+                Venue::Perp => self.perp_orders.get(id),
+            })
+            .filter(|o| o.user_id == *user_id)
+            .cloned()
+            .collect();
+        orders.sort_by_key(|o| o.id);
+        orders
+    }
+
+    /// reduce_only・close_position の注文を、いまのポジションで出せるか確かめる
+    ///
+    /// ポジションを減らす向きで、数量が ポジション - 同じ向きの reduce_only 注文の残り 以下なら出せる。
+    /// close_position なら数量をその全部にする。どちらでもない注文・シミュレータの注文は常に出せる
+    fn prepare_reduce_only(&mut self, order: &mut Order, venue: Venue) -> bool {
+        let Some(uid) = order.user_id else {
+            return true;
+        };
+        if !order.reduce_only && !order.close_position {
+            return true;
+        }
+        let position = self.position(&uid, venue);
+        let reduces = match order.side {
+            Side::Buy => position < 0,
+            Side::Sell => position > 0,
+        };
+        if !reduces {
+            return false;
+        }
+        let resting: u64 = self
+            .resting_reduce_only(&uid, venue)
+            .iter()
+            .filter(|o| o.side == order.side)
+            .map(|o| o.quantity - o.filled_quantity)
+            .sum();
+        let available = position.unsigned_abs().saturating_sub(resting);
+        if order.close_position {
+            order.quantity = available;
+        }
+        order.reduce_only = true;
+        order.quantity > 0 && order.quantity <= available
+    }
+
+    /// reduce_only 注文がポジションを反転させないように揃える（約定でポジションが変わった後に呼ぶ）
+    ///
+    /// 古い注文からポジションの分だけ残し、超える分は数量を減らすかキャンセルする。
+    /// ポジションがなくなった・向きが変わった場合はすべてキャンセルする
+    async fn enforce_reduce_only(&mut self, user_id: Uuid, venue: Venue) {
+        let orders = self.resting_reduce_only(&user_id, venue);
+        if orders.is_empty() {
+            return;
+        }
+        let position = self.position(&user_id, venue);
+        let mut budget = position.unsigned_abs();
+        for record in orders {
+            let reduces = match record.side {
+                Side::Buy => position < 0,
+                Side::Sell => position > 0,
+            };
+            let remaining = record.quantity - record.filled_quantity;
+            if reduces && remaining <= budget {
+                budget -= remaining;
+            } else if reduces && budget > 0 {
+                self.trim_order(record.id, user_id, venue, budget).await;
+                budget = 0;
+            } else if venue == Venue::Perp {
+                self.cancel_perp_order(record.id);
+            } else {
+                self.cancel_order(record.id, user_id).await;
+            }
+        }
+    }
+
+    /// 板に残っている注文の残数量を quantity まで減らし、減らした分のロックを解除する
+    async fn trim_order(&mut self, order_id: u64, user_id: Uuid, venue: Venue, quantity: u64) {
+        let book = if venue == Venue::Perp { &mut self.perp_book } else { &mut self.orderbook };
+        let Some(reduced) = book.reduce_order(order_id, quantity).filter(|&reduced| reduced > 0) else {
+            return;
+        };
+        let records = if venue == Venue::Perp { &mut self.perp_orders } else { &mut self.open_orders };
+        let Some(record) = records.get_mut(&order_id) else {
+            return;
+        };
+        // 発注時の数量を減らしたものとして記録する（約定済み + 残り）
+        record.quantity -= reduced;
+        record.updated_at = now_millis();
+        let (side, price) = (record.side, record.price);
+        if venue == Venue::Perp {
+            // 先物の注文はロックも注文記録の保存もない
+            self.feeds.publish_perp_execution(record, None);
+            return;
+        }
+        self.feeds.publish_execution(record, None);
+        let _ = self.db_tx.send(DbMessage::SaveOrder(record.clone())).await;
+
+        if venue == Venue::Margin {
+            self.market.margin.unlock(&user_id, side, price, reduced);
+            self.save_margin_account(user_id).await;
+        } else {
+            self.account_manager.unlock_balance(&user_id, side, price, reduced);
+            let asset = if side == Side::Buy { "USDC" } else { "BAD" };
+            update_balance(&self.db_tx, &self.feeds, &self.account_manager, user_id, asset).await;
+        }
+    }
+
+    /// 証拠金口座への依頼を処理し、処理後の口座の状態を返す
+    async fn handle_margin(&mut self, user_id: Uuid, request: MarginRequest) -> Result<MarginAccountSummary, MarginError> {
+        let now = now_millis();
+        let mark = self.market.margin.mark_price(&self.orderbook);
+        let margin = &mut self.market.margin;
+        match request {
+            MarginRequest::Get => {}
+            MarginRequest::TransferIn { asset, amount } => {
+                MarginManager::check_asset(&asset, amount)?;
+                self.account_manager
+                    .debit_available(&user_id, &asset, amount)
+                    .map_err(|_| MarginError::InsufficientBalance)?;
+                margin.transfer_in(&user_id, &asset, amount, now)?;
+                update_balance(&self.db_tx, &self.feeds, &self.account_manager, user_id, &asset).await;
+                // 振替で現物・証拠金それぞれの BAD のポジションが変わる
+                self.enforce_reduce_only(user_id, Venue::Spot).await;
+                self.enforce_reduce_only(user_id, Venue::Margin).await;
+            }
+            MarginRequest::TransferOut { asset, amount } => {
+                margin.transfer_out(&user_id, &asset, amount, mark, now)?;
+                self.account_manager.credit_available(&user_id, &asset, amount);
+                update_balance(&self.db_tx, &self.feeds, &self.account_manager, user_id, &asset).await;
+                self.enforce_reduce_only(user_id, Venue::Spot).await;
+                self.enforce_reduce_only(user_id, Venue::Margin).await;
+            }
+            MarginRequest::Borrow { asset, amount } => margin.borrow(&user_id, &asset, amount, mark, now)?,
+            MarginRequest::Repay { asset, amount } => margin.repay(&user_id, &asset, amount, now)?,
+        }
+        let summary = self.market.margin.summary(&user_id, mark, now);
+        self.save_margin_account(user_id).await;
+        Ok(summary)
+    }
+
+    /// 証拠金口座をDBへ保存する（口座を変えたら呼ぶ）
+    async fn save_margin_account(&self, user_id: Uuid) {
+        if let Some(record) = self.market.margin.record(&user_id) {
+            let _ = self.db_tx.send(DbMessage::SaveMarginAccount(record)).await;
+        }
+    }
+
+    /// 先物のインデックス価格（現物のマーク価格）とマーク価格
+    fn perp_prices(&self) -> (Option<Decimal>, Option<Decimal>) {
+        let index = self.market.margin.mark_price(&self.orderbook);
+        (index, self.market.perp.mark_price(self.perp_book.mid_price(), index))
+    }
+
+    /// 先物の注文を受け付けてマッチングする
+    ///
+    /// 約定は資産を動かさず、双方のポジションに反映する
+    async fn place_perp_order(&mut self, mut order: Order) -> Result<Vec<Trade>, PerpError> {
+        let now = now_millis();
+        let Some(uid) = order.user_id else {
+            return Err(PerpError::InvalidAmount);
+        };
+        if order.quantity == 0 && !order.close_position {
+            return Err(PerpError::InvalidAmount); // close_position の数量はこの後で決める
+        }
+
+        // 1. 証拠金チェック（reduce_only はポジションを減らすだけなので、向きと数量だけを見る）
+        if !self.prepare_reduce_only(&mut order, Venue::Perp) {
+            return Err(PerpError::WouldIncreasePosition);
+        }
+        if !order.reduce_only {
+            let (mut resting_buy, mut resting_sell) = (0, 0);
+            for record in self.perp_orders.values().filter(|o| o.user_id == uid) {
+                let remaining = record.quantity - record.filled_quantity;
+                match record.side {
+                    Side::Buy => resting_buy += remaining,
+                    Side::Sell => resting_sell += remaining,
+                }
+            }
+            match order.side {
+                Side::Buy => resting_buy += order.quantity,
+                Side::Sell => resting_sell += order.quantity,
+            }
+            let (_, mark) = self.perp_prices();
+            self.market.perp.check_margin(&uid, resting_buy, resting_sell, mark)?;
+        }
+
+        Ok(self.execute_perp(order, now).await)
+    }
+
+    /// 先物の注文をマッチングし、ポジション・注文記録に反映する（チェックは呼び出し側で済ませる）
+    ///
+    /// 約定は perp_trades に保存し、約定レポートは perp_execution_report として流す
+    async fn execute_perp(&mut self, order: Order, now: u128) -> Vec<Trade> {
+        // 1. マッチング実行 → 保存・ポジションに反映
+        let new_trades = self.perp_book.process_order(order.clone());
+        for trade in &new_trades {
+            let _ = self.db_tx.send(DbMessage::SavePerpTrade(trade.clone())).await;
+            let parties = [
+                (trade.taker_user_id, trade.taker_side),
+                (trade.maker_user_id, trade.taker_side.opposite()),
+            ];
+            for (user_id, side) in parties {
+                if let Some(uid) = user_id {
+                    self.market.perp.on_trade_match(&uid, side, trade.price, trade.quantity);
+                }
+            }
+        }
+        for uid in trade_users(&new_trades) {
+            self.save_perp_account(uid).await;
+        }
+
+        // 2. 注文記録を更新して約定レポートを送る（受付 → 約定ごと → 成行の残りの失効 の順）
+        if let Some(uid) = order.user_id {
+            let mut record = OrderRecord::new(&order, uid, now);
+            self.feeds.publish_perp_execution(&record, None);
+            for trade in &new_trades {
+                record.apply_fill(trade.price, trade.quantity, now);
+                self.feeds.publish_perp_execution(&record, Some(trade));
+            }
+            if record.status.is_open() && order.order_type == OrderType::Market {
+                record.close(OrderStatus::Expired, now);
+                self.feeds.publish_perp_execution(&record, None);
+            }
+            if record.status.is_open() {
+                self.perp_orders.insert(record.id, record);
+                if order.reduce_only {
+                    self.reduce_only_orders.insert(order.id);
                 }
+            }
+        }
+        for trade in &new_trades {
+            if let Some(maker) = self.perp_orders.get_mut(&trade.maker_id) {
+                maker.apply_fill(trade.price, trade.quantity, now);
+                self.feeds.publish_perp_execution(maker, Some(trade));
+                if !maker.status.is_open() {
+                    self.perp_orders.remove(&trade.maker_id);
+                    self.reduce_only_orders.remove(&trade.maker_id);
+                }
+            }
+        }
+
+        // 3. ポジションが変わったユーザーの reduce_only 注文を揃える
+        if !self.reduce_only_orders.is_empty() {
+            for uid in trade_users(&new_trades) {
+                self.enforce_reduce_only(uid, Venue::Perp).await;
+            }
+        }
+
+        new_trades
+    }
+
+    /// 先物の注文を板から外す（所有者の確認は呼び出し側で行う）
+    fn cancel_perp_order(&mut self, order_id: u64) -> Option<Order> {
+        let order = self.perp_book.cancel_order(order_id)?;
+        self.reduce_only_orders.remove(&order_id);
+        if let Some(mut record) = self.perp_orders.remove(&order_id) {
+            record.close(OrderStatus::Cancelled, now_millis());
+            self.feeds.publish_perp_execution(&record, None);
+        }
+        Some(order)
+    }
+
+    /// 先物口座への依頼を処理し、処理後の口座の状態を返す
+    async fn handle_perp(&mut self, user_id: Uuid, request: PerpRequest) -> Result<PerpAccountSummary, PerpError> {
+        let (_, mark) = self.perp_prices();
+        match request {
+            PerpRequest::Get => {}
+            PerpRequest::TransferIn { amount } => {
+                if amount <= Decimal::ZERO {
+                    return Err(PerpError::InvalidAmount);
+                }
+                self.account_manager
+                    .debit_available(&user_id, SETTLEMENT_ASSET, amount)
+                    .map_err(|_| PerpError::InsufficientBalance)?;
+                self.market.perp.transfer_in(&user_id, amount)?;
+                update_balance(&self.db_tx, &self.feeds, &self.account_manager, user_id, SETTLEMENT_ASSET).await;
+            }
+            PerpRequest::TransferOut { amount } => {
+                self.market.perp.transfer_out(&user_id, amount, mark)?;
+                self.account_manager.credit_available(&user_id, SETTLEMENT_ASSET, amount);
+                update_balance(&self.db_tx, &self.feeds, &self.account_manager, user_id, SETTLEMENT_ASSET).await;
+            }
+        }
+        self.save_perp_account(user_id).await;
+        Ok(self.market.perp.summary(&user_id, mark))
+    }
+
+    /// 先物口座をDBへ保存する（ウォレット・ポジションを変えたら呼ぶ）
+    async fn save_perp_account(&self, user_id: Uuid) {
+        if let Some(record) = self.market.perp.record(&user_id) {
+            let _ = self.db_tx.send(DbMessage::SavePerpAccount(record)).await;
+        }
+    }
+
+    /// 資金調達料を買い持ちと売り持ちの間で受け渡す（funding_interval ごと）
+    async fn settle_funding(&mut self) {
+        let (index, mark) = self.perp_prices();
+        if let Some(rate) = self.market.perp.settle_funding(mark, index, now_millis()) {
+            eprintln!("Funding: {} rate {} at mark price {:?}", PERP_SYMBOL, rate, mark);
+            for user_id in self.market.perp.position_holders() {
+                self.save_perp_account(user_id).await;
+            }
+        }
+    }
+
+    /// ウォレット + 含み損益 が maintenance_margin を下回った先物のポジションを清算する
+    ///
+    /// 1. そのユーザーの先物の注文をすべてキャンセル
+    /// 2. ポジションを閉じる成行注文を先物の板に流す（相手側の板が空なら、板に注文が来るまで待つ）
+    /// 3. 閉じ切ってウォレットがマイナスなら、貸し倒れにして0に戻す
+    ///
+    /// 板を食い尽くして閉じ切れなかった分は、相手側の板が空のあいだは待ち、板に注文が来たら続きを清算する
+    async fn liquidate_perp_positions(&mut self) {
+        let (_, Some(mark)) = self.perp_prices() else {
+            return;
+        };
+        for user_id in self.market.perp.undercollateralized(mark) {
+            // 清算注文のIDは、相手側の板があって流せるときだけ払い出す
+            let opposite_empty = if self.market.perp.position_size(&user_id) > 0 {
+                self.perp_book.bids.is_empty()
+            } else {
+                self.perp_book.asks.is_empty()
+            };
+            if opposite_empty {
+                continue;
+            }
 
-                trades_history.extend(new_trades.clone());
+            eprintln!("Liquidation: user {} {} at mark price {}", user_id, PERP_SYMBOL, mark);
+            let mut order_ids: Vec<u64> = self.perp_orders.values().filter(|o| o.user_id == user_id).map(|o| o.id).collect();
+            order_ids.sort();
+            for order_id in order_ids {
+                self.cancel_perp_order(order_id);
+            }
+            if let Some(order) = self.market.perp.liquidation_order(&user_id) {
+                self.execute_perp(order, now_millis()).await;
+            }
 
-                // 板情報を全クライアントに配信
-                // エラー（誰も聞いていない場合など）は無視して良い
-                // 板情報を全クライアントに配信
-                // 高速すぎる更新による詰まりを防ぐため、一定間隔でのみ配信する
-                if last_broadcast_time.elapsed() >= broadcast_interval {
-                    // エラー（誰も聞いていない場合など）は無視して良い
-                    let _ = broadcast_tx.send(orderbook.clone());
-                    last_broadcast_time = Instant::now();
+            if let Some(loss) = self.market.perp.write_off(&user_id) {
+                eprintln!("Bad debt: user {} {} {}", user_id, loss, SETTLEMENT_ASSET);
+                self.save_perp_account(user_id).await;
+            }
+        }
+    }
+
+    /// 証拠金率が maintenance_margin を下回った口座を清算する
+    ///
+    /// 1. そのユーザーの証拠金注文をすべてキャンセル
+    /// 2. 借入の分を成行注文で売買（OrderBook::process_order に流す。相手側の板が空なら出さない）
+    /// 3. 口座の資産で返せるだけ返済
+    async fn liquidate_undercollateralized(&mut self) {
+        let Some(mark) = self.market.margin.mark_price(&self.orderbook) else {
+            return;
+        };
+        for user_id in self.market.margin.undercollateralized(mark, now_millis()) {
+            // 相手側の板が空なら清算できないので、板に注文が来るまで待つ
+            if let Some((side, _)) = self.market.margin.liquidation_side(&user_id) {
+                let opposite_empty = match side {
+                    Side::Buy => self.orderbook.asks.is_empty(),
+                    Side::Sell => self.orderbook.bids.is_empty(),
+                };
+                if opposite_empty {
+                    continue;
                 }
+            }
+
+            eprintln!("Liquidation: user {} at mark price {}", user_id, mark);
+            let mut order_ids: Vec<u64> = self
+                .open_orders
+                .values()
+                .filter(|o| o.user_id == user_id && self.margin_orders.contains(&o.id))
+                .map(|o| o.id)
+                .collect();
+            order_ids.sort();
+            for order_id in order_ids {
+                self.cancel_order(order_id, user_id).await;
+            }
+
+            for order in self.market.margin.liquidation_orders(&user_id, &self.orderbook) {
+                self.execute(order, true, now_millis()).await;
+            }
+
+            for (asset, amount) in self.market.margin.settle_loans(&user_id, &self.orderbook) {
+                eprintln!("Bad debt: user {} {} {}", user_id, amount, asset);
+            }
+            self.save_margin_account(user_id).await;
+        }
+    }
+}
+
+/// マッチングエンジンを実行する（Actor Loop）
+pub async fn run_matching_engine(
+    mut rx: mpsc::Receiver<EngineMessage>,
+    db_tx: mpsc::Sender<DbMessage>,
+    account_manager: AccountManager,
+    feeds: MarketFeeds, // 板情報・ローソク足の配信チャンネル
+    market: MarketData, // ローソク足・ティッカー（起動時にDBから復元したもの）
+) {
+    let mut orderbook = OrderBook::new();
+    // 再起動しても約定IDが重複しないように続きから採番する
+    orderbook.next_trade_id = market.last_trade_id + 1;
+    let mut perp_book = OrderBook::new();
+    perp_book.next_trade_id = market.last_perp_trade_id + 1;
+
+    // 起動時に読み込んだ残高を、読み取り用コピーにも載せる
+    feeds.balances.load(&account_manager);
+
+    // 板・ティッカーは間引いて配信する（最後の変化も必ず届ける。publisher.rs 参照）
+    let mut publisher = CoalescingPublisher::new(&feeds.intervals);
+
+    // account_managerなどはmoveされる（所有権がこのタスクに移る）
+    let mut engine = Engine {
+        orderbook,
+        account_manager,
+        market,
+        feeds,
+        db_tx,
+        open_orders: HashMap::new(),
+        margin_orders: HashSet::new(),
+        perp_book,
+        perp_orders: HashMap::new(),
+        reduce_only_orders: HashSet::new(),
+    };
 
-                let _ = respond_to.send(new_trades);
+    // 先物の資金調達は funding_interval ごとに行う（最初の1回は1間隔後）
+    let funding_interval = engine.market.perp.config().funding_interval;
+    engine.market.perp.schedule_funding(now_millis());
+    let mut funding = tokio::time::interval_at(tokio::time::Instant::now() + funding_interval, funding_interval);
+    funding.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        // 前のメッセージで価格が動いていれば、証拠金率が下がった口座・先物のポジションを清算する
+        engine.liquidate_undercollateralized().await;
+        engine.liquidate_perp_positions().await;
+        // 前のメッセージで板が変わっていれば、間隔が空いている分はここで配信する
+        publish_due(&mut publisher, &engine.feeds, &mut engine.orderbook, &mut engine.market);
+        // 先物の板は差分を配信しないので、変化した価格の記録はここで捨てる（溜め続けないように）
+        engine.perp_book.take_delta();
+        let deadline = publisher.next_deadline();
+
+        let msg = tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            // 間隔内に来た変化は、間隔が空いた時点で配信する（trailing flush）
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => continue,
+            _ = funding.tick() => {
+                engine.settle_funding().await;
+                continue;
+            }
+        };
+
+        match msg {
+            EngineMessage::PlaceOrder { order, respond_to } => {
+                let _ = respond_to.send(engine.place_order(order, false).await);
+            },
+            EngineMessage::PlaceMarginOrder { order, respond_to } => {
+                let _ = respond_to.send(engine.place_order(order, true).await);
+            },
+            EngineMessage::Margin { user_id, request, respond_to } => {
+                let _ = respond_to.send(engine.handle_margin(user_id, request).await);
+            },
+            EngineMessage::PlacePerpOrder { order, respond_to } => {
+                let _ = respond_to.send(engine.place_perp_order(order).await);
+            },
+            EngineMessage::Perp { user_id, request, respond_to } => {
+                let _ = respond_to.send(engine.handle_perp(user_id, request).await);
+            },
+            EngineMessage::GetPerpOrderBook { respond_to } => {
+                let _ = respond_to.send(engine.perp_book.snapshot());
+            },
+            EngineMessage::GetPerpMarket { respond_to } => {
+                let (index, mark) = engine.perp_prices();
+                let _ = respond_to.send(engine.market.perp.market(mark, index));
+            },
+            EngineMessage::GetPerpOpenOrders { user_id, respond_to } => {
+                let mut orders: Vec<OrderRecord> = engine
+                    .perp_orders
+                    .values()
+                    .filter(|o| o.user_id == user_id)
+                    .cloned()
+                    .collect();
+                orders.sort_by_key(|o| o.id);
+                let _ = respond_to.send(orders);
             },
 
             EngineMessage::GetOrderBook { respond_to } => {
-                let _ = respond_to.send(orderbook.clone());
+                let _ = respond_to.send(engine.orderbook.snapshot());
+            },
+            EngineMessage::GetDepth { limit, group, respond_to } => {
+                let _ = respond_to.send(engine.orderbook.depth(limit, group));
             },
-            EngineMessage::GetTrades { respond_to } => {
-                let _ = respond_to.send(trades_history.clone());
+            EngineMessage::GetOrderBookL3 { respond_to } => {
+                let _ = respond_to.send(engine.orderbook.l3_snapshot());
+            },
+            EngineMessage::LoadAccount { balances, respond_to } => {
+                for b in &balances {
+                    engine.account_manager.load_balance(b.user_id, &b.asset, b.available, b.locked);
+                    engine.feeds.balances.set(b.user_id, &b.asset, b.available, b.locked);
+                }
+                let _ = respond_to.send(());
+            },
+            EngineMessage::GetBalances { user_id, respond_to } => {
+                let _ = respond_to.send(engine.account_manager.user_balances(&user_id));
+            },
+            EngineMessage::GetTicker { respond_to } => {
+                let _ = respond_to.send(ticker_snapshot(&engine.orderbook, &mut engine.market));
             },
 
             EngineMessage::CancelOrder { order_id, user_id, respond_to } => {
-                // 1. OrderBookからキャンセル試行
-                if let Some(order) = orderbook.cancel_order(order_id) {
-                    // 注文が見つかった
-
-                    // 2. 所有者チェック
-                    // シミュレータの注文などは user_id が None の可能性があるが、
-                    // Web経由のキャンセルは必ず user_id があるはず。
-                    if order.user_id == Some(user_id) {
-                        // 3. ロック解除 (返金)
-                        account_manager.unlock_balance(&user_id, order.side, order.price, order.quantity);
-
-                        // 4. 残高更新をDBへ通知
-                        let (avail, locked) = account_manager.get_balance(&user_id, if order.side == Side::Buy { "USDC" } else { "BAD" });
-                        let _ = db_tx.send(DbMessage::UpdateBalance { 
-                            user_id, 
-                            asset: (if order.side == Side::Buy { "USDC" } else { "BAD" }).to_string(), 
-                            available: avail, 
-                            locked 
-                        }).await;
-
-                        // 成功応答
-                        let _ = respond_to.send(Some(order));
-                        
-                        // 板情報の更新を配信（即時）
-                         // エラー（誰も聞いていない場合など）は無視して良い
-                        let _ = broadcast_tx.send(orderbook.clone());
-                        last_broadcast_time = Instant::now();
-
-                    } else {
-                        // 他人の注文はキャンセルできない（セキュリティ）
-                        // ※本当はここに来る前にAPI層で弾くべきだが、念のため。
-                        // OrderBookから削除してしまったので元に戻すべきだが、
-                        // 簡易実装では無視、または panic する。
-                        // 今回は「見つからなかった」ことにして返す（バグの元だが簡略化）
-                        eprintln!("Security Warning: User {} tried to cancel order {} belonging to {:?}", user_id, order_id, order.user_id);
+                // 所有者チェック（板から外す前に確認する）
+                // シミュレータの注文は記録がないので、誰もキャンセルできない
+                let is_perp = engine.perp_orders.contains_key(&order_id);
+                match engine.open_orders.get(&order_id).or_else(|| engine.perp_orders.get(&order_id)) {
+                    Some(record) if record.user_id == user_id => {}
+                    Some(record) => {
+                        eprintln!("Security Warning: User {} tried to cancel order {} belonging to {}", user_id, order_id, record.user_id);
                         let _ = respond_to.send(None);
+                        continue;
+                    }
+                    None => {
+                        // 注文が見つからない（既に約定済みなど）
+                        let _ = respond_to.send(None);
+                        continue;
                     }
-                } else {
-                    // 注文が見つからない（既に約定済みなど）
-                    let _ = respond_to.send(None);
                 }
+
+                let cancelled = if is_perp {
+                    engine.cancel_perp_order(order_id)
+                } else {
+                    engine.cancel_order(order_id, user_id).await
+                };
+                let _ = respond_to.send(cancelled);
+            }
+            EngineMessage::GetOpenOrders { user_id, respond_to } => {
+                let mut orders: Vec<OrderRecord> = engine
+                    .open_orders
+                    .values()
+                    .filter(|o| o.user_id == user_id)
+                    .cloned()
+                    .collect();
+                orders.sort_by_key(|o| o.id);
+                let _ = respond_to.send(orders);
+            }
+            EngineMessage::GetOrder { order_id, respond_to } => {
+                let _ = respond_to.send(engine.open_orders.get(&order_id).cloned());
             }
-        }
-        
-        if trades_history.len() > 5000 {
-            let tail = trades_history.len() - 2000;
-            trades_history.drain(0..tail);
         }
     }
 }
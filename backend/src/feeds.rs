@@ -0,0 +1,371 @@
+// =============================================================================
+// マーケットデータ配信チャネル
+// =============================================================================
+//
+// エンジンが発行するリアルタイムデータの broadcast チャネルをまとめたものです。
+// エンジンは送信側を、WebSocketハンドラは subscribe() した受信側を使います。
+//
+// 板情報などは接続数に関係なく1回だけJSONにして流し、各WebSocketはそれをそのまま送ります。
+// depth / diff_depth / trades は形式ごとに1回だけエンコードします（encoding.rs 参照）。
+//
+// ユーザー宛てのイベント（約定レポート・残高変化）は、再接続したクライアントが
+// 取りこぼした分を受け取れるよう、ユーザーごとに直近 USER_EVENT_HISTORY 件を保持します。
+// 各イベントの resume_token（"<起動時刻>.<ユーザーごとの連番>"）を購読時に渡すと、
+// その続きから送り直します。
+// =============================================================================
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::ws::Utf8Bytes;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::account::BalanceReplica;
+use crate::encoding::FeedFrame;
+use crate::models::{Candle, OrderRecord, Side, Trade};
+use crate::orderbook::{BookDelta, BookSnapshot, DepthSnapshot, OrderBook};
+use crate::publisher::PublishIntervals;
+use crate::ticker::Ticker;
+
+/// depth チャネルで配信する板の段数
+pub const DEPTH_CHANNEL_LEVELS: usize = 20;
+
+/// ユーザーごとに保持する直近のイベント数（これより古い resume_token からは再開できない）
+pub const USER_EVENT_HISTORY: usize = 1000;
+
+/// 公開の約定（注文IDや所有者は含まない）
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PublicTrade {
+    pub id: u64,
+    #[serde(serialize_with = "crate::encoding::price::serialize")]
+    pub price: Decimal,
+    pub quantity: u64,
+    pub taker_side: Side,
+    pub timestamp: u64, // ミリ秒（MessagePackでも整数になるよう u64 にする）
+}
+
+impl From<&Trade> for PublicTrade {
+    fn from(trade: &Trade) -> Self {
+        Self {
+            id: trade.id,
+            price: trade.price,
+            quantity: trade.quantity,
+            taker_side: trade.taker_side,
+            timestamp: trade.timestamp as u64,
+        }
+    }
+}
+
+/// 約定レポート（注文の状態が変わるたびに1件）
+///
+/// 約定によるものなら、その約定の価格・数量が付く
+#[derive(Debug, Serialize)]
+pub struct ExecutionReport<'a> {
+    pub resume_token: &'a str,
+    #[serde(flatten)]
+    pub order: &'a OrderRecord,
+    pub trade_id: Option<u64>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub last_price: Option<Decimal>,
+    pub last_quantity: Option<u64>,
+}
+
+/// 残高の変化（変化後の値）
+#[derive(Debug, Serialize)]
+pub struct BalanceEvent<'a> {
+    pub resume_token: &'a str,
+    pub asset: &'a str,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub locked: Decimal,
+}
+
+/// WebSocketで送る配信メッセージ（`type` で種類を区別する）
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedMessage<'a> {
+    /// 板全体のスナップショット（diff_depth の最初と resync 時）
+    Snapshot(&'a BookSnapshot),
+    /// 変化した価格帯だけの差分（diff_depth）
+    Delta(&'a BookDelta),
+    /// 上位の板（depth）
+    Depth(&'a DepthSnapshot),
+    /// 約定1件（trades）
+    Trade(&'a PublicTrade),
+    /// 24時間ティッカー（ticker）
+    Ticker(&'a Ticker),
+    /// 形成中のローソク足（candles）
+    Candle(&'a Candle),
+    /// 自分の注文の状態変化（user）
+    ExecutionReport(&'a ExecutionReport<'a>),
+    /// 自分の先物（BAD-PERP）の注文の状態変化（user）
+    PerpExecutionReport(&'a ExecutionReport<'a>),
+    /// 自分の残高の変化（user）
+    Balance(&'a BalanceEvent<'a>),
+}
+
+impl FeedMessage<'_> {
+    /// JSONにシリアライズする（失敗しない型だけなので、失敗したら None）
+    pub fn to_json(&self) -> Option<Utf8Bytes> {
+        serde_json::to_string(self).ok().map(Utf8Bytes::from)
+    }
+}
+
+/// 形式を選べるチャネル（depth / diff_depth / trades）で配信する内容
+#[derive(Debug)]
+pub enum MarketEvent {
+    Delta(BookDelta),
+    Depth(DepthSnapshot),
+    Trade(PublicTrade),
+}
+
+impl MarketEvent {
+    /// 配信メッセージとしての形（`type` 付き）
+    pub fn message(&self) -> FeedMessage<'_> {
+        match self {
+            Self::Delta(delta) => FeedMessage::Delta(delta),
+            Self::Depth(depth) => FeedMessage::Depth(depth),
+            Self::Trade(trade) => FeedMessage::Trade(trade),
+        }
+    }
+}
+
+/// 配信する板の差分
+#[derive(Debug, Clone)]
+pub struct BookUpdate {
+    pub prev_sequence: u64,
+    pub sequence: u64,
+    pub frame: FeedFrame, // {"type":"delta", ...}
+}
+
+/// 約定レポートの元になった約定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fill {
+    pub trade_id: u64,
+    pub price: Decimal,
+    pub quantity: u64,
+}
+
+/// ユーザー宛てのイベントの中身（FIXゲートウェイなど、JSON以外の形で送る側が使う）
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserEvent {
+    /// 注文の状態変化（約定によるものなら fill が付く）
+    Execution { order: OrderRecord, fill: Option<Fill> },
+    /// 先物の注文の状態変化（Execution と同じ形で、現物の注文と区別する）
+    PerpExecution { order: OrderRecord, fill: Option<Fill> },
+    /// 残高の変化（変化後の値）
+    Balance { asset: String, available: Decimal, locked: Decimal },
+}
+
+impl UserEvent {
+    /// resume_token を付けてJSONにする
+    fn to_json(&self, resume_token: &str) -> Option<Utf8Bytes> {
+        match self {
+            Self::Execution { order, fill } => FeedMessage::ExecutionReport(&ExecutionReport {
+                resume_token,
+                order,
+                trade_id: fill.map(|f| f.trade_id),
+                last_price: fill.map(|f| f.price),
+                last_quantity: fill.map(|f| f.quantity),
+            })
+            .to_json(),
+            Self::PerpExecution { order, fill } => FeedMessage::PerpExecutionReport(&ExecutionReport {
+                resume_token,
+                order,
+                trade_id: fill.map(|f| f.trade_id),
+                last_price: fill.map(|f| f.price),
+                last_quantity: fill.map(|f| f.quantity),
+            })
+            .to_json(),
+            Self::Balance { asset, available, locked } => FeedMessage::Balance(&BalanceEvent {
+                resume_token,
+                asset,
+                available: *available,
+                locked: *locked,
+            })
+            .to_json(),
+        }
+    }
+}
+
+/// 特定ユーザー宛ての配信（JSONにシリアライズ済み）
+#[derive(Debug, Clone)]
+pub struct UserUpdate {
+    pub user_id: Uuid,
+    pub seq: u64, // ユーザーごとの連番（1から）
+    pub resume_token: String,
+    pub event: UserEvent,
+    pub json: Utf8Bytes,
+}
+
+/// ユーザー1人分のイベント履歴
+#[derive(Debug, Default)]
+struct UserEventLog {
+    last_seq: u64,
+    events: VecDeque<UserUpdate>, // 古い順、最大 USER_EVENT_HISTORY 件
+}
+
+/// エンジンからの配信チャネル一式
+#[derive(Clone)]
+pub struct MarketFeeds {
+    /// 板情報の差分（価格帯ごとの集計、intervals.book ごとにまとめて配信）
+    pub book: broadcast::Sender<BookUpdate>,
+    /// 個々の注文まで含む板情報の全体（注文IDのみで所有者は含まない。配信タイミングは book と同じ）
+    pub book_l3: broadcast::Sender<Utf8Bytes>,
+    /// 上位 DEPTH_CHANNEL_LEVELS 段の板（配信タイミングは book と同じ）
+    pub depth: broadcast::Sender<FeedFrame>,
+    /// 約定（1件ずつ、発生した時点で送る。板の配信間隔とは無関係）
+    pub trades: broadcast::Sender<FeedFrame>,
+    /// 24時間ティッカー（intervals.ticker ごとにまとめて配信）
+    pub ticker: broadcast::Sender<Utf8Bytes>,
+    /// 形成中のローソク足（約定のたびに全時間足分）
+    pub candles: broadcast::Sender<Candle>,
+    /// ユーザーごとの約定レポート・残高変化（受信側で自分宛てだけを送る）
+    pub user: broadcast::Sender<UserUpdate>,
+    /// 間引いて配信するチャネルの配信間隔（publisher.rs 参照）
+    pub intervals: PublishIntervals,
+    /// エンジンの残高の読み取り用コピー（REST・gRPCの残高照会はここから読む）
+    pub balances: BalanceReplica,
+    /// 再開用に保持しているユーザーごとのイベント
+    user_events: Arc<Mutex<HashMap<Uuid, UserEventLog>>>,
+    /// resume_token の接頭辞（再起動前のトークンを見分けるため、起動時刻にする）
+    epoch: u128,
+}
+
+impl MarketFeeds {
+    /// 各チャネルを指定した容量で作成する
+    ///
+    /// 受信が遅れたクライアントは Lagged エラーで古いメッセージを読み飛ばす
+    pub fn new(capacity: usize) -> Self {
+        let (book, _) = broadcast::channel(capacity);
+        let (book_l3, _) = broadcast::channel(capacity);
+        let (depth, _) = broadcast::channel(capacity);
+        let (trades, _) = broadcast::channel(capacity);
+        let (ticker, _) = broadcast::channel(capacity);
+        let (candles, _) = broadcast::channel(capacity);
+        let (user, _) = broadcast::channel(capacity);
+        let epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        Self {
+            book,
+            book_l3,
+            depth,
+            trades,
+            ticker,
+            candles,
+            user,
+            intervals: PublishIntervals::default(),
+            balances: BalanceReplica::default(),
+            user_events: Arc::default(),
+            epoch,
+        }
+    }
+
+    /// 前回の配信以降の板の変化を配信する
+    ///
+    /// 変化がなければ何も送らない。誰も購読していないチャネルはシリアライズもしない
+    pub fn publish_book(&self, orderbook: &mut OrderBook) {
+        let delta = orderbook.take_delta();
+        if delta.is_empty() {
+            return;
+        }
+        // エラー（誰も聞いていない場合など）は無視して良い
+        if self.book.receiver_count() > 0 {
+            let _ = self.book.send(BookUpdate {
+                prev_sequence: delta.prev_sequence,
+                sequence: delta.sequence,
+                frame: FeedFrame::new(MarketEvent::Delta(delta)),
+            });
+        }
+        if self.depth.receiver_count() > 0 {
+            let depth = orderbook.depth(DEPTH_CHANNEL_LEVELS, None);
+            let _ = self.depth.send(FeedFrame::new(MarketEvent::Depth(depth)));
+        }
+        if self.book_l3.receiver_count() > 0
+            && let Ok(json) = serde_json::to_string(&orderbook.l3_snapshot())
+        {
+            let _ = self.book_l3.send(json.into());
+        }
+    }
+
+    /// 約定を1件配信する
+    pub fn publish_trade(&self, trade: &Trade) {
+        if self.trades.receiver_count() > 0 {
+            let _ = self.trades.send(FeedFrame::new(MarketEvent::Trade(PublicTrade::from(trade))));
+        }
+    }
+
+    /// ティッカーを配信する
+    pub fn publish_ticker(&self, ticker: &Ticker) {
+        if let Some(json) = FeedMessage::Ticker(ticker).to_json() {
+            let _ = self.ticker.send(json);
+        }
+    }
+
+    /// ユーザー宛てのイベントに連番を振り、履歴に残して配信する
+    ///
+    /// 購読者がいなくても、再開用に履歴には残す
+    fn publish_user_event(&self, user_id: Uuid, event: UserEvent) {
+        let mut logs = self.user_events.lock().unwrap();
+        let log = logs.entry(user_id).or_default();
+        let seq = log.last_seq + 1;
+        let resume_token = format!("{}.{}", self.epoch, seq);
+        let Some(json) = event.to_json(&resume_token) else {
+            return;
+        };
+        log.last_seq = seq;
+        if log.events.len() == USER_EVENT_HISTORY {
+            log.events.pop_front();
+        }
+        let update = UserUpdate { user_id, seq, resume_token, event, json };
+        log.events.push_back(update.clone());
+        // 履歴への追加と同じロックの中で送り、再開時の重複を連番で取り除けるようにする
+        let _ = self.user.send(update);
+    }
+
+    /// 注文の状態変化を、その注文のユーザーへ約定レポートとして配信する
+    pub fn publish_execution(&self, record: &OrderRecord, fill: Option<&Trade>) {
+        let fill = fill.map(|t| Fill { trade_id: t.id, price: t.price, quantity: t.quantity });
+        self.publish_user_event(record.user_id, UserEvent::Execution { order: record.clone(), fill });
+    }
+
+    /// 先物の注文の状態変化を、その注文のユーザーへ約定レポートとして配信する
+    pub fn publish_perp_execution(&self, record: &OrderRecord, fill: Option<&Trade>) {
+        let fill = fill.map(|t| Fill { trade_id: t.id, price: t.price, quantity: t.quantity });
+        self.publish_user_event(record.user_id, UserEvent::PerpExecution { order: record.clone(), fill });
+    }
+
+    /// 残高の変化をそのユーザーへ配信する
+    pub fn publish_balance(&self, user_id: Uuid, asset: &str, available: Decimal, locked: Decimal) {
+        let event = UserEvent::Balance { asset: asset.to_string(), available, locked };
+        self.publish_user_event(user_id, event);
+    }
+
+    /// resume_token より後のイベントを古い順に返す
+    ///
+    /// 再起動前のトークンや、履歴から押し出された古いトークンなら None（REST で取り直す必要がある）
+    pub fn user_events_since(&self, user_id: Uuid, resume_token: &str) -> Option<Vec<UserUpdate>> {
+        let (epoch, seq) = resume_token.split_once('.')?;
+        if epoch.parse::<u128>().ok()? != self.epoch {
+            return None;
+        }
+        self.user_events_after(user_id, seq.parse().ok()?)
+    }
+
+    /// 連番 seq より後のイベントを古い順に返す（履歴から押し出されていれば None）
+    pub fn user_events_after(&self, user_id: Uuid, seq: u64) -> Option<Vec<UserUpdate>> {
+        let logs = self.user_events.lock().unwrap();
+        let log = logs.get(&user_id)?;
+        let oldest = log.events.front()?.seq;
+        if seq > log.last_seq || seq + 1 < oldest {
+            return None;
+        }
+        Some(log.events.iter().filter(|e| e.seq > seq).cloned().collect())
+    }
+}
@@ -0,0 +1,283 @@
+// =============================================================================
+// FIX 4.4 メッセージ
+// =============================================================================
+//
+// FIXのメッセージは "タグ=値" を SOH（0x01）で区切って並べたものです。
+//
+//   8=FIX.4.4 | 9=<本文の長さ> | 35=<種類> | ... | 10=<チェックサム>
+//
+// - BodyLength(9) は 35= から 10= の直前までのバイト数
+// - CheckSum(10) は 10= の直前までの全バイトの合計を256で割った余り（3桁）
+//
+// このモジュールはメッセージの組み立て・読み取りだけを扱います。
+// ログオンやシーケンス番号などのセッション処理は fix_gateway.rs にあります。
+// テストやボットのFIXクライアントも同じ型を使えます。
+// =============================================================================
+
+use std::fmt;
+use std::str::FromStr;
+
+/// 対応しているFIXのバージョン（BeginString）
+pub const BEGIN_STRING: &str = "FIX.4.4";
+
+/// フィールドの区切り文字
+pub const SOH: u8 = 0x01;
+
+/// 本文の長さの上限（これより長いメッセージは壊れているとみなす）
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+/// 使用するタグ番号
+pub mod tag {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECKSUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const BUSINESS_REJECT_REASON: u32 = 380;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const USERNAME: u32 = 553;
+    pub const PASSWORD: u32 = 554;
+}
+
+/// 使用するメッセージの種類（MsgType）
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+    pub const BUSINESS_MESSAGE_REJECT: &str = "j";
+
+    /// 再送しないセッション層のメッセージか（ResendRequest には SequenceReset-GapFill で応える）
+    ///
+    /// Reject はセッション層でも再送の対象
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(msg_type, HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | SEQUENCE_RESET | LOGOUT | LOGON)
+    }
+}
+
+/// FIXメッセージ（BeginString・BodyLength・CheckSum を除いたフィールドを順番どおりに持つ）
+///
+/// 先頭は必ず MsgType(35)。BeginString などは encode 時に付ける
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    /// 指定した種類の空のメッセージを作る
+    pub fn new(msg_type: &str) -> Self {
+        Self { fields: vec![(tag::MSG_TYPE, msg_type.to_string())] }
+    }
+
+    /// フィールドを末尾に追加する（組み立て用）
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.push(tag, value);
+        self
+    }
+
+    /// フィールドを末尾に追加する
+    pub fn push(&mut self, tag: u32, value: impl ToString) {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    /// フィールドの値を置き換える（なければ末尾に追加）
+    pub fn set(&mut self, tag: u32, value: impl ToString) {
+        match self.fields.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.push(tag, value),
+        }
+    }
+
+    /// 最初に現れたフィールドの値
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v.as_str())
+    }
+
+    /// フィールドの値を型に変換して取得する（なければ、または解釈できなければ None）
+    pub fn parse<T: FromStr>(&self, tag: u32) -> Option<T> {
+        self.get(tag)?.parse().ok()
+    }
+
+    /// Y/N のフィールドが Y か
+    pub fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    pub fn msg_type(&self) -> &str {
+        &self.fields[0].1
+    }
+
+    /// MsgSeqNum(34)
+    pub fn seq_num(&self) -> Option<u64> {
+        self.parse(tag::MSG_SEQ_NUM)
+    }
+
+    /// MsgType(35) 以降のフィールド
+    pub fn fields(&self) -> &[(u32, String)] {
+        &self.fields
+    }
+
+    /// 送信用のバイト列にする（BeginString・BodyLength・CheckSum を付ける）
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        }
+        let mut out = format!("8={}\x019={}\x01", BEGIN_STRING, body.len()).into_bytes();
+        out.extend_from_slice(&body);
+        let checksum = checksum(&out);
+        out.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        out
+    }
+
+    /// バッファの先頭から1件読む
+    ///
+    /// まだ1件分届いていなければ Ok(None)。読めたら (メッセージ, 使ったバイト数) を返す
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, DecodeError> {
+        let prefix = format!("8={}\x019=", BEGIN_STRING);
+        let prefix = prefix.as_bytes();
+        let head = &buf[..buf.len().min(prefix.len())];
+        if head != &prefix[..head.len()] {
+            return Err(DecodeError::fatal("BeginString が FIX.4.4 ではありません"));
+        }
+        if buf.len() < prefix.len() {
+            return Ok(None);
+        }
+
+        // BodyLength
+        let rest = &buf[prefix.len()..];
+        let Some(end) = rest.iter().position(|&b| b == SOH) else {
+            if rest.len() > 6 {
+                return Err(DecodeError::fatal("BodyLength が不正です"));
+            }
+            return Ok(None);
+        };
+        let body_length: usize = std::str::from_utf8(&rest[..end])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&len| len <= MAX_BODY_LENGTH)
+            .ok_or_else(|| DecodeError::fatal("BodyLength が不正です"))?;
+
+        // 本文と "10=nnn<SOH>"（7バイト）
+        let body_start = prefix.len() + end + 1;
+        let total = body_start + body_length + 7;
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let trailer = &buf[body_start + body_length..total];
+        if !trailer.starts_with(b"10=") || trailer[6] != SOH {
+            return Err(DecodeError::fatal("BodyLength と本文の長さが一致しません"));
+        }
+        let expected = checksum(&buf[..body_start + body_length]);
+        let actual: Option<u8> = std::str::from_utf8(&trailer[3..6]).ok().and_then(|s| s.parse().ok());
+        if actual != Some(expected) {
+            // 区切りは分かるので、このメッセージだけ捨てて続きを読める
+            return Err(DecodeError::skip(total, "CheckSum が一致しません"));
+        }
+
+        let body = &buf[body_start..body_start + body_length];
+        let mut fields = Vec::new();
+        for field in body.split(|&b| b == SOH) {
+            if field.is_empty() {
+                continue;
+            }
+            let parsed = std::str::from_utf8(field).ok().and_then(|f| {
+                let (tag, value) = f.split_once('=')?;
+                Some((tag.parse::<u32>().ok()?, value.to_string()))
+            });
+            match parsed {
+                Some(field) => fields.push(field),
+                None => return Err(DecodeError::skip(total, "タグ=値 の形式ではないフィールドがあります")),
+            }
+        }
+        if fields.first().map(|(t, _)| *t) != Some(tag::MSG_TYPE) {
+            return Err(DecodeError::skip(total, "MsgType が先頭にありません"));
+        }
+        Ok(Some((Self { fields }, total)))
+    }
+}
+
+/// CheckSum(10) の値
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// 受信したバイト列をメッセージとして読めない
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    /// 壊れたメッセージの長さ（捨てて続きを読める場合）。None なら区切りが分からないので切断する
+    pub skip: Option<usize>,
+    pub reason: &'static str,
+}
+
+impl DecodeError {
+    fn fatal(reason: &'static str) -> Self {
+        Self { skip: None, reason }
+    }
+
+    fn skip(len: usize, reason: &'static str) -> Self {
+        Self { skip: Some(len), reason }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reason)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// UTCTimestamp 形式（YYYYMMDD-HH:MM:SS.sss）の時刻
+pub fn timestamp(millis: u128) -> String {
+    chrono::DateTime::from_timestamp_millis(millis as i64)
+        .unwrap_or_default()
+        .format("%Y%m%d-%H:%M:%S%.3f")
+        .to_string()
+}
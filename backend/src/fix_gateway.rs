@@ -0,0 +1,964 @@
+// =============================================================================
+// FIX 4.4 注文ゲートウェイ
+// =============================================================================
+//
+// REST・WebSocketとは別のTCPポートでFIX 4.4のセッションを受け付けます（アクセプター）。
+//
+// 【セッション】
+// - 最初のメッセージは Logon。Username(553) にAPIキー、Password(554) にそのsecretを入れる
+//   （trade 権限と接続元IPの許可が必要）。TargetCompID(56) は FixConfig::comp_id
+// - セッションは "FIX.4.4:<SenderCompID>-><TargetCompID>" で区別し、同時には1接続だけ
+// - MsgSeqNum は送受信のたびに保存し、再接続しても続きから使う
+//   （ResetSeqNumFlag=Y のLogonで1に戻す）
+// - 届いた MsgSeqNum が飛んでいれば ResendRequest を送り、埋まるまで後続は処理しない
+// - ResendRequest には、保存してあるアプリケーションメッセージを PossDupFlag=Y で送り直し、
+//   Heartbeat などセッション層のメッセージは SequenceReset-GapFill で埋める
+// - HeartBtInt ごとに何も送っていなければ Heartbeat を送り、相手から何も届かなければ
+//   TestRequest を送る。それにも応答がなければ切断する
+//
+// 【注文】
+// | 受信                            | エンジンへの依頼            |
+// |---------------------------------|-----------------------------|
+// | NewOrderSingle (D)              | PlaceOrder                  |
+// | OrderCancelRequest (F)          | CancelOrder                 |
+// | OrderCancelReplaceRequest (G)   | CancelOrder → PlaceOrder    |
+//
+// 結果はエンジンが user チャネルに流す約定レポートを ExecutionReport (8) にして返します。
+// 受け付けられなかった取消・訂正には OrderCancelReject (9) を返します。
+// 新規注文・取消・訂正は REST と同じレート制限（ユーザーと接続元IPのバケット、発注・取消の重み）を
+// エンジンに渡す前に消費し、超えたら拒否の ExecutionReport / OrderCancelReject を返します
+// （訂正は取消と発注の重みの合計）。
+// エンジンには注文の訂正がないので、訂正は「取消して新しい注文IDで出し直す」で実現します
+// （時間優先は失われ、CumQty は引き継がない）。
+//
+// Text(58) はASCIIしか送れないので、FIXで返すメッセージは英語にしています。
+// =============================================================================
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, oneshot};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::auth::now_millis;
+use crate::engine::EngineMessage;
+use crate::feeds::{UserEvent, UserUpdate};
+use crate::fix::{self, msg_type, tag, DecodeError, FixMessage, BEGIN_STRING};
+use crate::models::{ApiScope, FixSequence, Order, OrderRecord, OrderStatus, OrderType, Side, UnrepresentableOrder};
+use crate::ratelimit::RateKey;
+
+/// FIXで扱う銘柄（Symbol）
+pub const SYMBOL: &str = "BAD/USDC";
+
+/// 受け付ける HeartBtInt の上限（秒）
+const MAX_HEARTBEAT_SECS: u64 = 300;
+
+/// FIXゲートウェイの設定
+#[derive(Debug, Clone)]
+pub struct FixConfig {
+    pub addr: Option<String>,     // 待ち受けるアドレス（None なら起動しない）
+    pub comp_id: String,          // このサーバーの CompID（相手の TargetCompID）
+    pub logon_timeout: Duration,  // 接続してから Logon が届くまでの猶予
+}
+
+impl Default for FixConfig {
+    fn default() -> Self {
+        Self {
+            addr: Some("0.0.0.0:9878".to_string()),
+            comp_id: "BADBIT".to_string(),
+            logon_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// FIXの接続を受け付け続ける
+pub async fn run_fix_acceptor(listener: TcpListener, state: Arc<AppState>, config: FixConfig) {
+    let acceptor = Arc::new(Acceptor { state, config, active: Mutex::new(HashSet::new()) });
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move { acceptor.handle_connection(stream, peer).await });
+            }
+            Err(e) => eprintln!("FIX accept error: {}", e),
+        }
+    }
+}
+
+/// 全接続で共有する状態
+struct Acceptor {
+    state: Arc<AppState>,
+    config: FixConfig,
+    active: Mutex<HashSet<String>>, // ログオン中のセッションID
+}
+
+/// ログオン中のセッションIDを、切断時に外す
+struct ActiveGuard {
+    acceptor: Arc<Acceptor>,
+    session_id: String,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.acceptor.active.lock().unwrap().remove(&self.session_id);
+    }
+}
+
+/// 受信側（届いたバイト列をメッセージに区切る）
+struct Reader {
+    stream: OwnedReadHalf,
+    buf: Vec<u8>,
+}
+
+impl Reader {
+    /// 次のメッセージを読む（切断された、または区切りが分からなくなったら None）
+    ///
+    /// 待っている間に取り消されても、読んだ分はバッファに残る
+    async fn read(&mut self) -> Option<FixMessage> {
+        loop {
+            match FixMessage::decode(&self.buf) {
+                Ok(Some((msg, len))) => {
+                    self.buf.drain(..len);
+                    return Some(msg);
+                }
+                Ok(None) => {}
+                // 壊れたメッセージは処理せずに捨てる（MsgSeqNum も進めない）
+                Err(DecodeError { skip: Some(len), reason }) => {
+                    eprintln!("FIX garbled message ignored: {}", reason);
+                    self.buf.drain(..len);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("FIX stream error: {}", e);
+                    return None;
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            match self.stream.read(&mut chunk).await {
+                Ok(0) | Err(_) => return None,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+}
+
+impl Acceptor {
+    /// 1接続を処理する（Logon → セッション）
+    async fn handle_connection(self: Arc<Self>, stream: TcpStream, peer: SocketAddr) {
+        let (read_half, mut writer) = stream.into_split();
+        let mut reader = Reader { stream: read_half, buf: Vec::new() };
+
+        // 最初のメッセージが Logon でなければ、何も返さずに切断する
+        let logon = match tokio::time::timeout(self.config.logon_timeout, reader.read()).await {
+            Ok(Some(msg)) if msg.msg_type() == msg_type::LOGON => msg,
+            _ => return,
+        };
+
+        let (session_id, user_id, guard) = match self.authenticate(&logon, peer).await {
+            Ok(accepted) => accepted,
+            Err(text) => {
+                // 認証前なのでシーケンス番号は保存しない
+                let logout = FixMessage::new(msg_type::LOGOUT)
+                    .with(tag::SENDER_COMP_ID, &self.config.comp_id)
+                    .with(tag::TARGET_COMP_ID, logon.get(tag::SENDER_COMP_ID).unwrap_or_default())
+                    .with(tag::MSG_SEQ_NUM, 1)
+                    .with(tag::SENDING_TIME, fix::timestamp(now_millis()))
+                    .with(tag::TEXT, text);
+                let _ = writer.write_all(&logout.encode()).await;
+                return;
+            }
+        };
+
+        let Some(mut session) = Session::start(self.clone(), session_id, user_id, peer.ip(), &logon, writer).await else {
+            return;
+        };
+        session.run(reader).await;
+        drop(guard);
+    }
+
+    /// Logon のCompID・APIキーを確かめ、(セッションID, ユーザー, ログオン中の印) を返す
+    async fn authenticate(self: &Arc<Self>, logon: &FixMessage, peer: SocketAddr) -> Result<(String, Uuid, ActiveGuard), String> {
+        let sender = logon.get(tag::SENDER_COMP_ID).filter(|s| !s.is_empty()).ok_or("SenderCompID is required")?;
+        if logon.get(tag::TARGET_COMP_ID) != Some(self.config.comp_id.as_str()) {
+            return Err(format!("TargetCompID must be {}", self.config.comp_id));
+        }
+        let (Some(key), Some(secret)) = (logon.get(tag::USERNAME), logon.get(tag::PASSWORD)) else {
+            return Err("Username (API key) and Password (secret) are required".to_string());
+        };
+
+        let api_key = self
+            .state
+            .storage
+            .get_api_key(key)
+            .await
+            .map_err(|_| "internal error".to_string())?
+            .filter(|k| k.revoked_at.is_none())
+            // 比較にかかる時間から secret を推測されないよう、ハッシュ同士を比べる
+            .filter(|k| Sha256::digest(k.secret.as_bytes()) == Sha256::digest(secret.as_bytes()))
+            .ok_or("invalid API key or secret")?;
+        if !api_key.has_scope(ApiScope::Trade) {
+            return Err("API key does not have trade scope".to_string());
+        }
+        if !api_key.allows_ip(Some(peer.ip())) {
+            return Err("IP address is not allowed for this API key".to_string());
+        }
+
+        let session_id = format!("{}:{}->{}", BEGIN_STRING, sender, self.config.comp_id);
+        if !self.active.lock().unwrap().insert(session_id.clone()) {
+            return Err("session is already logged on".to_string());
+        }
+        let guard = ActiveGuard { acceptor: self.clone(), session_id: session_id.clone() };
+        Ok((session_id, api_key.user_id, guard))
+    }
+}
+
+/// この接続から出した注文の ClOrdID
+#[derive(Debug, Clone, Default)]
+struct OrderIds {
+    cl_ord_id: String,
+    orig_cl_ord_id: Option<String>, // 取消・訂正を受け付けた場合の元の ClOrdID
+    replaced: bool,                 // 訂正で取り消した元の注文（Cancelled は送らない）
+    replacement: bool,              // 訂正で出し直した注文（最初の New を Replaced として送る）
+}
+
+/// ログオン済みの1セッション
+struct Session {
+    acceptor: Arc<Acceptor>,
+    id: String,
+    counterparty: String, // 相手の CompID
+    user_id: Uuid,
+    ip: IpAddr,           // 接続元IP（レート制限用）
+    seq: FixSequence,
+    heartbeat: Duration,
+    writer: OwnedWriteHalf,
+    user_rx: broadcast::Receiver<UserUpdate>,
+    last_event_seq: Option<u64>,     // 最後に処理した user イベントの連番
+    last_sent: Instant,
+    last_received: Instant,
+    test_request: Option<Instant>,   // 応答待ちの TestRequest を送った時刻
+    awaiting_resend: Option<u64>,    // ResendRequest を送り、この MsgSeqNum まで埋まるのを待っている
+    orders: HashMap<u64, OrderIds>,  // 注文ID -> ClOrdID（終わった注文は外す）
+    cl_ord_ids: HashMap<String, u64>, // ClOrdID -> 注文ID（重複の確認・取消対象の特定用）
+    closed: bool,
+}
+
+impl Session {
+    /// Logon に応答してセッションを始める（応答できなければ None）
+    async fn start(
+        acceptor: Arc<Acceptor>,
+        id: String,
+        user_id: Uuid,
+        ip: IpAddr,
+        logon: &FixMessage,
+        writer: OwnedWriteHalf,
+    ) -> Option<Self> {
+        let storage = acceptor.state.storage.clone();
+        let reset = logon.flag(tag::RESET_SEQ_NUM_FLAG);
+        let seq = if reset {
+            storage.reset_fix_session(&id).await.ok()?;
+            FixSequence::default()
+        } else {
+            storage.get_fix_sequence(&id).await.ok()?.unwrap_or_default()
+        };
+        let heartbeat_secs = logon.parse::<u64>(tag::HEART_BT_INT).filter(|s| (1..=MAX_HEARTBEAT_SECS).contains(s));
+
+        // 約定レポートを取りこぼさないよう、注文を受け付ける前に購読しておく
+        let user_rx = acceptor.state.feeds.user.subscribe();
+        let now = Instant::now();
+        let mut session = Self {
+            counterparty: logon.get(tag::SENDER_COMP_ID).unwrap_or_default().to_string(),
+            acceptor,
+            id,
+            user_id,
+            ip,
+            seq,
+            heartbeat: Duration::from_secs(heartbeat_secs.unwrap_or(30)),
+            writer,
+            user_rx,
+            last_event_seq: None,
+            last_sent: now,
+            last_received: now,
+            test_request: None,
+            awaiting_resend: None,
+            orders: HashMap::new(),
+            cl_ord_ids: HashMap::new(),
+            closed: false,
+        };
+
+        let Some(seq_num) = logon.seq_num() else {
+            let _ = session.logout("MsgSeqNum is required").await;
+            return None;
+        };
+        if heartbeat_secs.is_none() {
+            let _ = session.logout(&format!("HeartBtInt must be 1..={}", MAX_HEARTBEAT_SECS)).await;
+            return None;
+        }
+        if seq_num < session.seq.next_incoming {
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", session.seq.next_incoming, seq_num);
+            let _ = session.logout(&text).await;
+            return None;
+        }
+
+        let mut reply = FixMessage::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, session.heartbeat.as_secs());
+        if reset {
+            reply.push(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        session.send(reply).await.ok()?;
+
+        if seq_num == session.seq.next_incoming {
+            session.seq.next_incoming += 1;
+            session.save_sequence().await.ok()?;
+        } else {
+            session.request_resend(seq_num).await.ok()?;
+        }
+        println!("FIX session logged on: {}", session.id);
+        Some(session)
+    }
+
+    /// 切断されるまで受信・約定レポート・ハートビートを処理する
+    async fn run(&mut self, mut reader: Reader) {
+        while !self.closed {
+            let timer = self.next_timer();
+            let result = tokio::select! {
+                msg = reader.read() => match msg {
+                    Some(msg) => {
+                        self.last_received = Instant::now();
+                        self.test_request = None;
+                        self.handle(msg).await
+                    }
+                    None => break,
+                },
+                event = self.user_rx.recv() => match event {
+                    Ok(update) => self.on_user_update(update).await,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        // 約定レポートは読み飛ばせないので、履歴から送り直す
+                        eprintln!("FIX session {} lagged by {}, replaying...", self.id, count);
+                        self.replay_user_events().await
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = tokio::time::sleep_until(timer) => self.on_timer().await,
+            };
+            if let Err(e) = result {
+                eprintln!("FIX session {} error: {}", self.id, e);
+                break;
+            }
+        }
+        println!("FIX session disconnected: {}", self.id);
+    }
+
+    // -------------------------------------------------------------------------
+    // 送信
+    // -------------------------------------------------------------------------
+
+    /// ヘッダーを付けたメッセージを作る
+    fn with_header(&self, body: &FixMessage, seq_num: u64) -> FixMessage {
+        let mut msg = FixMessage::new(body.msg_type())
+            .with(tag::SENDER_COMP_ID, &self.acceptor.config.comp_id)
+            .with(tag::TARGET_COMP_ID, &self.counterparty)
+            .with(tag::MSG_SEQ_NUM, seq_num)
+            .with(tag::SENDING_TIME, fix::timestamp(now_millis()));
+        for (tag, value) in &body.fields()[1..] {
+            msg.push(*tag, value);
+        }
+        msg
+    }
+
+    /// 次の MsgSeqNum を振って送る
+    ///
+    /// 再送できるよう、アプリケーションメッセージは送る前に保存する
+    async fn send(&mut self, body: FixMessage) -> io::Result<()> {
+        let seq_num = self.seq.next_outgoing;
+        let bytes = self.with_header(&body, seq_num).encode();
+        if !msg_type::is_admin(body.msg_type()) {
+            self.acceptor.state.storage.save_fix_message(&self.id, seq_num, &bytes).await.map_err(io::Error::other)?;
+        }
+        self.seq.next_outgoing += 1;
+        self.save_sequence().await?;
+        self.write(&bytes).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes).await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    async fn save_sequence(&self) -> io::Result<()> {
+        self.acceptor.state.storage.save_fix_sequence(&self.id, &self.seq).await.map_err(io::Error::other)
+    }
+
+    /// Logout を送って切断する
+    async fn logout(&mut self, text: &str) -> io::Result<()> {
+        self.closed = true;
+        let mut msg = FixMessage::new(msg_type::LOGOUT);
+        if !text.is_empty() {
+            msg.push(tag::TEXT, text);
+        }
+        self.send(msg).await
+    }
+
+    /// セッション層の Reject を送る
+    async fn reject(&mut self, msg: &FixMessage, reason: u32, ref_tag: Option<u32>, text: &str) -> io::Result<()> {
+        let mut reject = FixMessage::new(msg_type::REJECT).with(tag::REF_SEQ_NUM, msg.seq_num().unwrap_or(0));
+        if let Some(ref_tag) = ref_tag {
+            reject.push(tag::REF_TAG_ID, ref_tag);
+        }
+        reject.push(tag::REF_MSG_TYPE, msg.msg_type());
+        reject.push(tag::SESSION_REJECT_REASON, reason);
+        reject.push(tag::TEXT, text);
+        self.send(reject).await
+    }
+
+    /// 相手に seq_num より前の再送を求める（既に求めていれば何もしない）
+    async fn request_resend(&mut self, seq_num: u64) -> io::Result<()> {
+        if self.awaiting_resend.is_some_and(|until| until >= seq_num) {
+            return Ok(());
+        }
+        let begin = self.seq.next_incoming;
+        self.awaiting_resend = Some(seq_num);
+        let request = FixMessage::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, begin).with(tag::END_SEQ_NO, 0);
+        self.send(request).await
+    }
+
+    // -------------------------------------------------------------------------
+    // ハートビート
+    // -------------------------------------------------------------------------
+
+    /// 次にハートビートを確かめる時刻
+    ///
+    /// 受信は HeartBtInt の2割まで遅れを許してから TestRequest を送る
+    fn next_timer(&self) -> Instant {
+        let heartbeat_due = self.last_sent + self.heartbeat;
+        let receive_due = match self.test_request {
+            Some(sent) => sent + self.heartbeat,
+            None => self.last_received + self.heartbeat + self.heartbeat / 5,
+        };
+        heartbeat_due.min(receive_due)
+    }
+
+    async fn on_timer(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        match self.test_request {
+            Some(sent) if now >= sent + self.heartbeat => {
+                return self.logout("heartbeat timeout").await;
+            }
+            None if now >= self.last_received + self.heartbeat + self.heartbeat / 5 => {
+                self.test_request = Some(now);
+                let id = format!("TEST-{}", now_millis());
+                self.send(FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, id)).await?;
+            }
+            _ => {}
+        }
+        if now >= self.last_sent + self.heartbeat {
+            self.send(FixMessage::new(msg_type::HEARTBEAT)).await?;
+        }
+        Ok(())
+    }
+
+    // -------------------------------------------------------------------------
+    // 受信
+    // -------------------------------------------------------------------------
+
+    /// 届いたメッセージのヘッダーと MsgSeqNum を確かめ、種類ごとに処理する
+    async fn handle(&mut self, msg: FixMessage) -> io::Result<()> {
+        if msg.get(tag::SENDER_COMP_ID) != Some(self.counterparty.as_str())
+            || msg.get(tag::TARGET_COMP_ID) != Some(self.acceptor.config.comp_id.as_str())
+        {
+            self.reject(&msg, 9, None, "CompID problem").await?;
+            return self.logout("incorrect CompID").await;
+        }
+        let Some(seq_num) = msg.seq_num() else {
+            return self.logout("MsgSeqNum is required").await;
+        };
+
+        // SequenceReset-Reset は MsgSeqNum に関係なく受け付ける
+        if msg.msg_type() == msg_type::SEQUENCE_RESET && !msg.flag(tag::GAP_FILL_FLAG) {
+            return self.sequence_reset(&msg).await;
+        }
+        let expected = self.seq.next_incoming;
+        if seq_num > expected {
+            // 相手も再送を待っている場合に止まらないよう、ResendRequest には先に応える
+            if msg.msg_type() == msg_type::RESEND_REQUEST {
+                self.resend(&msg).await?;
+            }
+            return self.request_resend(seq_num).await;
+        }
+        if seq_num < expected {
+            if msg.flag(tag::POSS_DUP_FLAG) {
+                return Ok(()); // 処理済みのメッセージの再送
+            }
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", expected, seq_num);
+            return self.logout(&text).await;
+        }
+
+        if msg.msg_type() == msg_type::SEQUENCE_RESET {
+            return self.sequence_reset(&msg).await;
+        }
+        self.seq.next_incoming += 1;
+        if self.awaiting_resend.is_some_and(|until| until < self.seq.next_incoming) {
+            self.awaiting_resend = None;
+        }
+        self.save_sequence().await?;
+
+        match msg.msg_type() {
+            msg_type::HEARTBEAT | msg_type::REJECT => Ok(()),
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(msg_type::HEARTBEAT);
+                if let Some(id) = msg.get(tag::TEST_REQ_ID) {
+                    heartbeat.push(tag::TEST_REQ_ID, id);
+                }
+                self.send(heartbeat).await
+            }
+            msg_type::RESEND_REQUEST => self.resend(&msg).await,
+            msg_type::LOGOUT => self.logout("").await,
+            msg_type::LOGON => self.reject(&msg, 5, Some(tag::MSG_TYPE), "already logged on").await,
+            msg_type::NEW_ORDER_SINGLE => self.new_order(&msg).await,
+            msg_type::ORDER_CANCEL_REQUEST => self.cancel(&msg).await,
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.replace(&msg).await,
+            other => {
+                let reject = FixMessage::new(msg_type::BUSINESS_MESSAGE_REJECT)
+                    .with(tag::REF_SEQ_NUM, seq_num)
+                    .with(tag::REF_MSG_TYPE, other)
+                    .with(tag::BUSINESS_REJECT_REASON, 3) // Unsupported Message Type
+                    .with(tag::TEXT, "unsupported message type");
+                self.send(reject).await
+            }
+        }
+    }
+
+    /// SequenceReset（GapFill なら MsgSeqNum が期待どおりの場合だけ届く）
+    async fn sequence_reset(&mut self, msg: &FixMessage) -> io::Result<()> {
+        let Some(new_seq_no) = msg.parse::<u64>(tag::NEW_SEQ_NO) else {
+            return self.reject(msg, 1, Some(tag::NEW_SEQ_NO), "NewSeqNo is required").await;
+        };
+        if new_seq_no < self.seq.next_incoming {
+            return self.reject(msg, 5, Some(tag::NEW_SEQ_NO), "NewSeqNo must not decrease").await;
+        }
+        self.seq.next_incoming = new_seq_no;
+        if self.awaiting_resend.is_some_and(|until| until < new_seq_no) {
+            self.awaiting_resend = None;
+        }
+        self.save_sequence().await
+    }
+
+    /// 相手の ResendRequest に応える
+    async fn resend(&mut self, msg: &FixMessage) -> io::Result<()> {
+        let (Some(begin), Some(end)) = (msg.parse::<u64>(tag::BEGIN_SEQ_NO), msg.parse::<u64>(tag::END_SEQ_NO)) else {
+            return self.reject(msg, 1, Some(tag::BEGIN_SEQ_NO), "BeginSeqNo and EndSeqNo are required").await;
+        };
+        let last = self.seq.next_outgoing - 1;
+        let end = if end == 0 || end > last { last } else { end };
+        if begin == 0 || begin > end {
+            return Ok(());
+        }
+
+        let stored = self
+            .acceptor
+            .state
+            .storage
+            .get_fix_messages(&self.id, begin, end)
+            .await
+            .map_err(io::Error::other)?;
+        let mut next = begin;
+        for (seq_num, bytes) in stored {
+            let Ok(Some((original, _))) = FixMessage::decode(&bytes) else {
+                continue; // 壊れていれば GapFill で埋まる
+            };
+            if seq_num > next {
+                self.gap_fill(next, seq_num).await?;
+            }
+            // ヘッダーは付け直し、元の SendingTime は OrigSendingTime に移す
+            let mut body = FixMessage::new(original.msg_type());
+            for (tag, value) in &original.fields()[1..] {
+                if !matches!(*tag, tag::SENDER_COMP_ID | tag::TARGET_COMP_ID | tag::MSG_SEQ_NUM | tag::SENDING_TIME) {
+                    body.push(*tag, value);
+                }
+            }
+            let mut resent = self.with_header(&body, seq_num);
+            resent.push(tag::POSS_DUP_FLAG, "Y");
+            if let Some(sent_at) = original.get(tag::SENDING_TIME) {
+                resent.push(tag::ORIG_SENDING_TIME, sent_at);
+            }
+            self.write(&resent.encode()).await?;
+            next = seq_num + 1;
+        }
+        if next <= end {
+            self.gap_fill(next, end + 1).await?;
+        }
+        Ok(())
+    }
+
+    /// seq_num から new_seq_no の手前までを SequenceReset-GapFill で埋める
+    async fn gap_fill(&mut self, seq_num: u64, new_seq_no: u64) -> io::Result<()> {
+        let mut msg = self.with_header(&FixMessage::new(msg_type::SEQUENCE_RESET), seq_num);
+        msg.push(tag::POSS_DUP_FLAG, "Y");
+        msg.push(tag::GAP_FILL_FLAG, "Y");
+        msg.push(tag::NEW_SEQ_NO, new_seq_no);
+        self.write(&msg.encode()).await
+    }
+
+    // -------------------------------------------------------------------------
+    // 注文
+    // -------------------------------------------------------------------------
+
+    /// NewOrderSingle
+    async fn new_order(&mut self, msg: &FixMessage) -> io::Result<()> {
+        if let Some(missing) = missing_tag(msg, &[tag::CL_ORD_ID, tag::SYMBOL, tag::SIDE, tag::ORDER_QTY, tag::ORD_TYPE]) {
+            return self.reject(msg, 1, Some(missing), "required tag missing").await;
+        }
+        let order = match self.parse_order(msg) {
+            Ok(order) => order,
+            Err((reason, ref_tag, text)) => return self.reject(msg, reason, Some(ref_tag), text).await,
+        };
+        let cl_ord_id = msg.get(tag::CL_ORD_ID).unwrap_or_default().to_string();
+        if msg.get(tag::SYMBOL) != Some(SYMBOL) {
+            return self.send(rejected_order(msg, 1, "unknown symbol")).await;
+        }
+        if self.cl_ord_ids.contains_key(&cl_ord_id) {
+            return self.send(rejected_order(msg, 6, "duplicate ClOrdID")).await;
+        }
+        if !self.charge_rate_limit(self.acceptor.state.rate_limiter.config.order_weight) {
+            return self.send(rejected_order(msg, 99, "rate limit exceeded")).await;
+        }
+
+        let order_id = self.acceptor.state.order_ids.next_id();
+        self.cl_ord_ids.insert(cl_ord_id.clone(), order_id);
+        self.orders.insert(order_id, OrderIds { cl_ord_id, ..Default::default() });
+        self.place(Order { id: order_id, ..order }).await
+    }
+
+    /// OrderCancelRequest
+    async fn cancel(&mut self, msg: &FixMessage) -> io::Result<()> {
+        if let Some(missing) = missing_tag(msg, &[tag::CL_ORD_ID, tag::SYMBOL, tag::SIDE]) {
+            return self.reject(msg, 1, Some(missing), "required tag missing").await;
+        }
+        let cl_ord_id = msg.get(tag::CL_ORD_ID).unwrap_or_default().to_string();
+        if self.cl_ord_ids.contains_key(&cl_ord_id) {
+            return self.send(cancel_rejected(msg, 1, None, 6, "duplicate ClOrdID")).await;
+        }
+        if !self.charge_rate_limit(self.acceptor.state.rate_limiter.config.cancel_weight) {
+            return self.send(cancel_rejected(msg, 1, None, 99, "rate limit exceeded")).await;
+        }
+        let Some(record) = self.find_order(msg).await? else {
+            return self.send(cancel_rejected(msg, 1, None, 1, "unknown order")).await;
+        };
+        if !record.status.is_open() || !self.cancel_in_engine(record.id).await {
+            return self.send(cancel_rejected(msg, 1, Some(&record), 0, "too late to cancel")).await;
+        }
+        self.accept_cancel(record.id, cl_ord_id);
+        Ok(())
+    }
+
+    /// OrderCancelReplaceRequest（取消して出し直す）
+    ///
+    /// 出し直した注文が残高不足で拒否された場合、元の注文は取り消されたままになる
+    async fn replace(&mut self, msg: &FixMessage) -> io::Result<()> {
+        let required = [tag::CL_ORD_ID, tag::SYMBOL, tag::SIDE, tag::ORDER_QTY, tag::ORD_TYPE];
+        if let Some(missing) = missing_tag(msg, &required) {
+            return self.reject(msg, 1, Some(missing), "required tag missing").await;
+        }
+        let order = match self.parse_order(msg) {
+            Ok(order) => order,
+            Err((reason, ref_tag, text)) => return self.reject(msg, reason, Some(ref_tag), text).await,
+        };
+        let cl_ord_id = msg.get(tag::CL_ORD_ID).unwrap_or_default().to_string();
+        if self.cl_ord_ids.contains_key(&cl_ord_id) {
+            return self.send(cancel_rejected(msg, 2, None, 6, "duplicate ClOrdID")).await;
+        }
+        let limits = &self.acceptor.state.rate_limiter.config;
+        if !self.charge_rate_limit(limits.cancel_weight + limits.order_weight) {
+            return self.send(cancel_rejected(msg, 2, None, 99, "rate limit exceeded")).await;
+        }
+        let Some(record) = self.find_order(msg).await? else {
+            return self.send(cancel_rejected(msg, 2, None, 1, "unknown order")).await;
+        };
+        if order.order_type != OrderType::Limit || order.side != record.side {
+            return self.send(cancel_rejected(msg, 2, Some(&record), 99, "only price and quantity of a limit order can be changed")).await;
+        }
+        if order.quantity <= record.filled_quantity {
+            return self.send(cancel_rejected(msg, 2, Some(&record), 99, "OrderQty must exceed CumQty")).await;
+        }
+        if !record.status.is_open() {
+            return self.send(cancel_rejected(msg, 2, Some(&record), 0, "too late to replace")).await;
+        }
+        let Some(remaining) = self.cancel_in_engine_remaining(record.id).await else {
+            return self.send(cancel_rejected(msg, 2, Some(&record), 0, "too late to replace")).await;
+        };
+
+        // 取消までに約定した分を除いた残りを出し直す（OrderQty は約定済みを含む合計）
+        let filled = record.quantity - remaining;
+        let leaves = order.quantity.saturating_sub(filled);
+        if leaves == 0 {
+            // 取消までに訂正後の数量まで約定していた（取消として報告する）
+            self.accept_cancel(record.id, cl_ord_id);
+            return Ok(());
+        }
+        let orig_cl_ord_id = self.orders.get(&record.id).map(|ids| ids.cl_ord_id.clone());
+        self.orders.entry(record.id).or_default().replaced = true;
+        let order_id = self.acceptor.state.order_ids.next_id();
+        self.cl_ord_ids.insert(cl_ord_id.clone(), order_id);
+        self.orders.insert(
+            order_id,
+            OrderIds {
+                cl_ord_id,
+                orig_cl_ord_id: orig_cl_ord_id.or_else(|| Some(record.id.to_string())),
+                replacement: true,
+                ..Default::default()
+            },
+        );
+        self.place(Order { id: order_id, quantity: leaves, ..order }).await
+    }
+
+    /// 新規注文・訂正の共通の項目を読む（不正なら (SessionRejectReason, タグ, 理由)）
+    fn parse_order(&self, msg: &FixMessage) -> Result<Order, (u32, u32, &'static str)> {
+        let side = match msg.get(tag::SIDE) {
+            Some("1") => Side::Buy,
+            Some("2") => Side::Sell,
+            _ => return Err((5, tag::SIDE, "Side must be 1 (buy) or 2 (sell)")),
+        };
+        let order_type = match msg.get(tag::ORD_TYPE) {
+            Some("1") => OrderType::Market,
+            Some("2") => OrderType::Limit,
+            _ => return Err((5, tag::ORD_TYPE, "OrdType must be 1 (market) or 2 (limit)")),
+        };
+        // 数量は整数のみ（"10" や "10.0" は受け付ける）
+        let quantity = msg
+            .parse::<Decimal>(tag::ORDER_QTY)
+            .filter(|q| q.fract().is_zero() && *q > Decimal::ZERO)
+            .and_then(|q| u64::try_from(q).ok())
+            .ok_or((5, tag::ORDER_QTY, "OrderQty must be a positive integer"))?;
+        let price = match order_type {
+            OrderType::Limit => msg
+                .parse::<Decimal>(tag::PRICE)
+                .filter(|p| *p > Decimal::ZERO)
+                .ok_or((5, tag::PRICE, "Price must be positive for a limit order"))?,
+            OrderType::Market => Decimal::ZERO, // REST と同じく成行の価格は使わない
+        };
+        let order = Order { id: 0, price, quantity, side, user_id: Some(self.user_id), order_type, ..Default::default() };
+        // DB に保存できない価格・約定代金はエンジンに渡さない
+        match order.check_representable(&self.acceptor.state.assets) {
+            Ok(()) => Ok(order),
+            Err(UnrepresentableOrder::Tick(_)) => Err((5, tag::PRICE, "Price is finer than the price tick")),
+            Err(UnrepresentableOrder::Price) => Err((5, tag::PRICE, "Price must have at most 8 decimal places")),
+            Err(UnrepresentableOrder::Notional) => Err((5, tag::ORDER_QTY, "Price * OrderQty is out of range")),
+        }
+    }
+
+    /// REST と同じユーザー・接続元IPのバケットから weight 分を消費する（足りなければ false）
+    fn charge_rate_limit(&self, weight: u32) -> bool {
+        let keys = [RateKey::User(self.user_id), RateKey::Ip(self.ip)];
+        self.acceptor.state.rate_limiter.check(&keys, weight).allowed
+    }
+
+    /// 取消・訂正の対象（OrderID があればそれ、なければ OrigClOrdID）を探す
+    async fn find_order(&self, msg: &FixMessage) -> io::Result<Option<OrderRecord>> {
+        let order_id = match msg.parse::<u64>(tag::ORDER_ID) {
+            Some(order_id) => Some(order_id),
+            None => msg.get(tag::ORIG_CL_ORD_ID).and_then(|cl| self.cl_ord_ids.get(cl).copied()),
+        };
+        let Some(order_id) = order_id else {
+            return Ok(None);
+        };
+
+        let state = &self.acceptor.state;
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let _ = state.sender.send(EngineMessage::GetOrder { order_id, respond_to: resp_tx }).await;
+        let record = match resp_rx.await.map_err(io::Error::other)? {
+            Some(record) => Some(record),
+            None => state.storage.get_order(order_id).await.map_err(io::Error::other)?,
+        };
+        Ok(record.filter(|r| r.user_id == self.user_id))
+    }
+
+    /// エンジンに注文を渡す（結果は user チャネルから届く）
+    async fn place(&mut self, order: Order) -> io::Result<()> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let _ = self.acceptor.state.sender.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await;
+        resp_rx.await.map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    /// エンジンで取り消せたか
+    async fn cancel_in_engine(&self, order_id: u64) -> bool {
+        self.cancel_in_engine_remaining(order_id).await.is_some()
+    }
+
+    /// エンジンで取り消し、取り消した時点の残数量を返す（取り消せなければ None）
+    async fn cancel_in_engine_remaining(&self, order_id: u64) -> Option<u64> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let _ = self
+            .acceptor
+            .state
+            .sender
+            .send(EngineMessage::CancelOrder { order_id, user_id: self.user_id, respond_to: resp_tx })
+            .await;
+        resp_rx.await.ok().flatten().map(|order| order.quantity)
+    }
+
+    /// 取消を受け付けた注文の ClOrdID を、取消要求のものに付け替える
+    fn accept_cancel(&mut self, order_id: u64, cl_ord_id: String) {
+        self.cl_ord_ids.insert(cl_ord_id.clone(), order_id);
+        let ids = self.orders.entry(order_id).or_default();
+        let orig = std::mem::replace(&mut ids.cl_ord_id, cl_ord_id);
+        ids.orig_cl_ord_id = Some(if orig.is_empty() { order_id.to_string() } else { orig });
+    }
+
+    // -------------------------------------------------------------------------
+    // 約定レポート
+    // -------------------------------------------------------------------------
+
+    /// user チャネルのイベントを処理する（自分の約定レポートだけを送る）
+    async fn on_user_update(&mut self, update: UserUpdate) -> io::Result<()> {
+        if update.user_id != self.user_id || self.last_event_seq.is_some_and(|seq| update.seq <= seq) {
+            return Ok(());
+        }
+        self.last_event_seq = Some(update.seq);
+        let UserEvent::Execution { order, fill } = &update.event else {
+            return Ok(()); // 残高の変化・先物の約定レポートはFIXでは送らない
+        };
+
+        let mut ids = self.orders.get(&order.id).cloned().unwrap_or_default();
+        if !order.status.is_open() {
+            self.orders.remove(&order.id);
+        }
+        if ids.replaced && order.status == OrderStatus::Cancelled {
+            return Ok(()); // 訂正で出し直した注文の Replaced として報告する
+        }
+        let exec_type = match (order.status, fill) {
+            (_, Some(_)) => "F", // Trade
+            (OrderStatus::New, None) if ids.replacement => {
+                if let Some(ids) = self.orders.get_mut(&order.id) {
+                    ids.replacement = false;
+                }
+                "5" // Replaced
+            }
+            (OrderStatus::New, None) => "0",
+            (OrderStatus::Cancelled, None) => "4",
+            (OrderStatus::Rejected, None) => "8",
+            (OrderStatus::Expired, None) => "C",
+            (OrderStatus::PartiallyFilled | OrderStatus::Filled, None) => "F",
+        };
+        if ids.cl_ord_id.is_empty() {
+            // この接続以外（RESTなど）から出した注文
+            ids.cl_ord_id = order.id.to_string();
+        }
+
+        let mut report = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tag::ORDER_ID, order.id)
+            .with(tag::CL_ORD_ID, &ids.cl_ord_id);
+        if let Some(orig) = &ids.orig_cl_ord_id {
+            report.push(tag::ORIG_CL_ORD_ID, orig);
+        }
+        report.push(tag::EXEC_ID, &update.resume_token);
+        report.push(tag::EXEC_TYPE, exec_type);
+        report.push(tag::ORD_STATUS, ord_status(order.status));
+        report.push(tag::SYMBOL, SYMBOL);
+        report.push(tag::SIDE, side_code(order.side));
+        report.push(tag::ORD_TYPE, if order.order_type == OrderType::Limit { "2" } else { "1" });
+        if order.order_type == OrderType::Limit {
+            report.push(tag::PRICE, order.price);
+        }
+        report.push(tag::ORDER_QTY, order.quantity);
+        let leaves = if order.status.is_open() { order.quantity - order.filled_quantity } else { 0 };
+        report.push(tag::LEAVES_QTY, leaves);
+        report.push(tag::CUM_QTY, order.filled_quantity);
+        report.push(tag::AVG_PX, order.avg_fill_price().unwrap_or_default());
+        if let Some(fill) = fill {
+            report.push(tag::LAST_PX, fill.price);
+            report.push(tag::LAST_QTY, fill.quantity);
+        }
+        if order.status == OrderStatus::Rejected {
+            report.push(tag::ORD_REJ_REASON, 99);
+            report.push(tag::TEXT, "insufficient balance");
+        }
+        report.push(tag::TRANSACT_TIME, fix::timestamp(order.updated_at));
+        self.send(report).await
+    }
+
+    /// 取りこぼした user イベントを履歴から処理する
+    async fn replay_user_events(&mut self) -> io::Result<()> {
+        let Some(last) = self.last_event_seq else {
+            return Ok(());
+        };
+        let Some(events) = self.acceptor.state.feeds.user_events_after(self.user_id, last) else {
+            eprintln!("User events for {} are no longer available", self.user_id);
+            return Ok(());
+        };
+        for update in events {
+            self.on_user_update(update).await?;
+        }
+        Ok(())
+    }
+}
+
+/// 必須タグのうち、ないもの
+fn missing_tag(msg: &FixMessage, tags: &[u32]) -> Option<u32> {
+    tags.iter().copied().find(|&t| msg.get(t).is_none_or(str::is_empty))
+}
+
+fn side_code(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "1",
+        Side::Sell => "2",
+    }
+}
+
+/// OrdStatus(39)
+fn ord_status(status: OrderStatus) -> &'static str {
+    match status {
+        OrderStatus::New => "0",
+        OrderStatus::PartiallyFilled => "1",
+        OrderStatus::Filled => "2",
+        OrderStatus::Cancelled => "4",
+        OrderStatus::Rejected => "8",
+        OrderStatus::Expired => "C",
+    }
+}
+
+/// エンジンに渡す前に拒否した注文の ExecutionReport
+fn rejected_order(msg: &FixMessage, reason: u32, text: &str) -> FixMessage {
+    let mut report = FixMessage::new(msg_type::EXECUTION_REPORT)
+        .with(tag::ORDER_ID, "NONE")
+        .with(tag::CL_ORD_ID, msg.get(tag::CL_ORD_ID).unwrap_or_default())
+        .with(tag::EXEC_ID, Uuid::new_v4().simple())
+        .with(tag::EXEC_TYPE, "8")
+        .with(tag::ORD_STATUS, "8");
+    for t in [tag::SYMBOL, tag::SIDE, tag::ORD_TYPE, tag::PRICE, tag::ORDER_QTY] {
+        if let Some(value) = msg.get(t) {
+            report.push(t, value);
+        }
+    }
+    report
+        .with(tag::LEAVES_QTY, 0)
+        .with(tag::CUM_QTY, 0)
+        .with(tag::AVG_PX, 0)
+        .with(tag::ORD_REJ_REASON, reason)
+        .with(tag::TEXT, text)
+}
+
+/// OrderCancelReject（response_to: 1 = 取消, 2 = 訂正）
+fn cancel_rejected(msg: &FixMessage, response_to: u32, order: Option<&OrderRecord>, reason: u32, text: &str) -> FixMessage {
+    let order_id = order.map_or("NONE".to_string(), |o| o.id.to_string());
+    let status = order.map_or("8", |o| ord_status(o.status));
+    FixMessage::new(msg_type::ORDER_CANCEL_REJECT)
+        .with(tag::ORDER_ID, order_id)
+        .with(tag::CL_ORD_ID, msg.get(tag::CL_ORD_ID).unwrap_or_default())
+        .with(tag::ORIG_CL_ORD_ID, msg.get(tag::ORIG_CL_ORD_ID).unwrap_or_default())
+        .with(tag::ORD_STATUS, status)
+        .with(tag::CXL_REJ_RESPONSE_TO, response_to)
+        .with(tag::CXL_REJ_REASON, reason)
+        .with(tag::TEXT, text)
+}
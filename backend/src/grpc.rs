@@ -0,0 +1,416 @@
+// =============================================================================
+// gRPC API
+// =============================================================================
+//
+// proto/badbit.proto の Trading サービスを、REST とは別のポートで提供します。
+// 処理はREST・WebSocketと同じく、エンジンへの EngineMessage と MarketFeeds の配信で行います。
+//
+// | RPC             | 認証           | 対応するREST・WebSocket        |
+// |-----------------|----------------|--------------------------------|
+// | PlaceOrder      | trade          | POST /order                    |
+// | CancelOrder     | trade          | DELETE /order/{id}             |
+// | GetOrderBook    | 不要           | GET /orderbook                 |
+// | GetBalances     | read           | GET /balances                  |
+// | SubscribeTrades | 不要           | /ws の trades チャネル         |
+// | SubscribeBook   | 不要           | /ws の diff_depth チャネル     |
+//
+// 【認証】
+// REST と同じく、セッショントークン（authorization: Bearer <token>）か、APIキーの署名で認証します。
+// APIキーの場合は x-api-key / x-timestamp / x-signature（任意で x-recv-window）メタデータを付け、
+//   payload   = timestamp + "POST" + RPCのパス（例: /badbit.v1.Trading/PlaceOrder）+ リクエストメッセージのprotobuf
+//   signature = hex(HMAC-SHA256(secret, payload))
+// で署名します（REST のボディがprotobufになっただけ）。
+//
+// 【レート制限】
+// REST と同じバケット・重みを使います（発注は order_weight、取消は cancel_weight、それ以外は read_weight）。
+// 接続元IPのバケットは認証の前に、ユーザーのバケットは認証の後に消費し、
+// 足りなければ RESOURCE_EXHAUSTED（retry-after メタデータ付き）を返します。
+// =============================================================================
+
+use std::sync::Arc;
+
+use axum::http::{Method, StatusCode};
+use prost::Message;
+use rust_decimal::Decimal;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::api::{ApiError, AppState};
+use crate::auth::{self, UnverifiedApiKey, API_KEY_HEADER};
+use crate::encoding::FeedFrame;
+use crate::engine::EngineMessage;
+use crate::feeds::{BookUpdate, MarketEvent};
+use crate::models::{self, ApiScope};
+use crate::orderbook::{BookSnapshot, PriceLevel};
+use crate::ratelimit::RateKey;
+
+/// protoから生成したコード
+pub mod proto {
+    tonic::include_proto!("badbit.v1");
+}
+
+use proto::trading_server::{Trading, TradingServer};
+
+/// ストリーミングRPCで、クライアントが受け取るまで溜めておく件数
+const STREAM_BUFFER: usize = 256;
+
+/// gRPCサーバーの設定
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    pub addr: Option<String>, // 待ち受けるアドレス（None なら起動しない）
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self { addr: Some("0.0.0.0:50051".to_string()) }
+    }
+}
+
+/// gRPCサーバーを起動する
+pub async fn serve(listener: TcpListener, state: Arc<AppState>) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(TradingServer::new(TradingService { state }))
+        .serve_with_incoming(TcpIncoming::from(listener))
+        .await
+}
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        let message = e.message;
+        match e.status {
+            StatusCode::BAD_REQUEST => Status::invalid_argument(message),
+            StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+            StatusCode::FORBIDDEN => Status::permission_denied(message),
+            StatusCode::NOT_FOUND => Status::not_found(message),
+            StatusCode::CONFLICT => Status::already_exists(message),
+            StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+/// Trading サービスの実装
+pub struct TradingService {
+    state: Arc<AppState>,
+}
+
+impl TradingService {
+    /// リクエストのユーザーを特定する（APIキーなら署名と権限も確かめる）
+    ///
+    /// path は署名対象のRPCのパス（例: /badbit.v1.Trading/PlaceOrder）。
+    /// 認証の前後で接続元IP・ユーザーのバケットから weight 分を消費する
+    async fn authenticate<T: Message>(
+        &self,
+        request: &Request<T>,
+        path: &str,
+        scope: ApiScope,
+        weight: u32,
+    ) -> Result<Uuid, Status> {
+        self.charge_ip(request, weight)?;
+        let headers = request.metadata().as_ref();
+        let user_id = if let Some(key) = auth::header_str(headers, API_KEY_HEADER) {
+            let ip = request.remote_addr().map(|addr| addr.ip());
+            UnverifiedApiKey::check(&self.state, key, headers, ip)
+                .await?
+                .verify(&Method::POST, path, &request.get_ref().encode_to_vec(), scope)?
+                .user_id
+        } else {
+            let token = auth::bearer_token(headers).ok_or_else(|| Status::unauthenticated("ログインが必要です"))?;
+            auth::token_user(&self.state, token)
+                .await
+                .map_err(ApiError::from)?
+                .ok_or_else(|| Status::unauthenticated("セッションが無効か期限切れです"))?
+        };
+        self.charge(RateKey::User(user_id), weight)?;
+        Ok(user_id)
+    }
+
+    /// 接続元IPのバケットから weight 分を消費する（IPが不明なら制限しない）
+    fn charge_ip<T>(&self, request: &Request<T>, weight: u32) -> Result<(), Status> {
+        match request.remote_addr() {
+            Some(addr) => self.charge(RateKey::Ip(addr.ip()), weight),
+            None => Ok(()),
+        }
+    }
+
+    /// REST と同じバケットから weight 分を消費する
+    fn charge(&self, key: RateKey, weight: u32) -> Result<(), Status> {
+        let decision = self.state.rate_limiter.check(&[key], weight);
+        if decision.allowed {
+            return Ok(());
+        }
+        let mut status = Status::resource_exhausted("リクエストが多すぎます");
+        let secs = decision.retry_after.as_secs_f64().ceil().clamp(1.0, u32::MAX as f64) as u32;
+        status.metadata_mut().insert("retry-after", secs.into());
+        Err(status)
+    }
+
+    /// エンジンに問い合わせて応答を待つ
+    async fn ask<T>(&self, msg: impl FnOnce(oneshot::Sender<T>) -> EngineMessage) -> Result<T, Status> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let _ = self.state.sender.send(msg(resp_tx)).await;
+        resp_rx.await.map_err(|_| Status::from(ApiError::internal()))
+    }
+}
+
+#[tonic::async_trait]
+impl Trading for TradingService {
+    async fn place_order(
+        &self,
+        request: Request<proto::PlaceOrderRequest>,
+    ) -> Result<Response<proto::PlaceOrderResponse>, Status> {
+        let weight = self.state.rate_limiter.config.order_weight;
+        let user_id = self.authenticate(&request, "/badbit.v1.Trading/PlaceOrder", ApiScope::Trade, weight).await?;
+        let req = request.into_inner();
+
+        let side = side_from_proto(req.side)?;
+        let order_type = match proto::OrderType::try_from(req.order_type) {
+            Ok(proto::OrderType::Unspecified | proto::OrderType::Limit) => models::OrderType::Limit,
+            Ok(proto::OrderType::Market) => models::OrderType::Market,
+            Err(_) => return Err(Status::invalid_argument("order_type が不正です")),
+        };
+        if req.quantity == 0 {
+            return Err(Status::invalid_argument("quantity は1以上にしてください"));
+        }
+        let price = match order_type {
+            models::OrderType::Limit => req
+                .price
+                .parse::<Decimal>()
+                .ok()
+                .filter(|p| *p > Decimal::ZERO)
+                .ok_or_else(|| Status::invalid_argument("指値の price は正の数値を文字列で指定してください"))?,
+            models::OrderType::Market => Decimal::ZERO, // 成行の価格は使わない
+        };
+
+        let mut order = models::Order {
+            id: 0,
+            price,
+            quantity: req.quantity,
+            side,
+            user_id: Some(user_id),
+            order_type,
+            ..Default::default()
+        };
+        order.check_representable(&self.state.assets).map_err(|e| Status::invalid_argument(e.to_string()))?;
+        order.id = self.state.order_ids.next_id();
+        let order_id = order.id;
+        let trades = self.ask(|respond_to| EngineMessage::PlaceOrder { order, respond_to }).await?;
+        Ok(Response::new(proto::PlaceOrderResponse {
+            order_id,
+            trades: trades.iter().map(trade_to_proto).collect(),
+        }))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<proto::CancelOrderRequest>,
+    ) -> Result<Response<proto::CancelOrderResponse>, Status> {
+        let weight = self.state.rate_limiter.config.cancel_weight;
+        let user_id = self.authenticate(&request, "/badbit.v1.Trading/CancelOrder", ApiScope::Trade, weight).await?;
+        let order_id = request.into_inner().order_id;
+        let order = self
+            .ask(|respond_to| EngineMessage::CancelOrder { order_id, user_id, respond_to })
+            .await?
+            .ok_or_else(|| Status::not_found("注文が見つかりません"))?;
+        Ok(Response::new(proto::CancelOrderResponse {
+            order_id: order.id,
+            price: order.price.to_string(),
+            remaining_quantity: order.quantity,
+            side: side_to_proto(order.side) as i32,
+            order_type: match order.order_type {
+                models::OrderType::Limit => proto::OrderType::Limit,
+                models::OrderType::Market => proto::OrderType::Market,
+            } as i32,
+        }))
+    }
+
+    async fn get_order_book(
+        &self,
+        request: Request<proto::GetOrderBookRequest>,
+    ) -> Result<Response<proto::OrderBook>, Status> {
+        self.charge_ip(&request, self.state.rate_limiter.config.read_weight)?;
+        let book = self.ask(|respond_to| EngineMessage::GetOrderBook { respond_to }).await?;
+        Ok(Response::new(book_to_proto(&book)))
+    }
+
+    async fn get_balances(
+        &self,
+        request: Request<proto::GetBalancesRequest>,
+    ) -> Result<Response<proto::GetBalancesResponse>, Status> {
+        let weight = self.state.rate_limiter.config.read_weight;
+        let user_id = self.authenticate(&request, "/badbit.v1.Trading/GetBalances", ApiScope::Read, weight).await?;
+        let balances = self.state.feeds.balances.get(user_id);
+        Ok(Response::new(proto::GetBalancesResponse {
+            balances: balances
+                .into_iter()
+                .map(|b| proto::Balance {
+                    asset: b.asset,
+                    available: b.available.to_string(),
+                    locked: b.locked.to_string(),
+                })
+                .collect(),
+        }))
+    }
+
+    type SubscribeTradesStream = ReceiverStream<Result<proto::PublicTrade, Status>>;
+
+    async fn subscribe_trades(
+        &self,
+        request: Request<proto::SubscribeTradesRequest>,
+    ) -> Result<Response<Self::SubscribeTradesStream>, Status> {
+        self.charge_ip(&request, self.state.rate_limiter.config.read_weight)?;
+        let mut rx = self.state.feeds.trades.subscribe();
+        let (out, stream) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let frame: FeedFrame = match rx.recv().await {
+                    Ok(frame) => frame,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        // 約定は読み飛ばして続ける（/ws の trades と同じ）
+                        eprintln!("gRPC trades stream lagged by {}", count);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let MarketEvent::Trade(trade) = frame.event() else {
+                    continue;
+                };
+                let trade = proto::PublicTrade {
+                    id: trade.id,
+                    price: trade.price.to_string(),
+                    quantity: trade.quantity,
+                    taker_side: side_to_proto(trade.taker_side) as i32,
+                    timestamp: trade.timestamp,
+                };
+                if out.send(Ok(trade)).await.is_err() {
+                    break; // クライアントが切断した
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+
+    type SubscribeBookStream = ReceiverStream<Result<proto::BookUpdate, Status>>;
+
+    async fn subscribe_book(
+        &self,
+        request: Request<proto::SubscribeBookRequest>,
+    ) -> Result<Response<Self::SubscribeBookStream>, Status> {
+        self.charge_ip(&request, self.state.rate_limiter.config.read_weight)?;
+        // スナップショットより後の差分を取りこぼさないよう、先に購読しておく
+        let rx = self.state.feeds.book.subscribe();
+        let (out, stream) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(stream_book(self.state.clone(), rx, out));
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+/// 板のスナップショットを送り、その sequence を返す（送れなければ None）
+async fn send_book_snapshot(state: &AppState, out: &mpsc::Sender<Result<proto::BookUpdate, Status>>) -> Option<u64> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    state.sender.send(EngineMessage::GetOrderBook { respond_to: resp_tx }).await.ok()?;
+    let snapshot = resp_rx.await.ok()?;
+    let book = book_to_proto(&snapshot);
+    let update = proto::BookUpdate { snapshot: true, prev_sequence: 0, sequence: book.sequence, bids: book.bids, asks: book.asks };
+    out.send(Ok(update)).await.ok()?;
+    Some(snapshot.sequence)
+}
+
+/// SubscribeBook の配信（/ws の diff_depth と同じく、sequence が連続するように送る）
+async fn stream_book(
+    state: Arc<AppState>,
+    mut rx: broadcast::Receiver<BookUpdate>,
+    out: mpsc::Sender<Result<proto::BookUpdate, Status>>,
+) {
+    let Some(mut sequence) = send_book_snapshot(&state, &out).await else {
+        return;
+    };
+
+    loop {
+        let update = match rx.recv().await {
+            Ok(update) => update,
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                // 差分を読み飛ばすと板が壊れるので、スナップショットから送り直す
+                eprintln!("gRPC book stream lagged by {}, resyncing...", count);
+                match send_book_snapshot(&state, &out).await {
+                    Some(seq) => sequence = seq,
+                    None => break,
+                }
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if update.sequence <= sequence {
+            continue; // 送ったスナップショットに含まれている
+        }
+        if update.prev_sequence > sequence {
+            match send_book_snapshot(&state, &out).await {
+                Some(seq) => sequence = seq,
+                None => break,
+            }
+            continue;
+        }
+        let MarketEvent::Delta(delta) = update.frame.event() else {
+            continue;
+        };
+        let update = proto::BookUpdate {
+            snapshot: false,
+            prev_sequence: delta.prev_sequence,
+            sequence: delta.sequence,
+            bids: delta.bids.iter().map(level_to_proto).collect(),
+            asks: delta.asks.iter().map(level_to_proto).collect(),
+        };
+        if out.send(Ok(update)).await.is_err() {
+            break;
+        }
+        sequence = delta.sequence;
+    }
+}
+
+// =============================================================================
+// 型の変換
+// =============================================================================
+
+fn side_from_proto(side: i32) -> Result<models::Side, Status> {
+    match proto::Side::try_from(side) {
+        Ok(proto::Side::Buy) => Ok(models::Side::Buy),
+        Ok(proto::Side::Sell) => Ok(models::Side::Sell),
+        _ => Err(Status::invalid_argument("side は SIDE_BUY か SIDE_SELL を指定してください")),
+    }
+}
+
+fn side_to_proto(side: models::Side) -> proto::Side {
+    match side {
+        models::Side::Buy => proto::Side::Buy,
+        models::Side::Sell => proto::Side::Sell,
+    }
+}
+
+fn trade_to_proto(trade: &models::Trade) -> proto::Trade {
+    proto::Trade {
+        id: trade.id,
+        maker_id: trade.maker_id,
+        taker_id: trade.taker_id,
+        price: trade.price.to_string(),
+        quantity: trade.quantity,
+        taker_side: side_to_proto(trade.taker_side) as i32,
+        timestamp: trade.timestamp as u64,
+    }
+}
+
+fn level_to_proto(level: &PriceLevel) -> proto::PriceLevel {
+    proto::PriceLevel { price: level.price.to_string(), quantity: level.quantity, order_count: level.order_count as u64 }
+}
+
+fn book_to_proto(book: &BookSnapshot) -> proto::OrderBook {
+    proto::OrderBook {
+        sequence: book.sequence,
+        bids: book.bids.iter().map(level_to_proto).collect(),
+        asks: book.asks.iter().map(level_to_proto).collect(),
+    }
+}
@@ -1,6 +1,23 @@
 pub mod models;
+pub mod config;
 pub mod db;
+pub mod storage;
 pub mod account;
 pub mod orderbook;
 pub mod engine;
+pub mod margin;
+pub mod perp;
+pub mod candles;
+pub mod ticker;
+pub mod feeds;
+pub mod encoding;
+pub mod publisher;
 pub mod simulator;
+pub mod api;
+pub mod openapi;
+pub mod auth;
+pub mod ws;
+pub mod fix;
+pub mod fix_gateway;
+pub mod grpc;
+pub mod ratelimit;
@@ -13,11 +13,21 @@
 //
 // Refactored into modules:
 // - models: データ型 (Order, Trade, Side)
-// - db: データベース接続 & 永続化アクター
+// - config: 環境変数からの設定読み込み
+// - storage: 永続化の抽象化 (Storageトレイト, インメモリ実装)
+// - db: SQLite実装 & 永続化アクター
 // - account: 残高管理ロジック
 // - orderbook: 板管理ロジック
 // - engine: マッチングエンジンアクター
+// - margin: 証拠金取引（借入・利息・清算）のプロトタイプ
+// - perp: 無期限先物（ポジション・資金調達）のプロトタイプ
 // - simulator: 市場シミュレータ
+// - api: REST APIのハンドラーとルーター
+// - openapi: REST APIのOpenAPIスキーマ (/openapi.json, /docs)
+// - auth: ユーザー登録・ログイン・セッション認証
+// - ws: WebSocket配信
+// - fix / fix_gateway: FIX 4.4 のメッセージと注文ゲートウェイ
+// - grpc: gRPC API（proto/badbit.proto）
 // =============================================================================
 
 // --- 内部モジュール ---
@@ -31,186 +41,24 @@
 
 
 // --- 外部クレート（ライブラリ）のインポート ---
-use axum::{
-    extract::{State, ws::{Message, WebSocket, WebSocketUpgrade}}, // WebSocket機能を追加
-    routing::{get, post},     // HTTPメソッドに応じたルーティング
-    response::IntoResponse,   // レスポンス変換用トレイト
-    Json, Router,             // JSONレスポンスとルーター
-};
-use rust_decimal::Decimal;    // 固定小数点数
-use serde::{Deserialize, Serialize}; 
+use std::net::SocketAddr;      // 接続元アドレス（APIキーのIP制限用）
 use std::sync::Arc;           // スレッド間で安全に共有できるスマートポインタ
 use std::time::SystemTime;    // UNIXタイムスタンプ取得用
-use tokio::sync::{mpsc, oneshot, broadcast}; // broadcastを追加
-use tower_http::cors::CorsLayer;  // CORSヘッダーを追加するミドルウェア
-use uuid::Uuid;               // ユニークID生成
+use tokio::sync::mpsc;
 
 // --- モジュールからのインポート ---
-use rust_matching_engine::models::{Order, Trade, Side, OrderType};
-use rust_matching_engine::orderbook::OrderBook;
 use rust_matching_engine::account::AccountManager;
-use rust_matching_engine::engine::{self, EngineMessage};
-use rust_matching_engine::db::{self, DbMessage};
+use rust_matching_engine::api::{self, AppState};
+use rust_matching_engine::engine::{self, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::db::{self, DbMessage, DbWriterStatus};
+use rust_matching_engine::config::Config;
+use rust_matching_engine::models::AssetPrecisions;
+use rust_matching_engine::ratelimit::RateLimiter;
+use rust_matching_engine::storage;
 use rust_matching_engine::simulator;
-
-
-// =============================================================================
-// Webサーバーの状態
-// =============================================================================
-
-/// APIハンドラーが持つ共有状態
-#[derive(Clone)]
-struct AppState {
-    sender: mpsc::Sender<EngineMessage>,
-    db_pool: db::DbPool,      // データベース接続プール
-    user_id: Uuid,            // 現在のユーザーID（固定ユーザー）
-    broadcast_tx: broadcast::Sender<OrderBook>, // 板情報の配信チャンネル
-}
-
-// =============================================================================
-// APIハンドラー
-// =============================================================================
-
-/// GET /orderbook - 現在の板情報を取得
-async fn get_orderbook(State(state): State<Arc<AppState>>) -> Json<OrderBook> {
-    let (resp_tx, resp_rx) = oneshot::channel();
-    let _ = state.sender.send(EngineMessage::GetOrderBook { respond_to: resp_tx }).await;
-    let book = resp_rx.await.unwrap();
-    Json(book)
-}
-
-/// GET /trades - 取引履歴を取得
-async fn get_trades(State(state): State<Arc<AppState>>) -> Json<Vec<Trade>> {
-    let (resp_tx, resp_rx) = oneshot::channel();
-    let _ = state.sender.send(EngineMessage::GetTrades { respond_to: resp_tx }).await;
-    let trades = resp_rx.await.unwrap();
-    Json(trades)
-}
-
-/// GET /my-trades - 自分の取引履歴を取得
-async fn get_my_trades(State(state): State<Arc<AppState>>) -> Json<Vec<Trade>> {
-    let trades = db::get_user_trades(&state.db_pool, state.user_id)
-        .await
-        .unwrap_or_default();
-    Json(trades)
-}
-
-/// 残高レスポンス用の構造体
-#[derive(Serialize)]
-struct BalanceResponse {
-    usdc_available: String,
-    usdc_locked: String,
-    bad_available: String,
-    bad_locked: String,
-}
-
-/// GET /balance - ユーザーの残高を取得
-async fn get_balance(State(state): State<Arc<AppState>>) -> Json<BalanceResponse> {
-    let balances = db::get_balances(&state.db_pool, state.user_id)
-        .await
-        .unwrap_or_default();
-
-    let mut response = BalanceResponse {
-        usdc_available: "0".to_string(),
-        usdc_locked: "0".to_string(),
-        bad_available: "0".to_string(),
-        bad_locked: "0".to_string(),
-    };
-
-    for balance in balances {
-        match balance.asset.as_str() {
-            "USDC" => {
-                response.usdc_available = balance.available.to_string();
-                response.usdc_locked = balance.locked.to_string();
-            }
-            "BAD" => {
-                response.bad_available = balance.available.to_string();
-                response.bad_locked = balance.locked.to_string();
-            }
-            _ => {}
-        }
-    }
-
-    Json(response)
-}
-
-/// 新規注文APIのリクエストボディ
-#[derive(Deserialize)]
-struct CreateOrderPayload {
-    #[serde(with = "rust_decimal::serde::str")] // JSONから文字列として受け取る
-    price: Decimal,
-    quantity: u64,
-    side: Side,
-    #[serde(default = "default_order_type")]
-    order_type: OrderType,
-}
-
-/// POST /order - 新規注文を作成
-async fn create_order(
-    State(state): State<Arc<AppState>>,
-    Json(payload): Json<CreateOrderPayload>,
-) -> Json<Vec<Trade>> {
-    // 注文IDを生成
-    let new_order = Order {
-        id: (SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_millis()
-            % 10000000) as u64,
-        price: payload.price, // 成行の場合は0などの値が入ってくる想定
-        quantity: payload.quantity,
-        side: payload.side,
-        user_id: Some(state.user_id), // 注文者のIDを設定
-        order_type: payload.order_type,
-    };
-
-    let (resp_tx, resp_rx) = oneshot::channel();
-    
-    // エンジンに注文処理を依頼
-    let _ = state.sender.send(EngineMessage::PlaceOrder { 
-        order: new_order, 
-        respond_to: resp_tx 
-    }).await;
-
-    // 約定結果を受け取って返す
-    let new_trades = resp_rx.await.unwrap();
-    Json(new_trades)
-}
-
-/// DELETE /order/:id - 注文をキャンセル
-async fn cancel_order(
-    State(state): State<Arc<AppState>>,
-    axum::extract::Path(order_id): axum::extract::Path<u64>,
-) -> impl axum::response::IntoResponse {
-    let (resp_tx, resp_rx) = oneshot::channel();
-    
-    // エンジンにキャンセルを依頼
-    let _ = state.sender.send(EngineMessage::CancelOrder { 
-        order_id, 
-        user_id: state.user_id, // 自分の注文しかキャンセルできない
-        respond_to: resp_tx 
-    }).await;
-
-    // 結果待機
-    match resp_rx.await {
-        Ok(Some(order)) => {
-            // キャンセル成功: 削除された注文を返す
-            axum::response::Json(order).into_response()
-        },
-        Ok(None) => {
-            // 注文が見つからない (404 Not Found)
-            axum::http::StatusCode::NOT_FOUND.into_response()
-        },
-        Err(_) => {
-            // エンジンとの通信エラー (500)
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
-        }
-    }
-}
-
-fn default_order_type() -> OrderType {
-    OrderType::Limit
-}
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::fix_gateway;
+use rust_matching_engine::grpc;
 
 // =============================================================================
 // メイン関数
@@ -219,55 +67,81 @@ fn default_order_type() -> OrderType {
 #[tokio::main]
 async fn main() {
     // =========================================================================
-    // Step 0: データベースを初期化
+    // Step 0: 設定を読み込み、ストレージを初期化
     // =========================================================================
-    let (db_pool, user_id) = db::init_database("data.db")
+    let config = Config::from_env().expect("設定の読み込みに失敗しました");
+    let storage = storage::open(&config.storage)
         .await
-        .expect("データベースの初期化に失敗しました");
+        .expect("ストレージの初期化に失敗しました");
 
     // =========================================================================
     // Step 1: データをメモリにロード (AccountManagerの初期化)
     // =========================================================================
     let mut account_manager = AccountManager::new();
-    let initial_balances = db::get_balances(&db_pool, user_id).await.unwrap_or_default();
-    
+    // 全ユーザーの残高を読み込む（誰の注文でも残高チェックできるように）
+    let initial_balances = storage
+        .get_all_balances()
+        .await
+        .expect("残高の読み込みに失敗しました");
+
     for b in &initial_balances {
         account_manager.load_balance(b.user_id, &b.asset, b.available, b.locked);
     }
     println!("✅ 残高ロード完了: {} 件", initial_balances.len());
 
+    // 形成中のローソク足と24時間統計を復元（再起動しても途切れないように）
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let mut market = MarketData::load(&storage, now)
+        .await
+        .expect("マーケットデータの復元に失敗しました");
+    // 注文IDは使用済みの最大IDの続きから採番する（API・シミュレータで共有）
+    let order_ids = Arc::new(OrderIdGenerator::new(market.last_order_id));
+    // 証拠金口座・無期限先物（清算注文も同じ採番器を使う。口座は読み込んだものを使い続ける）
+    market.margin.configure(config.margin.clone(), order_ids.clone());
+    market.perp.configure(config.perp.clone(), order_ids.clone());
+    // 価格・金額の桁数の検証に使う資産の登録情報
+    let assets = AssetPrecisions::new(&storage.get_assets().await.expect("資産の読み込みに失敗しました"));
+
     // =========================================================================
     // Step 2: DB Writer Actor（永続化タスク）を起動
     // =========================================================================
     let (db_tx, db_rx) = mpsc::channel::<DbMessage>(10000);
-    let db_pool_for_writer = db_pool.clone();
+    let storage_for_writer = storage.clone();
+    // 書き込みの失敗は GET /health で返す
+    let db_status = Arc::new(DbWriterStatus::default());
+    let writer_status = db_status.clone();
     
     tokio::spawn(async move {
-        db::run_db_writer(db_rx, db_pool_for_writer).await;
+        db::run_db_writer(db_rx, storage_for_writer, writer_status).await;
     });
 
     // =========================================================================
     // Step 3: Engine Actor（マッチングエンジン）を起動
     // =========================================================================
     let (tx, rx) = mpsc::channel::<EngineMessage>(10000);
-    // 板情報配信用のbroadcastチャネル（容量10000）- Lag対策で増やす
-    let (broadcast_tx, _) = broadcast::channel::<OrderBook>(10000);
+    // 板情報・ローソク足配信用のbroadcastチャネル（容量10000）- Lag対策で増やす
+    let mut feeds = MarketFeeds::new(10000);
+    feeds.intervals = config.publish.clone(); // 板・ティッカーの配信間隔
     
     let engine_db_tx = db_tx.clone();
-    let engine_broadcast_tx = broadcast_tx.clone();
+    let engine_feeds = feeds.clone();
 
     // engine::run_matching_engine は async fn なので await が必要だが、
     // ここでは spawn するので async move ブロック内で呼び出す
     tokio::spawn(async move {
-        engine::run_matching_engine(rx, engine_db_tx, account_manager, engine_broadcast_tx).await;
+        engine::run_matching_engine(rx, engine_db_tx, account_manager, engine_feeds, market).await;
     });
 
     // =========================================================================
     // Step 4: 市場シミュレータを起動
     // =========================================================================
     let sim_sender = tx.clone();
+    let sim_order_ids = order_ids.clone();
     tokio::spawn(async move {
-        simulator::run_market_simulator(sim_sender).await;
+        simulator::run_market_simulator(sim_sender, sim_order_ids).await;
     });
 
     // =========================================================================
@@ -275,82 +149,46 @@ async fn main() {
     // =========================================================================
     let state = Arc::new(AppState {
         sender: tx.clone(),     // チャネルの送信側をクローン
-        db_pool: db_pool.clone(), // DBプール
-        user_id,                // デフォルトユーザーID
-        broadcast_tx: broadcast_tx.clone(), // broadcastチャネル
+        storage: storage.clone(), // ストレージ
+        feeds: feeds.clone(),   // broadcastチャネル
+        rate_limiter: Arc::new(RateLimiter::new(config.rate_limit.clone())), // レート制限
+        order_ids,              // 注文IDの採番器
+        ws: config.ws.clone(),  // /ws のハートビート設定
+        db_status,              // DB Writer の書き込み失敗の記録
+        assets,                 // 資産ごとの桁数
     });
 
-    // ルーターを構築
-    let app = Router::new()
-        .route("/orderbook", get(get_orderbook)) // GET /orderbook
-        .route("/trades", get(get_trades))       // GET /trades  
-        .route("/order", post(create_order))     // POST /order
-        .route("/order/{id}", axum::routing::delete(cancel_order)) // DELETE /order/{id}
-        .route("/my-trades", get(get_my_trades)) // GET /my-trades (自分の履歴)
-        .route("/balance", get(get_balance))     // GET /balance
-        .route("/ws", get(ws_handler))           // WebSocket
-        .layer(CorsLayer::permissive())          // CORS許可（開発用に全許可）
-        .with_state(state.clone());              // ハンドラーに状態を渡す
+    // FIXゲートウェイは別のポートで待ち受ける（注文・認証はREST/WebSocketと共通）
+    if let Some(addr) = &config.fix.addr {
+        let fix_listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .expect("FIXゲートウェイのポートを開けませんでした");
+        println!("FIXゲートウェイ起動中: {}", addr);
+        tokio::spawn(fix_gateway::run_fix_acceptor(fix_listener, state.clone(), config.fix.clone()));
+    }
+
+    // gRPCサーバーも別のポートで待ち受ける（エンジンへの送信側・認証はRESTと共通）
+    if let Some(addr) = &config.grpc.addr {
+        let grpc_listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .expect("gRPCサーバーのポートを開けませんでした");
+        println!("gRPCサーバー起動中: {}", addr);
+        let grpc_state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(grpc_listener, grpc_state).await {
+                eprintln!("gRPC server error: {}", e);
+            }
+        });
+    }
+
+    // ルーターを構築（エンドポイント一覧は api::router を参照）
+    let app = api::router(state);
 
     println!("サーバー起動中: http://localhost:8000");
     
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
-/// WebSocketハンドラ
-/// クライアントからの接続要求を受け入れ、WebSocket接続にアップグレードする
-async fn ws_handler(
-    ws: WebSocketUpgrade,
-    State(state): State<Arc<AppState>>,
-) -> impl axum::response::IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
-}
-
-/// WebSocket接続の実体
-/// 板情報(OrderBook)の更新をリアルタイムにクライアントへ送信する
-async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>) {
-    // broadcastチャネルを購読（新しい受信機を作成）
-    let mut rx = state.broadcast_tx.subscribe();
-
-    loop {
-        tokio::select! {
-            // 1. 新しい板情報が配信されたら、クライアントに送信
-            result = rx.recv() => {
-                match result {
-                    Ok(orderbook) => {
-                        // JSONにシリアライズ
-                        if let Ok(json_text) = serde_json::to_string(&orderbook) {
-                            // 送信（エラーならループを抜けて切断扱い）
-                            if socket.send(Message::Text(json_text.into())).await.is_err() {
-                                break;
-                            }
-                        }
-                    }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(count)) => {
-                        // 受信が遅れている場合はスキップして継続（切断しない）
-                        eprintln!("Broadcast channel lagged by {}, skipping...", count);
-                        continue;
-                    }
-                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
-                        eprintln!("Broadcast channel closed");
-                        break;
-                    }
-                }
-            }
-            // 2. クライアントからのメッセージ（切断検知など）
-            // これがないと、クライアントが切断してもループが止まらずリソースリークする可能性がある
-            msg = socket.recv() => {
-                match msg {
-                    Some(Ok(_)) => {
-                        // クライアントからのメッセージは無視（今回は一方通行）
-                        // 必要ならPing/Pong対応などをここに入れる
-                    }
-                    Some(Err(_)) | None => {
-                        // エラーまたは切断（None）
-                        break; 
-                    }
-                }
-            }
-        }
-    }
+    // APIキーのIP許可リストの判定に接続元アドレスを使う
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
@@ -0,0 +1,668 @@
+// =============================================================================
+// 証拠金取引（プロトタイプ）
+// =============================================================================
+//
+// 資産を借りてレバレッジをかけた現物売買を試すためのモジュールです。
+//
+// - 証拠金口座は現物の残高（AccountManager）とは別に、ユーザーごとに持つ。
+//   現物との間は振替で行き来させる
+// - 借りられる資産と日利は MarginConfig で決める。利息は単利で、口座を触るたびに
+//   経過時間の分を足す
+// - 口座の評価はマーク価格で行う。マーク価格は板の仲値（片側しかなければ最後の約定価格）
+// - 証拠金率 = 純資産 / 借入（利息込み）の評価額
+//   - 借入・引き出しの後に initial_margin を下回る操作は拒否する
+//   - maintenance_margin を下回ったら清算する。エンジンがそのユーザーの証拠金注文を
+//     キャンセルし、成行注文を OrderBook::process_order に流して借入を返済する
+//
+// 清算について:
+// - 相手側の板が空なら何もせず、板に注文が来るまで待つ
+// - 清算注文はロックせずに出すので、買い戻しは口座の USDC で払える数量（板の売りを安い順に
+//   たどって計算する）までにする。残高がマイナスになることはない
+// - 清算しても返し切れず、残りの資産では返済に充てる分を手に入れられない借入は
+//   貸し倒れ（bad_debt）として借入から外す（取引所の損失として記録するだけで、保険基金などは未実装）
+// - 清算した口座は、証拠金率が戻らなくても、振替・借入・返済・発注・約定で口座が変わるまで
+//   清算し直さない（同じ口座をメッセージのたびに清算し続けないように）。
+//   ただし清算の注文で相手側の板を食い尽くした場合は、板に注文が来たら続きを清算する
+//
+// 永続化について:
+// - 口座はエンジンが変えるたびに DbMessage::SaveMarginAccount で保存し、起動時に読み込む
+// - 保存形式は10^8倍の整数なので、利息も 10^-8 単位で足す。それ未満の端数は次に足す分へ
+//   持ち越すが保存はしない（再起動で消える）
+// - 注文は復元しないので、読み込むときに注文のロックは available に戻す
+// =============================================================================
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::engine::OrderIdGenerator;
+use crate::models::{MarginAccountRecord, MarginAssetRecord, Order, OrderType, Side, Trade};
+use crate::orderbook::OrderBook;
+use crate::storage::DECIMAL_SCALE;
+
+/// 売買する資産（数量の単位）
+pub const BASE_ASSET: &str = "BAD";
+/// 価格の単位になる資産
+pub const QUOTE_ASSET: &str = "USDC";
+
+/// 1日のミリ秒数（日利を経過時間に按分する）
+const DAY_MS: u128 = 24 * 60 * 60 * 1000;
+
+/// 証拠金取引の設定
+#[derive(Debug, Clone)]
+pub struct MarginConfig {
+    /// 借入・引き出しの後に必要な証拠金率（0.25 なら純資産の4倍まで借りられる）
+    pub initial_margin: Decimal,
+    /// これを下回ると清算する証拠金率
+    pub maintenance_margin: Decimal,
+    /// 借りられる資産 -> 日利
+    pub daily_rates: BTreeMap<String, Decimal>,
+}
+
+impl Default for MarginConfig {
+    fn default() -> Self {
+        Self {
+            initial_margin: Decimal::new(25, 2),
+            maintenance_margin: Decimal::new(10, 2),
+            daily_rates: BTreeMap::from([
+                (QUOTE_ASSET.to_string(), Decimal::new(3, 4)),
+                (BASE_ASSET.to_string(), Decimal::new(5, 4)),
+            ]),
+        }
+    }
+}
+
+/// 証拠金口座の操作が拒否された理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarginError {
+    /// 数量が0以下
+    InvalidAmount,
+    /// 証拠金口座で扱わない資産
+    UnsupportedAsset(String),
+    /// 借りられない資産
+    NotBorrowable(String),
+    /// 残高（返済なら借入額）が足りない
+    InsufficientBalance,
+    /// 板にも約定履歴にも価格がなく、口座を評価できない
+    NoMarkPrice,
+    /// 操作の後の証拠金率が initial_margin を下回る
+    BelowInitialMargin,
+}
+
+impl fmt::Display for MarginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarginError::InvalidAmount => write!(f, "数量は0より大きくしてください"),
+            MarginError::UnsupportedAsset(asset) => write!(f, "証拠金口座では {} を扱えません", asset),
+            MarginError::NotBorrowable(asset) => write!(f, "{} は借りられません", asset),
+            MarginError::InsufficientBalance => write!(f, "残高が足りません"),
+            MarginError::NoMarkPrice => write!(f, "マーク価格がないため口座を評価できません"),
+            MarginError::BelowInitialMargin => write!(f, "証拠金率が必要な水準を下回ります"),
+        }
+    }
+}
+
+impl std::error::Error for MarginError {}
+
+/// 証拠金口座への依頼（EngineMessage::Margin で送る）
+#[derive(Debug, Clone)]
+pub enum MarginRequest {
+    /// 口座の状態を見せてください
+    Get,
+    /// 現物の残高から証拠金口座へ移してください
+    TransferIn { asset: String, amount: Decimal },
+    /// 証拠金口座から現物の残高へ戻してください
+    TransferOut { asset: String, amount: Decimal },
+    /// 借りてください（証拠金口座の available に入る）
+    Borrow { asset: String, amount: Decimal },
+    /// 返済してください（利息から先に充てる）
+    Repay { asset: String, amount: Decimal },
+}
+
+/// 証拠金口座の1資産分の残高
+#[derive(Debug, Clone, Copy, Default)]
+struct MarginBalance {
+    available: Decimal,
+    locked: Decimal,
+}
+
+/// 1資産分の借入
+#[derive(Debug, Clone, Copy, Default)]
+struct Loan {
+    principal: Decimal,
+    interest: Decimal, // 未払いの利息
+    dust: Decimal,     // interest に足していない 10^-8 未満の端数（保存しない）
+}
+
+impl Loan {
+    fn owed(&self) -> Decimal {
+        self.principal + self.interest
+    }
+}
+
+/// ユーザーごとの証拠金口座
+#[derive(Debug, Clone, Default)]
+struct MarginAccount {
+    balances: BTreeMap<String, MarginBalance>,
+    loans: BTreeMap<String, Loan>,
+    bad_debt: BTreeMap<String, Decimal>, // 清算で返し切れずに貸し倒れにした額（資産 -> 累計）
+    accrued_at: u128, // 利息を最後に足した時刻（ミリ秒）
+    liquidated: bool, // 清算済みで、その後に口座が変わっていない
+}
+
+impl MarginAccount {
+    /// 前回から now までの利息を足す
+    fn accrue(&mut self, rates: &BTreeMap<String, Decimal>, now: u128) {
+        let elapsed = now.saturating_sub(self.accrued_at);
+        self.accrued_at = self.accrued_at.max(now);
+        if elapsed == 0 {
+            return;
+        }
+        for (asset, loan) in &mut self.loans {
+            let rate = rates.get(asset).copied().unwrap_or_default();
+            let accrued = loan.dust + loan.principal * rate * Decimal::from(elapsed as u64) / Decimal::from(DAY_MS as u64);
+            let storable = accrued.trunc_with_scale(DECIMAL_SCALE);
+            loan.interest += storable;
+            loan.dust = accrued - storable;
+        }
+    }
+
+    fn balance(&mut self, asset: &str) -> &mut MarginBalance {
+        self.balances.entry(asset.to_string()).or_default()
+    }
+
+    /// 支払う（注文でロックした分から先に使い、足りなければ available から）
+    ///
+    /// 清算の成行注文はロックせずに出すので、available から引かれる
+    fn debit(&mut self, asset: &str, amount: Decimal) {
+        let balance = self.balance(asset);
+        let from_locked = amount.min(balance.locked.max(Decimal::ZERO));
+        balance.locked -= from_locked;
+        balance.available -= amount - from_locked;
+    }
+
+    /// 評価額（マーク価格で QUOTE_ASSET に換算）
+    fn value(asset: &str, amount: Decimal, mark: Decimal) -> Decimal {
+        if asset == BASE_ASSET { amount * mark } else { amount }
+    }
+
+    /// (純資産, 借入) の評価額
+    fn equity_and_debt(&self, mark: Decimal) -> (Decimal, Decimal) {
+        let assets: Decimal = self.balances.iter().map(|(a, b)| Self::value(a, b.available + b.locked, mark)).sum();
+        let debt: Decimal = self.loans.iter().map(|(a, l)| Self::value(a, l.owed(), mark)).sum();
+        (assets - debt, debt)
+    }
+
+    /// 証拠金率（借入がなければNone）
+    ///
+    /// 借入がごく小さく Decimal に収まらないほど大きな比率になる場合は、上限・下限の値にする
+    fn margin_ratio(&self, mark: Decimal) -> Option<Decimal> {
+        let (equity, debt) = self.equity_and_debt(mark);
+        if debt <= Decimal::ZERO {
+            return None;
+        }
+        let saturated = if equity.is_sign_negative() { Decimal::MIN } else { Decimal::MAX };
+        Some(equity.checked_div(debt).unwrap_or(saturated))
+    }
+
+    fn has_debt(&self) -> bool {
+        self.loans.values().any(|l| l.owed() > Decimal::ZERO)
+    }
+
+    /// 返済する（利息から先に充てる）。実際に返した額を返す
+    fn repay(&mut self, asset: &str, amount: Decimal) -> Decimal {
+        let Some(loan) = self.loans.get_mut(asset) else {
+            return Decimal::ZERO;
+        };
+        let paid = amount.min(loan.owed());
+        let to_interest = paid.min(loan.interest);
+        loan.interest -= to_interest;
+        loan.principal -= paid - to_interest;
+        if loan.owed() == Decimal::ZERO {
+            self.loans.remove(asset);
+        }
+        self.balance(asset).available -= paid;
+        paid
+    }
+}
+
+/// 証拠金口座の1資産分の残高（GET /margin/account）
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct MarginBalanceView {
+    pub asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub available: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub locked: Decimal, // 証拠金注文に拘束されている分
+}
+
+/// 1資産分の借入（GET /margin/account）
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct LoanView {
+    pub asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub principal: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub interest: Decimal, // 未払いの利息
+}
+
+/// 貸し倒れにした額（GET /margin/account）
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct BadDebtView {
+    pub asset: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub amount: Decimal,
+}
+
+/// 証拠金口座の状態
+///
+/// 評価額はすべて USDC 建て。マーク価格がなければ評価額はnull
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct MarginAccountSummary {
+    pub balances: Vec<MarginBalanceView>,
+    pub loans: Vec<LoanView>,
+    pub bad_debt: Vec<BadDebtView>, // 清算で返し切れなかった分（借入からは外してある）
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub mark_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub equity: Option<Decimal>, // 純資産 = 資産 - 借入
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub debt: Option<Decimal>, // 借入（利息込み）
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub margin_ratio: Option<Decimal>, // 純資産 / 借入（借入がなければnull）
+}
+
+/// 全ユーザーの証拠金口座を管理する
+///
+/// AccountManager と同じくエンジンアクター内で保持する
+#[derive(Debug, Clone, Default)]
+pub struct MarginManager {
+    config: MarginConfig,
+    accounts: HashMap<Uuid, MarginAccount>,
+    last_price: Option<Decimal>, // 最後の約定価格（板が片側しかないときのマーク価格）
+    order_ids: Arc<OrderIdGenerator>, // 清算注文の採番用（APIと共有する）
+}
+
+impl MarginManager {
+    pub fn new(config: MarginConfig, order_ids: Arc<OrderIdGenerator>) -> Self {
+        Self { config, order_ids, ..Self::default() }
+    }
+
+    /// 設定と採番器を差し替える（MarketData::load で読み込んだ口座はそのまま）
+    pub fn configure(&mut self, config: MarginConfig, order_ids: Arc<OrderIdGenerator>) {
+        self.config = config;
+        self.order_ids = order_ids;
+    }
+
+    pub fn config(&self) -> &MarginConfig {
+        &self.config
+    }
+
+    /// 保存済みの口座を読み込む（起動時）
+    ///
+    /// 注文は復元しないので、ロックしていた分は available に戻す
+    pub fn load_account(&mut self, record: MarginAccountRecord) {
+        let mut account = MarginAccount { accrued_at: record.accrued_at, liquidated: record.liquidated, ..Default::default() };
+        for a in record.assets {
+            let total = a.available + a.locked;
+            if !total.is_zero() {
+                account.balance(&a.asset).available = total;
+            }
+            if !a.principal.is_zero() || !a.interest.is_zero() {
+                account.loans.insert(a.asset.clone(), Loan { principal: a.principal, interest: a.interest, dust: Decimal::ZERO });
+            }
+            if !a.bad_debt.is_zero() {
+                account.bad_debt.insert(a.asset, a.bad_debt);
+            }
+        }
+        self.accounts.insert(record.user_id, account);
+    }
+
+    /// 保存用の口座の状態（口座がなければNone）
+    pub fn record(&self, user_id: &Uuid) -> Option<MarginAccountRecord> {
+        let account = self.accounts.get(user_id)?;
+        fn entry<'a>(assets: &'a mut BTreeMap<String, MarginAssetRecord>, asset: &str) -> &'a mut MarginAssetRecord {
+            assets
+                .entry(asset.to_string())
+                .or_insert_with(|| MarginAssetRecord { asset: asset.to_string(), ..Default::default() })
+        }
+        let mut assets: BTreeMap<String, MarginAssetRecord> = BTreeMap::new();
+        for (asset, b) in &account.balances {
+            let a = entry(&mut assets, asset);
+            a.available = b.available;
+            a.locked = b.locked;
+        }
+        for (asset, l) in &account.loans {
+            let a = entry(&mut assets, asset);
+            a.principal = l.principal;
+            a.interest = l.interest;
+        }
+        for (asset, amount) in &account.bad_debt {
+            entry(&mut assets, asset).bad_debt = *amount;
+        }
+        Some(MarginAccountRecord {
+            user_id: *user_id,
+            accrued_at: account.accrued_at,
+            liquidated: account.liquidated,
+            assets: assets.into_values().collect(),
+        })
+    }
+
+    /// 約定を見て最後の約定価格を覚える
+    pub fn on_trades(&mut self, trades: &[Trade]) {
+        if let Some(trade) = trades.last() {
+            self.last_price = Some(trade.price);
+        }
+    }
+
+    /// マーク価格（板の仲値。板が片側しかなければ最後の約定価格）
+    pub fn mark_price(&self, orderbook: &OrderBook) -> Option<Decimal> {
+        orderbook.mid_price().or(self.last_price)
+    }
+
+    fn account(&mut self, user_id: &Uuid, now: u128) -> &mut MarginAccount {
+        let account = self.accounts.entry(*user_id).or_insert_with(|| MarginAccount { accrued_at: now, ..Default::default() });
+        account.accrue(&self.config.daily_rates, now);
+        account
+    }
+
+    /// 口座を変える操作用（清算済みの印を外し、次に証拠金率が下がれば清算し直す）
+    fn account_mut(&mut self, user_id: &Uuid, now: u128) -> &mut MarginAccount {
+        let account = self.account(user_id, now);
+        account.liquidated = false;
+        account
+    }
+
+    /// 証拠金口座で扱う資産か・数量が正かを確かめる
+    pub fn check_asset(asset: &str, amount: Decimal) -> Result<(), MarginError> {
+        if asset != BASE_ASSET && asset != QUOTE_ASSET {
+            return Err(MarginError::UnsupportedAsset(asset.to_string()));
+        }
+        if amount <= Decimal::ZERO {
+            return Err(MarginError::InvalidAmount);
+        }
+        Ok(())
+    }
+
+    /// 現物から移された分を入金する（現物側の引き落としはエンジンが行う）
+    pub fn transfer_in(&mut self, user_id: &Uuid, asset: &str, amount: Decimal, now: u128) -> Result<(), MarginError> {
+        Self::check_asset(asset, amount)?;
+        self.account_mut(user_id, now).balance(asset).available += amount;
+        Ok(())
+    }
+
+    /// 現物へ戻す分を引き落とす（借入があれば、戻した後も initial_margin を満たす必要がある）
+    pub fn transfer_out(&mut self, user_id: &Uuid, asset: &str, amount: Decimal, mark: Option<Decimal>, now: u128) -> Result<(), MarginError> {
+        Self::check_asset(asset, amount)?;
+        let initial_margin = self.config.initial_margin;
+        let account = self.account(user_id, now);
+        if account.balance(asset).available < amount {
+            return Err(MarginError::InsufficientBalance);
+        }
+        account.liquidated = false;
+        if account.has_debt() {
+            let mark = mark.ok_or(MarginError::NoMarkPrice)?;
+            let (equity, debt) = account.equity_and_debt(mark);
+            if equity - MarginAccount::value(asset, amount, mark) < debt * initial_margin {
+                return Err(MarginError::BelowInitialMargin);
+            }
+        }
+        account.balance(asset).available -= amount;
+        Ok(())
+    }
+
+    /// 借りる（借りた後も initial_margin を満たす必要がある）
+    pub fn borrow(&mut self, user_id: &Uuid, asset: &str, amount: Decimal, mark: Option<Decimal>, now: u128) -> Result<(), MarginError> {
+        Self::check_asset(asset, amount)?;
+        if !self.config.daily_rates.contains_key(asset) {
+            return Err(MarginError::NotBorrowable(asset.to_string()));
+        }
+        let mark = mark.ok_or(MarginError::NoMarkPrice)?;
+        let initial_margin = self.config.initial_margin;
+        let account = self.account(user_id, now);
+        // 借りた資産はそのまま口座に入るので、純資産は変わらず借入だけが増える
+        let (equity, debt) = account.equity_and_debt(mark);
+        if equity < (debt + MarginAccount::value(asset, amount, mark)) * initial_margin {
+            return Err(MarginError::BelowInitialMargin);
+        }
+        account.liquidated = false;
+        account.loans.entry(asset.to_string()).or_default().principal += amount;
+        account.balance(asset).available += amount;
+        Ok(())
+    }
+
+    /// 返済する（借入より多く指定した分は返さない）
+    pub fn repay(&mut self, user_id: &Uuid, asset: &str, amount: Decimal, now: u128) -> Result<(), MarginError> {
+        Self::check_asset(asset, amount)?;
+        let account = self.account(user_id, now);
+        let owed = account.loans.get(asset).map(Loan::owed).unwrap_or_default();
+        if account.balance(asset).available < amount.min(owed) {
+            return Err(MarginError::InsufficientBalance);
+        }
+        account.liquidated = false;
+        account.repay(asset, amount);
+        Ok(())
+    }
+
+    /// 証拠金注文の残高チェックとロック（AccountManager::try_lock_balance と同じ額）
+    pub fn try_lock(&mut self, user_id: &Uuid, side: Side, price: Decimal, quantity: u64, now: u128) -> Result<(), MarginError> {
+        let (asset, amount) = match side {
+            Side::Buy => (QUOTE_ASSET, price * Decimal::from(quantity)),
+            Side::Sell => (BASE_ASSET, Decimal::from(quantity)),
+        };
+        let account = self.account(user_id, now);
+        let balance = account.balance(asset);
+        if balance.available < amount {
+            return Err(MarginError::InsufficientBalance);
+        }
+        balance.available -= amount;
+        balance.locked += amount;
+        account.liquidated = false;
+        Ok(())
+    }
+
+    /// 証拠金注文の約定時の残高移動
+    pub fn on_trade_match(&mut self, user_id: &Uuid, side: Side, price: Decimal, quantity: u64) {
+        let qty_dec = Decimal::from(quantity);
+        let trade_value = price * qty_dec;
+        let account = self.accounts.entry(*user_id).or_default();
+        account.liquidated = false;
+        match side {
+            Side::Buy => {
+                account.debit(QUOTE_ASSET, trade_value);
+                account.balance(BASE_ASSET).available += qty_dec;
+            }
+            Side::Sell => {
+                account.debit(BASE_ASSET, qty_dec);
+                account.balance(QUOTE_ASSET).available += trade_value;
+            }
+        }
+    }
+
+    /// 証拠金注文のキャンセル時のロック解除
+    pub fn unlock(&mut self, user_id: &Uuid, side: Side, price: Decimal, quantity: u64) {
+        let (asset, amount) = match side {
+            Side::Buy => (QUOTE_ASSET, price * Decimal::from(quantity)),
+            Side::Sell => (BASE_ASSET, Decimal::from(quantity)),
+        };
+        let balance = self.accounts.entry(*user_id).or_default().balance(asset);
+        balance.locked -= amount;
+        balance.available += amount;
+    }
+
+    /// 口座の状態
+    pub fn summary(&mut self, user_id: &Uuid, mark: Option<Decimal>, now: u128) -> MarginAccountSummary {
+        let account = self.account(user_id, now);
+        let valuation = mark.map(|mark| account.equity_and_debt(mark));
+        MarginAccountSummary {
+            balances: account
+                .balances
+                .iter()
+                .map(|(asset, b)| MarginBalanceView { asset: asset.clone(), available: b.available, locked: b.locked })
+                .collect(),
+            loans: account
+                .loans
+                .iter()
+                .map(|(asset, l)| LoanView { asset: asset.clone(), principal: l.principal, interest: l.interest })
+                .collect(),
+            bad_debt: account
+                .bad_debt
+                .iter()
+                .map(|(asset, amount)| BadDebtView { asset: asset.clone(), amount: *amount })
+                .collect(),
+            mark_price: mark,
+            equity: valuation.map(|(equity, _)| equity),
+            debt: valuation.map(|(_, debt)| debt),
+            margin_ratio: mark.and_then(|mark| account.margin_ratio(mark)),
+        }
+    }
+
+    /// 利息を足したうえで、証拠金率が maintenance_margin を下回ったユーザー（ID順）
+    ///
+    /// 清算済みで、その後に口座が変わっていないユーザーは含めない
+    pub fn undercollateralized(&mut self, mark: Decimal, now: u128) -> Vec<Uuid> {
+        let mut users: Vec<Uuid> = Vec::new();
+        for (user_id, account) in &mut self.accounts {
+            if account.liquidated || !account.has_debt() {
+                continue;
+            }
+            account.accrue(&self.config.daily_rates, now);
+            if account.margin_ratio(mark).is_some_and(|ratio| ratio < self.config.maintenance_margin) {
+                users.push(*user_id);
+            }
+        }
+        users.sort();
+        users
+    }
+
+    /// BAD のポジション（保有 - 借入。利息を足したもの）
+    ///
+    /// 正なら買い持ち、負なら借りて売った売り持ち。reduce_only の注文はこれを0に近づける向きにしか出せない
+    pub fn base_position(&mut self, user_id: &Uuid, now: u128) -> Decimal {
+        let Some(account) = self.accounts.get_mut(user_id) else {
+            return Decimal::ZERO;
+        };
+        account.accrue(&self.config.daily_rates, now);
+        let held = account.balances.get(BASE_ASSET).map(|b| b.available + b.locked).unwrap_or_default();
+        let owed = account.loans.get(BASE_ASSET).map(Loan::owed).unwrap_or_default();
+        held - owed
+    }
+
+    /// 清算で売買する向きと数量（BAD の保有と借入の差。売買しなくてよければNone）
+    ///
+    /// 借入のほうが多ければ買い戻し、保有のほうが多ければ売って USDC にする。
+    /// BAD は整数単位でしか売買できないので、買い戻しは切り上げ、売りは切り捨て
+    pub fn liquidation_side(&self, user_id: &Uuid) -> Option<(Side, u64)> {
+        let account = self.accounts.get(user_id)?;
+        let held = account.balances.get(BASE_ASSET).map(|b| b.available + b.locked).unwrap_or_default();
+        let owed = account.loans.get(BASE_ASSET).map(Loan::owed).unwrap_or_default();
+        let (side, quantity) = if held < owed {
+            (Side::Buy, (owed - held).ceil())
+        } else {
+            (Side::Sell, (held - owed).floor())
+        };
+        let quantity = u64::try_from(quantity).unwrap_or_default();
+        (quantity > 0).then_some((side, quantity))
+    }
+
+    /// 清算の成行注文（証拠金注文をすべてキャンセルした後に呼ぶ）
+    ///
+    /// 注文はロックせずに出し、約定の代金は available から払う。
+    /// 買い戻しは、板の売りを安い順にたどって available の USDC で払える数量までにする
+    pub fn liquidation_orders(&mut self, user_id: &Uuid, orderbook: &OrderBook) -> Vec<Order> {
+        let Some((side, mut quantity)) = self.liquidation_side(user_id) else {
+            return Vec::new();
+        };
+        if side == Side::Buy {
+            let budget = self.accounts[user_id].balances.get(QUOTE_ASSET).map(|b| b.available).unwrap_or_default();
+            quantity = affordable_quantity(orderbook, budget, quantity);
+        }
+        if quantity == 0 {
+            return Vec::new();
+        }
+        vec![Order {
+            id: self.order_ids.next_id(),
+            price: Decimal::ZERO,
+            quantity,
+            side,
+            user_id: Some(*user_id),
+            order_type: OrderType::Market,
+            ..Default::default()
+        }]
+    }
+
+    /// 清算の後、口座の資産で返せるだけ借入を返し、清算済みの印を付ける
+    ///
+    /// 注文はすべてキャンセル済みなので、残っているロック（指値より安く買えた分など）も返済に充てる。
+    /// それでも返し切れず、残りの資産ではもう返済に充てる分を手に入れられない借入
+    /// （BAD ならマーク価格で1単位も買えない、USDC なら売れる BAD がない）は貸し倒れにして返す（資産, 額）。
+    ///
+    /// 清算の注文で相手側の板を食い尽くした場合は清算済みの印を付けず、板に注文が来たら続きを清算する
+    pub fn settle_loans(&mut self, user_id: &Uuid, orderbook: &OrderBook) -> Vec<(String, Decimal)> {
+        let mark = self.mark_price(orderbook);
+        let exhausted = match self.liquidation_side(user_id) {
+            Some((Side::Buy, _)) => orderbook.asks.is_empty(),
+            Some((Side::Sell, _)) => orderbook.bids.is_empty(),
+            None => false,
+        };
+        let Some(account) = self.accounts.get_mut(user_id) else {
+            return Vec::new();
+        };
+        account.liquidated = !exhausted;
+        for balance in account.balances.values_mut() {
+            balance.available += balance.locked;
+            balance.locked = Decimal::ZERO;
+        }
+        let assets: Vec<String> = account.loans.keys().cloned().collect();
+        for asset in assets {
+            let available = account.balance(&asset).available.max(Decimal::ZERO);
+            account.repay(&asset, available);
+        }
+
+        let base = account.balances.get(BASE_ASSET).map(|b| b.available).unwrap_or_default();
+        let quote = account.balances.get(QUOTE_ASSET).map(|b| b.available).unwrap_or_default();
+        let mut written_off: Vec<(String, Decimal)> = Vec::new();
+        for (asset, loan) in &account.loans {
+            let unrecoverable = match asset.as_str() {
+                BASE_ASSET => base <= Decimal::ZERO && mark.is_none_or(|mark| quote < mark),
+                _ => quote <= Decimal::ZERO && base < Decimal::ONE,
+            };
+            if unrecoverable {
+                written_off.push((asset.clone(), loan.owed()));
+            }
+        }
+        for (asset, amount) in &written_off {
+            account.loans.remove(asset);
+            *account.bad_debt.entry(asset.clone()).or_default() += *amount;
+        }
+        written_off
+    }
+}
+
+/// 板の売りを安い順にたどり、budget で買える数量（最大 max）
+///
+/// 成行の買いはこの順に約定するので、この数量までなら代金は budget を超えない。
+/// budget / 価格 が Decimal に収まらないほど大きければ、その価格の数量は全部買える
+fn affordable_quantity(orderbook: &OrderBook, budget: Decimal, max: u64) -> u64 {
+    let mut remaining = budget;
+    let mut quantity = 0;
+    for (price, orders) in &orderbook.asks {
+        let level: u64 = orders.iter().map(|o| o.quantity).sum();
+        let wanted = level.min(max - quantity);
+        let affordable = match remaining.checked_div(*price) {
+            Some(units) => u64::try_from(units.floor().max(Decimal::ZERO)).unwrap_or(u64::MAX).min(wanted),
+            None => wanted, // 価格0、または割った値が大きすぎる
+        };
+        quantity += affordable;
+        remaining -= *price * Decimal::from(affordable);
+        if affordable < level || quantity == max {
+            break;
+        }
+    }
+    quantity
+}
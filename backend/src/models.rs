@@ -1,19 +1,38 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::db::DEFAULT_ASSETS;
+use crate::margin::QUOTE_ASSET;
+use crate::storage::to_scaled;
+
 /// 注文の売買方向を表す列挙型
 /// 
 /// - Buy: 買い注文（指定価格以下の売り注文があれば約定、なければ板に追加）
 /// - Sell: 売り注文（指定価格以上の買い注文があれば約定、なければ板に追加）
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum Side {
     Buy,
     Sell,
 }
 
+impl Side {
+    /// 反対側（約定相手の売買方向）
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
 /// 注文の種類
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum OrderType {
     Limit,  // 指値注文
     Market, // 成行注文
@@ -27,7 +46,9 @@ pub enum OrderType {
 /// - quantity: 数量（いくつ欲しいか/売りたいか）
 /// - side: 買いか売りか
 /// - order_type: 指値か成行か
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// - reduce_only: ポジション（保有量）を減らす方向にしか約定しない注文か
+/// - close_position: ポジション全体を閉じる注文か（reduce_only を兼ね、数量はエンジンが決める）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct Order {
     pub id: u64,
     #[serde(with = "rust_decimal::serde::str")] // JSONでは文字列として扱う（精度を保つため）
@@ -38,29 +59,424 @@ pub struct Order {
     pub user_id: Option<Uuid>,
     #[serde(default = "default_order_type")] 
     pub order_type: OrderType,
+    #[serde(default)]
+    pub reduce_only: bool,
+    #[serde(default)]
+    pub close_position: bool,
 }
 
 fn default_order_type() -> OrderType {
     OrderType::Limit
 }
 
+/// 既定値（ID 0・価格 0・数量 0 の買いの指値で、reduce_only・close_position なし）
+///
+/// reduce_only などのフラグを使わない注文は `..Default::default()` で省略して書ける
+impl Default for Order {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            price: Decimal::ZERO,
+            quantity: 0,
+            side: Side::Buy,
+            user_id: None,
+            order_type: default_order_type(),
+            reduce_only: false,
+            close_position: false,
+        }
+    }
+}
+
+/// 受け付けられない注文の項目（価格の刻み・保存形式の10^8倍の i64 で表せない値）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnrepresentableOrder {
+    Tick(u32), // 価格が決済資産の桁数（値）より細かい
+    Price,     // 価格が小数点以下8桁を超える・範囲外
+    Notional,  // 価格 × 数量 が範囲外
+}
+
+impl fmt::Display for UnrepresentableOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnrepresentableOrder::Tick(precision) => write!(f, "price は小数点以下{}桁までで指定してください", precision),
+            UnrepresentableOrder::Price => write!(f, "price は小数点以下8桁まで・保存できる範囲で指定してください"),
+            UnrepresentableOrder::Notional => write!(f, "price × quantity が保存できる範囲を超えています"),
+        }
+    }
+}
+
+impl Order {
+    /// 価格が刻み（決済資産の桁数）に合い、価格と約定代金（価格 × 数量）が保存形式で表せるか
+    ///
+    /// 表せない注文が約定すると、エンジンの残高だけが動いて DB への保存が失敗する。
+    /// エンジンに渡す前に入口（REST・FIX・gRPC）で弾くこと
+    pub fn check_representable(&self, assets: &AssetPrecisions) -> Result<(), UnrepresentableOrder> {
+        if !assets.allows(QUOTE_ASSET, self.price) {
+            return Err(UnrepresentableOrder::Tick(assets.precision(QUOTE_ASSET).unwrap_or_default()));
+        }
+        to_scaled(self.price).map_err(|_| UnrepresentableOrder::Price)?;
+        match self.price.checked_mul(Decimal::from(self.quantity)).map(to_scaled) {
+            Some(Ok(_)) => Ok(()),
+            _ => Err(UnrepresentableOrder::Notional),
+        }
+    }
+}
+
 /// 約定（マッチングが成立した取引）を表す構造体
 /// 
 /// 取引が成立すると、買い手と売り手の注文がマッチして約定が生成されます。
 /// 
 /// # フィールド
+/// - id: 約定ID（エンジンが1から連番で採番。ページングのカーソルに使う）
 /// - maker_id: 先に板に注文を出していた側のID（流動性を提供した側）
 /// - taker_id: 後から来て即座に約定した側のID（流動性を消費した側）
 /// - price: 約定価格
 /// - quantity: 約定数量
+/// - taker_side: テイカーの売買方向（Buyなら買いが売り板を食った）
 /// - timestamp: 約定時刻（ミリ秒単位のUNIXタイムスタンプ）
-#[derive(Debug, Serialize, Clone)]
+/// - maker_user_id / taker_user_id: 注文の所有者（公開APIには出さない）
+#[derive(Debug, Serialize, Clone, PartialEq, ToSchema)]
 pub struct Trade {
+    pub id: u64,
     pub maker_id: u64,
     pub taker_id: u64,
     #[serde(with = "rust_decimal::serde::str")] // JSONでは文字列として扱う
     pub price: Decimal,
     pub quantity: u64,
+    pub taker_side: Side,
     pub timestamp: u128, // u128を使う理由: ミリ秒単位だとu64では2500万年後に溢れる
                           // u128なら事実上無限に使える
+    #[serde(skip)]
+    pub maker_user_id: Option<Uuid>, // シミュレータの注文ならNone
+    #[serde(skip)]
+    pub taker_user_id: Option<Uuid>,
+}
+
+/// 注文のライフサイクル上の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum OrderStatus {
+    New,             // 板に載った（未約定）
+    PartiallyFilled, // 一部約定
+    Filled,          // 全約定
+    Cancelled,       // キャンセル済み
+    Rejected,        // 受付拒否（残高不足など）
+    Expired,         // 成行の未約定分など、板に載らずに失効
+}
+
+impl OrderStatus {
+    /// DB保存用の文字列表現
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::New => "New",
+            OrderStatus::PartiallyFilled => "PartiallyFilled",
+            OrderStatus::Filled => "Filled",
+            OrderStatus::Cancelled => "Cancelled",
+            OrderStatus::Rejected => "Rejected",
+            OrderStatus::Expired => "Expired",
+        }
+    }
+
+    /// 文字列から復元する（不明な値はNone）
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "New" => Some(OrderStatus::New),
+            "PartiallyFilled" => Some(OrderStatus::PartiallyFilled),
+            "Filled" => Some(OrderStatus::Filled),
+            "Cancelled" => Some(OrderStatus::Cancelled),
+            "Rejected" => Some(OrderStatus::Rejected),
+            "Expired" => Some(OrderStatus::Expired),
+            _ => None,
+        }
+    }
+
+    /// まだ板に残っている（約定・キャンセルされうる）状態か
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+}
+
+/// 永続化される注文の記録
+///
+/// 板上の`Order`は残数量しか持たないため、履歴照会用に
+/// 元の数量・約定済み数量・状態を別に保持する。
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct OrderRecord {
+    pub id: u64,
+    pub user_id: Uuid,
+    pub side: Side,
+    pub order_type: OrderType,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price: Decimal,
+    pub quantity: u64,        // 発注時の数量
+    pub filled_quantity: u64, // 約定済み数量
+    #[serde(with = "rust_decimal::serde::str")]
+    pub filled_quote: Decimal, // 約定代金の合計（価格 × 数量）。平均約定価格の計算に使う
+    pub status: OrderStatus,
+    pub created_at: u128, // ミリ秒単位のUNIXタイムスタンプ
+    pub updated_at: u128,
+}
+
+impl OrderRecord {
+    /// 受け付けた注文から、未約定（New）の記録を作る
+    pub fn new(order: &Order, user_id: Uuid, now: u128) -> Self {
+        Self {
+            id: order.id,
+            user_id,
+            side: order.side,
+            order_type: order.order_type,
+            price: order.price,
+            quantity: order.quantity,
+            filled_quantity: 0,
+            filled_quote: Decimal::ZERO,
+            status: OrderStatus::New,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// 約定を反映する（全量約定なら Filled、それ以外は PartiallyFilled）
+    pub fn apply_fill(&mut self, price: Decimal, quantity: u64, now: u128) {
+        self.filled_quantity += quantity;
+        self.filled_quote += price * Decimal::from(quantity);
+        self.status = if self.filled_quantity >= self.quantity {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        self.updated_at = now;
+    }
+
+    /// 状態を変更する（キャンセル・失効など）
+    pub fn close(&mut self, status: OrderStatus, now: u128) {
+        self.status = status;
+        self.updated_at = now;
+    }
+
+    /// 平均約定価格（未約定、または約定代金を記録していなかった頃の注文ならNone）
+    pub fn avg_fill_price(&self) -> Option<Decimal> {
+        (self.filled_quantity > 0 && !self.filled_quote.is_zero())
+            .then(|| (self.filled_quote / Decimal::from(self.filled_quantity)).normalize())
+    }
+}
+
+/// ローソク足の時間足
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "15m")]
+    FifteenMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    /// サポートしている全ての時間足
+    pub const ALL: [CandleInterval; 5] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::FifteenMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+
+    /// 1本の長さ（ミリ秒）
+    pub fn duration_ms(&self) -> u128 {
+        match self {
+            CandleInterval::OneMinute => 60_000,
+            CandleInterval::FiveMinutes => 5 * 60_000,
+            CandleInterval::FifteenMinutes => 15 * 60_000,
+            CandleInterval::OneHour => 60 * 60_000,
+            CandleInterval::OneDay => 24 * 60 * 60_000,
+        }
+    }
+
+    /// DB保存・クエリパラメータ用の文字列表現
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::OneMinute => "1m",
+            CandleInterval::FiveMinutes => "5m",
+            CandleInterval::FifteenMinutes => "15m",
+            CandleInterval::OneHour => "1h",
+            CandleInterval::OneDay => "1d",
+        }
+    }
+
+    /// 文字列から復元する（不明な値はNone）
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|i| i.as_str() == s)
+    }
+}
+
+/// OHLCVローソク足
+///
+/// open_time はその足の開始時刻（時間足の長さで切り捨てたミリ秒）
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct Candle {
+    pub interval: CandleInterval,
+    pub open_time: u128,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub open: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub high: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub low: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub close: Decimal,
+    pub volume: u64, // 出来高（BAD数量）
+    #[serde(with = "rust_decimal::serde::str")]
+    pub quote_volume: Decimal, // 売買代金（USDC）
+    pub trade_count: u64,
+}
+
+/// 資産（通貨）の登録情報
+///
+/// 残高は登録のない資産でも持てる。表示名・桁数は画面の表示や入力の刻みに使う
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct Asset {
+    pub asset: String,        // 資産名（残高の asset と同じ。例: "USDC"）
+    pub display_name: String, // 表示名
+    pub precision: u32,       // 小数点以下の桁数
+}
+
+/// 資産ごとの小数点以下の桁数（起動時に assets テーブルから読み込む）
+///
+/// 入口（REST・FIX・gRPC）で、価格は決済資産（USDC）の桁数、
+/// 振替・借入・返済の金額はその資産の桁数より細かい値を弾くのに使う
+#[derive(Debug, Clone)]
+pub struct AssetPrecisions(HashMap<String, u32>);
+
+impl AssetPrecisions {
+    pub fn new(assets: &[Asset]) -> Self {
+        Self(assets.iter().map(|a| (a.asset.clone(), a.precision)).collect())
+    }
+
+    /// 資産の桁数（登録されていなければ None）
+    pub fn precision(&self, asset: &str) -> Option<u32> {
+        self.0.get(asset).copied()
+    }
+
+    /// value が asset の桁数に収まるか（登録されていない資産は確かめない）
+    pub fn allows(&self, asset: &str, value: Decimal) -> bool {
+        self.precision(asset).is_none_or(|precision| value.normalize().scale() <= precision)
+    }
+}
+
+impl Default for AssetPrecisions {
+    /// 新規DBと同じく DEFAULT_ASSETS の桁数
+    fn default() -> Self {
+        Self(DEFAULT_ASSETS.iter().map(|(asset, _, precision)| (asset.to_string(), *precision)).collect())
+    }
+}
+
+/// APIキーの権限
+///
+/// 権限は独立しており、trade を持つキーでも GET には read が必要
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    Read,     // 残高・約定履歴などの参照
+    Trade,    // 発注・キャンセル
+    Withdraw, // 資金の移動（証拠金口座・先物ウォレットとの振替）
+}
+
+impl ApiScope {
+    /// DB保存用の文字列表現
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Trade => "trade",
+            ApiScope::Withdraw => "withdraw",
+        }
+    }
+
+    /// 文字列から復元する（不明な値はNone）
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(ApiScope::Read),
+            "trade" => Some(ApiScope::Trade),
+            "withdraw" => Some(ApiScope::Withdraw),
+            _ => None,
+        }
+    }
+}
+
+/// ボット用のAPIキー
+///
+/// secret は署名の検証に使うので復元可能な形で保存し、
+/// 発行時のレスポンス以外では返さない
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct ApiKey {
+    pub key: String, // 公開ID（X-API-KEY ヘッダーで送る）
+    #[serde(skip)]
+    pub secret: String, // HMAC-SHA256 の鍵
+    pub user_id: Uuid,
+    pub label: String,
+    pub scopes: Vec<ApiScope>,
+    #[schema(value_type = Vec<String>)]
+    pub ip_allowlist: Vec<IpAddr>, // 空なら全IPを許可
+    pub created_at: u128,
+    pub revoked_at: Option<u128>, // 失効済みなら失効時刻
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// 接続元IPが許可リストに含まれるか（IPが不明ならリストがある限り拒否）
+    pub fn allows_ip(&self, ip: Option<IpAddr>) -> bool {
+        self.ip_allowlist.is_empty() || ip.is_some_and(|ip| self.ip_allowlist.contains(&ip))
+    }
+}
+
+/// FIXセッションのシーケンス番号（このサーバーから見た値）
+///
+/// 再接続しても続きから使えるよう、メッセージを送受信するたびに保存する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixSequence {
+    pub next_outgoing: u64, // 次に送るメッセージの MsgSeqNum
+    pub next_incoming: u64, // 次に届くはずの MsgSeqNum
+}
+
+impl Default for FixSequence {
+    fn default() -> Self {
+        Self { next_outgoing: 1, next_incoming: 1 }
+    }
+}
+
+/// 証拠金口座の保存形式（margin.rs の口座を、変わるたびにこの形で保存する）
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MarginAccountRecord {
+    pub user_id: Uuid,
+    pub accrued_at: u128, // 利息を最後に足した時刻（ミリ秒）
+    pub liquidated: bool, // 清算済みで、その後に口座が変わっていない
+    pub assets: Vec<MarginAssetRecord>, // 資産名の順
+}
+
+/// 証拠金口座の1資産分（残高・借入・貸し倒れ）
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MarginAssetRecord {
+    pub asset: String,
+    pub available: Decimal,
+    pub locked: Decimal,
+    pub principal: Decimal,
+    pub interest: Decimal, // 未払いの利息
+    pub bad_debt: Decimal,
+}
+
+/// 先物口座の保存形式（perp.rs のウォレット・ポジションを、変わるたびにこの形で保存する）
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PerpAccountRecord {
+    pub user_id: Uuid,
+    pub wallet_balance: Decimal, // USDC
+    pub size: i64,               // 符号付きの枚数（買い持ち +、売り持ち -）
+    pub entry_price: Decimal,    // 平均建値（ポジションがなければ0）
+    pub realized_pnl: Decimal,
+    pub funding: Decimal,        // 受け取った資金調達料の累計（払えば負）
+    pub bad_debt: Decimal,
 }
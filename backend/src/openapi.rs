@@ -0,0 +1,130 @@
+// =============================================================================
+// OpenAPI
+// =============================================================================
+//
+// api.rs のハンドラーに付けた `#[utoipa::path]` と、リクエスト・レスポンスの型の
+// `ToSchema` から、OpenAPI 3 のスキーマを組み立てます。
+//
+// - GET /openapi.json: スキーマ本体
+// - GET /docs: Swagger UI（本体はCDNから読み込む）
+//
+// 生成したスキーマは backend/openapi.json にも保存してあり、フロントエンドの型は
+// そこから生成します。ハンドラーや型を変えたら
+//   UPDATE_OPENAPI=1 cargo test --test openapi_test
+// で保存し直してください（保存し直さないと openapi_test が落ちます）。
+// WebSocket（/ws など）は OpenAPI で表せないので含めていません。
+// =============================================================================
+
+use axum::response::Html;
+use axum::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::api;
+
+/// REST API のスキーマ
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "badbit API", description = "BAD/USDC 取引所のREST API"),
+    paths(
+        api::register,
+        api::login,
+        api::logout,
+        api::create_api_key,
+        api::list_api_keys,
+        api::revoke_api_key,
+        api::get_orderbook,
+        api::get_orderbook_l3,
+        api::get_depth,
+        api::get_trades,
+        api::get_candles,
+        api::get_ticker,
+        api::get_assets,
+        api::get_health,
+        api::get_balance,
+        api::get_balances,
+        api::create_order,
+        api::cancel_order,
+        api::get_open_orders,
+        api::get_order,
+        api::get_order_history,
+        api::get_my_trades,
+        api::get_margin_account,
+        api::margin_transfer,
+        api::margin_borrow,
+        api::margin_repay,
+        api::create_margin_order,
+        api::get_perp_market,
+        api::get_perp_orderbook,
+        api::get_perp_account,
+        api::perp_transfer,
+        api::create_perp_order,
+        api::get_perp_open_orders,
+    ),
+    modifiers(&Finish),
+    tags(
+        (name = "auth", description = "ユーザー登録・ログイン"),
+        (name = "api-keys", description = "APIキーの管理（ブラウザのセッションのみ）"),
+        (name = "market", description = "板・約定・ローソク足などの公開情報"),
+        (name = "account", description = "残高・注文・自分の約定（要ログイン）"),
+        (name = "margin", description = "証拠金取引のプロトタイプ（要ログイン。口座は再起動で消える）"),
+        (name = "perp", description = "無期限先物 BAD-PERP のプロトタイプ（口座・ポジションは再起動で消える）"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// 認証方式（セッショントークン・APIキー）を登録する
+///
+/// Cargo.toml に license がなく空の license が入るので、それも外す
+struct Finish;
+
+impl Modify for Finish {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("POST /auth/login で発行したトークン"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-API-KEY",
+                "X-TIMESTAMP と X-SIGNATURE（HMAC-SHA256 署名）も必要。署名の作り方は auth.rs を参照",
+            ))),
+        );
+    }
+}
+
+/// GET /openapi.json - OpenAPI 3 のスキーマ
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI のページ（/openapi.json を読み込む）
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="ja">
+<head>
+  <meta charset="utf-8" />
+  <title>badbit API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// GET /docs - Swagger UI
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
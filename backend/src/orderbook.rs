@@ -1,7 +1,8 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::time::SystemTime;
 use rust_decimal::Decimal;
 use serde::Serialize;
+use utoipa::ToSchema;
 use crate::models::{Order, Trade, Side, OrderType};
 
 /// OrderBook（板）を表す構造体
@@ -11,6 +12,8 @@ use crate::models::{Order, Trade, Side, OrderType};
 /// # フィールド
 /// - bids: 買い注文一覧（価格→注文キューのマップ）
 /// - asks: 売り注文一覧（価格→注文キューのマップ）
+/// - next_trade_id: 次に発行する約定ID（板と一緒に採番状態を持つ。配信はしない）
+/// - sequence: 板が変化するたびに1ずつ増える番号（クライアントがスナップショットの新旧を比べるのに使う）
 /// 
 /// # なぜBTreeMapを使うのか？
 /// - 価格順にソートされた状態を維持できる
@@ -27,42 +30,156 @@ pub struct OrderBook {
     // これはDecimalを使う大きなメリットの一つ
     pub bids: BTreeMap<Decimal, VecDeque<Order>>, // 買い板
     pub asks: BTreeMap<Decimal, VecDeque<Order>>, // 売り板
+    pub next_trade_id: u64, // 再起動時はエンジンが保存済みの最大ID + 1 をセットする
+    pub sequence: u64,
+    // 前回 take_delta してから変化した価格（差分配信用）
+    changed_bids: BTreeSet<Decimal>,
+    changed_asks: BTreeSet<Decimal>,
+    delta_sequence: u64, // 前回 take_delta した時点の sequence
 }
 
-/// OrderBook用のカスタムシリアライズ実装
+/// 公開用の板の1価格帯（L2）
 /// 
-/// # なぜ手動実装するのか？
-/// - Decimalをそのままキーにするとフロントエンドで扱いにくい
-/// - 価格を文字列キーとしてJSONに出力したい
-/// - 例: { "100.500": [...] } のようなJSON形式にする
-impl Serialize for OrderBook {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        use serde::ser::SerializeStruct;
-        
-        // 2つのフィールド（bids, asks）を持つ構造体としてシリアライズ
-        let mut state = serializer.serialize_struct("OrderBook", 2)?;
-
-        // bidsをシリアライズ: Decimalを文字列キーに変換
-        let bids: BTreeMap<String, &VecDeque<Order>> = self
-            .bids
-            .iter()
-            .map(|(k, v)| (k.to_string(), v)) // Decimalはto_string()で正確な文字列に
-            .collect();
-        state.serialize_field("bids", &bids)?;
-
-        // asksも同様に変換
-        let asks: BTreeMap<String, &VecDeque<Order>> = self
-            .asks
-            .iter()
-            .map(|(k, v)| (k.to_string(), v))
-            .collect();
-        state.serialize_field("asks", &asks)?;
-
-        state.end()
+/// 個々の注文や所有者（user_id）は含めず、価格ごとの合計だけを出す。
+/// OrderBook 自体は Serialize を実装しないので、外に出すときは必ずこの形か L3Snapshot に変換する
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PriceLevel {
+    #[serde(serialize_with = "crate::encoding::price::serialize")] // MessagePackでは固定小数点の整数
+    pub price: Decimal,
+    pub quantity: u64,      // この価格の合計数量
+    pub order_count: usize, // この価格に並んでいる注文数
+}
+
+/// 公開用の板（L2）
+/// 
+/// bids は高い順、asks は安い順（どちらも最良気配が先頭）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct BookSnapshot {
+    pub sequence: u64, // このスナップショット時点の OrderBook::sequence
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+/// 板の差分（L2）
+/// 
+/// prev_sequence より後、sequence までに変化した価格帯の「変化後の値」だけを持つ。
+/// 数量0（order_count 0）はその価格帯が消えたことを表す。
+/// 値は増減ではなく絶対値なので、同じ差分を二重に適用しても結果は変わらない。
+/// 
+/// クライアントは手元の sequence が prev_sequence 以上なら適用でき、
+/// prev_sequence より小さければ取りこぼしがあるのでスナップショットを取り直す
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BookDelta {
+    pub prev_sequence: u64,
+    pub sequence: u64,
+    pub bids: Vec<PriceLevel>, // 価格の高い順
+    pub asks: Vec<PriceLevel>, // 価格の安い順
+}
+
+impl BookDelta {
+    /// 変化がないか
+    pub fn is_empty(&self) -> bool {
+        self.prev_sequence == self.sequence
+    }
+}
+
+/// L3配信用の注文（注文IDは出すが、所有者は出さない）
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct L3Order {
+    pub id: u64,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price: Decimal,
+    pub quantity: u64,
+}
+
+/// 個々の注文まで含む板（L3）
+/// 
+/// 並び順は BookSnapshot と同じで、同じ価格の中は時間優先（約定する順）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct L3Snapshot {
+    pub bids: Vec<L3Order>,
+    pub asks: Vec<L3Order>,
+}
+
+/// 板の深さ（GET /depth）の1段
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DepthLevel {
+    #[serde(serialize_with = "crate::encoding::price::serialize")]
+    pub price: Decimal,           // まとめた場合は刻みに丸めた価格
+    pub quantity: u64,
+    pub order_count: usize,
+    pub cumulative_quantity: u64, // 最良気配からこの段までの合計数量
+}
+
+/// 板の深さ（上位N段）
+/// 
+/// bids は高い順、asks は安い順（どちらも最良気配が先頭）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct DepthSnapshot {
+    pub sequence: u64, // このスナップショット時点の OrderBook::sequence
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// 最良気配から順に価格帯を読み、上位 limit 段にまとめる
+/// 
+/// group を指定すると価格を round で刻みに丸め、同じ刻みの価格帯を合算する。
+/// 丸めた価格が Decimal に収まらない価格帯は飛ばす（エンジンを止めないため、パニックさせない）。
+/// limit 段が埋まった時点で読むのをやめるので、板全体を走査・コピーしない
+fn depth_levels<'a>(
+    side: impl Iterator<Item = (&'a Decimal, &'a VecDeque<Order>)>,
+    limit: usize,
+    group: Option<Decimal>,
+    round: fn(Decimal) -> Decimal,
+) -> Vec<DepthLevel> {
+    let mut levels: Vec<DepthLevel> = Vec::with_capacity(limit);
+    for (price, orders) in side {
+        let price = match group {
+            Some(group) => match price.checked_div(group).and_then(|p| round(p).checked_mul(group)) {
+                Some(price) => price,
+                None => continue,
+            },
+            None => *price,
+        };
+        let quantity: u64 = orders.iter().map(|o| o.quantity).sum();
+        match levels.last_mut() {
+            Some(last) if last.price == price => {
+                last.quantity += quantity;
+                last.order_count += orders.len();
+            }
+            _ => {
+                if levels.len() == limit {
+                    break;
+                }
+                levels.push(DepthLevel { price, quantity, order_count: orders.len(), cumulative_quantity: 0 });
+            }
+        }
+    }
+
+    let mut cumulative = 0;
+    for level in &mut levels {
+        cumulative += level.quantity;
+        level.cumulative_quantity = cumulative;
     }
+    levels
+}
+
+/// 価格帯ごとに合計する
+fn levels<'a>(side: impl Iterator<Item = (&'a Decimal, &'a VecDeque<Order>)>) -> Vec<PriceLevel> {
+    side.map(|(price, orders)| PriceLevel {
+        price: *price,
+        quantity: orders.iter().map(|o| o.quantity).sum(),
+        order_count: orders.len(),
+    })
+    .collect()
+}
+
+/// 所有者を外して注文を並べる
+fn l3_orders<'a>(side: impl Iterator<Item = (&'a Decimal, &'a VecDeque<Order>)>) -> Vec<L3Order> {
+    side.flat_map(|(price, orders)| {
+        orders.iter().map(move |o| L3Order { id: o.id, price: *price, quantity: o.quantity })
+    })
+    .collect()
 }
 
 /// Defaultトレイトの実装
@@ -82,9 +199,72 @@ impl OrderBook {
         Self {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            next_trade_id: 1,
+            sequence: 0,
+            changed_bids: BTreeSet::new(),
+            changed_asks: BTreeSet::new(),
+            delta_sequence: 0,
         }
     }
 
+    /// 前回呼んでから変化した価格帯の差分を取り出す（変化の記録はクリアされる）
+    pub fn take_delta(&mut self) -> BookDelta {
+        let level = |side: &BTreeMap<Decimal, VecDeque<Order>>, price: Decimal| match side.get(&price) {
+            Some(orders) => PriceLevel {
+                price,
+                quantity: orders.iter().map(|o| o.quantity).sum(),
+                order_count: orders.len(),
+            },
+            None => PriceLevel { price, quantity: 0, order_count: 0 },
+        };
+        let delta = BookDelta {
+            prev_sequence: self.delta_sequence,
+            sequence: self.sequence,
+            bids: self.changed_bids.iter().rev().map(|p| level(&self.bids, *p)).collect(),
+            asks: self.changed_asks.iter().map(|p| level(&self.asks, *p)).collect(),
+        };
+        self.changed_bids.clear();
+        self.changed_asks.clear();
+        self.delta_sequence = self.sequence;
+        delta
+    }
+
+    /// 公開用の板（L2）を作る
+    pub fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            sequence: self.sequence,
+            bids: levels(self.bids.iter().rev()),
+            asks: levels(self.asks.iter()),
+        }
+    }
+
+    /// 上位 limit 段の板の深さを作る
+    /// 
+    /// group（価格の刻み、正の値）を指定すると、買いは切り捨て・売りは切り上げで
+    /// 刻みに丸めてまとめる（まとめた段が相手側と重ならないように）
+    pub fn depth(&self, limit: usize, group: Option<Decimal>) -> DepthSnapshot {
+        DepthSnapshot {
+            sequence: self.sequence,
+            bids: depth_levels(self.bids.iter().rev(), limit, group, |p| p.floor()),
+            asks: depth_levels(self.asks.iter(), limit, group, |p| p.ceil()),
+        }
+    }
+
+    /// 注文IDまで含む板（L3）を作る
+    pub fn l3_snapshot(&self) -> L3Snapshot {
+        L3Snapshot {
+            bids: l3_orders(self.bids.iter().rev()),
+            asks: l3_orders(self.asks.iter()),
+        }
+    }
+
+    /// 最良買値と最良売値の仲値（どちらかの板が空ならNone）
+    pub fn mid_price(&self) -> Option<Decimal> {
+        let best_bid = self.bids.keys().next_back()?;
+        let best_ask = self.asks.keys().next()?;
+        Some((best_bid + best_ask) / Decimal::TWO)
+    }
+
     /// 注文を処理し、マッチングを行う
     /// 
     /// これが取引所の心臓部。注文が来たら:
@@ -99,6 +279,7 @@ impl OrderBook {
     /// - 生成された約定のリスト（マッチしなければ空のVec）
     pub fn process_order(&mut self, mut taker_order: Order) -> Vec<Trade> {
         let mut trades = Vec::new();
+        let mut rested = false; // 残りを板に載せたか
         
         // 現在時刻を取得（約定のタイムスタンプ用）
         let now = SystemTime::now()
@@ -135,6 +316,7 @@ impl OrderBook {
                     // その価格にある注文一覧を取得
                     // unwrap()は安全: 上でkeysから取得したキーなので必ず存在する
                     let orders_at_price = self.asks.get_mut(&first_price).unwrap();
+                    self.changed_asks.insert(first_price);
                     
                     // その価格帯の注文を順番に処理
                     while taker_order.quantity > 0 && !orders_at_price.is_empty() {
@@ -147,12 +329,17 @@ impl OrderBook {
 
                         // 約定を記録
                         trades.push(Trade {
+                            id: self.next_trade_id,
                             maker_id: maker_order.id,
                             taker_id: taker_order.id,
                             price: first_price, // Decimalはそのまま使える
                             quantity: match_quantity,
+                            taker_side: taker_order.side,
                             timestamp: now,
+                            maker_user_id: maker_order.user_id,
+                            taker_user_id: taker_order.user_id,
                         });
+                        self.next_trade_id += 1;
 
                         // 各注文の残数量を更新
                         taker_order.quantity -= match_quantity;
@@ -178,6 +365,8 @@ impl OrderBook {
                         .entry(taker_price)           // そのキーのエントリーを取得
                         .or_default()                 // なければデフォルト値（空のVecDeque）を作成
                         .push_back(taker_order);       // キューの末尾に追加
+                    self.changed_bids.insert(taker_price);
+                    rested = true;
                 }
             }
             Side::Sell => {
@@ -202,18 +391,24 @@ impl OrderBook {
                     };
 
                     let orders_at_price = self.bids.get_mut(&first_price).unwrap();
+                    self.changed_bids.insert(first_price);
                     while taker_order.quantity > 0 && !orders_at_price.is_empty() {
                         let mut maker_order = orders_at_price.pop_front().unwrap();
                         let match_quantity =
                             std::cmp::min(taker_order.quantity, maker_order.quantity);
 
                         trades.push(Trade {
+                            id: self.next_trade_id,
                             maker_id: maker_order.id,
                             taker_id: taker_order.id,
                             price: first_price, // Decimalはそのまま使える
                             quantity: match_quantity,
+                            taker_side: taker_order.side,
                             timestamp: now,
+                            maker_user_id: maker_order.user_id,
+                            taker_user_id: taker_order.user_id,
                         });
+                        self.next_trade_id += 1;
 
                         taker_order.quantity -= match_quantity;
                         maker_order.quantity -= match_quantity;
@@ -233,9 +428,15 @@ impl OrderBook {
                         .entry(taker_price)
                         .or_default()                 // デフォルト値を使う（VecDequeは空のキュー）
                         .push_back(taker_order);
+                    self.changed_asks.insert(taker_price);
+                    rested = true;
                 }
             }
         }
+
+        if rested || !trades.is_empty() {
+            self.sequence += 1;
+        }
         trades
     }
 
@@ -248,22 +449,46 @@ impl OrderBook {
     /// - 注文IDから価格とSideがわからないため、全ての価格帯を探索する必要があります。
     // TODO: 注文ID -> (Price, Side) のインデックスを作ってO(1)にする
     pub fn cancel_order(&mut self, order_id: u64) -> Option<Order> {
-        // 1. 買い板(bids)を探索
-        for orders in self.bids.values_mut() {
-            if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
+        // 買い板(bids) → 売り板(asks) の順に探索
+        let sides = [(&mut self.bids, &mut self.changed_bids), (&mut self.asks, &mut self.changed_asks)];
+        for (side, changed) in sides {
+            let found = side
+                .iter()
+                .find_map(|(price, orders)| orders.iter().position(|o| o.id == order_id).map(|pos| (*price, pos)));
+            if let Some((price, pos)) = found {
+                let orders = side.get_mut(&price).unwrap();
                 let order = orders.remove(pos).unwrap();
-                // この価格の注文がなくなったらエントリー削除すべきだが、
-                // イテレータ中なのでここではできない（複雑になるため放置でも動作はする）
-                // 厳密には cleanup が必要
+                // この価格の注文がなくなったらエントリーを削除（空の価格帯を配信しないため）
+                if orders.is_empty() {
+                    side.remove(&price);
+                }
+                changed.insert(price);
+                self.sequence += 1;
                 return Some(order);
             }
         }
 
-        // 2. 売り板(asks)を探索
-        for orders in self.asks.values_mut() {
-            if let Some(pos) = orders.iter().position(|o| o.id == order_id) {
-                let order = orders.remove(pos).unwrap();
-                return Some(order);
+        None
+    }
+
+    /// 板に残っている注文の数量を quantity（残数量より小さい正の値）まで減らす
+    ///
+    /// 時間優先は保ったまま、その場で数量だけを変える。減らした数量を返す（見つからなければNone）。
+    /// 残数量が quantity 以下なら何も変えずに0を返す（配信の差分にも載らない）
+    pub fn reduce_order(&mut self, order_id: u64, quantity: u64) -> Option<u64> {
+        let sides = [(&mut self.bids, &mut self.changed_bids), (&mut self.asks, &mut self.changed_asks)];
+        for (side, changed) in sides {
+            for (price, orders) in side.iter_mut() {
+                if let Some(order) = orders.iter_mut().find(|o| o.id == order_id) {
+                    let reduced = order.quantity.saturating_sub(quantity);
+                    if reduced == 0 {
+                        return Some(0);
+                    }
+                    order.quantity -= reduced;
+                    changed.insert(*price);
+                    self.sequence += 1;
+                    return Some(reduced);
+                }
             }
         }
 
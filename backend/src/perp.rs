@@ -0,0 +1,470 @@
+// =============================================================================
+// 無期限先物（パーペチュアル・プロトタイプ）
+// =============================================================================
+//
+// 現物 BAD/USDC とは別に、BAD-PERP（1枚 = 1 BAD、USDC建て・USDC決済）の
+// 無期限先物を試すためのモジュールです。マッチングは現物と同じ OrderBook を使い、
+// 約定しても資産は移動せず、ポジションが変わります。
+//
+// - 証拠金はユーザーごとの USDC ウォレット。現物の残高から振り替える
+// - ポジションは符号付きの枚数（買い持ち +、売り持ち -）と平均建値。
+//   反対売買で減らした分の損益（実現損益）はウォレットに入る
+// - インデックス価格 = 現物のマーク価格（現物の板の仲値、なければ最後の約定価格）
+//   マーク価格 = インデックス価格 + ベーシス。ベーシスは 先物の板の仲値（なければ先物の最後の約定価格）
+//   - インデックス価格 で、±max_basis × インデックス価格 で頭打ちにする。
+//   先物の板に気配を1本置くだけでマーク価格（= 資金調達・証拠金の評価）を大きく動かせないようにするため
+// - funding_interval ごとに資金調達率 = (マーク - インデックス) / インデックス
+//   （±max_funding_rate で頭打ち）を計算し、買い持ちと売り持ちの間で
+//   ポジション × マーク価格 × 資金調達率 を受け渡す（正なら買い持ちが払う）
+// - 発注時に、板に残っている注文がすべて約定してもウォレット + 含み損益が
+//   initial_margin × 建玉の評価額 以上になることを確かめる
+// - reduce_only・close_position の注文（Order 参照）はポジションを減らすだけなので証拠金チェックをしない。
+//   向き・数量の確認と、約定後に残りがポジションを超えた分の削減はエンジンが行う
+// - ウォレット + 含み損益 が maintenance_margin × 建玉の評価額 を下回ったら清算（ロスカット）する。
+//   エンジンがそのユーザーの先物の注文をキャンセルし、ポジションを閉じる成行注文を先物の板に流す。
+//   相手側の板が空なら板に注文が来るまで待つ。閉じ切った後にウォレットがマイナスなら、
+//   その分を貸し倒れ（bad_debt）として0に戻す（保険基金などは未実装）
+//
+// 先物の約定は perp_trades テーブルに保存し、注文の状態変化は user チャネルに
+// perp_execution_report として流します（現物の約定履歴・ローソク足には載りません）。
+//
+// 永続化について:
+// - ウォレット・ポジションはエンジンが変えるたびに DbMessage::SavePerpAccount で保存し、起動時に読み込む
+// - 保存形式は10^8倍の整数なので、平均建値・確定損益・資金調達料は小数点以下8桁に丸める
+// - 先物の注文（板）・最後の約定価格・前回の資金調達率はメモリ上にだけあり、再起動すると消える
+// =============================================================================
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::engine::OrderIdGenerator;
+use crate::models::{Order, OrderType, PerpAccountRecord, Side};
+use crate::storage::DECIMAL_SCALE;
+
+/// 先物の銘柄名
+pub const PERP_SYMBOL: &str = "BAD-PERP";
+/// 証拠金・損益の資産
+pub const SETTLEMENT_ASSET: &str = "USDC";
+
+/// 無期限先物の設定
+#[derive(Debug, Clone)]
+pub struct PerpConfig {
+    /// 資金調達（funding）の間隔
+    pub funding_interval: Duration,
+    /// 資金調達率の上限（絶対値）
+    pub max_funding_rate: Decimal,
+    /// マーク価格のベーシス（インデックス価格との差）の上限（インデックス価格に対する比率）
+    pub max_basis: Decimal,
+    /// 建玉の評価額に対して必要な証拠金の比率（0.1 なら最大10倍）
+    pub initial_margin: Decimal,
+    /// これを下回ると清算する、建玉の評価額に対する ウォレット + 含み損益 の比率
+    pub maintenance_margin: Decimal,
+}
+
+impl Default for PerpConfig {
+    fn default() -> Self {
+        Self {
+            funding_interval: Duration::from_secs(8 * 60 * 60),
+            max_funding_rate: Decimal::new(75, 4),
+            max_basis: Decimal::new(5, 3),
+            initial_margin: Decimal::new(1, 1),
+            maintenance_margin: Decimal::new(5, 2),
+        }
+    }
+}
+
+/// 先物の操作が拒否された理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PerpError {
+    /// 数量が0以下
+    InvalidAmount,
+    /// 残高が足りない
+    InsufficientBalance,
+    /// 板にも現物にも価格がなく、建玉を評価できない
+    NoMarkPrice,
+    /// 証拠金が initial_margin に足りない
+    InsufficientMargin,
+    /// reduce_only なのにポジションを増やす（または反転させる）
+    WouldIncreasePosition,
+}
+
+impl fmt::Display for PerpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PerpError::InvalidAmount => write!(f, "数量は0より大きくしてください"),
+            PerpError::InsufficientBalance => write!(f, "残高が足りません"),
+            PerpError::NoMarkPrice => write!(f, "マーク価格がないため建玉を評価できません"),
+            PerpError::InsufficientMargin => write!(f, "証拠金が足りません"),
+            PerpError::WouldIncreasePosition => write!(f, "reduce_only の注文はポジションを減らす向き・数量にしてください"),
+        }
+    }
+}
+
+impl std::error::Error for PerpError {}
+
+/// 先物口座への依頼（EngineMessage::Perp で送る）
+#[derive(Debug, Clone)]
+pub enum PerpRequest {
+    /// 口座の状態を見せてください
+    Get,
+    /// 現物の USDC からウォレットへ移してください
+    TransferIn { amount: Decimal },
+    /// ウォレットから現物の USDC へ戻してください
+    TransferOut { amount: Decimal },
+}
+
+/// ユーザーごとのポジション
+#[derive(Debug, Clone, Copy, Default)]
+struct Position {
+    size: i64,             // 符号付きの枚数（買い持ち +、売り持ち -）
+    entry_price: Decimal,  // 平均建値（ポジションがなければ0）
+    realized_pnl: Decimal, // 反対売買で確定した損益の累計
+    funding: Decimal,      // 受け取った資金調達料の累計（払えば負）
+}
+
+impl Position {
+    /// 約定をポジションに反映し、確定した損益を返す
+    fn apply_fill(&mut self, side: Side, price: Decimal, quantity: u64) -> Decimal {
+        let signed = match side {
+            Side::Buy => quantity as i64,
+            Side::Sell => -(quantity as i64),
+        };
+        // 同じ向き（または新規）なら建値を加重平均する
+        if self.size == 0 || self.size.signum() == signed.signum() {
+            let held = Decimal::from(self.size.unsigned_abs());
+            let added = Decimal::from(quantity);
+            self.entry_price = ((held * self.entry_price + added * price) / (held + added)).round_dp(DECIMAL_SCALE);
+            self.size += signed;
+            return Decimal::ZERO;
+        }
+
+        // 反対売買: 減らした分の損益を確定し、超えた分は新しい向きのポジションになる
+        let closing = quantity.min(self.size.unsigned_abs());
+        let pnl = (Decimal::from(closing) * (price - self.entry_price) * Decimal::from(self.size.signum())).round_dp(DECIMAL_SCALE);
+        self.realized_pnl += pnl;
+        self.size += signed.signum() * closing as i64;
+        if quantity > closing {
+            self.size = signed.signum() * (quantity - closing) as i64;
+            self.entry_price = price;
+        } else if self.size == 0 {
+            self.entry_price = Decimal::ZERO;
+        }
+        pnl
+    }
+
+    /// 含み損益
+    fn unrealized_pnl(&self, mark: Decimal) -> Decimal {
+        Decimal::from(self.size) * (mark - self.entry_price)
+    }
+}
+
+/// ポジション（GET /perp/account）
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PositionView {
+    pub size: i64, // 符号付きの枚数（買い持ち +、売り持ち -）
+    #[serde(with = "rust_decimal::serde::str")]
+    pub entry_price: Decimal,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub unrealized_pnl: Option<Decimal>, // マーク価格がなければnull
+    #[serde(with = "rust_decimal::serde::str")]
+    pub realized_pnl: Decimal,
+    #[serde(with = "rust_decimal::serde::str")]
+    pub funding: Decimal, // 受け取った資金調達料の累計（払えば負）
+}
+
+/// 先物口座の状態
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PerpAccountSummary {
+    #[serde(with = "rust_decimal::serde::str")]
+    pub wallet_balance: Decimal, // USDC（実現損益・資金調達料は反映済み）
+    pub position: PositionView,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub mark_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub equity: Option<Decimal>, // ウォレット + 含み損益
+    #[serde(with = "rust_decimal::serde::str")]
+    pub bad_debt: Decimal, // 清算で払い切れずに0に戻した損失の累計
+}
+
+/// 先物市場の状態（GET /perp/market）
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PerpMarket {
+    pub symbol: String,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub index_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub mark_price: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub funding_rate: Option<Decimal>, // 今の価格で次に適用される資金調達率
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub last_funding_rate: Option<Decimal>, // 前回適用した資金調達率
+    pub next_funding_at: u128, // 次の資金調達の時刻（ミリ秒）
+    pub open_interest: u64, // 建玉（買い持ちの合計枚数）
+}
+
+/// 全ユーザーの先物口座を管理する
+///
+/// AccountManager と同じくエンジンアクター内で保持する
+#[derive(Debug, Clone, Default)]
+pub struct PerpManager {
+    config: PerpConfig,
+    wallets: HashMap<Uuid, Decimal>,
+    positions: HashMap<Uuid, Position>,
+    bad_debt: HashMap<Uuid, Decimal>,
+    last_price: Option<Decimal>, // 先物の最後の約定価格
+    last_funding_rate: Option<Decimal>,
+    next_funding_at: u128,
+    order_ids: Arc<OrderIdGenerator>, // 清算注文の採番用（APIと共有する）
+}
+
+impl PerpManager {
+    pub fn new(config: PerpConfig, order_ids: Arc<OrderIdGenerator>) -> Self {
+        Self { config, order_ids, ..Self::default() }
+    }
+
+    /// 設定と採番器を差し替える（MarketData::load で読み込んだ口座はそのまま）
+    pub fn configure(&mut self, config: PerpConfig, order_ids: Arc<OrderIdGenerator>) {
+        self.config = config;
+        self.order_ids = order_ids;
+    }
+
+    pub fn config(&self) -> &PerpConfig {
+        &self.config
+    }
+
+    /// 保存済みの口座を読み込む（起動時）
+    pub fn load_account(&mut self, record: PerpAccountRecord) {
+        let user_id = record.user_id;
+        self.wallets.insert(user_id, record.wallet_balance);
+        let position = Position {
+            size: record.size,
+            entry_price: record.entry_price,
+            realized_pnl: record.realized_pnl,
+            funding: record.funding,
+        };
+        self.positions.insert(user_id, position);
+        if !record.bad_debt.is_zero() {
+            self.bad_debt.insert(user_id, record.bad_debt);
+        }
+    }
+
+    /// 保存用の口座の状態（ウォレットもポジションもなければNone）
+    pub fn record(&self, user_id: &Uuid) -> Option<PerpAccountRecord> {
+        if !self.wallets.contains_key(user_id) && !self.positions.contains_key(user_id) {
+            return None;
+        }
+        let position = self.positions.get(user_id).copied().unwrap_or_default();
+        Some(PerpAccountRecord {
+            user_id: *user_id,
+            wallet_balance: self.wallets.get(user_id).copied().unwrap_or_default(),
+            size: position.size,
+            entry_price: position.entry_price,
+            realized_pnl: position.realized_pnl,
+            funding: position.funding,
+            bad_debt: self.bad_debt.get(user_id).copied().unwrap_or_default(),
+        })
+    }
+
+    /// ポジションを持っているユーザー（ID順。資金調達料を受け渡した相手）
+    pub fn position_holders(&self) -> Vec<Uuid> {
+        let mut users: Vec<Uuid> = self.positions.iter().filter(|(_, p)| p.size != 0).map(|(user_id, _)| *user_id).collect();
+        users.sort();
+        users
+    }
+
+    /// マーク価格（インデックス価格 + ±max_basis で頭打ちにしたベーシス）
+    ///
+    /// ベーシスは先物の板の仲値（なければ最後の約定価格）で測り、どちらもなければ0。
+    /// インデックス価格がなければ、先物の仲値・最後の約定価格をそのまま使う
+    pub fn mark_price(&self, book_mid: Option<Decimal>, index: Option<Decimal>) -> Option<Decimal> {
+        let fair = book_mid.or(self.last_price);
+        let Some(index) = index else {
+            return fair;
+        };
+        let max = index.abs() * self.config.max_basis;
+        Some(index + fair.map(|fair| (fair - index).clamp(-max, max)).unwrap_or_default())
+    }
+
+    /// 資金調達率 = (マーク - インデックス) / インデックス（±max_funding_rate で頭打ち）
+    pub fn funding_rate(&self, mark: Option<Decimal>, index: Option<Decimal>) -> Option<Decimal> {
+        let (mark, index) = (mark?, index?);
+        if index <= Decimal::ZERO {
+            return None;
+        }
+        let max = self.config.max_funding_rate;
+        Some(((mark - index) / index).clamp(-max, max))
+    }
+
+    /// 次の資金調達の時刻を決める（エンジンの起動時・資金調達のたびに呼ぶ）
+    pub fn schedule_funding(&mut self, now: u128) {
+        self.next_funding_at = now + self.config.funding_interval.as_millis();
+    }
+
+    /// 資金調達料を受け渡す。適用した資金調達率を返す
+    ///
+    /// 正なら買い持ちが払い、売り持ちが受け取る（全員ユーザーなので合計は0）
+    pub fn settle_funding(&mut self, mark: Option<Decimal>, index: Option<Decimal>, now: u128) -> Option<Decimal> {
+        self.schedule_funding(now);
+        let rate = self.funding_rate(mark, index)?;
+        let mark = mark?;
+        for (user_id, position) in &mut self.positions {
+            if position.size == 0 {
+                continue;
+            }
+            let payment = (Decimal::from(position.size) * mark * rate).round_dp(DECIMAL_SCALE);
+            position.funding -= payment;
+            *self.wallets.entry(*user_id).or_default() -= payment;
+        }
+        self.last_funding_rate = Some(rate);
+        Some(rate)
+    }
+
+    /// 先物の約定を見て最後の約定価格を覚え、ポジションに反映する（確定損益はウォレットへ）
+    pub fn on_trade_match(&mut self, user_id: &Uuid, side: Side, price: Decimal, quantity: u64) {
+        self.last_price = Some(price);
+        let pnl = self.positions.entry(*user_id).or_default().apply_fill(side, price, quantity);
+        *self.wallets.entry(*user_id).or_default() += pnl;
+    }
+
+    /// 現在のポジションの枚数（符号付き）
+    pub fn position_size(&self, user_id: &Uuid) -> i64 {
+        self.positions.get(user_id).map(|p| p.size).unwrap_or_default()
+    }
+
+    /// 新規注文の証拠金チェック
+    ///
+    /// resting_buy / resting_sell は板に残っている自分の注文の未約定数量（この注文を含む）。
+    /// どちらの向きが全部約定しても、ウォレット + 含み損益 >= initial_margin × 建玉の評価額 であること
+    pub fn check_margin(&self, user_id: &Uuid, resting_buy: u64, resting_sell: u64, mark: Option<Decimal>) -> Result<(), PerpError> {
+        let mark = mark.ok_or(PerpError::NoMarkPrice)?;
+        let size = self.position_size(user_id);
+        let worst = (size + resting_buy as i64).unsigned_abs().max((size - resting_sell as i64).unsigned_abs());
+        if self.equity(user_id, mark) < Decimal::from(worst) * mark * self.config.initial_margin {
+            return Err(PerpError::InsufficientMargin);
+        }
+        Ok(())
+    }
+
+    /// ウォレット + 含み損益 が maintenance_margin × 建玉の評価額 を下回ったユーザー（ID順）
+    pub fn undercollateralized(&self, mark: Decimal) -> Vec<Uuid> {
+        let mut users: Vec<Uuid> = self
+            .positions
+            .iter()
+            .filter(|(_, p)| p.size != 0)
+            .filter(|(user_id, p)| {
+                let required = Decimal::from(p.size.unsigned_abs()) * mark * self.config.maintenance_margin;
+                self.equity(user_id, mark) < required
+            })
+            .map(|(user_id, _)| *user_id)
+            .collect();
+        users.sort();
+        users
+    }
+
+    /// ポジションを閉じる清算の成行注文（先物の注文をすべてキャンセルした後に呼ぶ）
+    pub fn liquidation_order(&self, user_id: &Uuid) -> Option<Order> {
+        let size = self.position_size(user_id);
+        if size == 0 {
+            return None;
+        }
+        Some(Order {
+            id: self.order_ids.next_id(),
+            price: Decimal::ZERO,
+            quantity: size.unsigned_abs(),
+            side: if size > 0 { Side::Sell } else { Side::Buy },
+            user_id: Some(*user_id),
+            order_type: OrderType::Market,
+            reduce_only: true,
+            ..Default::default()
+        })
+    }
+
+    /// 清算でポジションを閉じ切った後、マイナスのウォレットを貸し倒れにして0に戻す。貸し倒れにした額を返す
+    pub fn write_off(&mut self, user_id: &Uuid) -> Option<Decimal> {
+        if self.position_size(user_id) != 0 {
+            return None;
+        }
+        let wallet = self.wallets.get_mut(user_id)?;
+        if *wallet >= Decimal::ZERO {
+            return None;
+        }
+        let loss = -*wallet;
+        *wallet = Decimal::ZERO;
+        *self.bad_debt.entry(*user_id).or_default() += loss;
+        Some(loss)
+    }
+
+    /// ウォレット + 含み損益
+    fn equity(&self, user_id: &Uuid, mark: Decimal) -> Decimal {
+        let wallet = self.wallets.get(user_id).copied().unwrap_or_default();
+        wallet + self.positions.get(user_id).map(|p| p.unrealized_pnl(mark)).unwrap_or_default()
+    }
+
+    /// 現物から移された USDC を入金する（現物側の引き落としはエンジンが行う）
+    pub fn transfer_in(&mut self, user_id: &Uuid, amount: Decimal) -> Result<(), PerpError> {
+        if amount <= Decimal::ZERO {
+            return Err(PerpError::InvalidAmount);
+        }
+        *self.wallets.entry(*user_id).or_default() += amount;
+        Ok(())
+    }
+
+    /// 現物へ戻す USDC を引き落とす（ポジションがあれば、戻した後も initial_margin を満たす必要がある）
+    pub fn transfer_out(&mut self, user_id: &Uuid, amount: Decimal, mark: Option<Decimal>) -> Result<(), PerpError> {
+        if amount <= Decimal::ZERO {
+            return Err(PerpError::InvalidAmount);
+        }
+        let wallet = self.wallets.get(user_id).copied().unwrap_or_default();
+        if wallet < amount {
+            return Err(PerpError::InsufficientBalance);
+        }
+        let size = self.position_size(user_id);
+        if size != 0 {
+            let mark = mark.ok_or(PerpError::NoMarkPrice)?;
+            let required = Decimal::from(size.unsigned_abs()) * mark * self.config.initial_margin;
+            if self.equity(user_id, mark) - amount < required {
+                return Err(PerpError::InsufficientMargin);
+            }
+        }
+        *self.wallets.entry(*user_id).or_default() -= amount;
+        Ok(())
+    }
+
+    /// 口座の状態
+    pub fn summary(&self, user_id: &Uuid, mark: Option<Decimal>) -> PerpAccountSummary {
+        let position = self.positions.get(user_id).copied().unwrap_or_default();
+        PerpAccountSummary {
+            wallet_balance: self.wallets.get(user_id).copied().unwrap_or_default(),
+            position: PositionView {
+                size: position.size,
+                entry_price: position.entry_price,
+                unrealized_pnl: mark.map(|mark| position.unrealized_pnl(mark)),
+                realized_pnl: position.realized_pnl,
+                funding: position.funding,
+            },
+            mark_price: mark,
+            equity: mark.map(|mark| self.equity(user_id, mark)),
+            bad_debt: self.bad_debt.get(user_id).copied().unwrap_or_default(),
+        }
+    }
+
+    /// 市場の状態
+    pub fn market(&self, mark: Option<Decimal>, index: Option<Decimal>) -> PerpMarket {
+        PerpMarket {
+            symbol: PERP_SYMBOL.to_string(),
+            index_price: index,
+            mark_price: mark,
+            funding_rate: self.funding_rate(mark, index),
+            last_funding_rate: self.last_funding_rate,
+            next_funding_at: self.next_funding_at,
+            open_interest: self.positions.values().filter(|p| p.size > 0).map(|p| p.size as u64).sum(),
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// =============================================================================
+// 配信の間引き（coalescing）
+// =============================================================================
+//
+// 注文のたびに板全体の差分やティッカーを流すと、配信が詰まってしまいます。
+// そこでエンジンは変化があったチャネルに印（dirty）を付けるだけにしておき、
+// チャネルごとの間隔を空けてまとめて配信します。
+//
+// - 前回の配信から間隔が空いていれば、変化はすぐに配信する（leading edge）
+// - 間隔内の変化は、間隔が空いた時点で必ず配信する（trailing flush）
+//
+// 後者がないと、連続した注文の最後の1件が間隔内に来た場合、
+// 次の注文が来るまで最終状態がクライアントに届きません。
+//
+// 約定とローソク足は間引かずに、発生した時点で配信します（engine.rs 参照）。
+// =============================================================================
+
+use std::time::{Duration, Instant};
+
+/// チャネルごとの配信間隔
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishIntervals {
+    pub book: Duration,   // 板（差分・上位の板・L3）
+    pub ticker: Duration, // 24時間ティッカー
+}
+
+impl Default for PublishIntervals {
+    fn default() -> Self {
+        Self {
+            book: Duration::from_millis(50), // 20fps
+            ticker: Duration::from_millis(250),
+        }
+    }
+}
+
+/// 1チャネル分の間引きの状態
+#[derive(Debug)]
+struct Throttle {
+    interval: Duration,
+    last_flush: Instant, // 前回の配信時刻
+    dirty: bool,         // 前回の配信以降に変化があった
+}
+
+impl Throttle {
+    fn new(interval: Duration) -> Self {
+        // 最初の変化はすぐに配信できるよう、1間隔前に配信したことにしておく
+        let now = Instant::now();
+        Self { interval, last_flush: now.checked_sub(interval).unwrap_or(now), dirty: false }
+    }
+
+    /// 次に配信してよい時刻（変化がなければNone）
+    fn deadline(&self) -> Option<Instant> {
+        self.dirty.then(|| self.last_flush + self.interval)
+    }
+
+    /// 配信すべきなら印を外して true を返す
+    fn take_due(&mut self, now: Instant) -> bool {
+        match self.deadline() {
+            Some(deadline) if deadline <= now => {
+                self.dirty = false;
+                self.last_flush = now;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 今配信すべきチャネル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DueChannels {
+    pub book: bool,
+    pub ticker: bool,
+}
+
+/// 板の変化を見て、チャネルごとに配信のタイミングを決める
+#[derive(Debug)]
+pub struct CoalescingPublisher {
+    seen_sequence: u64, // 最後に見た板の sequence
+    book: Throttle,
+    ticker: Throttle,
+}
+
+impl CoalescingPublisher {
+    pub fn new(intervals: &PublishIntervals) -> Self {
+        Self {
+            seen_sequence: 0,
+            book: Throttle::new(intervals.book),
+            ticker: Throttle::new(intervals.ticker),
+        }
+    }
+
+    /// 板の sequence を見て、変わっていれば全チャネルに印を付ける
+    ///
+    /// 約定でも sequence は進むので、ティッカーの変化もこれで拾える
+    pub fn observe(&mut self, sequence: u64) {
+        if sequence != self.seen_sequence {
+            self.seen_sequence = sequence;
+            self.book.dirty = true;
+            self.ticker.dirty = true;
+        }
+    }
+
+    /// 今配信すべきチャネルを返し、配信済みとして記録する
+    pub fn take_due(&mut self, now: Instant) -> DueChannels {
+        DueChannels {
+            book: self.book.take_due(now),
+            ticker: self.ticker.take_due(now),
+        }
+    }
+
+    /// 次に配信が必要になる時刻（未配信の変化がなければNone）
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match (self.book.deadline(), self.ticker.deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// =============================================================================
+// レート制限
+// =============================================================================
+//
+// 1つのクライアントが POST /order を連打してエンジンのチャネルを埋めてしまわないよう、
+// トークンバケットでリクエストを制限します。
+//
+// - バケットは「ユーザーごと」と「IPごと」の2種類。両方に残量がある場合だけ通す
+//   - IPごとのバケットは認証より前に数える（署名の検証やセッションの照会で DB を叩く前に弾く）
+//   - ユーザーごとのバケットは認証の後に数える（検証済みのユーザーで数える）
+// - リクエストは種類ごとに重み（消費トークン数）が違う（発注 > キャンセル・参照）
+// - 超過したら 429 と Retry-After、通常のレスポンスにも残量ヘッダーを付ける
+// - WebSocketは同時接続数をユーザー（未ログインならIP）ごとに制限する
+// - gRPC（grpc.rs）と FIX（fix_gateway.rs）も同じ RateLimiter のバケット・重みを使う
+//
+// バケットはプロセス内メモリにだけ持つ（再起動で満タンに戻る）。
+// =============================================================================
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use uuid::Uuid;
+
+use crate::api::{ApiError, AppState};
+use crate::auth::{self, ApiKeyAuth, ResolvedSession};
+
+/// 1種類のバケットの設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketConfig {
+    pub burst: u32,         // バケットの容量（一度に使える最大トークン数）
+    pub refill_per_sec: u32, // 1秒あたりに回復するトークン数
+}
+
+/// レート制限の設定
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    pub user: BucketConfig, // ログインユーザー（セッション・APIキー）ごと
+    pub ip: BucketConfig,   // 接続元IPごと
+    pub order_weight: u32,  // POST /order・/margin/order・/perp/order
+    pub cancel_weight: u32, // DELETE /order/{id}
+    pub read_weight: u32,   // それ以外
+    pub ws_connections_per_user: usize,
+    pub ws_connections_per_ip: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            user: BucketConfig { burst: 100, refill_per_sec: 20 },
+            ip: BucketConfig { burst: 200, refill_per_sec: 40 },
+            order_weight: 2,
+            cancel_weight: 1,
+            read_weight: 1,
+            ws_connections_per_user: 5,
+            ws_connections_per_ip: 20,
+        }
+    }
+}
+
+/// バケットを区別するキー
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateKey {
+    User(Uuid),
+    Ip(IpAddr),
+}
+
+/// トークンバケット
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(config: BucketConfig, now: Instant) -> Self {
+        Self { tokens: f64::from(config.burst), updated_at: now }
+    }
+
+    /// 経過時間分を回復させる
+    fn refill(&mut self, config: BucketConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * f64::from(config.refill_per_sec)).min(f64::from(config.burst));
+        self.updated_at = now;
+    }
+
+    /// weight 分のトークンが貯まるまでの時間
+    fn wait_for(&self, config: BucketConfig, weight: u32) -> Duration {
+        let missing = f64::from(weight) - self.tokens;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else if config.refill_per_sec == 0 {
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64(missing / f64::from(config.refill_per_sec))
+        }
+    }
+}
+
+/// レート制限の判定結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateDecision {
+    pub allowed: bool,
+    pub limit: u32,              // 最も残りの少ないバケットの容量
+    pub remaining: u32,          // そのバケットの残りトークン数（切り捨て）
+    pub retry_after: Duration,   // 拒否された場合、再試行できるまでの時間
+}
+
+/// 同時接続数を数えたまま切断まで保持する許可証
+///
+/// drop されると接続数が減る
+#[derive(Debug)]
+pub struct WsPermit {
+    limiter: Arc<RateLimiter>,
+    key: RateKey,
+}
+
+impl Drop for WsPermit {
+    fn drop(&mut self) {
+        let mut conns = self.limiter.ws_connections.lock().unwrap();
+        if let Some(count) = conns.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                conns.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// バケットがこの数を超えたら、満タンのもの（しばらく使われていない）を掃除する
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// レート制限の状態
+#[derive(Debug)]
+pub struct RateLimiter {
+    pub config: RateLimitConfig,
+    buckets: Mutex<HashMap<RateKey, TokenBucket>>,
+    ws_connections: Mutex<HashMap<RateKey, usize>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            ws_connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn bucket_config(&self, key: &RateKey) -> BucketConfig {
+        match key {
+            RateKey::User(_) => self.config.user,
+            RateKey::Ip(_) => self.config.ip,
+        }
+    }
+
+    /// 全てのキーのバケットから weight 分を消費する（1つでも足りなければ何も消費しない）
+    pub fn check(&self, keys: &[RateKey], weight: u32) -> RateDecision {
+        self.check_at(keys, weight, Instant::now())
+    }
+
+    /// 時刻を指定して判定する（テスト用）
+    pub fn check_at(&self, keys: &[RateKey], weight: u32, now: Instant) -> RateDecision {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|key, bucket| {
+                let config = self.bucket_config(key);
+                bucket.refill(config, now);
+                bucket.tokens < f64::from(config.burst)
+            });
+        }
+
+        // 1. 回復させてから足りるか確認
+        let mut retry_after = Duration::ZERO;
+        for key in keys {
+            let config = self.bucket_config(key);
+            let bucket = buckets.entry(*key).or_insert_with(|| TokenBucket::full(config, now));
+            bucket.refill(config, now);
+            retry_after = retry_after.max(bucket.wait_for(config, weight));
+        }
+        let allowed = retry_after.is_zero();
+
+        // 2. 全て足りていれば消費し、最も残りの少ないバケットを報告する
+        let mut decision = RateDecision { allowed, limit: 0, remaining: u32::MAX, retry_after };
+        for key in keys {
+            let config = self.bucket_config(key);
+            let bucket = buckets.get_mut(key).expect("上で作成済み");
+            if allowed {
+                bucket.tokens -= f64::from(weight);
+            }
+            let remaining = bucket.tokens.max(0.0) as u32;
+            if remaining < decision.remaining {
+                decision.remaining = remaining;
+                decision.limit = config.burst;
+            }
+        }
+        if keys.is_empty() {
+            decision.remaining = 0;
+        }
+        decision
+    }
+
+    /// WebSocketの同時接続枠を1つ確保する（上限なら None）
+    pub fn acquire_ws(self: &Arc<Self>, key: RateKey) -> Option<WsPermit> {
+        let max = match key {
+            RateKey::User(_) => self.config.ws_connections_per_user,
+            RateKey::Ip(_) => self.config.ws_connections_per_ip,
+        };
+        let mut conns = self.ws_connections.lock().unwrap();
+        let count = conns.entry(key).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(WsPermit { limiter: self.clone(), key })
+    }
+
+    /// リクエストの種類ごとの重み
+    pub fn weight(&self, method: &Method, path: &str) -> u32 {
+        let is_order = matches!(path, "/order" | "/margin/order" | "/perp/order");
+        let is_order_by_id = path.starts_with("/order/");
+        if method == Method::POST && is_order {
+            self.config.order_weight
+        } else if method == Method::DELETE && is_order_by_id {
+            self.config.cancel_weight
+        } else {
+            self.config.read_weight
+        }
+    }
+}
+
+/// 接続元IP（ConnectInfo がない環境では None）
+pub fn client_ip(req: &Request) -> Option<IpAddr> {
+    req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip())
+}
+
+/// レート制限のヘッダーを付ける（内側のミドルウェアが付けた値より残りが少なければ上書き）
+fn set_headers(headers: &mut HeaderMap, decision: &RateDecision) {
+    let inner = headers
+        .get("x-ratelimit-remaining")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u32>().ok());
+    if inner.is_some_and(|remaining| remaining <= decision.remaining) {
+        return;
+    }
+    headers.insert("x-ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from(decision.remaining));
+}
+
+/// バケットから消費し、足りなければ 429 を返す
+async fn charge(limiter: &RateLimiter, key: RateKey, req: Request, next: Next) -> Response {
+    let decision = limiter.check(&[key], limiter.weight(req.method(), req.uri().path()));
+    let mut response = if decision.allowed {
+        next.run(req).await
+    } else {
+        let mut response = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "リクエストが多すぎます").into_response();
+        // 秒単位で切り上げ（0秒だとすぐ再送されてしまうため最低1秒）
+        let secs = decision.retry_after.as_secs_f64().ceil().clamp(1.0, u32::MAX as f64) as u32;
+        response.headers_mut().insert("retry-after", HeaderValue::from(secs));
+        response
+    };
+    set_headers(response.headers_mut(), &decision);
+    response
+}
+
+/// IPごとのレート制限ミドルウェア
+///
+/// api_key_auth の外側で動き、認証より前に接続元IPで数える。
+/// IPが分からないリクエストは制限しない（テストなど）。
+pub async fn ip_rate_limit(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match client_ip(&req) {
+        Some(ip) => charge(&state.rate_limiter, RateKey::Ip(ip), req, next).await,
+        None => next.run(req).await,
+    }
+}
+
+/// ユーザーごとのレート制限ミドルウェア
+///
+/// api_key_auth の内側で動くので、APIキーのリクエストは検証済みのユーザーで数える。
+/// セッションのリクエストはここでユーザーを照会し、その結果を `ResolvedSession` として
+/// ハンドラーに渡す（AuthUser・SessionUser が照会し直さないように）。
+/// ユーザーが分からないリクエストはIPごとの制限だけにする。
+pub async fn user_rate_limit(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let user_id = match req.extensions().get::<ApiKeyAuth>() {
+        Some(auth) => Some(auth.user_id),
+        // セッションが無効でもここでは弾かない（401はハンドラーが返す）。
+        // 照会に失敗したら結果を渡さず、ハンドラーに照会し直させてエラーを返させる
+        None => match auth::session_user(&state, req.headers()).await {
+            Ok(user_id) => {
+                req.extensions_mut().insert(ResolvedSession(user_id));
+                user_id
+            }
+            Err(_) => None,
+        },
+    };
+    match user_id {
+        Some(user_id) => charge(&state.rate_limiter, RateKey::User(user_id), req, next).await,
+        None => next.run(req).await,
+    }
+}
@@ -1,34 +1,33 @@
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 use rand::Rng;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use crate::engine::EngineMessage;
+use crate::engine::{EngineMessage, OrderIdGenerator};
 use crate::models::{Order, Side, OrderType};
 
 /// 市場シミュレータを起動
 /// 
 /// 実際の取引参加者をシミュレートして、リアルな板を作ります。
 /// 10ミリ秒ごとにランダムな注文を生成します。
-pub async fn run_market_simulator(sim_sender: mpsc::Sender<EngineMessage>) {
+pub async fn run_market_simulator(sim_sender: mpsc::Sender<EngineMessage>, order_ids: Arc<OrderIdGenerator>) {
     // 10ミリ秒ごとに発火するタイマー
     let mut interval = tokio::time::interval(std::time::Duration::from_millis(10));
-    let mut id_counter: u64 = 2000000; // 注文IDのカウンター
     let mut base_price: Decimal = dec!(100.0);   // 基準価格（価格はこの周辺で動く）
 
     loop {
         interval.tick().await; // 10ミリ秒待つ
-        id_counter += 1;
 
         // ----------------------------------------------------
         // 現在の板情報を取得
         // ----------------------------------------------------
         // シミュレータがリアルな注文を出すには、現在の最良買値/売値を知る必要がある
-        // エンジンに問い合わせて取得
+        // エンジンに問い合わせて取得（板全体をコピーしないようティッカーで済ませる）
         let (resp_tx, resp_rx) = oneshot::channel();
-        let _ = sim_sender.send(EngineMessage::GetOrderBook { respond_to: resp_tx }).await;
+        let _ = sim_sender.send(EngineMessage::GetTicker { respond_to: resp_tx }).await;
         // エンジンが停止していたらシミュレータも終了
-        let book = match resp_rx.await {
-            Ok(b) => b,
+        let ticker = match resp_rx.await {
+            Ok(t) => t,
             Err(_) => break, 
         };
 
@@ -54,8 +53,8 @@ pub async fn run_market_simulator(sim_sender: mpsc::Sender<EngineMessage>) {
 
             // 最良買値と最良売値を取得（なければデフォルト値）
             // Decimalはそのままコピーできる（Copyトレイト実装済み）
-            let best_bid = book.bids.keys().next_back().copied().unwrap_or(base_price - dec!(0.5));
-            let best_ask = book.asks.keys().next().copied().unwrap_or(base_price + dec!(0.5));
+            let best_bid = ticker.best_bid.unwrap_or(base_price - dec!(0.5));
+            let best_ask = ticker.best_ask.unwrap_or(base_price + dec!(0.5));
             let mid_price = (best_bid + best_ask) / dec!(2); // 仲値
 
             // 1%の確率で基準価格を更新（価格のドリフトをシミュレート）
@@ -96,12 +95,13 @@ pub async fn run_market_simulator(sim_sender: mpsc::Sender<EngineMessage>) {
 
         // 注文オブジェクトを作成
         let new_order = Order {
-            id: id_counter,
+            id: order_ids.next_id(), // ユーザー注文とIDが重複しないよう共有の採番器を使う
             price,
             quantity,
             side,
             user_id: None, // シミュレータの注文は所有者なし
             order_type: OrderType::Limit,
+            ..Default::default()
         };
 
         // エンジンに注文を送信
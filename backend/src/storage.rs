@@ -0,0 +1,661 @@
+// =============================================================================
+// ストレージ抽象化レイヤー
+// =============================================================================
+//
+// 永続化処理を `Storage` トレイトの裏に隠し、バックエンドを差し替え可能にします。
+//
+// - SqliteStorage: 本番用（db.rs に実装）
+// - MemoryStorage: テスト・ベンチマーク用（プロセス内のHashMapに保存）
+//
+// エンジンやAPI層は `SharedStorage`（= Arc<dyn Storage>）だけを知っていればよく、
+// どちらのバックエンドを使うかは main.rs が設定から決めます。
+// =============================================================================
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::config::StorageBackend;
+use crate::db::{self, Balance, User};
+use crate::models::{ApiKey, Asset, Candle, CandleInterval, FixSequence, MarginAccountRecord, OrderRecord, PerpAccountRecord, Trade};
+
+/// ストレージ操作のエラー
+#[derive(Debug)]
+pub enum StorageError {
+    /// DBドライバが返したエラー
+    Database(sqlx::Error),
+    /// 保存されている値が壊れていて復元できない
+    Corrupt(String),
+    /// 値が保存形式（固定小数点）で表現できない
+    Unrepresentable(String),
+    /// 一意であるべき値（ユーザー名など）が既に存在する
+    Conflict(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Database(e) => write!(f, "database error: {}", e),
+            StorageError::Corrupt(msg) => write!(f, "corrupt data: {}", msg),
+            StorageError::Unrepresentable(msg) => write!(f, "unrepresentable value: {}", msg),
+            StorageError::Conflict(msg) => write!(f, "conflict: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(e: sqlx::Error) -> Self {
+        StorageError::Database(e)
+    }
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+// =============================================================================
+// 数値の保存形式
+// =============================================================================
+//
+// 金額・価格は「10^8倍した整数（i64）」として保存します。
+// - SQLでそのまま SUM / ORDER BY / 比較ができる（TEXTだとCASTが必要）
+// - 小数点以下8桁までは誤差なく往復できる
+// - それより細かい値や i64 に収まらない値は、黙って丸めずにエラーにする
+//
+// 例: 100.5 USDC -> 10_050_000_000
+//     SELECT SUM(price * quantity) / 1e8 FROM trades; -- 売買代金（USDC）
+
+/// 保存時の小数点以下の桁数
+pub const DECIMAL_SCALE: u32 = 8;
+
+/// Decimalを保存用の整数に変換する
+pub fn to_scaled(value: Decimal) -> StorageResult<i64> {
+    let normalized = value.normalize();
+    if normalized.scale() > DECIMAL_SCALE {
+        return Err(StorageError::Unrepresentable(format!(
+            "{} は小数点以下{}桁を超えています",
+            value, DECIMAL_SCALE
+        )));
+    }
+    normalized
+        .checked_mul(Decimal::from(10i64.pow(DECIMAL_SCALE)))
+        .and_then(|scaled| i64::try_from(scaled).ok())
+        .ok_or_else(|| StorageError::Unrepresentable(format!("{} は保存可能な範囲外です", value)))
+}
+
+/// 保存用の整数からDecimalに戻す
+pub fn from_scaled(value: i64) -> Decimal {
+    Decimal::new(value, DECIMAL_SCALE).normalize()
+}
+
+/// 永続化バックエンドの共通インターフェース
+///
+/// `Arc<dyn Storage>` としてタスク間で共有するため、Send + Sync を要求する。
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // --- ユーザー ---
+
+    /// デフォルトユーザーを確保し、そのIDを返す（いなければ初期残高付きで作成）
+    async fn ensure_default_user(&self) -> StorageResult<Uuid>;
+
+    /// パスワード付きのユーザーを作成し、初期残高を付与する
+    ///
+    /// ユーザー名が使われていれば StorageError::Conflict
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Uuid>;
+
+    /// ユーザー名でユーザーを取得
+    async fn get_user_by_username(&self, username: &str) -> StorageResult<Option<User>>;
+
+    // --- セッション ---
+
+    /// ログインセッションを保存（トークンはハッシュ化して渡す）
+    async fn create_session(&self, token_hash: &str, user_id: Uuid, expires_at: u128) -> StorageResult<()>;
+
+    /// 有効期限内のセッションのユーザーIDを取得
+    async fn get_session_user(&self, token_hash: &str, now: u128) -> StorageResult<Option<Uuid>>;
+
+    /// セッションを削除（ログアウト）
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()>;
+
+    // --- APIキー ---
+
+    /// APIキーを保存
+    async fn create_api_key(&self, api_key: &ApiKey) -> StorageResult<()>;
+
+    /// 公開IDでAPIキーを取得（失効済みも含む）
+    async fn get_api_key(&self, key: &str) -> StorageResult<Option<ApiKey>>;
+
+    /// ユーザーのAPIキーを発行順に取得（失効済みも含む）
+    async fn list_api_keys(&self, user_id: Uuid) -> StorageResult<Vec<ApiKey>>;
+
+    /// 本人の有効なAPIキーを失効させる（該当しなければ false）
+    async fn revoke_api_key(&self, user_id: Uuid, key: &str, now: u128) -> StorageResult<bool>;
+
+    // --- 残高 ---
+
+    /// ユーザーの全資産の残高を取得
+    async fn get_balances(&self, user_id: Uuid) -> StorageResult<Vec<Balance>>;
+
+    /// 全ユーザーの残高を取得（起動時にエンジンへ読み込む用）
+    async fn get_all_balances(&self) -> StorageResult<Vec<Balance>>;
+
+    /// 残高を上書きする（行がなければ作成）
+    async fn update_balance(
+        &self,
+        user_id: Uuid,
+        asset: &str,
+        available: Decimal,
+        locked: Decimal,
+    ) -> StorageResult<()>;
+
+    // --- 資産 ---
+
+    /// 登録されている資産を資産名の順に取得
+    async fn get_assets(&self) -> StorageResult<Vec<Asset>>;
+
+    /// 資産を登録する（登録済みなら表示名・桁数を上書き）
+    async fn save_asset(&self, asset: &Asset) -> StorageResult<()>;
+
+    // --- 証拠金口座 ---
+
+    /// 証拠金口座を保存（同じユーザーの口座は資産ごと上書き）
+    async fn save_margin_account(&self, account: &MarginAccountRecord) -> StorageResult<()>;
+
+    /// 全ユーザーの証拠金口座をユーザーID順に取得（起動時にエンジンへ読み込む用）
+    async fn get_margin_accounts(&self) -> StorageResult<Vec<MarginAccountRecord>>;
+
+    // --- 先物口座 ---
+
+    /// 先物口座（ウォレット・ポジション）を保存（同じユーザーの口座は上書き）
+    async fn save_perp_account(&self, account: &PerpAccountRecord) -> StorageResult<()>;
+
+    /// 全ユーザーの先物口座をユーザーID順に取得（起動時にエンジンへ読み込む用）
+    async fn get_perp_accounts(&self) -> StorageResult<Vec<PerpAccountRecord>>;
+
+    // --- 約定 ---
+
+    /// 約定を保存（シミュレータ同士の約定も含む全約定）
+    async fn save_trade(&self, trade: &Trade) -> StorageResult<()>;
+
+    /// 条件に合う約定を約定IDの古い順に取得
+    ///
+    /// from_id / start_time のどちらかを指定した場合はそこから limit 件、
+    /// どちらも省略した場合は最新の limit 件を返す
+    async fn get_trades(&self, query: &TradeQuery) -> StorageResult<Vec<Trade>>;
+
+    /// 保存済みの最大の約定ID（まだ約定がなければ0）
+    async fn last_trade_id(&self) -> StorageResult<u64>;
+
+    /// 無期限先物の約定を保存（約定IDは現物とは別に採番される）
+    async fn save_perp_trade(&self, trade: &Trade) -> StorageResult<()>;
+
+    /// 保存済みの最大の先物の約定ID（まだ約定がなければ0）
+    async fn last_perp_trade_id(&self) -> StorageResult<u64>;
+
+    // --- 注文 ---
+
+    /// 使用済みの最大の注文ID（なければ0。起動時に採番の続きを決めるのに使う）
+    async fn last_order_id(&self) -> StorageResult<u64>;
+
+    /// 注文記録を保存（同じIDがあれば上書き）
+    async fn save_order(&self, order: &OrderRecord) -> StorageResult<()>;
+
+    /// 注文IDで注文記録を取得
+    async fn get_order(&self, order_id: u64) -> StorageResult<Option<OrderRecord>>;
+
+    /// ユーザーの注文記録を新しい順に取得
+    async fn get_user_orders(&self, user_id: Uuid, limit: u32) -> StorageResult<Vec<OrderRecord>>;
+
+    // --- ローソク足 ---
+
+    /// ローソク足を保存（同じ時間足・開始時刻があれば上書き）
+    async fn save_candle(&self, candle: &Candle) -> StorageResult<()>;
+
+    /// 指定期間のローソク足を古い順に取得（from/toは開始時刻に対する閉区間）
+    ///
+    /// from を指定した場合は from から limit 本、
+    /// 省略した場合は to 以前の最新 limit 本を返す
+    async fn get_candles(
+        &self,
+        interval: CandleInterval,
+        from: Option<u128>,
+        to: Option<u128>,
+        limit: u32,
+    ) -> StorageResult<Vec<Candle>>;
+
+    // --- FIXセッション ---
+
+    /// FIXセッションのシーケンス番号を取得（初めてのセッションなら None）
+    async fn get_fix_sequence(&self, session_id: &str) -> StorageResult<Option<FixSequence>>;
+
+    /// FIXセッションのシーケンス番号を保存
+    async fn save_fix_sequence(&self, session_id: &str, sequence: &FixSequence) -> StorageResult<()>;
+
+    /// 送信したメッセージを保存（ResendRequest で送り直す）
+    async fn save_fix_message(&self, session_id: &str, seq: u64, message: &[u8]) -> StorageResult<()>;
+
+    /// 送信済みメッセージを seq の範囲（閉区間）で古い順に取得
+    async fn get_fix_messages(&self, session_id: &str, from: u64, to: u64) -> StorageResult<Vec<(u64, Vec<u8>)>>;
+
+    /// シーケンス番号を1に戻し、送信済みメッセージを消す（ResetSeqNumFlag=Y のログオン）
+    async fn reset_fix_session(&self, session_id: &str) -> StorageResult<()>;
+}
+
+/// 約定履歴の検索条件（カーソル方式のページング）
+///
+/// 続きを取得するときは「最後の約定の id + 1」を from_id に指定する
+#[derive(Debug, Clone, Default)]
+pub struct TradeQuery {
+    pub user_id: Option<Uuid>,    // 指定するとMaker/Takerどちらかがこのユーザーの約定だけ
+    pub from_id: Option<u64>,     // この約定ID以上
+    pub start_time: Option<u128>, // 約定時刻（ミリ秒）の下限（含む）
+    pub end_time: Option<u128>,   // 約定時刻（ミリ秒）の上限（含む）
+    pub limit: u32,
+}
+
+impl TradeQuery {
+    /// 約定が条件に合うか（インメモリ実装用）
+    fn matches(&self, trade: &Trade) -> bool {
+        self.user_id.is_none_or(|u| trade.maker_user_id == Some(u) || trade.taker_user_id == Some(u))
+            && self.from_id.is_none_or(|id| trade.id >= id)
+            && self.start_time.is_none_or(|t| trade.timestamp >= t)
+            && self.end_time.is_none_or(|t| trade.timestamp <= t)
+    }
+
+    /// 古い方から読み進めるか（false なら最新の limit 件）
+    pub fn is_forward(&self) -> bool {
+        self.from_id.is_some() || self.start_time.is_some()
+    }
+}
+
+/// タスク間で共有するストレージハンドル
+pub type SharedStorage = Arc<dyn Storage>;
+
+/// 設定に応じたストレージバックエンドを開く
+pub async fn open(backend: &StorageBackend) -> StorageResult<SharedStorage> {
+    match backend {
+        StorageBackend::Sqlite { path } => {
+            let pool = db::connect(path).await?;
+            println!("✅ データベース初期化完了: {}", path);
+            Ok(Arc::new(db::SqliteStorage::new(pool)))
+        }
+        StorageBackend::Memory => {
+            println!("✅ インメモリストレージを使用します（再起動でデータは消えます）");
+            Ok(Arc::new(MemoryStorage::new()))
+        }
+    }
+}
+
+// =============================================================================
+// インメモリ実装
+// =============================================================================
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    users: HashMap<String, User>,                           // username -> ユーザー
+    sessions: HashMap<String, (Uuid, u128)>,                // token_hash -> (user, expires_at)
+    api_keys: BTreeMap<String, ApiKey>,                     // key -> APIキー
+    balances: BTreeMap<(Uuid, String), (Decimal, Decimal)>, // (user, asset) -> (available, locked)
+    assets: BTreeMap<String, Asset>,                        // 資産名 -> 登録情報
+    margin_accounts: BTreeMap<Uuid, MarginAccountRecord>,   // user -> 証拠金口座
+    perp_accounts: BTreeMap<Uuid, PerpAccountRecord>,       // user -> 先物口座
+    trades: BTreeMap<u64, Trade>,                           // 約定ID -> 約定
+    perp_trades: BTreeMap<u64, Trade>,                      // 先物の約定ID -> 約定
+    orders: BTreeMap<u64, OrderRecord>,
+    candles: BTreeMap<(CandleInterval, u128), Candle>,
+    fix_sessions: HashMap<String, FixSequence>,
+    fix_messages: BTreeMap<(String, u64), Vec<u8>>, // (session_id, seq) -> 送信したメッセージ
+}
+
+/// プロセス内メモリに保存するストレージ
+///
+/// ファイルを作らないのでテストやベンチマークを高速・並列に実行できる。
+/// ロックは await をまたいで保持しないため std::sync::Mutex で十分。
+#[derive(Debug)]
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>,
+}
+
+impl Default for MemoryStorage {
+    /// SQLite版の新規DBと同じく、DEFAULT_ASSETS を登録した状態で始める
+    fn default() -> Self {
+        let assets = db::DEFAULT_ASSETS
+            .iter()
+            .map(|(asset, display_name, precision)| {
+                let asset = Asset { asset: asset.to_string(), display_name: display_name.to_string(), precision: *precision };
+                (asset.asset.clone(), asset)
+            })
+            .collect();
+        Self { state: Mutex::new(MemoryState { assets, ..Default::default() }) }
+    }
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ユーザーと初期残高を作成する（SQLite版の create_user と同じ振る舞い）
+    fn insert_user(&self, username: &str, password_hash: Option<&str>) -> StorageResult<Uuid> {
+        let mut state = self.state.lock().unwrap();
+        if state.users.contains_key(username) {
+            return Err(StorageError::Conflict(format!("ユーザー名 {:?} は既に使われています", username)));
+        }
+
+        let user = User {
+            id: Uuid::new_v4(),
+            username: username.to_string(),
+            password_hash: password_hash.map(str::to_string),
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+        };
+        let user_id = user.id;
+        state.users.insert(username.to_string(), user);
+        for (asset, amount) in db::DEFAULT_BALANCES {
+            state
+                .balances
+                .insert((user_id, asset.to_string()), (Decimal::from(*amount), Decimal::ZERO));
+        }
+        Ok(user_id)
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn ensure_default_user(&self) -> StorageResult<Uuid> {
+        if let Some(user) = self.state.lock().unwrap().users.get(db::DEFAULT_USERNAME) {
+            return Ok(user.id);
+        }
+        self.insert_user(db::DEFAULT_USERNAME, None)
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> StorageResult<Uuid> {
+        self.insert_user(username, Some(password_hash))
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> StorageResult<Option<User>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.users.get(username).cloned())
+    }
+
+    async fn create_session(&self, token_hash: &str, user_id: Uuid, expires_at: u128) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.sessions.insert(token_hash.to_string(), (user_id, expires_at));
+        Ok(())
+    }
+
+    async fn get_session_user(&self, token_hash: &str, now: u128) -> StorageResult<Option<Uuid>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .sessions
+            .get(token_hash)
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(user_id, _)| *user_id))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.sessions.remove(token_hash);
+        Ok(())
+    }
+
+    async fn create_api_key(&self, api_key: &ApiKey) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.api_keys.insert(api_key.key.clone(), api_key.clone());
+        Ok(())
+    }
+
+    async fn get_api_key(&self, key: &str) -> StorageResult<Option<ApiKey>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.api_keys.get(key).cloned())
+    }
+
+    async fn list_api_keys(&self, user_id: Uuid) -> StorageResult<Vec<ApiKey>> {
+        let state = self.state.lock().unwrap();
+        let mut keys: Vec<ApiKey> = state
+            .api_keys
+            .values()
+            .filter(|k| k.user_id == user_id)
+            .cloned()
+            .collect();
+        keys.sort_by(|a, b| (a.created_at, &a.key).cmp(&(b.created_at, &b.key)));
+        Ok(keys)
+    }
+
+    async fn revoke_api_key(&self, user_id: Uuid, key: &str, now: u128) -> StorageResult<bool> {
+        let mut state = self.state.lock().unwrap();
+        match state.api_keys.get_mut(key) {
+            Some(k) if k.user_id == user_id && k.revoked_at.is_none() => {
+                k.revoked_at = Some(now);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get_balances(&self, user_id: Uuid) -> StorageResult<Vec<Balance>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .balances
+            .range((user_id, String::new())..)
+            .take_while(|((uid, _), _)| *uid == user_id)
+            .map(|((uid, asset), (available, locked))| Balance {
+                user_id: *uid,
+                asset: asset.clone(),
+                available: *available,
+                locked: *locked,
+            })
+            .collect())
+    }
+
+    async fn get_all_balances(&self) -> StorageResult<Vec<Balance>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .balances
+            .iter()
+            .map(|((uid, asset), (available, locked))| Balance {
+                user_id: *uid,
+                asset: asset.clone(),
+                available: *available,
+                locked: *locked,
+            })
+            .collect())
+    }
+
+    async fn update_balance(
+        &self,
+        user_id: Uuid,
+        asset: &str,
+        available: Decimal,
+        locked: Decimal,
+    ) -> StorageResult<()> {
+        to_scaled(available)?;
+        to_scaled(locked)?;
+        let mut state = self.state.lock().unwrap();
+        state.balances.insert((user_id, asset.to_string()), (available, locked));
+        Ok(())
+    }
+
+    async fn get_assets(&self) -> StorageResult<Vec<Asset>> {
+        Ok(self.state.lock().unwrap().assets.values().cloned().collect())
+    }
+
+    async fn save_asset(&self, asset: &Asset) -> StorageResult<()> {
+        self.state.lock().unwrap().assets.insert(asset.asset.clone(), asset.clone());
+        Ok(())
+    }
+
+    async fn save_margin_account(&self, account: &MarginAccountRecord) -> StorageResult<()> {
+        for a in &account.assets {
+            for value in [a.available, a.locked, a.principal, a.interest, a.bad_debt] {
+                to_scaled(value)?;
+            }
+        }
+        self.state.lock().unwrap().margin_accounts.insert(account.user_id, account.clone());
+        Ok(())
+    }
+
+    async fn get_margin_accounts(&self) -> StorageResult<Vec<MarginAccountRecord>> {
+        Ok(self.state.lock().unwrap().margin_accounts.values().cloned().collect())
+    }
+
+    async fn save_perp_account(&self, account: &PerpAccountRecord) -> StorageResult<()> {
+        for value in [account.wallet_balance, account.entry_price, account.realized_pnl, account.funding, account.bad_debt] {
+            to_scaled(value)?;
+        }
+        self.state.lock().unwrap().perp_accounts.insert(account.user_id, account.clone());
+        Ok(())
+    }
+
+    async fn get_perp_accounts(&self) -> StorageResult<Vec<PerpAccountRecord>> {
+        Ok(self.state.lock().unwrap().perp_accounts.values().cloned().collect())
+    }
+
+    async fn save_trade(&self, trade: &Trade) -> StorageResult<()> {
+        to_scaled(trade.price)?;
+        let mut state = self.state.lock().unwrap();
+        state.trades.insert(trade.id, trade.clone());
+        Ok(())
+    }
+
+    async fn get_trades(&self, query: &TradeQuery) -> StorageResult<Vec<Trade>> {
+        let state = self.state.lock().unwrap();
+        let matching = state
+            .trades
+            .range(query.from_id.unwrap_or(0)..)
+            .map(|(_, t)| t)
+            .filter(|t| query.matches(t))
+            .cloned();
+        if query.is_forward() {
+            Ok(matching.take(query.limit as usize).collect())
+        } else {
+            let mut latest: Vec<Trade> = matching.rev().take(query.limit as usize).collect();
+            latest.reverse();
+            Ok(latest)
+        }
+    }
+
+    async fn last_trade_id(&self) -> StorageResult<u64> {
+        let state = self.state.lock().unwrap();
+        Ok(state.trades.keys().next_back().copied().unwrap_or(0))
+    }
+
+    async fn save_perp_trade(&self, trade: &Trade) -> StorageResult<()> {
+        to_scaled(trade.price)?;
+        let mut state = self.state.lock().unwrap();
+        state.perp_trades.insert(trade.id, trade.clone());
+        Ok(())
+    }
+
+    async fn last_perp_trade_id(&self) -> StorageResult<u64> {
+        let state = self.state.lock().unwrap();
+        Ok(state.perp_trades.keys().next_back().copied().unwrap_or(0))
+    }
+
+    async fn last_order_id(&self) -> StorageResult<u64> {
+        let state = self.state.lock().unwrap();
+        let from_orders = state.orders.keys().next_back().copied().unwrap_or(0);
+        let from_trades = state
+            .trades
+            .values()
+            .chain(state.perp_trades.values())
+            .map(|t| t.maker_id.max(t.taker_id))
+            .max()
+            .unwrap_or(0);
+        Ok(from_orders.max(from_trades))
+    }
+
+    async fn save_order(&self, order: &OrderRecord) -> StorageResult<()> {
+        to_scaled(order.price)?;
+        to_scaled(order.filled_quote)?;
+        let mut state = self.state.lock().unwrap();
+        state.orders.insert(order.id, order.clone());
+        Ok(())
+    }
+
+    async fn get_order(&self, order_id: u64) -> StorageResult<Option<OrderRecord>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.orders.get(&order_id).cloned())
+    }
+
+    async fn get_user_orders(&self, user_id: Uuid, limit: u32) -> StorageResult<Vec<OrderRecord>> {
+        let state = self.state.lock().unwrap();
+        let mut orders: Vec<OrderRecord> = state
+            .orders
+            .values()
+            .filter(|o| o.user_id == user_id)
+            .cloned()
+            .collect();
+        orders.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        orders.truncate(limit as usize);
+        Ok(orders)
+    }
+
+    async fn save_candle(&self, candle: &Candle) -> StorageResult<()> {
+        for value in [candle.open, candle.high, candle.low, candle.close, candle.quote_volume] {
+            to_scaled(value)?;
+        }
+        let mut state = self.state.lock().unwrap();
+        state
+            .candles
+            .insert((candle.interval, candle.open_time), candle.clone());
+        Ok(())
+    }
+
+    async fn get_candles(
+        &self,
+        interval: CandleInterval,
+        from: Option<u128>,
+        to: Option<u128>,
+        limit: u32,
+    ) -> StorageResult<Vec<Candle>> {
+        let state = self.state.lock().unwrap();
+        let start = (interval, from.unwrap_or(0));
+        let end = (interval, to.unwrap_or(u128::MAX));
+        let range = state.candles.range(start..=end).map(|(_, c)| c.clone());
+        if from.is_some() {
+            Ok(range.take(limit as usize).collect())
+        } else {
+            let mut latest: Vec<Candle> = range.rev().take(limit as usize).collect();
+            latest.reverse();
+            Ok(latest)
+        }
+    }
+
+    async fn get_fix_sequence(&self, session_id: &str) -> StorageResult<Option<FixSequence>> {
+        Ok(self.state.lock().unwrap().fix_sessions.get(session_id).copied())
+    }
+
+    async fn save_fix_sequence(&self, session_id: &str, sequence: &FixSequence) -> StorageResult<()> {
+        self.state.lock().unwrap().fix_sessions.insert(session_id.to_string(), *sequence);
+        Ok(())
+    }
+
+    async fn save_fix_message(&self, session_id: &str, seq: u64, message: &[u8]) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.fix_messages.insert((session_id.to_string(), seq), message.to_vec());
+        Ok(())
+    }
+
+    async fn get_fix_messages(&self, session_id: &str, from: u64, to: u64) -> StorageResult<Vec<(u64, Vec<u8>)>> {
+        if from > to {
+            return Ok(Vec::new());
+        }
+        let state = self.state.lock().unwrap();
+        let range = (session_id.to_string(), from)..=(session_id.to_string(), to);
+        Ok(state.fix_messages.range(range).map(|((_, seq), m)| (*seq, m.clone())).collect())
+    }
+
+    async fn reset_fix_session(&self, session_id: &str) -> StorageResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.fix_sessions.remove(session_id);
+        state.fix_messages.retain(|(id, _), _| id != session_id);
+        Ok(())
+    }
+}
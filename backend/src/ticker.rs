@@ -0,0 +1,176 @@
+// =============================================================================
+// 24時間ティッカー統計
+// =============================================================================
+//
+// 直近24時間の始値・高値・安値・出来高などを、約定のたびに差分更新します。
+// trades_history を毎回走査するのではなく、1分ごとのバケット（= 1分足）を
+// スライディングウィンドウとして保持し、合計値だけを増減させます。
+//
+// - 出来高・売買代金・約定回数: バケットの追加/削除時に加減算（O(1)）
+// - 高値・安値: 最大1440バケットを走査（約定ごとではなく、問い合わせ時のみ）
+// - ウィンドウの精度は1分単位（古いバケットは丸ごと捨てる）
+// =============================================================================
+
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::candles::open_time_for;
+use crate::models::{Candle, CandleInterval, Trade};
+use crate::storage::{SharedStorage, StorageResult};
+
+/// 24時間（ミリ秒）
+pub const WINDOW_MS: u128 = 24 * 60 * 60 * 1000;
+
+/// GET /ticker のレスポンス
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct Ticker {
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub last_price: Option<Decimal>, // 最終約定価格（24時間より前でも保持）
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub best_bid: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub best_ask: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub open_24h: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub high_24h: Option<Decimal>,
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub low_24h: Option<Decimal>,
+    pub base_volume_24h: u64, // BAD数量
+    #[serde(with = "rust_decimal::serde::str")]
+    pub quote_volume_24h: Decimal, // USDC
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price_change: Decimal, // 最終価格 - 24時間前の始値
+    #[serde(with = "rust_decimal::serde::str")]
+    pub price_change_percent: Decimal, // 小数点以下2桁に丸めたパーセント
+    pub trade_count_24h: u64,
+    pub timestamp: u128, // 統計を計算した時刻（ミリ秒）
+}
+
+/// 24時間統計を差分更新で保持する
+#[derive(Debug, Clone, Default)]
+pub struct TickerTracker {
+    buckets: VecDeque<Candle>, // 古い順の1分バケット
+    last_price: Option<Decimal>,
+    base_volume: u64,
+    quote_volume: Decimal,
+    trade_count: u64,
+}
+
+impl TickerTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 保存済みの1分足から直近24時間分を読み込んで復元する
+    pub async fn load(storage: &SharedStorage, now: u128) -> StorageResult<Self> {
+        let from = now.saturating_sub(WINDOW_MS);
+        let candles = storage
+            .get_candles(CandleInterval::OneMinute, Some(from), None, 24 * 60 + 1)
+            .await?;
+        let mut tracker = Self::new();
+        for candle in candles {
+            tracker.push_bucket(candle);
+        }
+        Ok(tracker)
+    }
+
+    /// 約定を反映する
+    pub fn on_trades(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            let minute = open_time_for(trade.timestamp, CandleInterval::OneMinute);
+            let quote = trade.price * Decimal::from(trade.quantity);
+
+            match self.buckets.back_mut() {
+                // 同じ分（時計が巻き戻った約定も最新バケットに含める）
+                Some(bucket) if minute <= bucket.open_time => {
+                    bucket.high = bucket.high.max(trade.price);
+                    bucket.low = bucket.low.min(trade.price);
+                    bucket.close = trade.price;
+                    bucket.volume += trade.quantity;
+                    bucket.quote_volume += quote;
+                    bucket.trade_count += 1;
+                    self.base_volume += trade.quantity;
+                    self.quote_volume += quote;
+                    self.trade_count += 1;
+                }
+                _ => self.push_bucket(Candle {
+                    interval: CandleInterval::OneMinute,
+                    open_time: minute,
+                    open: trade.price,
+                    high: trade.price,
+                    low: trade.price,
+                    close: trade.price,
+                    volume: trade.quantity,
+                    quote_volume: quote,
+                    trade_count: 1,
+                }),
+            }
+            self.last_price = Some(trade.price);
+        }
+        // 問い合わせがなくてもバケットが溜まり続けないように
+        if let Some(latest) = trades.last() {
+            self.evict(latest.timestamp);
+        }
+    }
+
+    /// 現在の統計を計算する
+    ///
+    /// 最良気配は板を持つ呼び出し側（エンジン）から渡してもらう
+    pub fn snapshot(&mut self, now: u128, best_bid: Option<Decimal>, best_ask: Option<Decimal>) -> Ticker {
+        self.evict(now);
+
+        let open = self.buckets.front().map(|b| b.open);
+        let high = self.buckets.iter().map(|b| b.high).max();
+        let low = self.buckets.iter().map(|b| b.low).min();
+
+        // 24時間以内に約定がなければ変化なし
+        let (price_change, price_change_percent) = match (open, self.last_price) {
+            (Some(open), Some(last)) if !open.is_zero() => {
+                let change = last - open;
+                (change, (change / open * Decimal::ONE_HUNDRED).round_dp(2))
+            }
+            _ => (Decimal::ZERO, Decimal::ZERO),
+        };
+
+        Ticker {
+            last_price: self.last_price,
+            best_bid,
+            best_ask,
+            open_24h: open,
+            high_24h: high,
+            low_24h: low,
+            base_volume_24h: self.base_volume,
+            quote_volume_24h: self.quote_volume,
+            price_change,
+            price_change_percent,
+            trade_count_24h: self.trade_count,
+            timestamp: now,
+        }
+    }
+
+    fn push_bucket(&mut self, bucket: Candle) {
+        self.base_volume += bucket.volume;
+        self.quote_volume += bucket.quote_volume;
+        self.trade_count += bucket.trade_count;
+        self.last_price = Some(bucket.close);
+        self.buckets.push_back(bucket);
+    }
+
+    /// 24時間より古いバケットを捨て、合計値から差し引く
+    fn evict(&mut self, now: u128) {
+        let cutoff = now.saturating_sub(WINDOW_MS);
+        while let Some(front) = self.buckets.front() {
+            if front.open_time >= cutoff {
+                break;
+            }
+            let old = self.buckets.pop_front().unwrap();
+            self.base_volume -= old.volume;
+            self.quote_volume -= old.quote_volume;
+            self.trade_count -= old.trade_count;
+        }
+    }
+}
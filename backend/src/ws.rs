@@ -0,0 +1,709 @@
+// =============================================================================
+// WebSocket配信
+// =============================================================================
+//
+// 板情報・約定・ティッカー・形成中のローソク足を、エンジンのbroadcastチャネルから
+// 接続中のクライアントへそのまま流します。
+//
+// /ws はクライアントがJSONで購読するチャネルを選びます。
+//
+//   → {"op": "subscribe", "channel": "diff_depth", "id": 1}
+//   ← {"type": "subscribed", "channel": "diff_depth", "id": 1}
+//   ← {"type": "snapshot", ...} / {"type": "delta", ...}
+//
+// | channel    | 内容                                                   |
+// |------------|--------------------------------------------------------|
+// | depth      | 上位20段の板（購読直後と板の配信のたびに全体）         |
+// | diff_depth | 板のスナップショットと、以降の sequence 付きの差分     |
+// | trades     | 約定1件ごと（板の配信間隔とは関係なく即時）            |
+// | ticker     | 24時間ティッカー（購読直後と、変化があればティッカーの配信間隔ごと）|
+// | candles    | 形成中のローソク足（"interval" で時間足を指定、省略時1m）|
+// | user       | 自分の約定レポート（現物・先物）・残高変化（要ログイン）|
+//
+// 購読の成否はチャネルごとに "subscribed" / "unsubscribed" / "error" で返し、
+// リクエストの "id" をそのまま付けます。{"op": "ping"} には {"type": "pong"} を返します。
+//
+// user の各イベントには resume_token が付きます。再接続時に最後に受け取った
+// resume_token を {"op": "subscribe", "channel": "user", "resume": "..."} で渡すと、
+// 取りこぼした分から送り直します（応答の "resumed" が false なら再開できないので、
+// REST で注文・残高を取り直してください）。
+//
+// depth / diff_depth / trades は "encoding": "msgpack" を付けるとMessagePack（Binaryフレーム）で
+// 受け取れます。接続時にサブプロトコル badbit.msgpack を選ぶと、それが接続全体の既定になります
+// （encoding.rs 参照）。応答は常にJSONです。
+//
+// diff_depth で配信が追いつかず差分を取りこぼした場合は、サーバーがスナップショットを
+// 送り直します（resync）。
+//
+// サーバーは一定間隔でPingフレームを送り、クライアントから何も届かないまま
+// idle_timeout が過ぎた接続は切断します（WsConfig 参照）。
+//
+// /ws/l3（注文単位の板）と /ws/candles は、接続するだけで配信が始まる単機能の接続です。
+// どの接続も注文の所有者（user_id）は含みません。
+//
+// 同時接続数はユーザーごと（`?token=<セッショントークン>` を付けた場合）、
+// 付けなければIPごとに制限します（ratelimit.rs 参照）。
+// =============================================================================
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame, Message, Utf8Bytes, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::api::{ApiError, AppState};
+use crate::auth;
+use crate::encoding::{Encoding, FeedFrame, JSON_PROTOCOL, MSGPACK_PROTOCOL};
+use crate::engine::EngineMessage;
+use crate::feeds::{BookUpdate, FeedMessage, MarketFeeds, UserUpdate, DEPTH_CHANNEL_LEVELS};
+use crate::models::CandleInterval;
+use crate::ratelimit::{RateKey, WsPermit};
+
+/// 1接続あたりの未送信メッセージの上限（超えると購読側の配信が待たされる）
+const OUTBOUND_BUFFER: usize = 256;
+
+/// /ws のハートビート設定
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    pub ping_interval: Duration, // サーバーからPingフレームを送る間隔
+    pub idle_timeout: Duration,  // クライアントから何も届かないまま、この時間が過ぎたら切断する
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(20),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// WebSocket接続時の認証用クエリ（ブラウザのWebSocketはヘッダーを付けられないため）
+#[derive(Deserialize)]
+pub(crate) struct WsAuthQuery {
+    token: Option<String>, // セッショントークン
+}
+
+/// 同時接続枠を確保する
+///
+/// ユーザーもIPも分からない接続は制限しない（None）。
+/// トークンが有効なら、そのユーザーIDも返す
+async fn acquire_permit(
+    state: &Arc<AppState>,
+    token: Option<&str>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Result<(Option<WsPermit>, Option<Uuid>), ApiError> {
+    let mut user_id = None;
+    if let Some(token) = token {
+        user_id = Some(
+            auth::token_user(state, token)
+                .await?
+                .ok_or_else(|| ApiError::unauthorized("セッションが無効か期限切れです"))?,
+        );
+    }
+
+    let key = match (user_id, connect_info) {
+        (Some(user_id), _) => RateKey::User(user_id),
+        (None, Some(Extension(ConnectInfo(addr)))) => RateKey::Ip(addr.ip()),
+        (None, None) => return Ok((None, None)),
+    };
+    let permit = state
+        .rate_limiter
+        .acquire_ws(key)
+        .ok_or_else(|| ApiError::new(StatusCode::TOO_MANY_REQUESTS, "WebSocketの同時接続数が上限に達しています"))?;
+    Ok((Some(permit), user_id))
+}
+
+/// WebSocketハンドラ
+/// クライアントからの接続要求を受け入れ、WebSocket接続にアップグレードする
+pub(crate) async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(auth_query): Query<WsAuthQuery>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    let (permit, user_id) = match acquire_permit(&state, auth_query.token.as_deref(), connect_info).await {
+        Ok(acquired) => acquired,
+        Err(e) => return e.into_response(),
+    };
+    // サブプロトコルで選ばれた形式を接続の既定にする
+    let ws = ws.protocols([JSON_PROTOCOL, MSGPACK_PROTOCOL]);
+    let encoding = ws
+        .selected_protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+        .and_then(Encoding::from_protocol)
+        .unwrap_or_default();
+    ws.on_upgrade(move |socket| async move {
+        let (session, outbound_rx) = Session::new(state, user_id, encoding);
+        session.run(socket, outbound_rx).await;
+        drop(permit); // 切断したら枠を返す
+    })
+}
+
+/// L3（注文単位の板）用WebSocketハンドラ
+pub(crate) async fn ws_l3_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(auth_query): Query<WsAuthQuery>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    let (permit, _) = match acquire_permit(&state, auth_query.token.as_deref(), connect_info).await {
+        Ok(acquired) => acquired,
+        Err(e) => return e.into_response(),
+    };
+    let rx = state.feeds.book_l3.subscribe();
+    ws.on_upgrade(|socket| async move {
+        handle_socket(socket, rx).await;
+        drop(permit);
+    })
+}
+
+// =============================================================================
+// /ws の購読プロトコル
+// =============================================================================
+
+/// 購読できるチャネル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Channel {
+    Depth,
+    DiffDepth,
+    Trades,
+    Ticker,
+    Candles(CandleInterval),
+    User,
+}
+
+impl Channel {
+    /// リクエストのチャネル名と時間足から決める（不明なチャネルならNone）
+    fn parse(name: &str, interval: Option<CandleInterval>) -> Option<Self> {
+        match name {
+            "depth" => Some(Self::Depth),
+            "diff_depth" => Some(Self::DiffDepth),
+            "trades" => Some(Self::Trades),
+            "ticker" => Some(Self::Ticker),
+            "candles" => Some(Self::Candles(interval.unwrap_or(CandleInterval::OneMinute))),
+            "user" => Some(Self::User),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Depth => "depth",
+            Self::DiffDepth => "diff_depth",
+            Self::Trades => "trades",
+            Self::Ticker => "ticker",
+            Self::Candles(_) => "candles",
+            Self::User => "user",
+        }
+    }
+
+    fn interval(self) -> Option<CandleInterval> {
+        match self {
+            Self::Candles(interval) => Some(interval),
+            _ => None,
+        }
+    }
+
+    /// JSON以外の形式でも配信できるか
+    fn supports_binary(self) -> bool {
+        matches!(self, Self::Depth | Self::DiffDepth | Self::Trades)
+    }
+}
+
+/// クライアントから /ws へのリクエスト
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum ClientRequest {
+    Subscribe(ChannelRequest),
+    Unsubscribe(ChannelRequest),
+    Ping { id: Option<u64> },
+}
+
+/// subscribe / unsubscribe の対象
+#[derive(Deserialize)]
+struct ChannelRequest {
+    channel: String,
+    interval: Option<CandleInterval>, // candles のみ
+    token: Option<String>,            // user のみ（接続時に ?token= を付けていれば不要）
+    resume: Option<String>,           // user のみ。最後に受け取ったイベントの resume_token
+    encoding: Option<Encoding>,       // depth / diff_depth / trades のみ。省略時は接続の既定
+    id: Option<u64>,                  // 応答にそのまま付ける
+}
+
+/// 購読の成否の応答
+#[derive(Serialize)]
+struct Ack {
+    channel: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<CandleInterval>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>, // 形式を選べるチャネルなら、実際に配信する形式
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resumed: Option<bool>, // resume を指定した場合、続きから送れるか
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+}
+
+impl Ack {
+    fn new(channel: Channel, id: Option<u64>) -> Self {
+        Self { channel: channel.name(), interval: channel.interval(), encoding: None, resumed: None, id }
+    }
+}
+
+/// サーバーからの応答（配信メッセージとは別）
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Subscribed(Ack),
+    Unsubscribed(Ack),
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        message: String,
+    },
+    Pong {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+    },
+}
+
+impl Reply {
+    fn error(request: &ChannelRequest, message: impl Into<String>) -> Self {
+        Self::Error { channel: Some(request.channel.clone()), id: request.id, message: message.into() }
+    }
+}
+
+/// /ws の1接続の状態
+struct Session {
+    state: Arc<AppState>,
+    user_id: Option<Uuid>,                          // ログイン済みならそのユーザー（user チャネル用）
+    encoding: Encoding,                             // 形式を指定しない購読で使う形式（サブプロトコルで決まる）
+    outbound: mpsc::Sender<Message>,                // 購読タスクからの配信を socket へ渡す
+    subscriptions: HashMap<Channel, JoinHandle<()>>, // 購読中のチャネルと、その配信タスク
+}
+
+impl Session {
+    /// 接続の状態と、配信を受け取る側を作る
+    fn new(state: Arc<AppState>, user_id: Option<Uuid>, encoding: Encoding) -> (Self, mpsc::Receiver<Message>) {
+        let (outbound, outbound_rx) = mpsc::channel(OUTBOUND_BUFFER);
+        (Self { state, user_id, encoding, outbound, subscriptions: HashMap::new() }, outbound_rx)
+    }
+
+    /// 切断されるまでリクエストの処理・配信・ハートビートを行う
+    async fn run(mut self, mut socket: WebSocket, mut outbound_rx: mpsc::Receiver<Message>) {
+        let config = self.state.ws.clone();
+        let mut ping = tokio::time::interval_at(Instant::now() + config.ping_interval, config.ping_interval);
+        let idle = tokio::time::sleep(config.idle_timeout);
+        tokio::pin!(idle);
+
+        loop {
+            tokio::select! {
+                // 1. 購読タスクからの配信をクライアントへ
+                Some(msg) = outbound_rx.recv() => {
+                    if socket.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+                // 2. クライアントからのリクエスト（何か届いたら生きているとみなす）
+                msg = socket.recv() => {
+                    let msg = match msg {
+                        Some(Ok(msg)) => msg,
+                        Some(Err(_)) | None => break,
+                    };
+                    idle.as_mut().reset(Instant::now() + config.idle_timeout);
+                    match msg {
+                        Message::Text(text) => {
+                            let reply = self.handle_request(&text).await;
+                            if let Ok(json) = serde_json::to_string(&reply)
+                                && socket.send(Message::Text(json.into())).await.is_err()
+                            {
+                                break;
+                            }
+                        }
+                        Message::Close(_) => break,
+                        // Pingへの応答はaxumが自動で返す。Pongは生存確認だけ
+                        _ => {}
+                    }
+                }
+                // 3. ハートビート
+                _ = ping.tick() => {
+                    if socket.send(Message::Ping(Default::default())).await.is_err() {
+                        break;
+                    }
+                }
+                // 4. 応答のない接続を切る
+                _ = &mut idle => {
+                    let _ = socket.send(Message::Close(Some(CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "idle timeout".into(),
+                    }))).await;
+                    break;
+                }
+            }
+        }
+
+        for (_, task) in self.subscriptions.drain() {
+            task.abort();
+        }
+    }
+
+    /// 1件のリクエストを処理し、応答を返す
+    async fn handle_request(&mut self, text: &str) -> Reply {
+        let request = match serde_json::from_str::<ClientRequest>(text) {
+            Ok(request) => request,
+            Err(e) => {
+                return Reply::Error { channel: None, id: None, message: format!("リクエストを解釈できません: {}", e) };
+            }
+        };
+        match request {
+            ClientRequest::Subscribe(request) => self.subscribe(request).await,
+            ClientRequest::Unsubscribe(request) => {
+                let Some(channel) = Channel::parse(&request.channel, request.interval) else {
+                    return Reply::error(&request, "不明なチャネルです");
+                };
+                match self.subscriptions.remove(&channel) {
+                    Some(task) => {
+                        task.abort();
+                        Reply::Unsubscribed(Ack::new(channel, request.id))
+                    }
+                    None => Reply::error(&request, "購読していません"),
+                }
+            }
+            ClientRequest::Ping { id } => Reply::Pong { id },
+        }
+    }
+
+    /// チャネルを購読し、配信タスクを起動する
+    ///
+    /// broadcast の受信側はここで作るので、応答（subscribed）より後の更新は取りこぼさない
+    async fn subscribe(&mut self, request: ChannelRequest) -> Reply {
+        let Some(channel) = Channel::parse(&request.channel, request.interval) else {
+            return Reply::error(&request, "不明なチャネルです");
+        };
+        if self.subscriptions.contains_key(&channel) {
+            return Reply::error(&request, "既に購読しています");
+        }
+        // 形式を選べないチャネルは、接続の既定によらずJSONで送る
+        let encoding = if channel.supports_binary() {
+            request.encoding.unwrap_or(self.encoding)
+        } else if request.encoding.is_some_and(|encoding| encoding != Encoding::Json) {
+            return Reply::error(&request, "このチャネルはJSONでのみ配信します");
+        } else {
+            Encoding::Json
+        };
+
+        let state = self.state.clone();
+        let out = self.outbound.clone();
+        let mut resumed = None;
+        let feeds = &state.feeds;
+        let task = match channel {
+            Channel::Depth => {
+                let rx = feeds.depth.subscribe();
+                tokio::spawn(async move {
+                    if send_depth(&state, &out, encoding).await.is_some() {
+                        forward(rx, out, move |frame: FeedFrame| frame.encode(encoding)).await;
+                    }
+                })
+            }
+            Channel::DiffDepth => {
+                // スナップショットを取る前に購読しておく（間の差分を取りこぼさないため）
+                let rx = feeds.book.subscribe();
+                tokio::spawn(stream_diff_depth(state, rx, out, encoding))
+            }
+            Channel::Trades => {
+                tokio::spawn(forward(feeds.trades.subscribe(), out, move |frame: FeedFrame| frame.encode(encoding)))
+            }
+            Channel::Ticker => {
+                let rx = feeds.ticker.subscribe();
+                tokio::spawn(async move {
+                    if send_ticker(&state, &out).await.is_some() {
+                        forward(rx, out, |json| Some(Message::Text(json))).await;
+                    }
+                })
+            }
+            Channel::Candles(interval) => {
+                // 購読している時間足だけを送る
+                tokio::spawn(forward(feeds.candles.subscribe(), out, move |candle| {
+                    if candle.interval == interval { FeedMessage::Candle(&candle).to_json().map(Message::Text) } else { None }
+                }))
+            }
+            Channel::User => {
+                if let Some(token) = &request.token {
+                    match auth::token_user(&state, token).await {
+                        Ok(Some(user_id)) => self.user_id = Some(user_id),
+                        Ok(None) => return Reply::error(&request, "セッションが無効か期限切れです"),
+                        Err(e) => return Reply::error(&request, e.to_string()),
+                    }
+                }
+                let Some(user_id) = self.user_id else {
+                    return Reply::error(&request, "user チャネルにはログインが必要です");
+                };
+                // 履歴を読む前に購読しておき、重なった分は連番で取り除く
+                let rx = feeds.user.subscribe();
+                let missed = request.resume.as_deref().and_then(|token| feeds.user_events_since(user_id, token));
+                resumed = request.resume.as_ref().map(|_| missed.is_some());
+                tokio::spawn(stream_user_events(state.feeds.clone(), user_id, missed.unwrap_or_default(), rx, out))
+            }
+        };
+        self.subscriptions.insert(channel, task);
+        let encoding = channel.supports_binary().then_some(encoding);
+        Reply::Subscribed(Ack { encoding, resumed, ..Ack::new(channel, request.id) })
+    }
+}
+
+/// broadcast チャネルの配信を、送るものだけフレームにして接続へ流す
+///
+/// 板の差分以外は次の配信で最新状態になるので、取りこぼしは読み飛ばす
+async fn forward<T: Clone>(
+    mut rx: broadcast::Receiver<T>,
+    out: mpsc::Sender<Message>,
+    encode: impl Fn(T) -> Option<Message>,
+) {
+    loop {
+        match rx.recv().await {
+            Ok(item) => {
+                if let Some(msg) = encode(item)
+                    && out.send(msg).await.is_err()
+                {
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                eprintln!("Feed channel lagged by {}, skipping...", count);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// user チャネルの配信
+/// 取りこぼした分を送り、以降は自分宛てのイベントだけを送る
+async fn stream_user_events(
+    feeds: MarketFeeds,
+    user_id: Uuid,
+    missed: Vec<UserUpdate>,
+    mut rx: broadcast::Receiver<UserUpdate>,
+    out: mpsc::Sender<Message>,
+) {
+    let mut last_seq = 0;
+    let mut pending = missed;
+    loop {
+        for update in pending.drain(..) {
+            if update.seq > last_seq {
+                last_seq = update.seq;
+                if out.send(Message::Text(update.json)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        match rx.recv().await {
+            Ok(update) if update.user_id == user_id => pending.push(update),
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                // 約定レポートは読み飛ばせないので、履歴から送り直す
+                eprintln!("User channel lagged by {}, replaying...", count);
+                match feeds.user_events_after(user_id, last_seq) {
+                    Some(events) => pending = events,
+                    None => eprintln!("User events for {} are no longer available", user_id),
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// エンジンから上位の板を取って送る（エンジン停止・切断なら None）
+async fn send_depth(state: &AppState, out: &mpsc::Sender<Message>, encoding: Encoding) -> Option<()> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    state
+        .sender
+        .send(EngineMessage::GetDepth { limit: DEPTH_CHANNEL_LEVELS, group: None, respond_to: resp_tx })
+        .await
+        .ok()?;
+    let depth = resp_rx.await.ok()?;
+    out.send(encoding.encode(&FeedMessage::Depth(&depth))?).await.ok()
+}
+
+/// エンジンから現在のティッカーを取って送る（エンジン停止・切断なら None）
+async fn send_ticker(state: &AppState, out: &mpsc::Sender<Message>) -> Option<()> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    state.sender.send(EngineMessage::GetTicker { respond_to: resp_tx }).await.ok()?;
+    let ticker = resp_rx.await.ok()?;
+    out.send(Message::Text(FeedMessage::Ticker(&ticker).to_json()?)).await.ok()
+}
+
+/// エンジンから板のスナップショットを取って送る
+///
+/// 送ったスナップショットの sequence を返す（エンジン停止・切断なら None）
+async fn send_book_snapshot(state: &AppState, out: &mpsc::Sender<Message>, encoding: Encoding) -> Option<u64> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    state.sender.send(EngineMessage::GetOrderBook { respond_to: resp_tx }).await.ok()?;
+    let snapshot = resp_rx.await.ok()?;
+    out.send(encoding.encode(&FeedMessage::Snapshot(&snapshot))?).await.ok()?;
+    Some(snapshot.sequence)
+}
+
+/// diff_depth の配信
+/// スナップショットを送り、以降は差分を sequence が連続するように送る
+async fn stream_diff_depth(
+    state: Arc<AppState>,
+    mut rx: broadcast::Receiver<BookUpdate>,
+    out: mpsc::Sender<Message>,
+    encoding: Encoding,
+) {
+    let Some(mut sequence) = send_book_snapshot(&state, &out, encoding).await else {
+        return;
+    };
+
+    loop {
+        let update = match rx.recv().await {
+            Ok(update) => update,
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                // 差分を読み飛ばすと板が壊れるので、スナップショットから送り直す
+                eprintln!("Book channel lagged by {}, resyncing...", count);
+                match send_book_snapshot(&state, &out, encoding).await {
+                    Some(seq) => sequence = seq,
+                    None => break,
+                }
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if update.sequence <= sequence {
+            // 送ったスナップショットに含まれている
+            continue;
+        }
+        if update.prev_sequence > sequence {
+            // 間が抜けている（通常は起きない）
+            match send_book_snapshot(&state, &out, encoding).await {
+                Some(seq) => sequence = seq,
+                None => break,
+            }
+            continue;
+        }
+        // エンコード済みのフレームを共有して送る
+        let Some(msg) = update.frame.encode(encoding) else {
+            continue;
+        };
+        if out.send(msg).await.is_err() {
+            break;
+        }
+        sequence = update.sequence;
+    }
+}
+
+/// シリアライズ済みのメッセージをそのままクライアントへ送信する（L3）
+async fn handle_socket(mut socket: WebSocket, mut rx: broadcast::Receiver<Utf8Bytes>) {
+    loop {
+        tokio::select! {
+            // 1. 新しい板情報が配信されたら、クライアントに送信
+            result = rx.recv() => {
+                match result {
+                    Ok(json_text) => {
+                        // 送信（エラーならループを抜けて切断扱い）
+                        if socket.send(Message::Text(json_text)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(count)) => {
+                        // L3は毎回板全体を送るので、読み飛ばしても次の配信で最新になる
+                        eprintln!("Broadcast channel lagged by {}, skipping...", count);
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        eprintln!("Broadcast channel closed");
+                        break;
+                    }
+                }
+            }
+            // 2. クライアントからのメッセージ（切断検知など）
+            // これがないと、クライアントが切断してもループが止まらずリソースリークする可能性がある
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(_)) => {
+                        // クライアントからのメッセージは無視（今回は一方通行）
+                        // 必要ならPing/Pong対応などをここに入れる
+                    }
+                    Some(Err(_)) | None => {
+                        // エラーまたは切断（None）
+                        break; 
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// WebSocket /ws/candles のクエリパラメータ
+#[derive(Deserialize)]
+pub(crate) struct CandleStreamQuery {
+    interval: Option<CandleInterval>, // 省略時は1m
+    token: Option<String>,            // セッショントークン（同時接続数をユーザー単位で数える）
+}
+
+/// ローソク足用WebSocketハンドラ
+pub(crate) async fn ws_candles_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CandleStreamQuery>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    let (permit, _) = match acquire_permit(&state, query.token.as_deref(), connect_info).await {
+        Ok(acquired) => acquired,
+        Err(e) => return e.into_response(),
+    };
+    let interval = query.interval.unwrap_or(CandleInterval::OneMinute);
+    ws.on_upgrade(move |socket| async move {
+        handle_candle_socket(socket, state, interval).await;
+        drop(permit);
+    })
+}
+
+/// 形成中のローソク足を、約定のたびにクライアントへ送信する
+/// 
+/// 過去の足は GET /candles で取得し、以降はこのストリームで更新する想定
+async fn handle_candle_socket(mut socket: WebSocket, state: Arc<AppState>, interval: CandleInterval) {
+    let mut rx = state.feeds.candles.subscribe();
+
+    loop {
+        tokio::select! {
+            result = rx.recv() => {
+                match result {
+                    // 購読している時間足だけを送る
+                    Ok(candle) if candle.interval == interval => {
+                        if let Ok(json_text) = serde_json::to_string(&candle)
+                            && socket.send(Message::Text(json_text.into())).await.is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(count)) => {
+                        // 形成中の足は次の更新で最新状態になるので、読み飛ばして問題ない
+                        eprintln!("Candle channel lagged by {}, skipping...", count);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+            }
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(_)) => {}
+                    Some(Err(_)) | None => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,512 @@
+use axum::body::{to_bytes, Body};
+use axum::extract::ConnectInfo;
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::api::{self, AppState};
+use rust_matching_engine::auth;
+use rust_matching_engine::ratelimit::{RateLimitConfig, RateLimiter};
+use rust_matching_engine::db::{self, Balance};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::ws::WsConfig;
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_decimal_macros::dec;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tower::ServiceExt;
+
+/// インメモリのストレージとエンジンでAPIを組み立てる
+fn test_app() -> (Router, mpsc::Sender<EngineMessage>) {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let (db_tx, db_rx) = mpsc::channel(100);
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let feeds = MarketFeeds::new(100);
+
+    let storage_for_writer = storage.clone();
+    tokio::spawn(async move {
+        db::run_db_writer(db_rx, storage_for_writer, Default::default()).await;
+    });
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), engine_feeds, MarketData::default()).await;
+    });
+
+    let rate_limiter = Arc::new(RateLimiter::new(RateLimitConfig::default()));
+    let app = api::router(Arc::new(AppState {
+        sender: eng_tx.clone(),
+        storage,
+        feeds,
+        rate_limiter,
+        order_ids: Arc::new(OrderIdGenerator::default()),
+        ws: WsConfig::default(),
+        db_status: Default::default(),
+        assets: Default::default(),
+    }));
+    (app, eng_tx)
+}
+
+/// リクエストを送り、ステータスとJSONボディを返す
+async fn send(app: &Router, method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+    let mut req = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        req = req.header("Authorization", format!("Bearer {}", token));
+    }
+    let req = match body {
+        Some(body) => req.header("Content-Type", "application/json").body(Body::from(body.to_string())),
+        None => req.body(Body::empty()),
+    }
+    .unwrap();
+    call(app, req).await
+}
+
+/// 組み立て済みのリクエストを送る
+async fn call(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let bytes = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    let json = if bytes.is_empty() { Value::Null } else { serde_json::from_slice(&bytes).unwrap() };
+    (status, json)
+}
+
+/// 登録してログインし、(ユーザーID, トークン) を返す
+async fn register_and_login(app: &Router, username: &str) -> (String, String) {
+    let creds = json!({ "username": username, "password": "correct horse" });
+    let (status, body) = send(app, "POST", "/auth/register", None, Some(creds.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let user_id = body["user_id"].as_str().unwrap().to_string();
+    let (status, body) = send(app, "POST", "/auth/login", None, Some(creds)).await;
+    assert_eq!(status, StatusCode::OK);
+    (user_id, body["token"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_register_login_logout() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "alice").await;
+
+    let (status, body) = send(&app, "GET", "/balance", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["usdc_available"], "10000");
+
+    let (status, _) = send(&app, "POST", "/auth/logout", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+
+    // ログアウト後のトークンは使えない
+    let (status, _) = send(&app, "GET", "/balance", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_register_validation_and_duplicates() {
+    let (app, _) = test_app();
+    let creds = json!({ "username": "bob", "password": "password123" });
+    let (status, _) = send(&app, "POST", "/auth/register", None, Some(creds.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, body) = send(&app, "POST", "/auth/register", None, Some(creds)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body["error"].is_string());
+
+    let (status, _) = send(&app, "POST", "/auth/register", None, Some(json!({ "username": "x", "password": "password123" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, "POST", "/auth/register", None, Some(json!({ "username": "carol", "password": "short" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_login_failures_are_indistinguishable() {
+    let (app, _) = test_app();
+    register_and_login(&app, "dave").await;
+
+    let (wrong_status, wrong_body) = send(&app, "POST", "/auth/login", None, Some(json!({ "username": "dave", "password": "wrong password" }))).await;
+    let (unknown_status, unknown_body) = send(&app, "POST", "/auth/login", None, Some(json!({ "username": "nobody", "password": "wrong password" }))).await;
+    assert_eq!(wrong_status, StatusCode::UNAUTHORIZED);
+    assert_eq!(unknown_status, StatusCode::UNAUTHORIZED);
+    assert_eq!(wrong_body, unknown_body);
+}
+
+#[tokio::test]
+async fn test_private_endpoints_require_token() {
+    let (app, _) = test_app();
+    let order = json!({ "price": "100", "quantity": 1, "side": "Buy" });
+    for (method, uri, body) in [
+        ("GET", "/balance", None),
+        ("GET", "/balances", None),
+        ("GET", "/my-trades", None),
+        ("POST", "/order", Some(order)),
+        ("DELETE", "/order/1", None),
+        ("GET", "/orders/open", None),
+        ("GET", "/orders/history", None),
+        ("GET", "/orders/1", None),
+        ("GET", "/margin/account", None),
+        ("POST", "/margin/borrow", Some(json!({ "asset": "USDC", "amount": "1" }))),
+        ("GET", "/perp/account", None),
+        ("GET", "/perp/orders/open", None),
+    ] {
+        let (status, _) = send(&app, method, uri, None, body.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        let (status, _) = send(&app, method, uri, Some("bogus"), body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+    }
+
+    // 公開APIはトークンなしで使える
+    let (status, _) = send(&app, "GET", "/trades", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, perp) = send(&app, "GET", "/perp/market", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(perp["symbol"], "BAD-PERP");
+    let (status, assets) = send(&app, "GET", "/assets", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(assets[0], json!({ "asset": "BAD", "display_name": "Badbit", "precision": 0 }));
+}
+
+/// 初期残高にBADはないので、テスト用に付与する
+async fn grant_bad(engine: &mpsc::Sender<EngineMessage>, user_id: &str, amount: rust_decimal::Decimal) {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    engine.send(EngineMessage::LoadAccount {
+        balances: vec![Balance { user_id: user_id.parse().unwrap(), asset: "BAD".to_string(), available: amount, locked: dec!(0) }],
+        respond_to: resp_tx,
+    }).await.unwrap();
+    resp_rx.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_two_users_trade_with_each_other() {
+    let (app, engine) = test_app();
+    let (seller_id, seller) = register_and_login(&app, "seller").await;
+    let (_, buyer) = register_and_login(&app, "buyer").await;
+    grant_bad(&engine, &seller_id, dec!(10)).await;
+
+    let (status, trades) = send(&app, "POST", "/order", Some(&seller), Some(json!({ "price": "100", "quantity": 10, "side": "Sell" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(trades.as_array().unwrap().len(), 0);
+
+    let (_, trades) = send(&app, "POST", "/order", Some(&buyer), Some(json!({ "price": "100", "quantity": 4, "side": "Buy" }))).await;
+    assert_eq!(trades.as_array().unwrap().len(), 1);
+
+    // Maker・Takerの両方が精算される（残高はエンジンから読むので、DB Writerを待たなくても反映されている）
+    let (_, balance) = send(&app, "GET", "/balance", Some(&seller), None).await;
+    assert_eq!(balance["usdc_available"], "10400");
+    assert_eq!(balance["bad_available"], "0");
+    assert_eq!(balance["bad_locked"], "6");
+    let (_, balance) = send(&app, "GET", "/balance", Some(&buyer), None).await;
+    assert_eq!(balance["usdc_available"], "9600");
+    assert_eq!(balance["bad_available"], "4");
+
+    // GET /balances は資産ごとの一覧で、合計も付く
+    let (status, balances) = send(&app, "GET", "/balances", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(balances, json!([
+        { "asset": "BAD", "available": "0", "locked": "6", "total": "6" },
+        { "asset": "USDC", "available": "10400", "locked": "0", "total": "10400" },
+    ]));
+
+    // DB Writerへの反映を待つ
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    // 約定はそれぞれの履歴に載る
+    for token in [&seller, &buyer] {
+        let (_, mine) = send(&app, "GET", "/my-trades", Some(token), None).await;
+        assert_eq!(mine.as_array().unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_unstorable_prices_and_amounts_are_rejected() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "trader").await;
+
+    // 小数点以下8桁を超える・保存できる範囲を超える価格と約定代金は、エンジンに渡さず 400
+    for path in ["/order", "/margin/order", "/perp/order"] {
+        for (price, quantity) in [("1.000000001", 1), ("100000000000", 1), ("1000000000", 100), ("0", 1)] {
+            let order = json!({ "price": price, "quantity": quantity, "side": "Buy" });
+            let (status, body) = send(&app, "POST", path, Some(&token), Some(order)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} {} x {}", path, price, quantity);
+            assert!(body["error"].is_string());
+        }
+    }
+    let transfer = json!({ "asset": "USDC", "amount": "0.000000001", "direction": "to_margin" });
+    let (status, _) = send(&app, "POST", "/margin/transfer", Some(&token), Some(transfer)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let transfer = json!({ "amount": "0.000000001", "direction": "to_margin" });
+    let (status, _) = send(&app, "POST", "/perp/transfer", Some(&token), Some(transfer)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, balance) = send(&app, "GET", "/balance", Some(&token), None).await;
+    assert_eq!((balance["usdc_available"].as_str(), balance["usdc_locked"].as_str()), (Some("10000"), Some("0")));
+    let (_, open) = send(&app, "GET", "/orders/open", Some(&token), None).await;
+    assert!(open.as_array().unwrap().is_empty());
+
+    // DB への書き込みは1件も失敗していない
+    let (status, health) = send(&app, "GET", "/health", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health, json!({ "status": "ok", "db_write_failures": 0, "last_db_error": null }));
+}
+
+#[tokio::test]
+async fn test_prices_and_amounts_follow_asset_precision() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "precise").await;
+
+    // 価格は USDC の桁数（小数点以下6桁）まで
+    for path in ["/order", "/margin/order", "/perp/order"] {
+        let order = json!({ "price": "100.0000001", "quantity": 1, "side": "Buy" });
+        let (status, body) = send(&app, "POST", path, Some(&token), Some(order)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+        assert!(body["error"].as_str().unwrap().contains("6桁"));
+    }
+    let order = json!({ "price": "100.000001", "quantity": 1, "side": "Buy" });
+    let (status, _) = send(&app, "POST", "/order", Some(&token), Some(order)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 金額はその資産の桁数まで（BAD は整数）
+    let transfer = json!({ "asset": "USDC", "amount": "0.0000001", "direction": "to_margin" });
+    let (status, _) = send(&app, "POST", "/margin/transfer", Some(&token), Some(transfer)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let transfer = json!({ "amount": "0.0000001", "direction": "to_margin" });
+    let (status, _) = send(&app, "POST", "/perp/transfer", Some(&token), Some(transfer)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let transfer = json!({ "asset": "USDC", "amount": "100.000001", "direction": "to_margin" });
+    let (status, _) = send(&app, "POST", "/margin/transfer", Some(&token), Some(transfer)).await;
+    assert_eq!(status, StatusCode::OK);
+    for path in ["/margin/borrow", "/margin/repay"] {
+        let (status, body) = send(&app, "POST", path, Some(&token), Some(json!({ "asset": "BAD", "amount": "0.5" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+        assert!(body["error"].as_str().unwrap().contains("BAD"));
+    }
+}
+
+#[tokio::test]
+async fn test_order_status_endpoints() {
+    let (app, engine) = test_app();
+    let (seller_id, seller) = register_and_login(&app, "seller").await;
+    let (_, buyer) = register_and_login(&app, "buyer").await;
+    grant_bad(&engine, &seller_id, dec!(10)).await;
+
+    send(&app, "POST", "/order", Some(&seller), Some(json!({ "price": "100", "quantity": 6, "side": "Sell" }))).await;
+    send(&app, "POST", "/order", Some(&seller), Some(json!({ "price": "102", "quantity": 4, "side": "Sell" }))).await;
+    send(&app, "POST", "/order", Some(&buyer), Some(json!({ "price": "102", "quantity": 8, "side": "Buy" }))).await;
+
+    // 板に残っているのは自分の注文だけ（部分約定の状態付き）
+    let (status, open) = send(&app, "GET", "/orders/open", Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let open = open.as_array().unwrap();
+    assert_eq!(open.len(), 1);
+    assert_eq!(open[0]["status"], "PartiallyFilled");
+    assert_eq!(open[0]["filled_quantity"], 2);
+    assert_eq!(open[0]["avg_fill_price"], "102");
+    let open_id = open[0]["id"].as_u64().unwrap();
+    let (_, open) = send(&app, "GET", "/orders/open", Some(&buyer), None).await;
+    assert!(open.as_array().unwrap().is_empty());
+
+    // 他人の注文は照会もキャンセルもできない
+    let (status, _) = send(&app, "GET", &format!("/orders/{}", open_id), Some(&buyer), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "DELETE", &format!("/order/{}", open_id), Some(&buyer), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = send(&app, "DELETE", &format!("/order/{}", open_id), Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // DB Writerへの反映を待つ
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let (status, order) = send(&app, "GET", &format!("/orders/{}", open_id), Some(&seller), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(order["status"], "Cancelled");
+
+    // 履歴は新しい順で、約定・キャンセル済みも含む
+    let (_, history) = send(&app, "GET", "/orders/history", Some(&seller), None).await;
+    let statuses: Vec<&str> = history.as_array().unwrap().iter().map(|o| o["status"].as_str().unwrap()).collect();
+    assert_eq!(statuses, vec!["Cancelled", "Filled"]);
+    let (_, history) = send(&app, "GET", "/orders/history?limit=1", Some(&buyer), None).await;
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["status"], "Filled");
+    assert_eq!(history[0]["avg_fill_price"], "100.5");
+}
+
+#[tokio::test]
+async fn test_public_orderbook_hides_owners() {
+    let (app, _) = test_app();
+    let (user_id, token) = register_and_login(&app, "alice").await;
+    send(&app, "POST", "/order", Some(&token), Some(json!({ "price": "99", "quantity": 3, "side": "Buy" }))).await;
+    send(&app, "POST", "/order", Some(&token), Some(json!({ "price": "99", "quantity": 2, "side": "Buy" }))).await;
+
+    let (status, book) = send(&app, "GET", "/orderbook", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(book, json!({ "sequence": 2, "bids": [{ "price": "99", "quantity": 5, "order_count": 2 }], "asks": [] }));
+
+    let (status, l3) = send(&app, "GET", "/orderbook/l3", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(l3["bids"].as_array().unwrap().len(), 2);
+    assert!(l3["bids"][0]["id"].is_u64());
+    assert!(!l3.to_string().contains(&user_id));
+
+    let (status, depth) = send(&app, "GET", "/depth?limit=5&group=0.5", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(depth["sequence"], 2);
+    assert_eq!(depth["bids"], json!([{ "price": "99.0", "quantity": 5, "order_count": 2, "cumulative_quantity": 5 }]));
+    let (status, _) = send(&app, "GET", "/depth?group=0", None, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // 価格の刻み（USDC の桁数、小数点以下6桁）より細かい刻みは受け付けない
+    for group in ["0.0000000000000000000000000001", "0.0000001"] {
+        let (status, _) = send(&app, "GET", &format!("/depth?group={}", group), None, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let (status, _) = send(&app, "GET", "/depth?group=0.000001", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+// =============================================================================
+// APIキー
+// =============================================================================
+
+/// APIキーを発行して (key, secret) を返す
+async fn create_key(app: &Router, token: &str, body: Value) -> (String, String) {
+    let (status, created) = send(app, "POST", "/api-keys", Some(token), Some(body)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+    (created["key"].as_str().unwrap().to_string(), created["secret"].as_str().unwrap().to_string())
+}
+
+/// APIキーで署名したリクエストを組み立てる
+fn signed(method: Method, uri: &str, key: &str, secret: &str, timestamp: u128, body: &str) -> Request<Body> {
+    let timestamp = timestamp.to_string();
+    let signature = auth::sign_request(secret, &timestamp, &method, uri, body.as_bytes());
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("X-API-KEY", key)
+        .header("X-TIMESTAMP", timestamp)
+        .header("X-SIGNATURE", signature)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn now() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+#[tokio::test]
+async fn test_api_key_lifecycle() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "bot_owner").await;
+    let (key, secret) = create_key(&app, &token, json!({ "label": "mm-bot", "scopes": ["read", "trade"] })).await;
+    assert_eq!(secret.len(), 64);
+
+    // 一覧には secret を含めない
+    let (status, list) = send(&app, "GET", "/api-keys", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(list.as_array().unwrap().len(), 1);
+    assert_eq!(list[0]["key"], key.as_str());
+    assert_eq!(list[0]["scopes"], json!(["read", "trade"]));
+    assert!(list[0].get("secret").is_none());
+
+    let (status, _) = call(&app, signed(Method::GET, "/balance", &key, &secret, now(), "")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&app, "DELETE", &format!("/api-keys/{}", key), Some(&token), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, "DELETE", &format!("/api-keys/{}", key), Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // 失効したキーは使えない
+    let (status, _) = call(&app, signed(Method::GET, "/balance", &key, &secret, now(), "")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_api_key_scopes() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "scoped").await;
+    let (read_key, read_secret) = create_key(&app, &token, json!({ "scopes": ["read"] })).await;
+    let (trade_key, trade_secret) = create_key(&app, &token, json!({ "scopes": ["trade"] })).await;
+    let order = r#"{"price":"100","quantity":1,"side":"Buy"}"#;
+
+    let (status, _) = call(&app, signed(Method::POST, "/order", &read_key, &read_secret, now(), order)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, signed(Method::POST, "/order", &trade_key, &trade_secret, now(), order)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, signed(Method::GET, "/my-trades?limit=10", &trade_key, &trade_secret, now(), "")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = call(&app, signed(Method::GET, "/my-trades?limit=10", &read_key, &read_secret, now(), "")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 現物の残高から資金を移す振替には withdraw が必要
+    let (withdraw_key, withdraw_secret) = create_key(&app, &token, json!({ "scopes": ["withdraw"] })).await;
+    let margin = r#"{"asset":"USDC","amount":"10","direction":"to_margin"}"#;
+    let perp = r#"{"amount":"10","direction":"to_margin"}"#;
+    for (path, body) in [("/margin/transfer", margin), ("/perp/transfer", perp)] {
+        let (status, _) = call(&app, signed(Method::POST, path, &trade_key, &trade_secret, now(), body)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&app, signed(Method::POST, path, &withdraw_key, &withdraw_secret, now(), body)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = call(&app, signed(Method::POST, "/order", &withdraw_key, &withdraw_secret, now(), order)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // APIキーでAPIキーは発行できない
+    let body = r#"{"scopes":["read","trade","withdraw"]}"#;
+    let (status, _) = call(&app, signed(Method::POST, "/api-keys", &trade_key, &trade_secret, now(), body)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_api_key_rejects_bad_signatures_and_stale_requests() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "careful").await;
+    let (key, secret) = create_key(&app, &token, json!({ "scopes": ["trade"] })).await;
+    let order = r#"{"price":"100","quantity":1,"side":"Buy"}"#;
+
+    // 別の secret で署名
+    let (status, _) = call(&app, signed(Method::POST, "/order", &key, "not-the-secret", now(), order)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // 署名後にボディを改ざん
+    let mut req = signed(Method::POST, "/order", &key, &secret, now(), order);
+    *req.body_mut() = Body::from(r#"{"price":"100","quantity":100,"side":"Buy"}"#);
+    let (status, _) = call(&app, req).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // recv-window（既定5秒）より古い / 未来すぎるタイムスタンプ
+    let (status, _) = call(&app, signed(Method::POST, "/order", &key, &secret, now() - 10_000, order)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = call(&app, signed(Method::POST, "/order", &key, &secret, now() + 10_000, order)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // recv-window を広げれば古いリクエストも受け付ける
+    let mut req = signed(Method::POST, "/order", &key, &secret, now() - 10_000, order);
+    req.headers_mut().insert("X-RECV-WINDOW", "30000".parse().unwrap());
+    let (status, _) = call(&app, req).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // 存在しないキー
+    let (status, _) = call(&app, signed(Method::GET, "/balance", "unknown", &secret, now(), "")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_api_key_ip_allowlist() {
+    let (app, _) = test_app();
+    let (_, token) = register_and_login(&app, "fenced").await;
+    let (key, secret) = create_key(&app, &token, json!({ "scopes": ["read"], "ip_allowlist": ["10.0.0.1"] })).await;
+
+    let from = |ip: [u8; 4]| {
+        let mut req = signed(Method::GET, "/balance", &key, &secret, now(), "");
+        req.extensions_mut().insert(ConnectInfo(SocketAddr::from((ip, 40000))));
+        req
+    };
+    let (status, _) = call(&app, from([10, 0, 0, 1])).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = call(&app, from([10, 0, 0, 2])).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // 接続元が分からない場合も拒否
+    let (status, _) = call(&app, signed(Method::GET, "/balance", &key, &secret, now(), "")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
@@ -1,16 +1,27 @@
-use rust_matching_engine::engine::{run_matching_engine, EngineMessage};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData};
 use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::feeds::MarketFeeds;
 use rust_matching_engine::db::DbMessage;
 use rust_matching_engine::models::{Order, Side, OrderType};
 use rust_decimal_macros::dec;
 use uuid::Uuid;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot};
+
+/// 注文記録の保存（SaveOrder）を読み飛ばして、次のDBメッセージを受け取る
+async fn recv_skipping_orders(db_rx: &mut mpsc::Receiver<DbMessage>) -> Option<DbMessage> {
+    loop {
+        match db_rx.recv().await {
+            Some(DbMessage::SaveOrder(_)) => continue,
+            other => return other,
+        }
+    }
+}
 
 #[tokio::test]
 async fn test_cancel_order_releases_funds() {
     let (eng_tx, eng_rx) = mpsc::channel(10);
     let (db_tx, mut db_rx) = mpsc::channel(10);
-    let (broadcast_tx, _) = broadcast::channel(100);
+    let feeds = MarketFeeds::new(100);
 
     let user_id = Uuid::new_v4();
     let mut am = AccountManager::new();
@@ -19,20 +30,20 @@ async fn test_cancel_order_releases_funds() {
     am.load_balance(user_id, "USDC", dec!(1000), dec!(0));
 
     tokio::spawn(async move {
-        run_matching_engine(eng_rx, db_tx, am, broadcast_tx).await;
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
     });
 
     // 1. 注文 (100 * 5 = 500 USDC ロック)
     let (resp_tx, resp_rx) = oneshot::channel();
     let order_id = 1;
     eng_tx.send(EngineMessage::PlaceOrder {
-        order: Order { id: order_id, price: dec!(100), quantity: 5, side: Side::Buy, user_id: Some(user_id), order_type: OrderType::Limit },
+        order: Order { id: order_id, price: dec!(100), quantity: 5, side: Side::Buy, user_id: Some(user_id), order_type: OrderType::Limit, ..Default::default() },
         respond_to: resp_tx
     }).await.unwrap();
     let _ = resp_rx.await.unwrap();
 
     // ロック確認 (DBMessage)
-    match db_rx.recv().await {
+    match recv_skipping_orders(&mut db_rx).await {
         Some(DbMessage::UpdateBalance { user_id: uid, asset, available, locked }) => {
             assert_eq!(uid, user_id);
             assert_eq!(asset, "USDC");
@@ -56,7 +67,7 @@ async fn test_cancel_order_releases_funds() {
     assert_eq!(o.id, order_id);
 
     // 3. 残高解除の確認 (DBMessageを受け取るはず)
-    match db_rx.recv().await {
+    match recv_skipping_orders(&mut db_rx).await {
         Some(DbMessage::UpdateBalance { user_id: uid, asset, available, locked }) => {
             assert_eq!(uid, user_id);
             assert_eq!(asset, "USDC");
@@ -0,0 +1,123 @@
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::candles::{open_time_for, CandleAggregator};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::models::{CandleInterval, Order, OrderType, Side, Trade};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+fn trade(price: Decimal, quantity: u64, timestamp: u128) -> Trade {
+    Trade {
+        id: 1,
+        maker_id: 1,
+        taker_id: 2,
+        price,
+        quantity,
+        taker_side: Side::Buy,
+        timestamp,
+        maker_user_id: None,
+        taker_user_id: None,
+    }
+}
+
+#[test]
+fn test_open_time_is_truncated_to_interval() {
+    assert_eq!(open_time_for(125_000, CandleInterval::OneMinute), 120_000);
+    assert_eq!(open_time_for(125_000, CandleInterval::FiveMinutes), 0);
+    assert_eq!(open_time_for(3_600_000 * 25 + 5, CandleInterval::OneDay), 86_400_000);
+}
+
+#[test]
+fn test_ohlcv_within_one_period() {
+    let mut agg = CandleAggregator::new();
+    agg.apply_trades(&[trade(dec!(100), 5, 60_000), trade(dec!(103), 2, 60_500)]);
+    agg.apply_trades(&[trade(dec!(98), 1, 61_000), trade(dec!(101), 4, 119_999)]);
+
+    let c = agg.current(CandleInterval::OneMinute).unwrap();
+    assert_eq!(c.open_time, 60_000);
+    assert_eq!(c.open, dec!(100));
+    assert_eq!(c.high, dec!(103));
+    assert_eq!(c.low, dec!(98));
+    assert_eq!(c.close, dec!(101));
+    assert_eq!(c.volume, 12);
+    assert_eq!(c.quote_volume, dec!(1208)); // 500 + 206 + 98 + 404
+    assert_eq!(c.trade_count, 4);
+}
+
+#[test]
+fn test_new_period_starts_new_candle() {
+    let mut agg = CandleAggregator::new();
+    agg.apply_trades(&[trade(dec!(100), 5, 60_000)]);
+    let updated = agg.apply_trades(&[trade(dec!(105), 3, 120_000)]);
+
+    // 全時間足分が返る
+    assert_eq!(updated.len(), CandleInterval::ALL.len());
+
+    let one_min = agg.current(CandleInterval::OneMinute).unwrap();
+    assert_eq!(one_min.open_time, 120_000);
+    assert_eq!(one_min.open, dec!(105));
+    assert_eq!(one_min.volume, 3);
+
+    // 5分足はまだ同じ足
+    let five_min = agg.current(CandleInterval::FiveMinutes).unwrap();
+    assert_eq!(five_min.open, dec!(100));
+    assert_eq!(five_min.close, dec!(105));
+    assert_eq!(five_min.volume, 8);
+}
+
+#[tokio::test]
+async fn test_load_restores_in_progress_candle() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let mut before_restart = CandleAggregator::new();
+    for candle in before_restart.apply_trades(&[trade(dec!(100), 5, 60_000)]) {
+        storage.save_candle(&candle).await.unwrap();
+    }
+
+    // 同じ1分足の中で再起動した
+    let mut agg = CandleAggregator::load(&storage, 90_000).await.unwrap();
+    agg.apply_trades(&[trade(dec!(99), 1, 90_000)]);
+    let c = agg.current(CandleInterval::OneMinute).unwrap();
+    assert_eq!(c.open, dec!(100));
+    assert_eq!(c.low, dec!(99));
+    assert_eq!(c.volume, 6);
+
+    // 足が閉じた後の再起動では復元しない
+    let later = CandleAggregator::load(&storage, 180_000).await.unwrap();
+    assert!(later.current(CandleInterval::OneMinute).is_none());
+    assert!(later.current(CandleInterval::OneDay).is_some());
+}
+
+#[tokio::test]
+async fn test_engine_publishes_candle_updates() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+    let mut candle_rx = feeds.candles.subscribe();
+
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), feeds, MarketData::default()).await;
+    });
+    // DBメッセージは読み捨てる
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    for (id, side) in [(1, Side::Sell), (2, Side::Buy)] {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        eng_tx.send(EngineMessage::PlaceOrder {
+            order: Order { id, price: dec!(100), quantity: 10, side, user_id: None, order_type: OrderType::Limit, ..Default::default() },
+            respond_to: resp_tx,
+        }).await.unwrap();
+        resp_rx.await.unwrap();
+    }
+
+    let mut intervals = Vec::new();
+    for _ in 0..CandleInterval::ALL.len() {
+        let candle = candle_rx.recv().await.unwrap();
+        assert_eq!(candle.close, dec!(100));
+        assert_eq!(candle.volume, 10);
+        intervals.push(candle.interval);
+    }
+    assert_eq!(intervals, CandleInterval::ALL.to_vec());
+}
@@ -1,20 +1,34 @@
-use rust_matching_engine::db::{init_database, get_balances, update_balance, save_trade};
+use rust_matching_engine::db::{init_database, run_db_writer, DbMessage, DbWriterStatus, SqliteStorage, create_session, create_user, get_assets, get_balances, get_order, get_session_user, get_trades, get_user_by_username, last_trade_id, migrate, update_balance, save_trade, DbPool, SCHEMA_VERSION};
+use rust_matching_engine::models::{OrderStatus, Side, Trade};
+use rust_matching_engine::storage::{StorageError, TradeQuery};
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 use uuid::Uuid;
-use std::fs;
 
-// ヘルパー: ランダムなDBパスを生成
-fn temp_db_path() -> String {
-    let id = Uuid::new_v4();
-    format!("test_db_{}.sqlite", id)
+// インメモリSQLiteを使うので一時ファイルの作成・削除は不要
+const DB_PATH: &str = ":memory:";
+
+fn trade(id: u64, price: Decimal, quantity: u64, timestamp: u128, taker_user_id: Option<Uuid>) -> Trade {
+    Trade {
+        id,
+        maker_id: id * 10,
+        taker_id: id * 10 + 1,
+        price,
+        quantity,
+        taker_side: Side::Buy,
+        timestamp,
+        maker_user_id: None,
+        taker_user_id,
+    }
 }
 
 #[tokio::test]
 async fn test_db_init_and_default_user() {
-    let db_path = temp_db_path();
-    
     // 1. Init Database
-    let (pool, default_user_id) = init_database(&db_path).await.expect("Failed to init db");
+    let (pool, default_user_id) = init_database(DB_PATH).await.expect("Failed to init db");
 
     // 2. Check default user balance
     let balances = get_balances(&pool, default_user_id).await.expect("Failed to get balances");
@@ -27,16 +41,11 @@ async fn test_db_init_and_default_user() {
     let bad = balances.iter().find(|b| b.asset == "BAD").expect("BAD missing");
     assert_eq!(bad.available, dec!(0));
     assert_eq!(bad.locked, dec!(0));
-
-    // Cleanup
-    pool.close().await;
-    let _ = fs::remove_file(db_path);
 }
 
 #[tokio::test]
 async fn test_db_update_balance() {
-    let db_path = temp_db_path();
-    let (pool, user_id) = init_database(&db_path).await.expect("Failed to init db");
+    let (pool, user_id) = init_database(DB_PATH).await.expect("Failed to init db");
 
     // Update USDC balance
     update_balance(&pool, user_id, "USDC", dec!(5000), dec!(1000))
@@ -49,51 +58,266 @@ async fn test_db_update_balance() {
     
     assert_eq!(usdc.available, dec!(5000));
     assert_eq!(usdc.locked, dec!(1000));
-
-    // Cleanup
-    pool.close().await;
-    let _ = fs::remove_file(db_path);
 }
 
 #[tokio::test]
 async fn test_db_save_trade() {
-    let db_path = temp_db_path();
-    let (pool, user_id) = init_database(&db_path).await.expect("Failed to init db");
-
-    let maker_id = 100;
-    let taker_id = 101;
-    let price = dec!(150.5);
-    let quantity = 10;
-    let timestamp = 1234567890;
-
-    save_trade(
-        &pool,
-        maker_id,
-        taker_id,
-        price,
-        quantity,
-        timestamp,
-        Some(user_id),
-    )
-    .await
-    .expect("Failed to save trade");
+    let (pool, user_id) = init_database(DB_PATH).await.expect("Failed to init db");
+
+    let mut saved = trade(42, dec!(150.5), 10, 1234567890, Some(user_id));
+    saved.taker_side = Side::Sell;
+
+    save_trade(&pool, &saved).await.expect("Failed to save trade");
 
     // Verify directly with SQL query
-    let row: (i64, i64, String, i64, i64, String) = sqlx::query_as(
-        "SELECT maker_order_id, taker_order_id, price, quantity, timestamp, user_id FROM trades LIMIT 1"
+    let row: (i64, i64, i64, i64, i64, i64, String, String) = sqlx::query_as(
+        "SELECT id, maker_order_id, taker_order_id, price, quantity, timestamp, taker_side, taker_user_id FROM trades LIMIT 1"
     )
     .fetch_one(&pool)
     .await
     .expect("Failed to fetch trade");
 
-    assert_eq!(row.0, maker_id as i64);
-    assert_eq!(row.1, taker_id as i64);
-    assert_eq!(row.2, "150.5"); // Stored as string
-    assert_eq!(row.3, 10);
-    assert_eq!(row.4, timestamp as i64);
-    assert_eq!(row.5, user_id.to_string());
+    assert_eq!(row.0, 42);
+    assert_eq!(row.1, 420);
+    assert_eq!(row.2, 421);
+    assert_eq!(row.3, 15_050_000_000); // Stored as price * 10^8
+    assert_eq!(row.4, 10);
+    assert_eq!(row.5, 1234567890);
+    assert_eq!(row.6, "Sell");
+    assert_eq!(row.7, user_id.to_string());
+}
+
+#[tokio::test]
+async fn test_db_trade_value_is_aggregatable_in_sql() {
+    let (pool, user_id) = init_database(DB_PATH).await.expect("Failed to init db");
+
+    save_trade(&pool, &trade(1, dec!(100.25), 4, 1000, Some(user_id))).await.unwrap();
+    save_trade(&pool, &trade(2, dec!(99.5), 2, 2000, None)).await.unwrap();
+
+    // 文字列のCASTなしで集計・並び替えができる
+    let (quote_volume,): (i64,) = sqlx::query_as("SELECT SUM(price * quantity) FROM trades")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(quote_volume, 60_000_000_000); // (100.25*4 + 99.5*2) * 10^8
+
+    let (max_price,): (i64,) = sqlx::query_as("SELECT price FROM trades ORDER BY price DESC LIMIT 1")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(max_price, 10_025_000_000);
+}
+
+#[tokio::test]
+async fn test_db_decimal_round_trip_is_lossless() {
+    let (pool, user_id) = init_database(DB_PATH).await.expect("Failed to init db");
+
+    update_balance(&pool, user_id, "USDC", dec!(0.00000001), dec!(12345678901.12345678))
+        .await
+        .unwrap();
+    let balances = get_balances(&pool, user_id).await.unwrap();
+    let usdc = balances.iter().find(|b| b.asset == "USDC").unwrap();
+    assert_eq!(usdc.available, dec!(0.00000001));
+    assert_eq!(usdc.locked, dec!(12345678901.12345678));
+
+    // 9桁目以降は丸めずにエラー
+    let err = update_balance(&pool, user_id, "USDC", dec!(0.000000001), dec!(0)).await;
+    assert!(matches!(err, Err(StorageError::Unrepresentable(_))));
+}
+
+#[tokio::test]
+async fn test_db_writer_records_failed_writes() {
+    let (pool, _) = init_database(DB_PATH).await.expect("Failed to init db");
+    let status = Arc::new(DbWriterStatus::default());
+    let (db_tx, db_rx) = mpsc::channel(10);
+    let writer = tokio::spawn(run_db_writer(db_rx, Arc::new(SqliteStorage::new(pool)), status.clone()));
+
+    // 保存できない約定はログに出すだけでなく、数えて最後のエラーを残す
+    db_tx.send(DbMessage::SaveTrade(trade(1, dec!(100), 1, 1000, None))).await.unwrap();
+    db_tx.send(DbMessage::SaveTrade(trade(2, dec!(0.000000001), 1, 1000, None))).await.unwrap();
+    drop(db_tx);
+    writer.await.unwrap();
+    assert_eq!(status.failures(), 1);
+    assert!(status.last_error().unwrap().starts_with("SaveTrade: "));
+}
+
+#[tokio::test]
+async fn test_db_corrupt_balance_is_an_error() {
+    let (pool, user_id) = init_database(DB_PATH).await.expect("Failed to init db");
+
+    sqlx::query("UPDATE balances SET available = 'not a number' WHERE user_id = ? AND asset = 'USDC'")
+        .bind(user_id.to_string())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // 以前は0として扱われていたが、今はエラーになる
+    assert!(get_balances(&pool, user_id).await.is_err());
+}
+
+// 旧スキーマ（金額をTEXTで保存）のDBを作る
+async fn legacy_pool() -> DbPool {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    for ddl in [
+        "CREATE TABLE users (id TEXT PRIMARY KEY, username TEXT UNIQUE NOT NULL, created_at INTEGER NOT NULL)",
+        "CREATE TABLE balances (user_id TEXT NOT NULL, asset TEXT NOT NULL, available TEXT NOT NULL, locked TEXT NOT NULL, PRIMARY KEY (user_id, asset))",
+        "CREATE TABLE trades (id INTEGER PRIMARY KEY AUTOINCREMENT, maker_order_id INTEGER NOT NULL, taker_order_id INTEGER NOT NULL, price TEXT NOT NULL, quantity INTEGER NOT NULL, timestamp INTEGER NOT NULL, user_id TEXT)",
+    ] {
+        sqlx::query(ddl).execute(&pool).await.unwrap();
+    }
+    pool
+}
+
+#[tokio::test]
+async fn test_db_migrates_text_columns() {
+    let pool = legacy_pool().await;
+    let user_id = "6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11";
+    sqlx::query("INSERT INTO users VALUES (?, 'trader', 1700000000)")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO balances VALUES (?, 'USDC', '9500.125', '500')")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO trades (maker_order_id, taker_order_id, price, quantity, timestamp, user_id) VALUES (1, 2, '100.5', 5, 1234, ?)")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    migrate(&pool).await.expect("Migration failed");
+
+    let (version,): (i64,) = sqlx::query_as("PRAGMA user_version").fetch_one(&pool).await.unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+
+    let uid = user_id.parse().unwrap();
+    let balances = get_balances(&pool, uid).await.unwrap();
+    assert_eq!(balances[0].available, dec!(9500.125));
+    assert_eq!(balances[0].locked, dec!(500));
+
+    let query = TradeQuery { user_id: Some(uid), limit: 50, ..Default::default() };
+    let trades = get_trades(&pool, &query).await.unwrap();
+    assert_eq!(trades[0].id, 1);
+    assert_eq!(trades[0].price, dec!(100.5));
+    assert_eq!(trades[0].taker_user_id, Some(uid));
+
+    // 作成日時は秒からミリ秒に変換される
+    let (created_at,): (i64,) = sqlx::query_as("SELECT created_at FROM users").fetch_one(&pool).await.unwrap();
+    assert_eq!(created_at, 1_700_000_000_000);
+
+    // 2回目は何もしない
+    migrate(&pool).await.expect("Second migration failed");
+}
+
+#[tokio::test]
+async fn test_db_migration_rolls_back_on_corrupt_row() {
+    let pool = legacy_pool().await;
+    sqlx::query("INSERT INTO balances VALUES ('6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11', 'USDC', 'garbage', '0')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let err = migrate(&pool).await;
+    assert!(matches!(err, Err(StorageError::Corrupt(msg)) if msg.contains("balances.available")));
+
+    // 旧データはそのまま残っている
+    let (available,): (String,) = sqlx::query_as("SELECT available FROM balances")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(available, "garbage");
+    let (version,): (i64,) = sqlx::query_as("PRAGMA user_version").fetch_one(&pool).await.unwrap();
+    assert_eq!(version, 0);
+}
+
+#[tokio::test]
+async fn test_db_migrates_v1_trades() {
+    // v1のスキーマ（テイカーの user_id だけを保存していた）
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let user_id = "6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11";
+    for ddl in [
+        "CREATE TABLE users (id TEXT PRIMARY KEY, username TEXT UNIQUE NOT NULL, created_at INTEGER NOT NULL)",
+        "CREATE TABLE balances (user_id TEXT NOT NULL, asset TEXT NOT NULL, available INTEGER NOT NULL, locked INTEGER NOT NULL, PRIMARY KEY (user_id, asset))",
+        "CREATE TABLE trades (id INTEGER PRIMARY KEY AUTOINCREMENT, maker_order_id INTEGER NOT NULL, taker_order_id INTEGER NOT NULL, price INTEGER NOT NULL, quantity INTEGER NOT NULL, timestamp INTEGER NOT NULL, user_id TEXT)",
+        "CREATE INDEX idx_trades_user ON trades (user_id, timestamp)",
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id TEXT NOT NULL, side TEXT NOT NULL, order_type TEXT NOT NULL, price INTEGER NOT NULL, quantity INTEGER NOT NULL, filled_quantity INTEGER NOT NULL, status TEXT NOT NULL, created_at INTEGER NOT NULL, updated_at INTEGER NOT NULL)",
+        "INSERT INTO orders VALUES (2, '6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11', 'Sell', 'Limit', 10000000000, 5, 5, 'Filled', 1000, 1234)",
+        "INSERT INTO trades (maker_order_id, taker_order_id, price, quantity, timestamp, user_id) VALUES (1, 2, 10050000000, 5, 1234, '6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11')",
+        "INSERT INTO trades (maker_order_id, taker_order_id, price, quantity, timestamp, user_id) VALUES (3, 4, 10100000000, 1, 2345, '6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11')",
+        "PRAGMA user_version = 1",
+    ] {
+        sqlx::query(ddl).execute(&pool).await.unwrap();
+    }
+
+    migrate(&pool).await.expect("Migration failed");
+
+    let (version,): (i64,) = sqlx::query_as("PRAGMA user_version").fetch_one(&pool).await.unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+
+    let uid = user_id.parse().unwrap();
+    let query = TradeQuery { user_id: Some(uid), limit: 50, ..Default::default() };
+    let trades = get_trades(&pool, &query).await.unwrap();
+    assert_eq!(trades.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(trades[0].price, dec!(100.5));
+    // 注文記録が残っていればそこからテイカーの方向を復元する
+    assert_eq!(trades[0].taker_side, Side::Sell);
+    assert_eq!(trades[1].taker_side, Side::Buy);
+    assert_eq!(trades[0].maker_user_id, None);
+
+    // 既存の注文は約定代金が分からないので、平均約定価格なしになる
+    let order = get_order(&pool, 2).await.unwrap().expect("order missing");
+    assert_eq!(order.status, OrderStatus::Filled);
+    assert_eq!(order.filled_quote, dec!(0));
+    assert_eq!(order.avg_fill_price(), None);
+
+    // 新しい約定は続きのIDで保存できる
+    assert_eq!(last_trade_id(&pool).await.unwrap(), 2);
+    save_trade(&pool, &trade(3, dec!(101), 1, 3456, None)).await.unwrap();
+    assert_eq!(last_trade_id(&pool).await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn test_db_migrates_v2_users() {
+    // v2のスキーマ（パスワード・セッションがなかった）
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    for ddl in [
+        "CREATE TABLE users (id TEXT PRIMARY KEY, username TEXT UNIQUE NOT NULL, created_at INTEGER NOT NULL)",
+        "CREATE TABLE balances (user_id TEXT NOT NULL, asset TEXT NOT NULL, available INTEGER NOT NULL, locked INTEGER NOT NULL, PRIMARY KEY (user_id, asset))",
+        "INSERT INTO users VALUES ('6f1c5b8e-8a47-4c43-9a3a-1d2a7c1f0b11', 'default_user', 1000)",
+        "PRAGMA user_version = 2",
+    ] {
+        sqlx::query(ddl).execute(&pool).await.unwrap();
+    }
+
+    migrate(&pool).await.expect("Migration failed");
+
+    let (version,): (i64,) = sqlx::query_as("PRAGMA user_version").fetch_one(&pool).await.unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+
+    // 既存ユーザーはパスワードなし（ログイン不可）のまま残る
+    let user = get_user_by_username(&pool, "default_user").await.unwrap().unwrap();
+    assert_eq!(user.password_hash, None);
+
+    // パスワード付きのユーザーとセッションを作成できる
+    let user_id = create_user(&pool, "alice", Some("hash")).await.unwrap();
+    create_session(&pool, "token-hash", user_id, 2_000).await.unwrap();
+    assert_eq!(get_session_user(&pool, "token-hash", 1_000).await.unwrap(), Some(user_id));
 
-    // Cleanup
-    pool.close().await;
-    let _ = fs::remove_file(db_path);
+    // 後から追加された資産の登録も、初期の資産つきで作られる
+    assert_eq!(get_assets(&pool).await.unwrap().len(), 2);
 }
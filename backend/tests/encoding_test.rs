@@ -0,0 +1,105 @@
+use axum::extract::ws::Message;
+use rust_matching_engine::encoding::{Encoding, FeedFrame};
+use rust_matching_engine::feeds::{FeedMessage, MarketEvent, PublicTrade};
+use rust_matching_engine::models::{Order, OrderType, Side};
+use rust_matching_engine::orderbook::OrderBook;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde_json::{json, Value};
+
+fn limit(id: u64, price: Decimal, quantity: u64, side: Side) -> Order {
+    Order { id, price, quantity, side, user_id: None, order_type: OrderType::Limit, ..Default::default() }
+}
+
+/// JSONのTextフレームとMessagePackのBinaryフレームを、それぞれ値に戻す（あわせてフレームの大きさも返す）
+fn decode_both(message: &FeedMessage) -> ((Value, usize), (Value, usize)) {
+    let Some(Message::Text(json)) = Encoding::Json.encode(message) else {
+        panic!("JSONはTextフレームになるはず");
+    };
+    let Some(Message::Binary(bytes)) = Encoding::Msgpack.encode(message) else {
+        panic!("MessagePackはBinaryフレームになるはず");
+    };
+    ((serde_json::from_str(&json).unwrap(), json.len()), (rmp_serde::from_slice(&bytes).unwrap(), bytes.len()))
+}
+
+/// 小数点以下のある価格・空の側・削除された価格帯を含む板
+fn sample_book() -> OrderBook {
+    let mut ob = OrderBook::new();
+    ob.process_order(limit(1, dec!(99.5), 5, Side::Buy));
+    ob.process_order(limit(2, dec!(99.5), 3, Side::Buy));
+    ob.process_order(limit(3, dec!(100.25), 7, Side::Sell));
+    ob.take_delta();
+    ob.process_order(limit(4, dec!(100.25), 7, Side::Buy)); // 売りの価格帯が消える
+    ob
+}
+
+#[test]
+fn test_msgpack_uses_arrays_and_fixed_point_prices() {
+    let mut ob = sample_book();
+    let snapshot = ob.snapshot();
+    let depth = ob.depth(20, None);
+    let delta = ob.take_delta();
+    let trade = PublicTrade {
+        id: 42,
+        price: dec!(100.25),
+        quantity: 7,
+        taker_side: Side::Buy,
+        timestamp: 1_700_000_000_123,
+    };
+
+    // 構造体はフィールドの宣言順の配列、価格は 10^8 倍した整数になる
+    let expected = [
+        (FeedMessage::Snapshot(&snapshot), json!(["snapshot", 4, [[9_950_000_000u64, 8, 2]], []])),
+        (FeedMessage::Delta(&delta), json!(["delta", 3, 4, [], [[10_025_000_000u64, 0, 0]]])),
+        (FeedMessage::Depth(&depth), json!(["depth", 4, [[9_950_000_000u64, 8, 2, 8]], []])),
+        (FeedMessage::Trade(&trade), json!(["trade", 42, 10_025_000_000u64, 7, "Buy", 1_700_000_000_123u64])),
+    ];
+    for (message, msgpack) in expected {
+        let ((json, _), (decoded, _)) = decode_both(&message);
+        assert_eq!(decoded, msgpack, "{:?}", message);
+        // JSONは今まで通りキー名付きで、価格は文字列
+        assert_eq!(json["type"], msgpack[0]);
+    }
+    let ((json, _), _) = decode_both(&FeedMessage::Delta(&delta));
+    assert_eq!(json["asks"][0], json!({ "price": "100.25", "quantity": 0, "order_count": 0 }));
+
+    // 整数にできない価格（小数点以下8桁超）だけは文字列のまま
+    let odd = PublicTrade { price: dec!(0.000000001), ..trade };
+    let (_, (decoded, _)) = decode_both(&FeedMessage::Trade(&odd));
+    assert_eq!(decoded[2], "0.000000001");
+}
+
+#[test]
+fn test_msgpack_frames_are_much_smaller_than_json() {
+    let mut ob = OrderBook::new();
+    for i in 0..50 {
+        ob.process_order(limit(i, dec!(100) - Decimal::new(i as i64, 2), 10 + i, Side::Buy));
+        ob.process_order(limit(100 + i, dec!(101) + Decimal::new(i as i64, 2), 10 + i, Side::Sell));
+    }
+    let snapshot = ob.snapshot();
+    let depth = ob.depth(20, None);
+
+    for message in [FeedMessage::Snapshot(&snapshot), FeedMessage::Depth(&depth)] {
+        let ((_, json_len), (_, msgpack_len)) = decode_both(&message);
+        assert!(msgpack_len * 3 < json_len, "{:?}: msgpack {} bytes, json {} bytes", message, msgpack_len, json_len);
+    }
+}
+
+#[test]
+fn test_feed_frame_encodes_each_format_once_and_shares_it() {
+    let mut ob = sample_book();
+    let frame = FeedFrame::new(MarketEvent::Delta(ob.take_delta()));
+    let copy = frame.clone();
+
+    let json = frame.encode(Encoding::Json).unwrap();
+    let msgpack = copy.encode(Encoding::Msgpack).unwrap();
+    assert!(matches!(json, Message::Text(_)));
+    assert!(matches!(msgpack, Message::Binary(_)));
+
+    // clone した側でも同じバッファを使う
+    let (Message::Binary(a), Some(Message::Binary(b))) = (&msgpack, frame.encode(Encoding::Msgpack)) else {
+        unreachable!();
+    };
+    assert_eq!(a.as_ptr(), b.as_ptr());
+    assert_eq!(json, Encoding::Json.encode(&frame.event().message()).unwrap());
+}
@@ -1,34 +1,45 @@
-use rust_matching_engine::engine::{run_matching_engine, EngineMessage};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData};
 use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::feeds::MarketFeeds;
 use rust_matching_engine::db::DbMessage;
-use rust_matching_engine::models::{Order, Side, OrderType};
+use rust_matching_engine::models::{Order, OrderRecord, OrderStatus, Side, OrderType};
 use rust_decimal_macros::dec;
 use uuid::Uuid;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot};
+
+/// 注文記録の保存（SaveOrder）を読み飛ばして、次のDBメッセージを受け取る
+async fn recv_skipping_orders(db_rx: &mut mpsc::Receiver<DbMessage>) -> Option<DbMessage> {
+    loop {
+        match db_rx.recv().await {
+            Some(DbMessage::SaveOrder(_)) => continue,
+            other => return other,
+        }
+    }
+}
 
 #[tokio::test]
 async fn test_engine_place_order_no_match() {
     let (eng_tx, eng_rx) = mpsc::channel(10);
-    let (db_tx, mut db_rx) = mpsc::channel(10);
-    let (broadcast_tx, _) = broadcast::channel(100);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
     let user_id = Uuid::new_v4();
     let mut am = AccountManager::new();
     am.load_balance(user_id, "BAD", dec!(100), dec!(0));
     
     tokio::spawn(async move {
-        run_matching_engine(eng_rx, db_tx, am, broadcast_tx).await;
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
     });
 
     let (resp_tx, resp_rx) = oneshot::channel();
     eng_tx.send(EngineMessage::PlaceOrder { 
-        order: Order { id: 1, price: dec!(100), quantity: 10, side: Side::Sell, user_id: Some(user_id), order_type: OrderType::Limit }, 
+        order: Order { id: 1, price: dec!(100), quantity: 10, side: Side::Sell, user_id: Some(user_id), order_type: OrderType::Limit, ..Default::default() }, 
         respond_to: resp_tx 
     }).await.unwrap();
 
     let trades = resp_rx.await.unwrap();
     assert!(trades.is_empty());
 
-    match db_rx.recv().await {
+    match recv_skipping_orders(&mut db_rx).await {
         Some(DbMessage::UpdateBalance { user_id: uid, asset, available, locked }) => {
             assert_eq!(uid, user_id);
             assert_eq!(asset, "BAD");
@@ -42,8 +53,8 @@ async fn test_engine_place_order_no_match() {
 #[tokio::test]
 async fn test_engine_match_trade() {
     let (eng_tx, eng_rx) = mpsc::channel(10);
-    let (db_tx, mut db_rx) = mpsc::channel(10);
-    let (broadcast_tx, _) = broadcast::channel(100);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
 
     let maker_id = Uuid::new_v4();
     let taker_id = Uuid::new_v4();
@@ -53,19 +64,19 @@ async fn test_engine_match_trade() {
     am.load_balance(taker_id, "USDC", dec!(10000), dec!(0));
 
     tokio::spawn(async move {
-        run_matching_engine(eng_rx, db_tx, am, broadcast_tx).await;
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
     });
 
     // 1. Place Maker Order
     let (resp_tx1, resp_rx1) = oneshot::channel();
     eng_tx.send(EngineMessage::PlaceOrder { 
-        order: Order { id: 1, price: dec!(100), quantity: 10, side: Side::Sell, user_id: Some(maker_id), order_type: OrderType::Limit }, 
+        order: Order { id: 1, price: dec!(100), quantity: 10, side: Side::Sell, user_id: Some(maker_id), order_type: OrderType::Limit, ..Default::default() }, 
         respond_to: resp_tx1 
     }).await.unwrap();
     let _ = resp_rx1.await.unwrap();
     
     // Verify Maker's DB update
-    match db_rx.recv().await {
+    match recv_skipping_orders(&mut db_rx).await {
         Some(DbMessage::UpdateBalance { user_id, .. }) => {
             assert_eq!(user_id, maker_id, "First message should be for maker");
         },
@@ -75,7 +86,7 @@ async fn test_engine_match_trade() {
     // 2. Place Taker Order
     let (resp_tx2, resp_rx2) = oneshot::channel();
     eng_tx.send(EngineMessage::PlaceOrder { 
-        order: Order { id: 2, price: dec!(100), quantity: 10, side: Side::Buy, user_id: Some(taker_id), order_type: OrderType::Limit }, 
+        order: Order { id: 2, price: dec!(100), quantity: 10, side: Side::Buy, user_id: Some(taker_id), order_type: OrderType::Limit, ..Default::default() }, 
         respond_to: resp_tx2 
     }).await.unwrap();
     
@@ -86,7 +97,7 @@ async fn test_engine_match_trade() {
     // Expect: Lock UpdateBalance -> SaveTrade -> Final UpdateBalance (USDC) -> Final UpdateBalance (BAD)
     
     // A. Lock Update (Taker)
-    match db_rx.recv().await {
+    match recv_skipping_orders(&mut db_rx).await {
         Some(DbMessage::UpdateBalance { user_id, .. }) => {
              assert_eq!(user_id, taker_id, "Lock message should be for taker");
         },
@@ -94,10 +105,300 @@ async fn test_engine_match_trade() {
     }
 
     // B. Save Trade
-    match db_rx.recv().await {
-        Some(DbMessage::SaveTrade { user_id, .. }) => {
-             assert_eq!(user_id, Some(taker_id));
+    match recv_skipping_orders(&mut db_rx).await {
+        Some(DbMessage::SaveTrade(trade)) => {
+             assert_eq!(trade.taker_user_id, Some(taker_id));
+             assert_eq!(trade.taker_side, Side::Buy);
         },
         m => panic!("Expected SaveTrade, got {:?}", m),
     }
-}
\ No newline at end of file
+}
+/// 注文記録の保存だけを集める（同じ注文は最後の状態）
+fn saved_orders(db_rx: &mut mpsc::Receiver<DbMessage>) -> Vec<OrderRecord> {
+    let mut orders: Vec<OrderRecord> = Vec::new();
+    while let Ok(msg) = db_rx.try_recv() {
+        if let DbMessage::SaveOrder(order) = msg {
+            orders.retain(|o| o.id != order.id);
+            orders.push(order);
+        }
+    }
+    orders.sort_by_key(|o| o.id);
+    orders
+}
+
+async fn place(eng_tx: &mpsc::Sender<EngineMessage>, order: Order) -> usize {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap().len()
+}
+
+async fn open_orders(eng_tx: &mpsc::Sender<EngineMessage>, user_id: Uuid) -> Vec<OrderRecord> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetOpenOrders { user_id, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+#[tokio::test]
+async fn test_engine_tracks_order_status() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+
+    let maker_id = Uuid::new_v4();
+    let taker_id = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(maker_id, "BAD", dec!(100), dec!(0));
+    am.load_balance(taker_id, "USDC", dec!(1000), dec!(0));
+
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    // 売り2本（100で6、101で4）
+    place(&eng_tx, Order { id: 1, price: dec!(100), quantity: 6, side: Side::Sell, user_id: Some(maker_id), order_type: OrderType::Limit, ..Default::default() }).await;
+    place(&eng_tx, Order { id: 2, price: dec!(101), quantity: 4, side: Side::Sell, user_id: Some(maker_id), order_type: OrderType::Limit, ..Default::default() }).await;
+    // 8枚の買いで 100×6 + 101×2 が約定する
+    let trades = place(&eng_tx, Order { id: 3, price: dec!(101), quantity: 8, side: Side::Buy, user_id: Some(taker_id), order_type: OrderType::Limit, ..Default::default() }).await;
+    assert_eq!(trades, 2);
+    // 残高不足は拒否
+    place(&eng_tx, Order { id: 4, price: dec!(100), quantity: 100, side: Side::Buy, user_id: Some(taker_id), order_type: OrderType::Limit, ..Default::default() }).await;
+
+    let saved = saved_orders(&mut db_rx);
+    let status: Vec<(u64, OrderStatus, u64)> = saved.iter().map(|o| (o.id, o.status, o.filled_quantity)).collect();
+    assert_eq!(status, vec![
+        (1, OrderStatus::Filled, 6),
+        (2, OrderStatus::PartiallyFilled, 2),
+        (3, OrderStatus::Filled, 8),
+        (4, OrderStatus::Rejected, 0),
+    ]);
+    assert_eq!(saved[2].avg_fill_price(), Some(dec!(100.25)));
+
+    // 板に残っているのは id=2 だけ
+    let open = open_orders(&eng_tx, maker_id).await;
+    assert_eq!(open.iter().map(|o| o.id).collect::<Vec<_>>(), vec![2]);
+    assert!(open_orders(&eng_tx, taker_id).await.is_empty());
+
+    // 他人の注文はキャンセルできず、板にも残ったまま
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::CancelOrder { order_id: 2, user_id: taker_id, respond_to: resp_tx }).await.unwrap();
+    assert!(resp_rx.await.unwrap().is_none());
+    assert_eq!(open_orders(&eng_tx, maker_id).await.len(), 1);
+
+    // 本人がキャンセルすると Cancelled で保存される
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::CancelOrder { order_id: 2, user_id: maker_id, respond_to: resp_tx }).await.unwrap();
+    assert_eq!(resp_rx.await.unwrap().map(|o| o.quantity), Some(2));
+    assert!(open_orders(&eng_tx, maker_id).await.is_empty());
+    let saved = saved_orders(&mut db_rx);
+    assert_eq!(saved.len(), 1);
+    assert_eq!((saved[0].id, saved[0].status, saved[0].filled_quantity), (2, OrderStatus::Cancelled, 2));
+}
+
+#[tokio::test]
+async fn test_engine_market_order_remainder_expires() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+
+    let user_id = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user_id, "BAD", dec!(100), dec!(0));
+
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    // シミュレータの買い（記録なし）に成行売りをぶつける
+    place(&eng_tx, Order { id: 1, price: dec!(99), quantity: 3, side: Side::Buy, user_id: None, order_type: OrderType::Limit, ..Default::default() }).await;
+    place(&eng_tx, Order { id: 2, price: dec!(0), quantity: 5, side: Side::Sell, user_id: Some(user_id), order_type: OrderType::Market, ..Default::default() }).await;
+
+    let saved = saved_orders(&mut db_rx);
+    assert_eq!(saved.len(), 1);
+    assert_eq!((saved[0].status, saved[0].filled_quantity), (OrderStatus::Expired, 3));
+    assert_eq!(saved[0].avg_fill_price(), Some(dec!(99)));
+    assert!(open_orders(&eng_tx, user_id).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_engine_sends_execution_reports_and_balance_events() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+    let mut user_rx = feeds.user.subscribe();
+
+    let user_id = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user_id, "BAD", dec!(100), dec!(0));
+
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, engine_feeds, MarketData::default()).await;
+    });
+
+    place(&eng_tx, Order { id: 1, price: dec!(99), quantity: 3, side: Side::Buy, user_id: None, order_type: OrderType::Limit, ..Default::default() }).await;
+    place(&eng_tx, Order { id: 2, price: dec!(0), quantity: 5, side: Side::Sell, user_id: Some(user_id), order_type: OrderType::Market, ..Default::default() }).await;
+
+    let mut events = Vec::new();
+    while let Ok(update) = user_rx.try_recv() {
+        assert_eq!(update.user_id, user_id);
+        assert_eq!(update.seq, events.len() as u64 + 1);
+        events.push(serde_json::from_str::<serde_json::Value>(&update.json).unwrap());
+    }
+    let summary: Vec<(&str, &str)> = events
+        .iter()
+        .map(|e| (e["type"].as_str().unwrap(), e["asset"].as_str().or(e["status"].as_str()).unwrap()))
+        .collect();
+    // ロック → 精算 → 受付 → 約定 → 成行の残りの失効
+    assert_eq!(summary, vec![
+        ("balance", "BAD"),
+        ("balance", "USDC"),
+        ("balance", "BAD"),
+        ("execution_report", "New"),
+        ("execution_report", "PartiallyFilled"),
+        ("execution_report", "Expired"),
+    ]);
+    assert_eq!((events[1]["available"].as_str(), events[2]["available"].as_str()), (Some("297"), Some("95")));
+    assert_eq!(events[3]["last_quantity"], serde_json::Value::Null);
+    assert_eq!((events[4]["last_price"].as_str(), events[4]["last_quantity"].as_u64()), (Some("99"), Some(3)));
+    assert_eq!(events[4]["trade_id"], 1);
+
+    // resume_token の続きだけを取り出せる（他の起動のトークンや不正な値は再開できない）
+    let token = events[3]["resume_token"].as_str().unwrap();
+    let missed = feeds.user_events_since(user_id, token).unwrap();
+    assert_eq!(missed.iter().map(|u| u.seq).collect::<Vec<_>>(), vec![5, 6]);
+    assert!(feeds.user_events_since(user_id, events[5]["resume_token"].as_str().unwrap()).unwrap().is_empty());
+    assert!(feeds.user_events_since(user_id, "1.1").is_none());
+    assert!(feeds.user_events_since(user_id, "garbage").is_none());
+    assert!(feeds.user_events_since(Uuid::new_v4(), token).is_none());
+}
+
+#[tokio::test]
+async fn test_engine_balances_and_replica_skip_the_db() {
+    // DBへの書き込みは一切処理しない（DB Writerが遅れている状態）
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+    let replica = feeds.balances.clone();
+    let maker_id = Uuid::new_v4();
+    let taker_id = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(maker_id, "BAD", dec!(10), dec!(0));
+    am.load_balance(taker_id, "USDC", dec!(1000), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    let get_balances = |user_id: Uuid| {
+        let eng_tx = eng_tx.clone();
+        async move {
+            let (resp_tx, resp_rx) = oneshot::channel();
+            eng_tx.send(EngineMessage::GetBalances { user_id, respond_to: resp_tx }).await.unwrap();
+            resp_rx.await.unwrap()
+        }
+    };
+    let amounts = |balances: Vec<rust_matching_engine::db::Balance>| {
+        balances.into_iter().map(|b| (b.asset, b.available, b.locked)).collect::<Vec<_>>()
+    };
+
+    // 起動時に読み込んだ残高は、エンジンが動き出した時点でコピーにも載っている
+    let loaded = vec![("BAD".to_string(), dec!(10), dec!(0))];
+    assert_eq!(amounts(get_balances(maker_id).await), loaded);
+    assert_eq!(amounts(replica.get(maker_id)), loaded);
+
+    for (id, side, user_id, quantity) in [(1, Side::Sell, maker_id, 5), (2, Side::Buy, taker_id, 2)] {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let order = Order { id, price: dec!(100), quantity, side, user_id: Some(user_id), order_type: OrderType::Limit, ..Default::default() };
+        eng_tx.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+        resp_rx.await.unwrap();
+    }
+
+    // 応答を受け取った時点で、エンジンにもコピーにも約定後の残高がある（資産名の順）
+    let maker = vec![
+        ("BAD".to_string(), dec!(5), dec!(3)),
+        ("USDC".to_string(), dec!(200), dec!(0)),
+    ];
+    assert_eq!(amounts(get_balances(maker_id).await), maker);
+    assert_eq!(amounts(replica.get(maker_id)), maker);
+    let taker = vec![
+        ("BAD".to_string(), dec!(2), dec!(0)),
+        ("USDC".to_string(), dec!(800), dec!(0)),
+    ];
+    assert_eq!(amounts(get_balances(taker_id).await), taker);
+    assert_eq!(amounts(replica.get(taker_id)), taker);
+
+    // 知らないユーザーは空
+    assert!(get_balances(Uuid::new_v4()).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_engine_reduce_only_spot_orders() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+
+    let user_id = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user_id, "BAD", dec!(10), dec!(0));
+    am.load_balance(user_id, "USDC", dec!(1000), dec!(0));
+
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    let order = |id, side, quantity| Order {
+        id, price: dec!(105), quantity, side, user_id: Some(user_id), order_type: OrderType::Limit, reduce_only: true, close_position: false,
+    };
+    // 現物のポジションは BAD の保有量。買いと、保有量を超える売りは拒否
+    place(&eng_tx, order(1, Side::Buy, 1)).await;
+    place(&eng_tx, order(2, Side::Sell, 11)).await;
+    place(&eng_tx, order(3, Side::Sell, 6)).await;
+    // close_position は残り（10 - 6）を売る
+    place(&eng_tx, Order { close_position: true, ..order(4, Side::Sell, 0) }).await;
+
+    let saved = saved_orders(&mut db_rx);
+    let status: Vec<(u64, OrderStatus, u64)> = saved.iter().map(|o| (o.id, o.status, o.quantity)).collect();
+    assert_eq!(status, vec![
+        (1, OrderStatus::Rejected, 1),
+        (2, OrderStatus::Rejected, 11),
+        (3, OrderStatus::New, 6),
+        (4, OrderStatus::New, 4),
+    ]);
+    let open: Vec<(u64, u64)> = open_orders(&eng_tx, user_id).await.iter().map(|o| (o.id, o.quantity)).collect();
+    assert_eq!(open, vec![(3, 6), (4, 4)]);
+
+    // 全部拘束されたので、これ以上の reduce_only は出せない
+    place(&eng_tx, order(5, Side::Sell, 1)).await;
+    let saved = saved_orders(&mut db_rx);
+    assert_eq!((saved[0].id, saved[0].status), (5, OrderStatus::Rejected));
+}
+
+#[tokio::test]
+async fn test_engine_spot_position_excludes_ordinary_sells() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let feeds = MarketFeeds::new(100);
+
+    let user_id = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user_id, "BAD", dec!(10), dec!(0));
+
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    // 普通の売り 4枚が板に残っていると、ポジションは残りの 6
+    let order = |id, quantity| Order { id, price: dec!(105), quantity, side: Side::Sell, user_id: Some(user_id), ..Default::default() };
+    place(&eng_tx, order(1, 4)).await;
+    place(&eng_tx, Order { reduce_only: true, ..order(2, 7) }).await;
+    place(&eng_tx, Order { close_position: true, ..order(3, 0) }).await;
+
+    let saved = saved_orders(&mut db_rx);
+    let status: Vec<(u64, OrderStatus, u64)> = saved.iter().map(|o| (o.id, o.status, o.quantity)).collect();
+    assert_eq!(status, vec![
+        (1, OrderStatus::New, 4),
+        (2, OrderStatus::Rejected, 7),
+        (3, OrderStatus::New, 6),
+    ]);
+    let open: Vec<(u64, u64)> = open_orders(&eng_tx, user_id).await.iter().map(|o| (o.id, o.quantity)).collect();
+    assert_eq!(open, vec![(1, 4), (3, 6)]);
+}
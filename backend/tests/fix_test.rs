@@ -0,0 +1,521 @@
+use rust_decimal_macros::dec;
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::api::AppState;
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::fix::{msg_type, tag, FixMessage};
+use rust_matching_engine::fix_gateway::{run_fix_acceptor, FixConfig, SYMBOL};
+use rust_matching_engine::models::{ApiKey, ApiScope, Order, OrderType, Side};
+use rust_matching_engine::ratelimit::{BucketConfig, RateLimitConfig, RateLimiter};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_matching_engine::ws::WsConfig;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+const KEY: &str = "fix-key";
+const SECRET: &str = "fix-secret";
+const READ_ONLY_KEY: &str = "fix-read-key";
+
+/// エンジンとFIXゲートウェイを起動し、(アドレス, エンジンの送信側) を返す
+///
+/// trade 権限の KEY と read 権限だけの READ_ONLY_KEY を持つユーザーを1人作る
+async fn start_gateway() -> (SocketAddr, mpsc::Sender<EngineMessage>) {
+    start_gateway_with_limits(RateLimitConfig::default()).await
+}
+
+/// レート制限の設定を指定して start_gateway する
+async fn start_gateway_with_limits(rate_limit: RateLimitConfig) -> (SocketAddr, mpsc::Sender<EngineMessage>) {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let feeds = MarketFeeds::new(100);
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), engine_feeds, MarketData::default()).await;
+    });
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    let user_id = storage.create_user("fix-trader", "hash").await.unwrap();
+    let balances = storage.get_balances(user_id).await.unwrap();
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::LoadAccount { balances, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+    for (key, scope) in [(KEY, ApiScope::Trade), (READ_ONLY_KEY, ApiScope::Read)] {
+        let api_key = ApiKey {
+            key: key.to_string(),
+            secret: SECRET.to_string(),
+            user_id,
+            label: "fix".to_string(),
+            scopes: vec![scope],
+            ip_allowlist: Vec::new(),
+            created_at: 0,
+            revoked_at: None,
+        };
+        storage.create_api_key(&api_key).await.unwrap();
+    }
+
+    let state = Arc::new(AppState {
+        sender: eng_tx.clone(),
+        storage,
+        feeds,
+        rate_limiter: Arc::new(RateLimiter::new(rate_limit)),
+        order_ids: Arc::new(OrderIdGenerator::default()),
+        ws: WsConfig::default(),
+        db_status: Default::default(),
+        assets: Default::default(),
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(run_fix_acceptor(listener, state, FixConfig::default()));
+    (addr, eng_tx)
+}
+
+/// テスト用のFIXクライアント
+struct FixClient {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    next_seq: u64,
+}
+
+impl FixClient {
+    async fn connect(addr: SocketAddr, next_seq: u64) -> Self {
+        Self { stream: TcpStream::connect(addr).await.unwrap(), buf: Vec::new(), next_seq }
+    }
+
+    /// ヘッダーを付け、次の MsgSeqNum で送る
+    async fn send(&mut self, body: FixMessage) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.send_with_seq(body, seq).await;
+    }
+
+    /// MsgSeqNum を指定して送る（next_seq は進めない）
+    async fn send_with_seq(&mut self, body: FixMessage, seq: u64) {
+        let mut msg = FixMessage::new(body.msg_type())
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "BADBIT")
+            .with(tag::MSG_SEQ_NUM, seq)
+            .with(tag::SENDING_TIME, "20260101-00:00:00.000");
+        for (tag, value) in &body.fields()[1..] {
+            msg.push(*tag, value);
+        }
+        self.stream.write_all(&msg.encode()).await.unwrap();
+    }
+
+    /// 次のメッセージを読む（切断されたら None）
+    async fn recv_any(&mut self) -> Option<FixMessage> {
+        loop {
+            if let Some((msg, len)) = FixMessage::decode(&self.buf).unwrap() {
+                self.buf.drain(..len);
+                return Some(msg);
+            }
+            let mut chunk = [0u8; 4096];
+            let n = tokio::time::timeout(Duration::from_secs(3), self.stream.read(&mut chunk))
+                .await
+                .expect("メッセージが届かない")
+                .unwrap_or(0);
+            if n == 0 {
+                return None;
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// 次のメッセージを読む（Heartbeat は飛ばす）
+    async fn recv(&mut self) -> FixMessage {
+        loop {
+            let msg = self.recv_any().await.expect("切断された");
+            if msg.msg_type() != msg_type::HEARTBEAT {
+                return msg;
+            }
+        }
+    }
+
+    /// 切断されるまで待つ
+    async fn expect_closed(&mut self) {
+        while let Some(msg) = self.recv_any().await {
+            assert_eq!(msg.msg_type(), msg_type::HEARTBEAT, "切断前に予期しないメッセージ: {:?}", msg);
+        }
+    }
+
+    /// Logon を送り、応答を返す
+    async fn logon(&mut self, key: &str, heartbeat: u64, reset: bool) -> FixMessage {
+        let mut logon = FixMessage::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, heartbeat)
+            .with(tag::USERNAME, key)
+            .with(tag::PASSWORD, SECRET);
+        if reset {
+            logon.push(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        self.send(logon).await;
+        self.recv().await
+    }
+}
+
+fn new_order(cl_ord_id: &str, side: &str, quantity: u64, price: &str) -> FixMessage {
+    FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+        .with(tag::CL_ORD_ID, cl_ord_id)
+        .with(tag::SYMBOL, SYMBOL)
+        .with(tag::SIDE, side)
+        .with(tag::ORDER_QTY, quantity)
+        .with(tag::ORD_TYPE, 2)
+        .with(tag::PRICE, price)
+}
+
+/// シミュレータと同じく所有者なしの指値を出す
+async fn place_unowned(engine: &mpsc::Sender<EngineMessage>, id: u64, price: rust_decimal::Decimal, quantity: u64, side: Side) {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let order = Order { id, price, quantity, side, user_id: None, order_type: OrderType::Limit, ..Default::default() };
+    engine.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_orders_are_reported_as_execution_reports() {
+    let (addr, engine) = start_gateway().await;
+    let mut client = FixClient::connect(addr, 1).await;
+    let logon = client.logon(KEY, 30, true).await;
+    assert_eq!(logon.msg_type(), msg_type::LOGON);
+    assert_eq!(logon.get(tag::HEART_BT_INT), Some("30"));
+
+    // 受付
+    client.send(new_order("c1", "1", 2, "100")).await;
+    let accepted = client.recv().await;
+    assert_eq!(accepted.msg_type(), msg_type::EXECUTION_REPORT);
+    assert_eq!((accepted.get(tag::EXEC_TYPE), accepted.get(tag::ORD_STATUS)), (Some("0"), Some("0")));
+    assert_eq!(accepted.get(tag::CL_ORD_ID), Some("c1"));
+    assert_eq!(accepted.get(tag::LEAVES_QTY), Some("2"));
+    let order_id = accepted.get(tag::ORDER_ID).unwrap().to_string();
+
+    // 一部約定
+    place_unowned(&engine, 1_000_000, dec!(100), 1, Side::Sell).await;
+    let fill = client.recv().await;
+    assert_eq!((fill.get(tag::EXEC_TYPE), fill.get(tag::ORD_STATUS)), (Some("F"), Some("1")));
+    assert_eq!((fill.get(tag::LAST_PX), fill.get(tag::LAST_QTY)), (Some("100"), Some("1")));
+    assert_eq!((fill.get(tag::CUM_QTY), fill.get(tag::LEAVES_QTY)), (Some("1"), Some("1")));
+
+    // 取消（OrigClOrdID で指定）
+    let cancel = FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+        .with(tag::ORIG_CL_ORD_ID, "c1")
+        .with(tag::CL_ORD_ID, "c2")
+        .with(tag::SYMBOL, SYMBOL)
+        .with(tag::SIDE, 1);
+    client.send(cancel).await;
+    let cancelled = client.recv().await;
+    assert_eq!((cancelled.get(tag::EXEC_TYPE), cancelled.get(tag::ORD_STATUS)), (Some("4"), Some("4")));
+    assert_eq!((cancelled.get(tag::CL_ORD_ID), cancelled.get(tag::ORIG_CL_ORD_ID)), (Some("c2"), Some("c1")));
+    assert_eq!(cancelled.get(tag::ORDER_ID), Some(order_id.as_str()));
+    assert_eq!(cancelled.get(tag::LEAVES_QTY), Some("0"));
+
+    // 終わった注文は取り消せない（エンジンにも残っていない）
+    let cancel = FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+        .with(tag::ORDER_ID, &order_id)
+        .with(tag::CL_ORD_ID, "c3")
+        .with(tag::SYMBOL, SYMBOL)
+        .with(tag::SIDE, 1);
+    client.send(cancel).await;
+    let rejected = client.recv().await;
+    assert_eq!(rejected.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+    assert_eq!(rejected.get(tag::CXL_REJ_RESPONSE_TO), Some("1"));
+    assert_eq!(rejected.get(tag::CL_ORD_ID), Some("c3"));
+}
+
+#[tokio::test]
+async fn test_replace_reenters_the_order_under_a_new_order_id() {
+    let (addr, _) = start_gateway().await;
+    let mut client = FixClient::connect(addr, 1).await;
+    client.logon(KEY, 30, true).await;
+    client.send(new_order("c1", "1", 5, "100")).await;
+    let old_id = client.recv().await.get(tag::ORDER_ID).unwrap().to_string();
+
+    let replace = |cl_ord_id: &str, side: &str| {
+        FixMessage::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+            .with(tag::ORIG_CL_ORD_ID, "c1")
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, SYMBOL)
+            .with(tag::SIDE, side)
+            .with(tag::ORDER_QTY, 3)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "99")
+    };
+
+    // 売買の向きは変えられない
+    client.send(replace("c2", "2")).await;
+    let rejected = client.recv().await;
+    assert_eq!(rejected.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+    assert_eq!(rejected.get(tag::CXL_REJ_RESPONSE_TO), Some("2"));
+    assert_eq!(rejected.get(tag::ORD_STATUS), Some("0"));
+
+    // 元の注文の Cancelled は送らず、出し直した注文を Replaced として報告する
+    client.send(replace("c3", "1")).await;
+    let replaced = client.recv().await;
+    assert_eq!(replaced.msg_type(), msg_type::EXECUTION_REPORT);
+    assert_eq!((replaced.get(tag::EXEC_TYPE), replaced.get(tag::ORD_STATUS)), (Some("5"), Some("0")));
+    assert_eq!((replaced.get(tag::CL_ORD_ID), replaced.get(tag::ORIG_CL_ORD_ID)), (Some("c3"), Some("c1")));
+    assert_eq!((replaced.get(tag::PRICE), replaced.get(tag::ORDER_QTY)), (Some("99"), Some("3")));
+    assert_ne!(replaced.get(tag::ORDER_ID), Some(old_id.as_str()));
+}
+
+#[tokio::test]
+async fn test_invalid_orders_are_rejected() {
+    let (addr, _) = start_gateway().await;
+    let mut client = FixClient::connect(addr, 1).await;
+    client.logon(KEY, 30, true).await;
+
+    // 必須タグがなければセッション層の Reject
+    let missing_qty = FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+        .with(tag::CL_ORD_ID, "c1")
+        .with(tag::SYMBOL, SYMBOL)
+        .with(tag::SIDE, 1)
+        .with(tag::ORD_TYPE, 2)
+        .with(tag::PRICE, "100");
+    client.send(missing_qty).await;
+    let reject = client.recv().await;
+    assert_eq!(reject.msg_type(), msg_type::REJECT);
+    assert_eq!((reject.get(tag::REF_TAG_ID), reject.get(tag::SESSION_REJECT_REASON)), (Some("38"), Some("1")));
+    assert_eq!(reject.get(tag::REF_SEQ_NUM), Some("2"));
+
+    // 数量は正の整数のみ
+    client.send(new_order("c1", "1", 0, "100")).await;
+    let reject = client.recv().await;
+    assert_eq!((reject.get(tag::REF_TAG_ID), reject.get(tag::SESSION_REJECT_REASON)), (Some("38"), Some("5")));
+
+    // 価格の刻み（USDC の桁数、小数点以下6桁）より細かい価格
+    client.send(new_order("c1", "1", 1, "1.0000001")).await;
+    let reject = client.recv().await;
+    assert_eq!((reject.get(tag::REF_TAG_ID), reject.get(tag::SESSION_REJECT_REASON)), (Some("44"), Some("5")));
+
+    // DB に保存できない価格（小数点以下9桁）・約定代金はエンジンに渡さない
+    client.send(new_order("c1", "1", 1, "1.000000001")).await;
+    let reject = client.recv().await;
+    assert_eq!((reject.get(tag::REF_TAG_ID), reject.get(tag::SESSION_REJECT_REASON)), (Some("44"), Some("5")));
+    client.send(new_order("c1", "1", 100, "1000000000")).await;
+    let reject = client.recv().await;
+    assert_eq!((reject.get(tag::REF_TAG_ID), reject.get(tag::SESSION_REJECT_REASON)), (Some("38"), Some("5")));
+
+    // 知らない銘柄は ExecutionReport で拒否
+    let mut unknown_symbol = new_order("c1", "1", 1, "100");
+    unknown_symbol.set(tag::SYMBOL, "ETH/USDC");
+    client.send(unknown_symbol).await;
+    let report = client.recv().await;
+    assert_eq!((report.get(tag::EXEC_TYPE), report.get(tag::ORD_STATUS)), (Some("8"), Some("8")));
+    assert_eq!(report.get(tag::ORD_REJ_REASON), Some("1"));
+
+    // ClOrdID の重複
+    client.send(new_order("c1", "1", 1, "100")).await;
+    assert_eq!(client.recv().await.get(tag::EXEC_TYPE), Some("0"));
+    client.send(new_order("c1", "1", 1, "100")).await;
+    let report = client.recv().await;
+    assert_eq!(report.get(tag::ORD_REJ_REASON), Some("6"));
+
+    // 残高不足はエンジンが拒否する
+    client.send(new_order("c2", "1", 1000, "100")).await;
+    let report = client.recv().await;
+    assert_eq!((report.get(tag::EXEC_TYPE), report.get(tag::ORD_STATUS)), (Some("8"), Some("8")));
+    assert_eq!(report.get(tag::CL_ORD_ID), Some("c2"));
+
+    // 対応していないメッセージ
+    client.send(FixMessage::new("AE")).await;
+    let reject = client.recv().await;
+    assert_eq!(reject.msg_type(), msg_type::BUSINESS_MESSAGE_REJECT);
+    assert_eq!(reject.get(tag::BUSINESS_REJECT_REASON), Some("3"));
+}
+
+#[tokio::test]
+async fn test_orders_are_rate_limited_like_rest() {
+    // ユーザーの枠は 5（回復しない）、発注の重みは 2、取消は 1
+    let user = BucketConfig { burst: 5, refill_per_sec: 0 };
+    let (addr, _) = start_gateway_with_limits(RateLimitConfig { user, ..RateLimitConfig::default() }).await;
+    let mut client = FixClient::connect(addr, 1).await;
+    client.logon(KEY, 30, true).await;
+
+    for cl_ord_id in ["c1", "c2"] {
+        client.send(new_order(cl_ord_id, "1", 1, "100")).await;
+        assert_eq!(client.recv().await.get(tag::EXEC_TYPE), Some("0"));
+    }
+
+    // 枠が足りなければエンジンに渡さずに拒否する
+    client.send(new_order("c3", "1", 1, "100")).await;
+    let report = client.recv().await;
+    assert_eq!((report.get(tag::EXEC_TYPE), report.get(tag::ORD_STATUS)), (Some("8"), Some("8")));
+    assert_eq!((report.get(tag::ORD_REJ_REASON), report.get(tag::TEXT)), (Some("99"), Some("rate limit exceeded")));
+
+    // 取消は重み 1 なので残りの枠で通り、その後は拒否する
+    for (cl_ord_id, orig) in [("c4", "c1"), ("c5", "c2")] {
+        let cancel = FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+            .with(tag::ORIG_CL_ORD_ID, orig)
+            .with(tag::CL_ORD_ID, cl_ord_id)
+            .with(tag::SYMBOL, SYMBOL)
+            .with(tag::SIDE, 1);
+        client.send(cancel).await;
+        let reply = client.recv().await;
+        if cl_ord_id == "c4" {
+            assert_eq!(reply.get(tag::EXEC_TYPE), Some("4"));
+        } else {
+            assert_eq!(reply.msg_type(), msg_type::ORDER_CANCEL_REJECT);
+            assert_eq!((reply.get(tag::CXL_REJ_REASON), reply.get(tag::TEXT)), (Some("99"), Some("rate limit exceeded")));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_sequence_numbers_persist_and_messages_are_resent() {
+    let (addr, _) = start_gateway().await;
+    let mut client = FixClient::connect(addr, 1).await;
+    let logon = client.logon(KEY, 30, true).await;
+    assert_eq!(logon.seq_num(), Some(1));
+    assert_eq!(logon.get(tag::RESET_SEQ_NUM_FLAG), Some("Y"));
+    client.send(new_order("c1", "1", 1, "100")).await;
+    let report = client.recv().await;
+    assert_eq!(report.seq_num(), Some(2));
+    client.send(FixMessage::new(msg_type::LOGOUT)).await;
+    let logout = client.recv().await;
+    assert_eq!((logout.msg_type(), logout.seq_num()), (msg_type::LOGOUT, Some(3)));
+    client.expect_closed().await;
+
+    // 再接続すると続きの番号から使う。小さすぎる番号のLogonは切断される
+    let mut stale = FixClient::connect(addr, 1).await;
+    let logout = stale.logon(KEY, 30, false).await;
+    assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    assert!(logout.get(tag::TEXT).unwrap().contains("expecting 4"));
+    stale.expect_closed().await;
+
+    let mut client = FixClient::connect(addr, 4).await;
+    let logon = client.logon(KEY, 30, false).await;
+    assert_eq!((logon.msg_type(), logon.seq_num()), (msg_type::LOGON, Some(5)));
+
+    // 再送要求: アプリケーションメッセージは PossDup で、それ以外は GapFill で埋める
+    let resend = FixMessage::new(msg_type::RESEND_REQUEST).with(tag::BEGIN_SEQ_NO, 1).with(tag::END_SEQ_NO, 0);
+    client.send(resend).await;
+    let gap = client.recv().await;
+    assert_eq!((gap.msg_type(), gap.seq_num()), (msg_type::SEQUENCE_RESET, Some(1)));
+    assert_eq!((gap.get(tag::GAP_FILL_FLAG), gap.get(tag::NEW_SEQ_NO)), (Some("Y"), Some("2")));
+    let resent = client.recv().await;
+    assert_eq!((resent.msg_type(), resent.seq_num()), (msg_type::EXECUTION_REPORT, Some(2)));
+    assert_eq!(resent.get(tag::POSS_DUP_FLAG), Some("Y"));
+    assert_eq!(resent.get(tag::ORIG_SENDING_TIME), report.get(tag::SENDING_TIME));
+    assert_eq!(resent.get(tag::CL_ORD_ID), Some("c1"));
+    let gap = client.recv().await;
+    assert_eq!((gap.msg_type(), gap.seq_num()), (msg_type::SEQUENCE_RESET, Some(3)));
+    assert_eq!(gap.get(tag::NEW_SEQ_NO), Some("6"));
+}
+
+#[tokio::test]
+async fn test_gap_in_incoming_sequence_triggers_resend_request() {
+    let (addr, _) = start_gateway().await;
+    let mut client = FixClient::connect(addr, 1).await;
+    client.logon(KEY, 30, true).await;
+
+    // 2 を飛ばして 3 を送ると、2 からの再送を求められ、3 は処理されない
+    client.send_with_seq(new_order("c1", "1", 1, "100"), 3).await;
+    let request = client.recv().await;
+    assert_eq!(request.msg_type(), msg_type::RESEND_REQUEST);
+    assert_eq!((request.get(tag::BEGIN_SEQ_NO), request.get(tag::END_SEQ_NO)), (Some("2"), Some("0")));
+
+    // GapFill で 2 を埋めてから送り直すと処理される
+    let gap_fill = FixMessage::new(msg_type::SEQUENCE_RESET)
+        .with(tag::GAP_FILL_FLAG, "Y")
+        .with(tag::NEW_SEQ_NO, 3)
+        .with(tag::POSS_DUP_FLAG, "Y");
+    client.send_with_seq(gap_fill, 2).await;
+    client.send_with_seq(new_order("c1", "1", 1, "100").with(tag::POSS_DUP_FLAG, "Y"), 3).await;
+    let report = client.recv().await;
+    assert_eq!(report.get(tag::EXEC_TYPE), Some("0"));
+    assert_eq!(report.get(tag::CL_ORD_ID), Some("c1"));
+
+    // 処理済みの番号は PossDup なら無視し、そうでなければ切断する
+    client.send_with_seq(FixMessage::new(msg_type::HEARTBEAT).with(tag::POSS_DUP_FLAG, "Y"), 2).await;
+    client.send_with_seq(FixMessage::new(msg_type::TEST_REQUEST).with(tag::TEST_REQ_ID, "t1"), 4).await;
+    let heartbeat = client.recv_any().await.unwrap();
+    assert_eq!((heartbeat.msg_type(), heartbeat.get(tag::TEST_REQ_ID)), (msg_type::HEARTBEAT, Some("t1")));
+    client.send_with_seq(FixMessage::new(msg_type::HEARTBEAT), 2).await;
+    let logout = client.recv().await;
+    assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    assert!(logout.get(tag::TEXT).unwrap().contains("too low"));
+}
+
+#[tokio::test]
+async fn test_silent_counterparty_gets_test_request_then_disconnected() {
+    let (addr, _) = start_gateway().await;
+    let mut client = FixClient::connect(addr, 1).await;
+    client.logon(KEY, 1, true).await;
+
+    // 何も送らないと Heartbeat → TestRequest → Logout の順に届く
+    let heartbeat = client.recv_any().await.unwrap();
+    assert_eq!(heartbeat.msg_type(), msg_type::HEARTBEAT);
+    let test_request = client.recv().await;
+    assert_eq!(test_request.msg_type(), msg_type::TEST_REQUEST);
+    assert!(test_request.get(tag::TEST_REQ_ID).is_some());
+    let logout = client.recv().await;
+    assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    assert_eq!(logout.get(tag::TEXT), Some("heartbeat timeout"));
+    client.expect_closed().await;
+}
+
+#[tokio::test]
+async fn test_logon_requires_a_trade_api_key() {
+    let (addr, _) = start_gateway().await;
+
+    // secret の誤り
+    let mut client = FixClient::connect(addr, 1).await;
+    let logon = FixMessage::new(msg_type::LOGON)
+        .with(tag::ENCRYPT_METHOD, 0)
+        .with(tag::HEART_BT_INT, 30)
+        .with(tag::USERNAME, KEY)
+        .with(tag::PASSWORD, "wrong");
+    client.send(logon).await;
+    let logout = client.recv().await;
+    assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    assert_eq!(logout.get(tag::TEXT), Some("invalid API key or secret"));
+    client.expect_closed().await;
+
+    // read 権限だけのキー
+    let mut client = FixClient::connect(addr, 1).await;
+    let logout = client.logon(READ_ONLY_KEY, 30, true).await;
+    assert_eq!(logout.msg_type(), msg_type::LOGOUT);
+    assert_eq!(logout.get(tag::TEXT), Some("API key does not have trade scope"));
+    client.expect_closed().await;
+
+    // Logon より前のメッセージには応答せず切断する
+    let mut client = FixClient::connect(addr, 1).await;
+    client.send(FixMessage::new(msg_type::HEARTBEAT)).await;
+    assert!(client.recv_any().await.is_none());
+
+    // 同じセッションは同時に1接続だけ
+    let mut first = FixClient::connect(addr, 1).await;
+    assert_eq!(first.logon(KEY, 30, true).await.msg_type(), msg_type::LOGON);
+    let mut second = FixClient::connect(addr, 1).await;
+    let logout = second.logon(KEY, 30, true).await;
+    assert_eq!(logout.get(tag::TEXT), Some("session is already logged on"));
+}
+
+#[test]
+fn test_codec_round_trip_and_framing() {
+    let msg = FixMessage::new(msg_type::NEW_ORDER_SINGLE).with(tag::CL_ORD_ID, "c1").with(tag::PRICE, dec!(100.5));
+    let bytes = msg.encode();
+    let text = String::from_utf8(bytes.clone()).unwrap();
+    assert!(text.starts_with("8=FIX.4.4\x019=20\x0135=D\x0111=c1\x0144=100.5\x0110="));
+
+    // 2件続けて届いても1件ずつ読める
+    let mut buf = bytes.clone();
+    buf.extend_from_slice(&bytes);
+    let (decoded, len) = FixMessage::decode(&buf).unwrap().unwrap();
+    assert_eq!((decoded, len), (msg.clone(), bytes.len()));
+
+    // 途中までなら続きを待つ
+    assert_eq!(FixMessage::decode(&bytes[..bytes.len() - 1]).unwrap(), None);
+    assert_eq!(FixMessage::decode(b"8=FIX").unwrap(), None);
+
+    // CheckSum が合わなければ、そのメッセージだけ捨てられる
+    let mut corrupt = bytes.clone();
+    corrupt[bytes.len() - 3] = if corrupt[bytes.len() - 3] == b'0' { b'1' } else { b'0' };
+    assert_eq!(FixMessage::decode(&corrupt).unwrap_err().skip, Some(bytes.len()));
+
+    // BeginString が違えば区切れないので切断する
+    assert_eq!(FixMessage::decode(b"8=FIX.4.2\x019=5\x01").unwrap_err().skip, None);
+}
@@ -0,0 +1,265 @@
+use axum::http::Method;
+use prost::Message;
+use rust_decimal_macros::dec;
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::api::AppState;
+use rust_matching_engine::auth::{generate_token, hash_token, sign_request};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::grpc::proto::trading_client::TradingClient;
+use rust_matching_engine::grpc::proto::{
+    self, CancelOrderRequest, GetBalancesRequest, GetOrderBookRequest, PlaceOrderRequest, SubscribeBookRequest,
+    SubscribeTradesRequest,
+};
+use rust_matching_engine::grpc;
+use rust_matching_engine::models::{ApiKey, ApiScope, Order, OrderType, Side};
+use rust_matching_engine::ratelimit::{BucketConfig, RateLimitConfig, RateLimiter};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_matching_engine::ws::WsConfig;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+
+const READ_KEY: &str = "grpc-read-key";
+const SECRET: &str = "grpc-secret";
+
+struct TestServer {
+    client: TradingClient<Channel>,
+    engine: mpsc::Sender<EngineMessage>,
+    token: String, // ログイン済みユーザーのセッショントークン
+}
+
+/// エンジンとgRPCサーバーを起動し、ユーザーを1人ログインさせる
+///
+/// そのユーザーには read 権限だけの READ_KEY も発行しておく
+async fn start_server() -> TestServer {
+    start_server_with_limits(RateLimitConfig::default()).await
+}
+
+/// レート制限の設定を指定して start_server する
+async fn start_server_with_limits(rate_limit: RateLimitConfig) -> TestServer {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let feeds = MarketFeeds::new(100);
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), engine_feeds, MarketData::default()).await;
+    });
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    let user_id = storage.create_user("grpc-trader", "hash").await.unwrap();
+    let balances = storage.get_balances(user_id).await.unwrap();
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::LoadAccount { balances, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+    let token = generate_token();
+    storage.create_session(&hash_token(&token), user_id, u128::MAX).await.unwrap();
+    let api_key = ApiKey {
+        key: READ_KEY.to_string(),
+        secret: SECRET.to_string(),
+        user_id,
+        label: "grpc".to_string(),
+        scopes: vec![ApiScope::Read],
+        ip_allowlist: Vec::new(),
+        created_at: 0,
+        revoked_at: None,
+    };
+    storage.create_api_key(&api_key).await.unwrap();
+
+    let state = Arc::new(AppState {
+        sender: eng_tx.clone(),
+        storage,
+        feeds,
+        rate_limiter: Arc::new(RateLimiter::new(rate_limit)),
+        order_ids: Arc::new(OrderIdGenerator::new(100)),
+        ws: WsConfig::default(),
+        db_status: Default::default(),
+        assets: Default::default(),
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(grpc::serve(listener, state));
+    let client = TradingClient::connect(format!("http://{}", addr)).await.unwrap();
+    TestServer { client, engine: eng_tx, token }
+}
+
+/// セッショントークン付きのリクエスト
+fn with_token<T>(token: &str, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+    request
+}
+
+/// APIキーで署名したリクエスト
+fn signed<T: Message>(path: &str, secret: &str, message: T) -> Request<T> {
+    let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis().to_string();
+    let signature = sign_request(secret, &timestamp, &Method::POST, path, &message.encode_to_vec());
+    let mut request = Request::new(message);
+    let metadata = request.metadata_mut();
+    metadata.insert("x-api-key", READ_KEY.parse().unwrap());
+    metadata.insert("x-timestamp", timestamp.parse().unwrap());
+    metadata.insert("x-signature", signature.parse().unwrap());
+    request
+}
+
+fn limit(price: &str, quantity: u64, side: proto::Side) -> PlaceOrderRequest {
+    PlaceOrderRequest {
+        price: price.to_string(),
+        quantity,
+        side: side as i32,
+        order_type: proto::OrderType::Limit as i32,
+    }
+}
+
+/// シミュレータと同じく所有者なしの指値を出す
+async fn place_unowned(engine: &mpsc::Sender<EngineMessage>, id: u64, price: rust_decimal::Decimal, quantity: u64, side: Side) {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let order = Order { id, price, quantity, side, user_id: None, order_type: OrderType::Limit, ..Default::default() };
+    engine.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_place_and_cancel_order_with_session_token() {
+    let TestServer { mut client, engine, token } = start_server().await;
+    place_unowned(&engine, 1, dec!(101), 1, Side::Sell).await;
+
+    // 板にある売りと一部約定し、残りは板に載る
+    let placed = client.place_order(with_token(&token, limit("101", 3, proto::Side::Buy))).await.unwrap().into_inner();
+    assert_eq!(placed.trades.len(), 1);
+    assert_eq!((placed.trades[0].price.as_str(), placed.trades[0].quantity), ("101", 1));
+    assert_eq!(placed.trades[0].taker_id, placed.order_id);
+    assert_eq!(placed.trades[0].taker_side, proto::Side::Buy as i32);
+
+    let book = client.get_order_book(GetOrderBookRequest {}).await.unwrap().into_inner();
+    assert_eq!(book.bids.len(), 1);
+    assert_eq!((book.bids[0].price.as_str(), book.bids[0].quantity, book.bids[0].order_count), ("101", 2, 1));
+    assert!(book.asks.is_empty());
+
+    let cancel = CancelOrderRequest { order_id: placed.order_id };
+    let cancelled = client.cancel_order(with_token(&token, cancel)).await.unwrap().into_inner();
+    assert_eq!((cancelled.order_id, cancelled.remaining_quantity), (placed.order_id, 2));
+    assert_eq!(cancelled.side, proto::Side::Buy as i32);
+
+    // 取消済みの注文は見つからない
+    let err = client.cancel_order(with_token(&token, cancel)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_invalid_orders_are_rejected() {
+    let TestServer { mut client, token, .. } = start_server().await;
+
+    let err = client.place_order(with_token(&token, limit("abc", 1, proto::Side::Buy))).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client.place_order(with_token(&token, limit("100", 0, proto::Side::Buy))).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client.place_order(with_token(&token, limit("100", 1, proto::Side::Unspecified))).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    // 価格の刻み（USDC の桁数）より細かい価格
+    let err = client.place_order(with_token(&token, limit("1.0000001", 1, proto::Side::Buy))).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    // DB に保存できない価格・約定代金
+    let err = client.place_order(with_token(&token, limit("1.000000001", 1, proto::Side::Buy))).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client.place_order(with_token(&token, limit("1000000000", 100, proto::Side::Buy))).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_authentication_matches_rest() {
+    let TestServer { mut client, token, .. } = start_server().await;
+
+    // 認証なし・無効なトークン
+    let err = client.place_order(limit("100", 1, proto::Side::Buy)).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    let err = client.get_balances(with_token("invalid", GetBalancesRequest {})).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    // セッショントークン
+    let balances = client.get_balances(with_token(&token, GetBalancesRequest {})).await.unwrap().into_inner();
+    let usdc = balances.balances.iter().find(|b| b.asset == "USDC").unwrap();
+    assert_eq!((usdc.available.as_str(), usdc.locked.as_str()), ("10000", "0"));
+
+    // APIキー: read 権限で残高は読めるが、発注はできない
+    let path = "/badbit.v1.Trading/GetBalances";
+    let balances = client.get_balances(signed(path, SECRET, GetBalancesRequest {})).await.unwrap().into_inner();
+    assert_eq!(balances.balances.len(), 2);
+    let err = client.get_balances(signed(path, "wrong", GetBalancesRequest {})).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    let order = limit("100", 1, proto::Side::Buy);
+    let err = client.place_order(signed("/badbit.v1.Trading/PlaceOrder", SECRET, order)).await.unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    // 署名は RPC のパスにも掛かっている
+    let order = limit("100", 1, proto::Side::Buy);
+    let err = client.place_order(signed(path, SECRET, order)).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn test_user_rate_limit_uses_rest_weights() {
+    // ユーザーの枠は 5（回復しない）、発注の重みは 2、取消と照会は 1
+    let user = BucketConfig { burst: 5, refill_per_sec: 0 };
+    let TestServer { mut client, token, .. } =
+        start_server_with_limits(RateLimitConfig { user, ..RateLimitConfig::default() }).await;
+
+    let first = client.place_order(with_token(&token, limit("100", 1, proto::Side::Buy))).await.unwrap().into_inner();
+    client.place_order(with_token(&token, limit("100", 1, proto::Side::Buy))).await.unwrap();
+    let err = client.place_order(with_token(&token, limit("100", 1, proto::Side::Buy))).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    assert!(err.metadata().get("retry-after").is_some());
+
+    // 残りの 1 で取消は通り、その後は照会も拒否される
+    let cancel = CancelOrderRequest { order_id: first.order_id };
+    client.cancel_order(with_token(&token, cancel)).await.unwrap();
+    let err = client.get_balances(with_token(&token, GetBalancesRequest {})).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn test_ip_rate_limit_applies_before_authentication() {
+    // 接続元IPの枠は 3（回復しない）
+    let ip = BucketConfig { burst: 3, refill_per_sec: 0 };
+    let TestServer { mut client, .. } = start_server_with_limits(RateLimitConfig { ip, ..RateLimitConfig::default() }).await;
+
+    client.get_order_book(GetOrderBookRequest {}).await.unwrap();
+    let err = client.place_order(limit("100", 1, proto::Side::Buy)).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+    // 枠を使い切ると、認証なしのリクエストも認証を試す前に拒否する
+    let err = client.place_order(limit("100", 1, proto::Side::Buy)).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    let err = client.get_order_book(GetOrderBookRequest {}).await.unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn test_subscribe_book_and_trades() {
+    let TestServer { mut client, engine, .. } = start_server().await;
+    place_unowned(&engine, 1, dec!(99), 5, Side::Buy).await;
+
+    let mut book = client.subscribe_book(SubscribeBookRequest {}).await.unwrap().into_inner();
+    let mut trades = client.subscribe_trades(SubscribeTradesRequest {}).await.unwrap().into_inner();
+    let next = Duration::from_secs(2);
+
+    // 購読直後はスナップショット
+    let snapshot = tokio::time::timeout(next, book.message()).await.unwrap().unwrap().unwrap();
+    assert!(snapshot.snapshot);
+    assert_eq!((snapshot.bids[0].price.as_str(), snapshot.bids[0].quantity), ("99", 5));
+
+    // 以降は変化した価格帯だけが、sequence が連続する差分で届く
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    place_unowned(&engine, 2, dec!(99), 2, Side::Sell).await;
+    let delta = tokio::time::timeout(next, book.message()).await.unwrap().unwrap().unwrap();
+    assert!(!delta.snapshot);
+    assert_eq!(delta.prev_sequence, snapshot.sequence);
+    assert_eq!((delta.bids[0].price.as_str(), delta.bids[0].quantity), ("99", 3));
+
+    let trade = tokio::time::timeout(next, trades.message()).await.unwrap().unwrap().unwrap();
+    assert_eq!((trade.price.as_str(), trade.quantity), ("99", 2));
+    assert_eq!(trade.taker_side, proto::Side::Sell as i32);
+}
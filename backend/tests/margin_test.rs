@@ -0,0 +1,438 @@
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::db::{run_db_writer, DbMessage, DbWriterStatus};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::margin::{MarginAccountSummary, MarginConfig, MarginError, MarginManager, MarginRequest};
+use rust_matching_engine::models::{Order, OrderRecord, OrderStatus, OrderType, Side, Trade};
+use rust_matching_engine::orderbook::OrderBook;
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+const DAY_MS: u128 = 24 * 60 * 60 * 1000;
+
+fn limit(id: u64, side: Side, price: Decimal, quantity: u64, user_id: Option<Uuid>) -> Order {
+    Order { id, price, quantity, side, user_id, order_type: OrderType::Limit, ..Default::default() }
+}
+
+fn market(id: u64, side: Side, quantity: u64, user_id: Option<Uuid>) -> Order {
+    Order { id, price: dec!(0), quantity, side, user_id, order_type: OrderType::Market, ..Default::default() }
+}
+
+#[test]
+fn test_borrow_limits_interest_and_repay() {
+    let mut margin = MarginManager::new(MarginConfig::default(), Arc::new(OrderIdGenerator::default()));
+    let user = Uuid::new_v4();
+
+    // 板がなく約定もなければ評価できない
+    let mut book = OrderBook::new();
+    margin.transfer_in(&user, "USDC", dec!(100), 0).unwrap();
+    assert_eq!(margin.borrow(&user, "USDC", dec!(1), margin.mark_price(&book), 0), Err(MarginError::NoMarkPrice));
+
+    // 仲値 10 がマーク価格になる
+    book.process_order(limit(1, Side::Buy, dec!(9), 10, None));
+    book.process_order(limit(2, Side::Sell, dec!(11), 10, None));
+    let mark = margin.mark_price(&book);
+    assert_eq!(mark, Some(dec!(10)));
+
+    // initial_margin 0.25: 純資産 100 に対して 400 まで借りられる
+    assert_eq!(margin.borrow(&user, "USDC", dec!(401), mark, 0), Err(MarginError::BelowInitialMargin));
+    margin.borrow(&user, "USDC", dec!(400), mark, 0).unwrap();
+    assert_eq!(margin.borrow(&user, "ETH", dec!(1), mark, 0), Err(MarginError::UnsupportedAsset("ETH".to_string())));
+    assert_eq!(margin.borrow(&user, "USDC", dec!(0), mark, 0), Err(MarginError::InvalidAmount));
+    // 借りた後は、担保を現物へ戻せない
+    assert_eq!(margin.transfer_out(&user, "USDC", dec!(1), mark, 0), Err(MarginError::BelowInitialMargin));
+
+    // 1日で日利 0.0003 の利息が付く
+    let summary = margin.summary(&user, mark, DAY_MS);
+    assert_eq!(summary.loans[0].principal, dec!(400));
+    assert_eq!(summary.loans[0].interest, dec!(0.12));
+    assert_eq!(summary.equity, Some(dec!(99.88)));
+    assert_eq!(summary.margin_ratio, Some(dec!(99.88) / dec!(400.12)));
+
+    // 返済は利息から先に充てる
+    margin.repay(&user, "USDC", dec!(50.12), DAY_MS).unwrap();
+    let summary = margin.summary(&user, mark, DAY_MS);
+    assert_eq!((summary.loans[0].principal, summary.loans[0].interest), (dec!(350), dec!(0)));
+    assert_eq!(summary.balances[0].available, dec!(449.88));
+
+    // 借入より多く指定しても、借入の分しか返さない
+    margin.repay(&user, "USDC", dec!(1000), DAY_MS).unwrap();
+    let summary = margin.summary(&user, mark, DAY_MS);
+    assert!(summary.loans.is_empty());
+    assert_eq!(summary.balances[0].available, dec!(99.88));
+    assert_eq!(summary.margin_ratio, None);
+}
+
+#[test]
+fn test_interest_accrues_per_asset_over_time() {
+    let mut margin = MarginManager::new(MarginConfig::default(), Arc::new(OrderIdGenerator::default()));
+    let user = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    book.process_order(limit(1, Side::Buy, dec!(9), 10, None));
+    book.process_order(limit(2, Side::Sell, dec!(11), 10, None));
+    let mark = margin.mark_price(&book);
+
+    margin.transfer_in(&user, "USDC", dec!(1000), 0).unwrap();
+    margin.borrow(&user, "BAD", dec!(100), mark, 0).unwrap();
+    margin.borrow(&user, "USDC", dec!(200), mark, 0).unwrap();
+
+    // 半日で BAD は日利 0.0005、USDC は日利 0.0003 の半分
+    let summary = margin.summary(&user, mark, DAY_MS / 2);
+    assert_eq!((summary.loans[0].asset.as_str(), summary.loans[0].interest), ("BAD", dec!(0.025)));
+    assert_eq!((summary.loans[1].asset.as_str(), summary.loans[1].interest), ("USDC", dec!(0.03)));
+
+    // 同じ時刻に何度見ても二重には付かず、利息には利息が付かない（元本にだけ付く）
+    margin.summary(&user, mark, DAY_MS / 2);
+    let summary = margin.summary(&user, mark, 2 * DAY_MS);
+    assert_eq!(summary.loans[0].interest, dec!(0.1));
+    assert_eq!(summary.loans[1].interest, dec!(0.12));
+    assert_eq!(summary.debt, Some(dec!(100.1) * dec!(10) + dec!(200.12)));
+
+    // 元本を減らすと、その後の利息も減る
+    margin.repay(&user, "USDC", dec!(100.12), 2 * DAY_MS).unwrap();
+    let summary = margin.summary(&user, mark, 3 * DAY_MS);
+    assert_eq!((summary.loans[1].principal, summary.loans[1].interest), (dec!(100), dec!(0.03)));
+}
+
+#[test]
+fn test_interest_is_kept_at_the_stored_scale() {
+    let mut margin = MarginManager::new(MarginConfig::default(), Arc::new(OrderIdGenerator::default()));
+    let user = Uuid::new_v4();
+    let mark = Some(dec!(10));
+    margin.transfer_in(&user, "USDC", dec!(1000), 0).unwrap();
+    margin.borrow(&user, "USDC", dec!(1), mark, 0).unwrap();
+
+    // 1440ミリ秒の利息 0.000000005 は 10^-8 未満なので足さずに持ち越し、積もった分だけ足す
+    let summary = margin.summary(&user, mark, 1440);
+    assert_eq!(summary.loans[0].interest, dec!(0));
+    let summary = margin.summary(&user, mark, 2 * 1440);
+    assert_eq!(summary.loans[0].interest, dec!(0.00000001));
+    for step in 3..=10 {
+        margin.summary(&user, mark, step * 1440);
+    }
+    let summary = margin.summary(&user, mark, 10 * 1440);
+    assert_eq!(summary.loans[0].interest, dec!(0.00000005));
+    let summary = margin.summary(&user, mark, DAY_MS / 3);
+    assert_eq!(summary.loans[0].interest, dec!(0.0001));
+}
+
+#[test]
+fn test_extreme_ratios_saturate_instead_of_overflowing() {
+    let mut margin = MarginManager::new(MarginConfig::default(), Arc::new(OrderIdGenerator::default()));
+    let user = Uuid::new_v4();
+    let huge = dec!(1000000000000000000000000000);
+    let mark = Some(dec!(0.00000001));
+    margin.transfer_in(&user, "USDC", huge, 0).unwrap();
+    margin.borrow(&user, "BAD", dec!(5), mark, 0).unwrap();
+
+    // 純資産 / 借入 が Decimal に収まらなければ上限にする
+    assert_eq!(margin.summary(&user, mark, 0).margin_ratio, Some(Decimal::MAX));
+
+    // 借りた 5 BAD を売って買い戻しが必要な状態にする。予算 / 価格 が収まらなければ板の数量を全部買う
+    margin.on_trade_match(&user, Side::Sell, dec!(0.00000001), 5);
+    let mut book = OrderBook::new();
+    book.process_order(limit(1, Side::Sell, Decimal::new(1, 20), 3, None));
+    let orders = margin.liquidation_orders(&user, &book);
+    assert_eq!((orders[0].side, orders[0].quantity), (Side::Buy, 3));
+}
+
+async fn margin_request(eng_tx: &mpsc::Sender<EngineMessage>, user_id: Uuid, request: MarginRequest) -> Result<MarginAccountSummary, MarginError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::Margin { user_id, request, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+async fn place(eng_tx: &mpsc::Sender<EngineMessage>, order: Order, margin: bool) -> Vec<Trade> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let msg = if margin {
+        EngineMessage::PlaceMarginOrder { order, respond_to: resp_tx }
+    } else {
+        EngineMessage::PlaceOrder { order, respond_to: resp_tx }
+    };
+    eng_tx.send(msg).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+fn balance(summary: &MarginAccountSummary, asset: &str) -> (Decimal, Decimal) {
+    summary
+        .balances
+        .iter()
+        .find(|b| b.asset == asset)
+        .map(|b| (b.available, b.locked))
+        .unwrap_or_default()
+}
+
+#[tokio::test]
+async fn test_engine_liquidates_below_maintenance_margin() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let user = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user, "USDC", dec!(100), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    // 板: 買い 9（10枚）・8（1000枚）、売り 11（1000枚）→ 仲値 10
+    place(&eng_tx, limit(1, Side::Buy, dec!(9), 10, None), false).await;
+    place(&eng_tx, limit(2, Side::Buy, dec!(8), 1000, None), false).await;
+    place(&eng_tx, limit(3, Side::Sell, dec!(11), 1000, None), false).await;
+
+    // 現物から100 USDCを移し、400 USDC借りる
+    let summary = margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: "USDC".to_string(), amount: dec!(100) }).await.unwrap();
+    assert_eq!(balance(&summary, "USDC"), (dec!(100), dec!(0)));
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetBalances { user_id: user, respond_to: resp_tx }).await.unwrap();
+    assert_eq!(resp_rx.await.unwrap()[0].available, dec!(0));
+    let err = margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: "USDC".to_string(), amount: dec!(1) }).await;
+    assert_eq!(err, Err(MarginError::InsufficientBalance));
+    margin_request(&eng_tx, user, MarginRequest::Borrow { asset: "USDC".to_string(), amount: dec!(400) }).await.unwrap();
+
+    // 成行買い 45枚 @11 = 495 USDC。売り注文 5枚は板に残る（証拠金率 55/400 で維持できている）
+    assert_eq!(place(&eng_tx, market(4, Side::Buy, 45, Some(user)), true).await.len(), 1);
+    place(&eng_tx, limit(5, Side::Sell, dec!(20), 5, Some(user)), true).await;
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+    assert_eq!(balance(&summary, "BAD"), (dec!(40), dec!(5)));
+    assert_eq!(balance(&summary, "USDC"), (dec!(5), dec!(0)));
+    assert!(summary.margin_ratio.unwrap() > dec!(0.1));
+
+    // 買い 9 が食われて仲値が 9.5 に下がると、証拠金率 32.5/400 < 0.1 で清算される
+    place(&eng_tx, market(6, Side::Sell, 10, None), false).await;
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+
+    // 売り注文はキャンセルされ、45枚すべて @8 で売って 365 USDC を返済に充てる
+    // 残りの 35 + 利息 は、口座に何も残っていないので貸し倒れになる
+    assert_eq!(balance(&summary, "BAD"), (dec!(0), dec!(0)));
+    assert_eq!(balance(&summary, "USDC"), (dec!(0), dec!(0)));
+    assert!(summary.loans.is_empty());
+    assert_eq!(summary.bad_debt.len(), 1);
+    assert_eq!(summary.bad_debt[0].asset, "USDC");
+    assert!(summary.bad_debt[0].amount >= dec!(35) && summary.bad_debt[0].amount < dec!(35.01));
+
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetOpenOrders { user_id: user, respond_to: resp_tx }).await.unwrap();
+    assert!(resp_rx.await.unwrap().is_empty());
+
+    let mut saved: Vec<OrderRecord> = Vec::new();
+    while let Ok(msg) = db_rx.try_recv() {
+        if let DbMessage::SaveOrder(record) = msg {
+            saved.push(record);
+        }
+    }
+    assert!(saved.iter().any(|r| r.id == 5 && r.status == OrderStatus::Cancelled));
+    let liquidation = saved.iter().find(|r| r.user_id == user && r.id != 4 && r.id != 5).expect("清算注文");
+    assert_eq!(liquidation.side, Side::Sell);
+    assert_eq!(liquidation.status, OrderStatus::Filled);
+    assert_eq!(liquidation.filled_quantity, 45);
+}
+
+async fn open_orders(eng_tx: &mpsc::Sender<EngineMessage>, user_id: Uuid) -> Vec<OrderRecord> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetOpenOrders { user_id, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+fn saved_orders(db_rx: &mut mpsc::Receiver<DbMessage>, user_id: Uuid) -> Vec<OrderRecord> {
+    let mut saved: Vec<OrderRecord> = Vec::new();
+    while let Ok(msg) = db_rx.try_recv() {
+        if let DbMessage::SaveOrder(record) = msg
+            && record.user_id == user_id
+        {
+            saved.push(record);
+        }
+    }
+    saved
+}
+
+#[tokio::test]
+async fn test_transfer_out_trims_margin_reduce_only_orders() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    let user = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user, "USDC", dec!(100), dec!(0));
+    am.load_balance(user, "BAD", dec!(20), dec!(0));
+    // 利息で借入が端数になるとポジションが切り捨てで変わるので、BAD の利息を0にする
+    let config = MarginConfig { daily_rates: BTreeMap::from([("BAD".to_string(), dec!(0))]), ..MarginConfig::default() };
+    let margin = MarginManager::new(config, Arc::new(OrderIdGenerator::default()));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, MarketFeeds::new(100), MarketData { margin, ..MarketData::default() }).await;
+    });
+
+    place(&eng_tx, limit(1, Side::Buy, dec!(9), 10, None), false).await;
+    place(&eng_tx, limit(2, Side::Sell, dec!(11), 10, None), false).await;
+    for asset in ["USDC", "BAD"] {
+        let amount = if asset == "USDC" { dec!(100) } else { dec!(20) };
+        margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: asset.to_string(), amount }).await.unwrap();
+    }
+    // 保有 25 - 借入 5 = ポジション 20 のうち 15 を reduce_only で売りに出す
+    margin_request(&eng_tx, user, MarginRequest::Borrow { asset: "BAD".to_string(), amount: dec!(5) }).await.unwrap();
+    place(&eng_tx, Order { reduce_only: true, ..limit(3, Side::Sell, dec!(50), 15, Some(user)) }, true).await;
+
+    // 8 BAD を現物へ戻すとポジションは 12 になり、売り注文も 12 に減ってロックが 3 戻る
+    let summary = margin_request(&eng_tx, user, MarginRequest::TransferOut { asset: "BAD".to_string(), amount: dec!(8) }).await.unwrap();
+    assert_eq!(balance(&summary, "BAD"), (dec!(5), dec!(12)));
+    let open: Vec<(u64, u64)> = open_orders(&eng_tx, user).await.iter().map(|o| (o.id, o.quantity)).collect();
+    assert_eq!(open, vec![(3, 12)]);
+}
+
+#[tokio::test]
+async fn test_liquidation_waits_for_an_empty_book_side() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(1000);
+    let user = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user, "USDC", dec!(100), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, MarketFeeds::new(100), MarketData::default()).await;
+    });
+
+    place(&eng_tx, limit(1, Side::Buy, dec!(9), 10, None), false).await;
+    place(&eng_tx, limit(2, Side::Sell, dec!(11), 1000, None), false).await;
+    margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: "USDC".to_string(), amount: dec!(100) }).await.unwrap();
+    margin_request(&eng_tx, user, MarginRequest::Borrow { asset: "USDC".to_string(), amount: dec!(400) }).await.unwrap();
+    place(&eng_tx, market(3, Side::Buy, 45, Some(user)), true).await;
+    place(&eng_tx, limit(4, Side::Sell, dec!(20), 5, Some(user)), true).await;
+
+    // 買いが空になり、マーク価格（最後の約定 9）で証拠金率 10/400 まで下がる。
+    // 売る相手がいないので清算せず、売り注文もそのまま残る
+    place(&eng_tx, market(5, Side::Sell, 10, None), false).await;
+    for _ in 0..3 {
+        let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+        assert!(summary.margin_ratio.unwrap() < dec!(0.1));
+        assert_eq!(balance(&summary, "BAD"), (dec!(40), dec!(5)));
+        assert_eq!(summary.loans.len(), 1);
+    }
+    assert_eq!(open_orders(&eng_tx, user).await.len(), 1);
+    assert!(saved_orders(&mut db_rx, user).iter().all(|r| r.id == 3 || r.id == 4));
+
+    // 買いが入ると清算される: 45枚 @8 で 360 USDC、手元の 5 と合わせて返済し、残りは貸し倒れ
+    place(&eng_tx, limit(6, Side::Buy, dec!(8), 1000, None), false).await;
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+    assert_eq!(balance(&summary, "BAD"), (dec!(0), dec!(0)));
+    assert_eq!(balance(&summary, "USDC"), (dec!(0), dec!(0)));
+    assert!(summary.loans.is_empty());
+    assert!(summary.bad_debt[0].amount >= dec!(35) && summary.bad_debt[0].amount < dec!(35.01));
+    assert!(open_orders(&eng_tx, user).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_partial_liquidation_resumes_when_liquidity_returns() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(1000);
+    let user = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user, "USDC", dec!(100), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, MarketFeeds::new(100), MarketData::default()).await;
+    });
+
+    // 板の買いが薄い: 9（10枚）・8（20枚）
+    place(&eng_tx, limit(1, Side::Buy, dec!(9), 10, None), false).await;
+    place(&eng_tx, limit(2, Side::Buy, dec!(8), 20, None), false).await;
+    place(&eng_tx, limit(3, Side::Sell, dec!(11), 1000, None), false).await;
+    margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: "USDC".to_string(), amount: dec!(100) }).await.unwrap();
+    margin_request(&eng_tx, user, MarginRequest::Borrow { asset: "USDC".to_string(), amount: dec!(400) }).await.unwrap();
+    place(&eng_tx, market(4, Side::Buy, 45, Some(user)), true).await;
+
+    // 仲値 9.5 で清算されるが、売れたのは 20枚 @8 だけ。残りの 25枚と借入は口座に残る
+    place(&eng_tx, market(5, Side::Sell, 10, None), false).await;
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+    assert_eq!(balance(&summary, "BAD"), (dec!(25), dec!(0)));
+    assert_eq!(balance(&summary, "USDC"), (dec!(0), dec!(0)));
+    assert!(summary.loans[0].principal >= dec!(235) && summary.loans[0].principal < dec!(235.01));
+    assert!(summary.bad_debt.is_empty());
+    let saved = saved_orders(&mut db_rx, user);
+    let first = saved.iter().find(|r| r.id != 4).expect("清算注文");
+    assert_eq!((first.side, first.quantity, first.filled_quantity, first.status), (Side::Sell, 45, 20, OrderStatus::Expired));
+
+    // 買いが入ると続きを清算する: 25枚 @7 = 175 を返済し、残りの約 60 は貸し倒れ
+    place(&eng_tx, limit(6, Side::Buy, dec!(7), 1000, None), false).await;
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+    assert_eq!(balance(&summary, "BAD"), (dec!(0), dec!(0)));
+    assert_eq!(balance(&summary, "USDC"), (dec!(0), dec!(0)));
+    assert!(summary.loans.is_empty());
+    assert!(summary.bad_debt[0].amount >= dec!(60) && summary.bad_debt[0].amount < dec!(60.01));
+    let saved = saved_orders(&mut db_rx, user);
+    assert_eq!(saved.len(), 1);
+    assert_eq!((saved[0].side, saved[0].quantity, saved[0].status), (Side::Sell, 25, OrderStatus::Filled));
+
+    // 清算済みの口座は、口座が変わらない限り何度メッセージが来ても清算し直さない
+    margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+    assert!(saved_orders(&mut db_rx, user).is_empty());
+}
+
+#[tokio::test]
+async fn test_short_liquidation_buys_back_only_what_it_can_afford() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    let user = Uuid::new_v4();
+    let mut am = AccountManager::new();
+    am.load_balance(user, "USDC", dec!(100), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, MarketFeeds::new(100), MarketData::default()).await;
+    });
+
+    // 仲値 10 で 30 BAD 借りて @9 で売る（USDC 370、借入 30 BAD）
+    place(&eng_tx, limit(1, Side::Buy, dec!(9), 1000, None), false).await;
+    place(&eng_tx, limit(2, Side::Sell, dec!(11), 10, None), false).await;
+    place(&eng_tx, limit(3, Side::Sell, dec!(20), 5, None), false).await;
+    place(&eng_tx, limit(4, Side::Sell, dec!(30), 1000, None), false).await;
+    margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: "USDC".to_string(), amount: dec!(100) }).await.unwrap();
+    margin_request(&eng_tx, user, MarginRequest::Borrow { asset: "BAD".to_string(), amount: dec!(30) }).await.unwrap();
+    place(&eng_tx, market(5, Side::Sell, 30, Some(user)), true).await;
+
+    // 売り 11 が食われて仲値 14.5 になり清算される。370 USDC で買えるのは 5枚 @20 と 9枚 @30 まで
+    place(&eng_tx, market(6, Side::Buy, 10, None), false).await;
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+
+    // 残高はマイナスにならず、返し切れない 16 BAD + 利息 は貸し倒れになる
+    assert_eq!(balance(&summary, "USDC"), (dec!(0), dec!(0)));
+    assert_eq!(balance(&summary, "BAD"), (dec!(0), dec!(0)));
+    assert!(summary.loans.is_empty());
+    assert_eq!(summary.bad_debt[0].asset, "BAD");
+    assert!(summary.bad_debt[0].amount >= dec!(16) && summary.bad_debt[0].amount < dec!(16.01));
+}
+
+#[tokio::test]
+async fn test_margin_accounts_survive_a_restart() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let user = Uuid::new_v4();
+
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, db_rx) = mpsc::channel(1000);
+    let writer = tokio::spawn(run_db_writer(db_rx, storage.clone(), Arc::new(DbWriterStatus::default())));
+    let mut am = AccountManager::new();
+    am.load_balance(user, "USDC", dec!(100), dec!(0));
+    let engine = tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, MarketFeeds::new(100), MarketData::default()).await;
+    });
+
+    // 担保 100 USDC・借入 200 USDC で、買い注文に 50 USDC をロックしたまま止める
+    place(&eng_tx, limit(1, Side::Buy, dec!(9), 10, None), false).await;
+    place(&eng_tx, limit(2, Side::Sell, dec!(11), 10, None), false).await;
+    margin_request(&eng_tx, user, MarginRequest::TransferIn { asset: "USDC".to_string(), amount: dec!(100) }).await.unwrap();
+    margin_request(&eng_tx, user, MarginRequest::Borrow { asset: "USDC".to_string(), amount: dec!(200) }).await.unwrap();
+    place(&eng_tx, limit(3, Side::Buy, dec!(5), 10, Some(user)), true).await;
+    drop(eng_tx);
+    engine.await.unwrap();
+    writer.await.unwrap();
+
+    // 再起動: 借入は残り、注文は復元しないのでロックは available に戻る
+    let market = MarketData::load(&storage, 0).await.unwrap();
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), MarketFeeds::new(100), market).await;
+    });
+    let summary = margin_request(&eng_tx, user, MarginRequest::Get).await.unwrap();
+    assert_eq!(balance(&summary, "USDC"), (dec!(300), dec!(0)));
+    assert_eq!(summary.loans.len(), 1);
+    assert_eq!(summary.loans[0].principal, dec!(200));
+}
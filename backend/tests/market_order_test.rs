@@ -15,6 +15,7 @@ fn create_limit_order(id: u64, price: Decimal, quantity: u64, side: Side) -> Ord
         side,
         user_id: None,
         order_type: OrderType::Limit,
+        ..Default::default()
     }
 }
 
@@ -26,6 +27,7 @@ fn create_market_order(id: u64, quantity: u64, side: Side) -> Order {
         side,
         user_id: None,
         order_type: OrderType::Market,
+        ..Default::default()
     }
 }
 
@@ -58,7 +60,7 @@ fn test_market_buy_fills_multiple_levels() {
     // Remaining asks: 5 @ 101
     assert_eq!(ob.asks.get(&deci(101)).unwrap()[0].quantity, 5);
     // Order 1 at 100 should be gone
-    assert!(ob.asks.get(&deci(100)).is_none());
+    assert!(!ob.asks.contains_key(&deci(100)));
 }
 
 #[test]
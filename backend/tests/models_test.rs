@@ -11,6 +11,7 @@ fn test_order_serialization() {
         side: Side::Buy,
         user_id: None,
         order_type: OrderType::Limit,
+        ..Default::default()
     };
 
     let json_str = serde_json::to_string(&order).unwrap();
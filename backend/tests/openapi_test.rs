@@ -0,0 +1,98 @@
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use rust_matching_engine::api::{self, AppState};
+use rust_matching_engine::engine::OrderIdGenerator;
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::ratelimit::{RateLimitConfig, RateLimiter};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_matching_engine::ws::WsConfig;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+/// スキーマの取得だけなのでエンジンは起動しない
+fn test_app() -> Router {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let (eng_tx, _) = mpsc::channel(1);
+    api::router(Arc::new(AppState {
+        sender: eng_tx,
+        storage,
+        feeds: MarketFeeds::new(1),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        order_ids: Arc::new(OrderIdGenerator::default()),
+        ws: WsConfig::default(),
+        db_status: Default::default(),
+        assets: Default::default(),
+    }))
+}
+
+async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+    let res = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+    let status = res.status();
+    let bytes = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+async fn served_spec() -> Value {
+    let (status, body) = get(&test_app(), "/openapi.json").await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_served_spec_matches_committed_file() {
+    let spec = served_spec().await;
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("openapi.json");
+
+    // UPDATE_OPENAPI=1 のときは保存し直す
+    if std::env::var_os("UPDATE_OPENAPI").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(&spec).unwrap() + "\n").unwrap();
+        return;
+    }
+
+    let committed: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(
+        spec == committed,
+        "backend/openapi.json が古くなっています。`UPDATE_OPENAPI=1 cargo test --test openapi_test` で保存し直し、\
+         フロントエンドの型も生成し直してください"
+    );
+}
+
+#[tokio::test]
+async fn test_spec_describes_rest_types() {
+    let spec = served_spec().await;
+    let schemas = &spec["components"]["schemas"];
+
+    // Decimal は精度を保つため文字列
+    let payload = &schemas["CreateOrderPayload"];
+    assert_eq!(payload["properties"]["price"]["type"], "string");
+    assert_eq!(payload["properties"]["side"]["$ref"], "#/components/schemas/Side");
+    let required: Vec<&str> = payload["required"].as_array().unwrap().iter().map(|v| v.as_str().unwrap()).collect();
+    assert!(required.contains(&"price") && !required.contains(&"order_type"));
+
+    // 公開APIに出さないフィールドは含まない
+    let trade = &schemas["Trade"]["properties"];
+    assert_eq!(trade["price"]["type"], "string");
+    assert!(trade.get("maker_user_id").is_none());
+    assert!(schemas["ApiKey"]["properties"].get("secret").is_none());
+
+    for name in ["BalanceResponse", "Order", "OrderResponse", "ErrorResponse"] {
+        assert!(schemas.get(name).is_some(), "{} がありません", name);
+    }
+
+    // 要ログインのAPIには認証方式が付いている
+    let security = &spec["paths"]["/order"]["post"]["security"];
+    assert_eq!(security.as_array().unwrap().len(), 2);
+    assert!(spec["paths"]["/orderbook"]["get"].get("security").is_none());
+}
+
+#[tokio::test]
+async fn test_swagger_ui_page() {
+    let (status, body) = get(&test_app(), "/docs").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("SwaggerUIBundle"));
+    assert!(body.contains("/openapi.json"));
+}
@@ -15,6 +15,7 @@ fn create_order(id: u64, price: Decimal, quantity: u64, side: Side) -> Order {
         side,
         user_id: None,
         order_type: OrderType::Limit,
+        ..Default::default()
     }
 }
 
@@ -163,3 +164,188 @@ fn test_price_time_priority() {
     assert_eq!(ob.asks.get(&deci(100)).unwrap()[0].quantity, 5);
     assert_eq!(ob.asks.get(&deci(100)).unwrap()[0].id, 2);
 }
+
+#[test]
+fn test_trade_ids_are_sequential_and_record_taker() {
+    let mut ob = OrderBook::new();
+    ob.next_trade_id = 41; // 再起動後に続きから採番する想定
+    let maker_user = uuid::Uuid::new_v4();
+    let mut maker = create_order(1, deci(100), 5, Side::Buy);
+    maker.user_id = Some(maker_user);
+    ob.process_order(maker);
+    ob.process_order(create_order(2, deci(99), 5, Side::Buy));
+
+    let trades = ob.process_order(create_order(3, deci(99), 8, Side::Sell));
+    assert_eq!(trades.iter().map(|t| t.id).collect::<Vec<_>>(), vec![41, 42]);
+    assert!(trades.iter().all(|t| t.taker_side == Side::Sell));
+    assert_eq!(trades[0].maker_user_id, Some(maker_user));
+    assert_eq!(trades[1].maker_user_id, None);
+    assert_eq!(ob.next_trade_id, 43);
+}
+
+#[test]
+fn test_snapshot_aggregates_levels_without_owners() {
+    let mut ob = OrderBook::new();
+    let owner = uuid::Uuid::new_v4();
+    ob.process_order(Order { user_id: Some(owner), ..create_order(1, deci(100), 5, Side::Buy) });
+    ob.process_order(create_order(2, deci(100), 3, Side::Buy));
+    ob.process_order(create_order(3, deci(99), 1, Side::Buy));
+    ob.process_order(create_order(4, deci(102), 2, Side::Sell));
+    ob.process_order(create_order(5, deci(101), 4, Side::Sell));
+
+    // L2: 最良気配が先頭、価格ごとに合計数量と注文数
+    let book = ob.snapshot();
+    let bids: Vec<(Decimal, u64, usize)> = book.bids.iter().map(|l| (l.price, l.quantity, l.order_count)).collect();
+    let asks: Vec<(Decimal, u64, usize)> = book.asks.iter().map(|l| (l.price, l.quantity, l.order_count)).collect();
+    assert_eq!(bids, vec![(deci(100), 8, 2), (deci(99), 1, 1)]);
+    assert_eq!(asks, vec![(deci(101), 4, 1), (deci(102), 2, 1)]);
+
+    // L3: 注文IDは時間優先順に並ぶ
+    let l3 = ob.l3_snapshot();
+    assert_eq!(l3.bids.iter().map(|o| o.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(l3.asks.iter().map(|o| o.id).collect::<Vec<_>>(), vec![5, 4]);
+
+    // どちらの形式にも所有者は出ない
+    for json in [serde_json::to_string(&book).unwrap(), serde_json::to_string(&l3).unwrap()] {
+        assert!(!json.contains("user_id"));
+        assert!(!json.contains(&owner.to_string()));
+    }
+    assert_eq!(
+        serde_json::to_value(&book.bids[0]).unwrap(),
+        serde_json::json!({ "price": "100", "quantity": 8, "order_count": 2 })
+    );
+}
+
+#[test]
+fn test_depth_groups_levels_with_cumulative_totals() {
+    let mut ob = OrderBook::new();
+    for (id, price, qty, side) in [
+        (1, "100.12", 1, Side::Buy),
+        (2, "100.05", 2, Side::Buy),
+        (3, "99.98", 3, Side::Buy),
+        (4, "99.50", 4, Side::Buy),
+        (5, "100.21", 5, Side::Sell),
+        (6, "100.29", 6, Side::Sell),
+        (7, "100.31", 7, Side::Sell),
+    ] {
+        ob.process_order(create_order(id, price.parse().unwrap(), qty, side));
+    }
+
+    // まとめない場合は上位 limit 段
+    let depth = ob.depth(2, None);
+    assert_eq!(depth.bids.iter().map(|l| l.price.to_string()).collect::<Vec<_>>(), vec!["100.12", "100.05"]);
+    assert_eq!(depth.bids[1].cumulative_quantity, 3);
+
+    // 0.1刻み: 買いは切り捨て、売りは切り上げ
+    let depth = ob.depth(2, Some("0.1".parse().unwrap()));
+    let bids: Vec<(String, u64, usize, u64)> = depth.bids.iter().map(|l| (l.price.to_string(), l.quantity, l.order_count, l.cumulative_quantity)).collect();
+    assert_eq!(bids, vec![("100.1".to_string(), 1, 1, 1), ("100.0".to_string(), 2, 1, 3)]);
+    let asks: Vec<(String, u64, usize, u64)> = depth.asks.iter().map(|l| (l.price.to_string(), l.quantity, l.order_count, l.cumulative_quantity)).collect();
+    assert_eq!(asks, vec![("100.3".to_string(), 11, 2, 11), ("100.4".to_string(), 7, 1, 18)]);
+
+    // 1刻みなら 100.12 と 100.05 がまとまる
+    let depth = ob.depth(10, Some(deci(1)));
+    assert_eq!(depth.bids.iter().map(|l| (l.price, l.quantity)).collect::<Vec<_>>(), vec![(deci(100), 3), (deci(99), 7)]);
+    assert_eq!(depth.sequence, 7);
+
+    // 割り算が Decimal の範囲を超える刻みでもパニックせず、その価格帯を飛ばす
+    let depth = ob.depth(10, Some(Decimal::new(1, 28)));
+    assert!(depth.bids.is_empty() && depth.asks.is_empty());
+}
+
+#[test]
+fn test_sequence_and_cancel_cleanup() {
+    let mut ob = OrderBook::new();
+    ob.process_order(create_order(1, deci(100), 10, Side::Buy));
+    assert_eq!(ob.sequence, 1);
+
+    // 何も変わらない成行は番号を進めない
+    ob.process_order(Order { order_type: OrderType::Market, ..create_order(2, deci(0), 5, Side::Buy) });
+    assert_eq!(ob.sequence, 1);
+
+    // キャンセルで空になった価格帯は消える
+    assert!(ob.cancel_order(1).is_some());
+    assert_eq!(ob.sequence, 2);
+    assert!(ob.bids.is_empty());
+    assert!(ob.cancel_order(1).is_none());
+    assert_eq!(ob.sequence, 2);
+}
+
+#[test]
+fn test_reduce_order_keeps_priority_and_skips_no_ops() {
+    let mut ob = OrderBook::new();
+    ob.process_order(create_order(1, deci(100), 10, Side::Sell));
+    ob.process_order(create_order(2, deci(100), 5, Side::Sell));
+    ob.take_delta();
+
+    // 数量だけが減り、順番（時間優先）はそのまま
+    assert_eq!(ob.reduce_order(1, 4), Some(6));
+    assert_eq!(ob.sequence, 3);
+    let ids: Vec<(u64, u64)> = ob.asks[&deci(100)].iter().map(|o| (o.id, o.quantity)).collect();
+    assert_eq!(ids, vec![(1, 4), (2, 5)]);
+    let delta = ob.take_delta();
+    assert_eq!((delta.asks[0].price, delta.asks[0].quantity), (deci(100), 9));
+
+    // 残数量以上を指定しても何も変わらず、番号も進まず差分も出ない
+    assert_eq!(ob.reduce_order(1, 4), Some(0));
+    assert_eq!(ob.reduce_order(2, 8), Some(0));
+    assert_eq!(ob.sequence, 3);
+    assert!(ob.take_delta().is_empty());
+    assert_eq!(ob.reduce_order(3, 1), None);
+}
+
+/// 差分を手元の板（価格 -> 数量）に適用する
+fn apply_levels(side: &mut std::collections::BTreeMap<Decimal, u64>, levels: &[rust_matching_engine::orderbook::PriceLevel]) {
+    for level in levels {
+        if level.quantity == 0 {
+            side.remove(&level.price);
+        } else {
+            side.insert(level.price, level.quantity);
+        }
+    }
+}
+
+#[test]
+fn test_deltas_reproduce_the_book() {
+    let mut ob = OrderBook::new();
+    let mut bids = std::collections::BTreeMap::new();
+    let mut asks = std::collections::BTreeMap::new();
+    let mut sequence = 0;
+
+    // 決まった擬似乱数で、指値・成行・キャンセルを混ぜる
+    let mut seed: u64 = 42;
+    let mut next = |n: u64| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % n
+    };
+    for id in 1..=500 {
+        let side = if next(2) == 0 { Side::Buy } else { Side::Sell };
+        if next(10) == 0 {
+            ob.cancel_order(next(id));
+        } else if next(10) == 0 {
+            ob.process_order(Order { order_type: OrderType::Market, ..create_order(id, deci(0), next(20) + 1, side) });
+        } else {
+            ob.process_order(create_order(id, deci(95 + next(10) as i64), next(20) + 1, side));
+        }
+
+        // 数回に1回まとめて差分を取る（スロットリング相当）
+        if next(3) == 0 {
+            let delta = ob.take_delta();
+            assert_eq!(delta.prev_sequence, sequence);
+            sequence = delta.sequence;
+            apply_levels(&mut bids, &delta.bids);
+            apply_levels(&mut asks, &delta.asks);
+        }
+    }
+    let delta = ob.take_delta();
+    apply_levels(&mut bids, &delta.bids);
+    apply_levels(&mut asks, &delta.asks);
+
+    let snapshot = ob.snapshot();
+    assert_eq!(delta.sequence, snapshot.sequence);
+    assert_eq!(bids.into_iter().rev().collect::<Vec<_>>(), snapshot.bids.iter().map(|l| (l.price, l.quantity)).collect::<Vec<_>>());
+    assert_eq!(asks.into_iter().collect::<Vec<_>>(), snapshot.asks.iter().map(|l| (l.price, l.quantity)).collect::<Vec<_>>());
+
+    // 変化がなければ空の差分
+    assert!(ob.take_delta().is_empty());
+}
@@ -0,0 +1,507 @@
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::db::{run_db_writer, DbMessage, DbWriterStatus};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::{MarketFeeds, UserUpdate};
+use rust_matching_engine::models::{Order, OrderType, Side, Trade};
+use rust_matching_engine::perp::{PerpAccountSummary, PerpConfig, PerpError, PerpManager, PerpMarket, PerpRequest};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use uuid::Uuid;
+
+fn limit(id: u64, side: Side, price: Decimal, quantity: u64, user_id: Option<Uuid>) -> Order {
+    Order { id, price, quantity, side, user_id, order_type: OrderType::Limit, ..Default::default() }
+}
+
+#[test]
+fn test_position_pnl_and_funding() {
+    let mut perp = PerpManager::new(PerpConfig::default(), Arc::new(OrderIdGenerator::default()));
+    let (long, short) = (Uuid::new_v4(), Uuid::new_v4());
+    perp.transfer_in(&long, dec!(100)).unwrap();
+    perp.transfer_in(&short, dec!(100)).unwrap();
+
+    // 10 @ 10 と 10 @ 13 で建値は 11.5
+    for (price, quantity) in [(dec!(10), 10), (dec!(13), 10)] {
+        perp.on_trade_match(&long, Side::Buy, price, quantity);
+        perp.on_trade_match(&short, Side::Sell, price, quantity);
+    }
+    let summary = perp.summary(&long, Some(dec!(12)));
+    assert_eq!(summary.position.size, 20);
+    assert_eq!(summary.position.entry_price, dec!(11.5));
+    assert_eq!(summary.position.unrealized_pnl, Some(dec!(10)));
+    assert_eq!(perp.summary(&short, Some(dec!(12))).position.unrealized_pnl, Some(dec!(-10)));
+
+    // 25枚売ると20枚分の損益が確定し、残り5枚は売り持ちになる
+    perp.on_trade_match(&long, Side::Sell, dec!(12), 25);
+    let summary = perp.summary(&long, Some(dec!(12)));
+    assert_eq!(summary.position.size, -5);
+    assert_eq!(summary.position.entry_price, dec!(12));
+    assert_eq!(summary.position.realized_pnl, dec!(10));
+    assert_eq!(summary.wallet_balance, dec!(110));
+
+    // 資金調達率は (マーク - インデックス) / インデックス を ±0.0075 で頭打ち
+    assert_eq!(perp.funding_rate(Some(dec!(10.05)), Some(dec!(10))), Some(dec!(0.005)));
+    assert_eq!(perp.funding_rate(Some(dec!(12)), Some(dec!(10))), Some(dec!(0.0075)));
+    assert_eq!(perp.funding_rate(Some(dec!(12)), None), None);
+
+    // マークがインデックスより高いと、売り持ちは受け取る（ここでは short が -20枚、long が -5枚）
+    let rate = perp.settle_funding(Some(dec!(10.05)), Some(dec!(10)), 0).unwrap();
+    assert_eq!(rate, dec!(0.005));
+    assert_eq!(perp.summary(&short, None).position.funding, dec!(1.005)); // 20 × 10.05 × 0.005
+    assert_eq!(perp.summary(&long, None).position.funding, dec!(0.25125)); // 5 × 10.05 × 0.005
+    assert_eq!(perp.summary(&short, None).wallet_balance, dec!(101.005));
+}
+
+#[test]
+fn test_mark_price_is_index_plus_clamped_basis() {
+    let mut perp = PerpManager::new(PerpConfig::default(), Arc::new(OrderIdGenerator::default()));
+
+    // 価格がなければ評価できない。インデックスだけならインデックス、先物だけなら先物の仲値
+    assert_eq!(perp.mark_price(None, None), None);
+    assert_eq!(perp.mark_price(None, Some(dec!(10))), Some(dec!(10)));
+    assert_eq!(perp.mark_price(Some(dec!(12)), None), Some(dec!(12)));
+
+    // ベーシスは ±0.5% × インデックス で頭打ち
+    assert_eq!(perp.mark_price(Some(dec!(10.02)), Some(dec!(10))), Some(dec!(10.02)));
+    assert_eq!(perp.mark_price(Some(dec!(14)), Some(dec!(10))), Some(dec!(10.05)));
+    assert_eq!(perp.mark_price(Some(dec!(1)), Some(dec!(10))), Some(dec!(9.95)));
+
+    // 先物の板に仲値がなければ、最後の約定価格でベーシスを測る
+    perp.on_trade_match(&Uuid::new_v4(), Side::Buy, dec!(9.97), 1);
+    assert_eq!(perp.mark_price(None, Some(dec!(10))), Some(dec!(9.97)));
+}
+
+#[test]
+fn test_entry_price_and_pnl_are_kept_at_the_stored_scale() {
+    let mut perp = PerpManager::new(PerpConfig::default(), Arc::new(OrderIdGenerator::default()));
+    let user = Uuid::new_v4();
+    perp.transfer_in(&user, dec!(100)).unwrap();
+
+    // 平均建値 (10 + 2 × 11) / 3 は小数点以下8桁に丸める
+    perp.on_trade_match(&user, Side::Buy, dec!(10), 1);
+    perp.on_trade_match(&user, Side::Buy, dec!(11), 2);
+    let summary = perp.summary(&user, None);
+    assert_eq!(summary.position.entry_price, dec!(10.66666667));
+
+    // 確定損益は丸めた建値から計算するので、そのまま保存できる
+    perp.on_trade_match(&user, Side::Sell, dec!(12), 1);
+    let record = perp.record(&user).unwrap();
+    assert_eq!((record.size, record.realized_pnl, record.wallet_balance), (2, dec!(1.33333333), dec!(101.33333333)));
+}
+
+fn reduce_only(order: Order) -> Order {
+    Order { reduce_only: true, ..order }
+}
+
+async fn place(eng_tx: &mpsc::Sender<EngineMessage>, order: Order) -> Result<Vec<Trade>, PerpError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlacePerpOrder { order, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+async fn account(eng_tx: &mpsc::Sender<EngineMessage>, user_id: Uuid, request: PerpRequest) -> Result<PerpAccountSummary, PerpError> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::Perp { user_id, request, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+async fn market(eng_tx: &mpsc::Sender<EngineMessage>) -> PerpMarket {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetPerpMarket { respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap()
+}
+
+#[tokio::test]
+async fn test_engine_perp_positions_and_funding() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut am = AccountManager::new();
+    am.load_balance(alice, "USDC", dec!(1000), dec!(0));
+    am.load_balance(bob, "USDC", dec!(1000), dec!(0));
+    let perp = PerpManager::new(PerpConfig { funding_interval: Duration::from_millis(300), ..PerpConfig::default() }, Arc::new(OrderIdGenerator::default()));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData { perp, ..MarketData::default() }).await;
+    });
+
+    // 価格がまだないので建玉を評価できない
+    account(&eng_tx, alice, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    account(&eng_tx, bob, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    assert_eq!(place(&eng_tx, limit(1, Side::Sell, dec!(12), 10, Some(alice))).await, Err(PerpError::NoMarkPrice));
+
+    // 現物の板（仲値 10）がインデックス価格になる
+    let (resp_tx, _) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(2, Side::Buy, dec!(9), 10, None), respond_to: resp_tx }).await.unwrap();
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(3, Side::Sell, dec!(11), 10, None), respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+    assert_eq!(market(&eng_tx).await.index_price, Some(dec!(10)));
+
+    // 証拠金 100 × 10倍 = 評価額 1000 まで
+    assert_eq!(place(&eng_tx, limit(4, Side::Sell, dec!(12), 101, Some(alice))).await, Err(PerpError::InsufficientMargin));
+    assert!(place(&eng_tx, limit(5, Side::Sell, dec!(12), 10, Some(alice))).await.unwrap().is_empty());
+    let trades = place(&eng_tx, limit(6, Side::Buy, dec!(12), 10, Some(bob))).await.unwrap();
+    assert_eq!(trades.len(), 1);
+
+    // 約定は資産を動かさず、ポジションになる
+    let summary = account(&eng_tx, bob, PerpRequest::Get).await.unwrap();
+    assert_eq!((summary.position.size, summary.position.entry_price), (10, dec!(12)));
+    assert_eq!(summary.wallet_balance, dec!(100));
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetBalances { user_id: bob, respond_to: resp_tx }).await.unwrap();
+    assert_eq!(resp_rx.await.unwrap()[0].available, dec!(900));
+
+    // reduce_only はポジションを減らす向きだけ。残った注文は CancelOrder で取り消せる
+    assert_eq!(place(&eng_tx, reduce_only(limit(7, Side::Buy, dec!(11), 1, Some(bob)))).await, Err(PerpError::WouldIncreasePosition));
+    place(&eng_tx, reduce_only(limit(8, Side::Sell, dec!(14), 4, Some(bob)))).await.unwrap();
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::CancelOrder { order_id: 8, user_id: bob, respond_to: resp_tx }).await.unwrap();
+    assert_eq!(resp_rx.await.unwrap().map(|o| o.quantity), Some(4));
+
+    // 4枚を 14 で手仕舞うと、損益が確定してウォレットに入る
+    place(&eng_tx, reduce_only(limit(9, Side::Sell, dec!(14), 4, Some(bob)))).await.unwrap();
+    place(&eng_tx, reduce_only(limit(10, Side::Buy, dec!(14), 4, Some(alice)))).await.unwrap();
+    let summary = account(&eng_tx, bob, PerpRequest::Get).await.unwrap();
+    assert_eq!(summary.position.size, 6);
+    assert_eq!(summary.position.realized_pnl, dec!(8));
+    assert_eq!(summary.wallet_balance, dec!(108));
+    assert_eq!(account(&eng_tx, alice, PerpRequest::Get).await.unwrap().wallet_balance, dec!(92));
+    assert_eq!(market(&eng_tx).await.open_interest, 6);
+
+    // 最後の約定 14 はインデックス 10 より高いので、マークは 10 + 0.5% = 10.05 で頭打ち。
+    // 資金調達では買い持ちの bob が払う
+    assert_eq!(market(&eng_tx).await.mark_price, Some(dec!(10.05)));
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(market(&eng_tx).await.last_funding_rate, Some(dec!(0.005)));
+    let bob_funding = account(&eng_tx, bob, PerpRequest::Get).await.unwrap().position.funding;
+    let alice_funding = account(&eng_tx, alice, PerpRequest::Get).await.unwrap().position.funding;
+    assert!(bob_funding < dec!(0));
+    assert_eq!(alice_funding, -bob_funding);
+}
+
+async fn perp_open_orders(eng_tx: &mpsc::Sender<EngineMessage>, user_id: Uuid) -> Vec<(u64, u64)> {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetPerpOpenOrders { user_id, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap().iter().map(|o| (o.id, o.quantity - o.filled_quantity)).collect()
+}
+
+#[tokio::test]
+async fn test_engine_reduce_only_orders_follow_the_position() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut am = AccountManager::new();
+    am.load_balance(alice, "USDC", dec!(1000), dec!(0));
+    am.load_balance(bob, "USDC", dec!(1000), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    // インデックス価格（現物の仲値 10）
+    let (resp_tx, _) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(1, Side::Buy, dec!(9), 10, None), respond_to: resp_tx }).await.unwrap();
+    let (resp_tx, _) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(2, Side::Sell, dec!(11), 10, None), respond_to: resp_tx }).await.unwrap();
+    account(&eng_tx, alice, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    account(&eng_tx, bob, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+
+    // ポジションがなければ reduce_only は出せない
+    assert_eq!(place(&eng_tx, reduce_only(limit(3, Side::Sell, dec!(15), 1, Some(bob)))).await, Err(PerpError::WouldIncreasePosition));
+
+    // bob が6枚の買い持ちになる
+    place(&eng_tx, limit(4, Side::Sell, dec!(10), 6, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(5, Side::Buy, dec!(10), 6, Some(bob))).await.unwrap();
+
+    // close_position は数量をポジション全体にする。それ以上の reduce_only は出せない
+    let close = Order { close_position: true, ..limit(6, Side::Sell, dec!(15), 0, Some(bob)) };
+    place(&eng_tx, close).await.unwrap();
+    assert_eq!(perp_open_orders(&eng_tx, bob).await, vec![(6, 6)]);
+    assert_eq!(place(&eng_tx, reduce_only(limit(7, Side::Sell, dec!(16), 1, Some(bob)))).await, Err(PerpError::WouldIncreasePosition));
+
+    // 通常の売りでポジションが2枚に減ると、残っている reduce_only も2枚に減らされる
+    place(&eng_tx, limit(8, Side::Buy, dec!(9), 4, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(9, Side::Sell, dec!(9), 4, Some(bob))).await.unwrap();
+    assert_eq!(perp_open_orders(&eng_tx, bob).await, vec![(6, 2)]);
+
+    // 売り持ちに反転すると、ポジションを増やす向きになった reduce_only はキャンセルされる
+    place(&eng_tx, limit(10, Side::Buy, dec!(9), 3, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(11, Side::Sell, dec!(9), 3, Some(bob))).await.unwrap();
+    assert_eq!(account(&eng_tx, bob, PerpRequest::Get).await.unwrap().position.size, -1);
+    assert!(perp_open_orders(&eng_tx, bob).await.is_empty());
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetPerpOrderBook { respond_to: resp_tx }).await.unwrap();
+    assert!(resp_rx.await.unwrap().asks.is_empty());
+}
+
+#[tokio::test]
+async fn test_engine_funding_follows_the_clamped_mark() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let mut am = AccountManager::new();
+    for user in [alice, bob, carol] {
+        am.load_balance(user, "USDC", dec!(1000), dec!(0));
+    }
+    let perp = PerpManager::new(PerpConfig { funding_interval: Duration::from_millis(300), ..PerpConfig::default() }, Arc::new(OrderIdGenerator::default()));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData { perp, ..MarketData::default() }).await;
+    });
+
+    // インデックス 10。bob が 10枚の買い持ち、alice が 10枚の売り持ち
+    let (resp_tx, _) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(1, Side::Buy, dec!(9), 10, None), respond_to: resp_tx }).await.unwrap();
+    let (resp_tx, _) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(2, Side::Sell, dec!(11), 10, None), respond_to: resp_tx }).await.unwrap();
+    for user in [alice, bob, carol] {
+        account(&eng_tx, user, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    }
+    place(&eng_tx, limit(3, Side::Sell, dec!(9.9), 10, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(4, Side::Buy, dec!(9.9), 10, Some(bob))).await.unwrap();
+
+    // 約定 9.9 ではマークは 9.95 まで（資金調達率 -0.5%）
+    let market_now = market(&eng_tx).await;
+    assert_eq!((market_now.mark_price, market_now.funding_rate), (Some(dec!(9.95)), Some(dec!(-0.005))));
+
+    // carol が 19 / 21 に気配を置いて仲値を 20 にしても、マークは 10.05（資金調達率 +0.5%）で止まる
+    place(&eng_tx, limit(5, Side::Buy, dec!(19), 1, Some(carol))).await.unwrap();
+    place(&eng_tx, limit(6, Side::Sell, dec!(21), 1, Some(carol))).await.unwrap();
+    let market_now = market(&eng_tx).await;
+    assert_eq!((market_now.mark_price, market_now.funding_rate), (Some(dec!(10.05)), Some(dec!(0.005))));
+
+    // 正の資金調達率では買い持ちが払い、売り持ちが受け取る: 10枚 × 10.05 × 0.005 = 0.5025
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(market(&eng_tx).await.last_funding_rate, Some(dec!(0.005)));
+    let bob_account = account(&eng_tx, bob, PerpRequest::Get).await.unwrap();
+    assert_eq!((bob_account.position.funding, bob_account.wallet_balance), (dec!(-0.5025), dec!(99.4975)));
+    let alice_account = account(&eng_tx, alice, PerpRequest::Get).await.unwrap();
+    assert_eq!((alice_account.position.funding, alice_account.wallet_balance), (dec!(0.5025), dec!(100.5025)));
+    // ポジションのない carol は受け渡しに加わらない
+    assert_eq!(account(&eng_tx, carol, PerpRequest::Get).await.unwrap().position.funding, dec!(0));
+}
+
+/// 現物の板に気配を置く（インデックス価格を決める）
+async fn spot_quote(eng_tx: &mpsc::Sender<EngineMessage>, id: u64, side: Side, price: Decimal) {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::PlaceOrder { order: limit(id, side, price, 10, None), respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+}
+
+/// 先物の約定レポートを (注文ID, 状態, 約定数量) で取り出す
+fn perp_reports(user_rx: &mut broadcast::Receiver<UserUpdate>, user_id: Uuid) -> Vec<(u64, String, Option<u64>)> {
+    let mut reports = Vec::new();
+    while let Ok(update) = user_rx.try_recv() {
+        let event: serde_json::Value = serde_json::from_str(&update.json).unwrap();
+        if update.user_id == user_id && event["type"] == "perp_execution_report" {
+            reports.push((event["id"].as_u64().unwrap(), event["status"].as_str().unwrap().to_string(), event["last_quantity"].as_u64()));
+        }
+    }
+    reports
+}
+
+#[tokio::test]
+async fn test_engine_perp_margin_counts_resting_orders() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut am = AccountManager::new();
+    am.load_balance(alice, "USDC", dec!(1000), dec!(0));
+    am.load_balance(bob, "USDC", dec!(1000), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    spot_quote(&eng_tx, 1, Side::Buy, dec!(9)).await;
+    spot_quote(&eng_tx, 2, Side::Sell, dec!(11)).await;
+    account(&eng_tx, alice, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+
+    // ウォレット 100 はマーク 10 で 100枚分。板に残っている売り 60枚も数える
+    place(&eng_tx, limit(3, Side::Sell, dec!(12), 60, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(4, Side::Sell, dec!(13), 40, Some(alice))).await.unwrap();
+    assert_eq!(place(&eng_tx, limit(5, Side::Sell, dec!(14), 1, Some(alice))).await, Err(PerpError::InsufficientMargin));
+
+    // 買いと売りは同時には約定しないので、向きごとに見る
+    place(&eng_tx, limit(6, Side::Buy, dec!(8), 100, Some(alice))).await.unwrap();
+    assert_eq!(place(&eng_tx, limit(7, Side::Buy, dec!(8), 1, Some(alice))).await, Err(PerpError::InsufficientMargin));
+
+    // 注文をキャンセルすれば、その分また出せる
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::CancelOrder { order_id: 4, user_id: alice, respond_to: resp_tx }).await.unwrap();
+    assert!(resp_rx.await.unwrap().is_some());
+    place(&eng_tx, limit(8, Side::Sell, dec!(14), 40, Some(alice))).await.unwrap();
+
+    // ウォレットが空なら出せず、拒否された注文は板に残らない
+    assert_eq!(place(&eng_tx, limit(9, Side::Buy, dec!(8), 1, Some(bob))).await, Err(PerpError::InsufficientMargin));
+    assert!(perp_open_orders(&eng_tx, bob).await.is_empty());
+    assert_eq!(perp_open_orders(&eng_tx, alice).await.len(), 3);
+}
+
+#[tokio::test]
+async fn test_engine_reduce_only_trimmed_after_fill_with_reports() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let mut user_rx = feeds.user.subscribe();
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+    let mut am = AccountManager::new();
+    am.load_balance(alice, "USDC", dec!(1000), dec!(0));
+    am.load_balance(bob, "USDC", dec!(1000), dec!(0));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData::default()).await;
+    });
+
+    spot_quote(&eng_tx, 1, Side::Buy, dec!(9)).await;
+    spot_quote(&eng_tx, 2, Side::Sell, dec!(11)).await;
+    account(&eng_tx, alice, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    account(&eng_tx, bob, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+
+    // bob が6枚の買い持ちになり、4枚と2枚の reduce_only で手仕舞いを待つ
+    place(&eng_tx, limit(3, Side::Sell, dec!(10), 6, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(4, Side::Buy, dec!(10), 6, Some(bob))).await.unwrap();
+    place(&eng_tx, reduce_only(limit(5, Side::Sell, dec!(15), 4, Some(bob)))).await.unwrap();
+    place(&eng_tx, reduce_only(limit(6, Side::Sell, dec!(16), 2, Some(bob)))).await.unwrap();
+
+    // 通常の売り3枚でポジションが3枚になると、先に出した 5 は3枚に減り、6 はキャンセルされる
+    place(&eng_tx, limit(7, Side::Buy, dec!(9), 3, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(8, Side::Sell, dec!(9), 3, Some(bob))).await.unwrap();
+    assert_eq!(perp_open_orders(&eng_tx, bob).await, vec![(5, 3)]);
+
+    // 減らした reduce_only が1枚約定すると、残り2枚はポジション2枚とちょうど合う
+    place(&eng_tx, limit(9, Side::Buy, dec!(15), 1, Some(alice))).await.unwrap();
+    assert_eq!(account(&eng_tx, bob, PerpRequest::Get).await.unwrap().position.size, 2);
+    assert_eq!(perp_open_orders(&eng_tx, bob).await, vec![(5, 2)]);
+
+    let status = |id: u64, status: &str, last_quantity: Option<u64>| (id, status.to_string(), last_quantity);
+    assert_eq!(perp_reports(&mut user_rx, bob), vec![
+        status(4, "New", None),
+        status(4, "Filled", Some(6)),
+        status(5, "New", None),
+        status(6, "New", None),
+        status(8, "New", None),
+        status(8, "Filled", Some(3)),
+        status(5, "New", None), // 数量を3枚に減らした
+        status(6, "Cancelled", None),
+        status(5, "PartiallyFilled", Some(1)),
+    ]);
+
+    // 先物の約定は先物用のテーブルに保存する（IDは現物と別に1から採番）
+    let mut saved = Vec::new();
+    while let Ok(msg) = db_rx.try_recv() {
+        match msg {
+            DbMessage::SavePerpTrade(trade) => saved.push((trade.id, trade.maker_id, trade.taker_id, trade.quantity)),
+            DbMessage::SaveTrade(_) => panic!("perp trade saved as a spot trade"),
+            _ => {}
+        }
+    }
+    assert_eq!(saved, vec![(1, 3, 4, 6), (2, 7, 8, 3), (3, 5, 9, 1)]);
+}
+
+#[tokio::test]
+async fn test_engine_liquidates_underwater_perp_positions() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(1000);
+    let feeds = MarketFeeds::new(100);
+    let mut user_rx = feeds.user.subscribe();
+    let (alice, bob, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let mut am = AccountManager::new();
+    for user in [alice, bob, carol] {
+        am.load_balance(user, "USDC", dec!(1000), dec!(0));
+    }
+    let perp = PerpManager::new(PerpConfig::default(), Arc::new(OrderIdGenerator::new(100)));
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, feeds, MarketData { perp, ..MarketData::default() }).await;
+    });
+
+    // インデックス 10。bob はウォレット 10 で目一杯の 10枚を買い持ちにする
+    spot_quote(&eng_tx, 1, Side::Buy, dec!(7)).await;
+    spot_quote(&eng_tx, 2, Side::Sell, dec!(13)).await;
+    account(&eng_tx, alice, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    account(&eng_tx, bob, PerpRequest::TransferIn { amount: dec!(10) }).await.unwrap();
+    account(&eng_tx, carol, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    place(&eng_tx, limit(3, Side::Sell, dec!(10), 10, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(4, Side::Buy, dec!(10), 10, Some(bob))).await.unwrap();
+    place(&eng_tx, limit(5, Side::Sell, dec!(20), 1, Some(bob))).await.unwrap();
+
+    // インデックスが 9 に下がると、マーク 9.045 で含み損 -9.55。
+    // ウォレット + 含み損益 0.45 < 10枚 × 9.045 × 5% なので清算対象だが、買い板が空なので待つ
+    spot_quote(&eng_tx, 6, Side::Sell, dec!(11)).await;
+    assert_eq!(market(&eng_tx).await.mark_price, Some(dec!(9.045)));
+    assert_eq!(account(&eng_tx, bob, PerpRequest::Get).await.unwrap().position.size, 10);
+    assert_eq!(perp_open_orders(&eng_tx, bob).await, vec![(5, 1)]);
+
+    // carol が 8.5 に買いを置くと、bob の注文をキャンセルしてから成行で閉じる
+    place(&eng_tx, limit(7, Side::Buy, dec!(8.5), 10, Some(carol))).await.unwrap();
+    let summary = account(&eng_tx, bob, PerpRequest::Get).await.unwrap();
+    assert_eq!(summary.position.size, 0);
+    assert_eq!(summary.position.realized_pnl, dec!(-15));
+    // ウォレットの -5 は貸し倒れにして0に戻す
+    assert_eq!((summary.wallet_balance, summary.bad_debt), (dec!(0), dec!(5)));
+    assert!(perp_open_orders(&eng_tx, bob).await.is_empty());
+    assert_eq!(account(&eng_tx, carol, PerpRequest::Get).await.unwrap().position.size, 10);
+
+    // 清算注文は PerpManager の採番器から ID を取る
+    let status = |id: u64, status: &str, last_quantity: Option<u64>| (id, status.to_string(), last_quantity);
+    assert_eq!(perp_reports(&mut user_rx, bob), vec![
+        status(4, "New", None),
+        status(4, "Filled", Some(10)),
+        status(5, "New", None),
+        status(5, "Cancelled", None),
+        status(101, "New", None),
+        status(101, "Filled", Some(10)),
+    ]);
+    let mut saved = Vec::new();
+    while let Ok(msg) = db_rx.try_recv() {
+        if let DbMessage::SavePerpTrade(trade) = msg {
+            saved.push((trade.maker_id, trade.taker_id, trade.price));
+        }
+    }
+    assert_eq!(saved, vec![(3, 4, dec!(10)), (7, 101, dec!(8.5))]);
+}
+
+#[tokio::test]
+async fn test_perp_accounts_survive_a_restart() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, db_rx) = mpsc::channel(1000);
+    let writer = tokio::spawn(run_db_writer(db_rx, storage.clone(), Arc::new(DbWriterStatus::default())));
+    let mut am = AccountManager::new();
+    am.load_balance(alice, "USDC", dec!(1000), dec!(0));
+    am.load_balance(bob, "USDC", dec!(1000), dec!(0));
+    let engine = tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, am, MarketFeeds::new(100), MarketData::default()).await;
+    });
+
+    // 現物の仲値 10 で、bob が alice から 10枚 @12 で買い持ちにする
+    for order in [limit(1, Side::Buy, dec!(9), 10, None), limit(2, Side::Sell, dec!(11), 10, None)] {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        eng_tx.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+        resp_rx.await.unwrap();
+    }
+    account(&eng_tx, alice, PerpRequest::TransferIn { amount: dec!(100) }).await.unwrap();
+    account(&eng_tx, bob, PerpRequest::TransferIn { amount: dec!(150) }).await.unwrap();
+    place(&eng_tx, limit(3, Side::Sell, dec!(12), 10, Some(alice))).await.unwrap();
+    place(&eng_tx, limit(4, Side::Buy, dec!(12), 10, Some(bob))).await.unwrap();
+    drop(eng_tx);
+    engine.await.unwrap();
+    writer.await.unwrap();
+
+    // 再起動してもウォレットとポジションは残る
+    let market = MarketData::load(&storage, 0).await.unwrap();
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, _db_rx) = mpsc::channel(1000);
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), MarketFeeds::new(100), market).await;
+    });
+    let summary = account(&eng_tx, bob, PerpRequest::Get).await.unwrap();
+    assert_eq!((summary.wallet_balance, summary.position.size, summary.position.entry_price), (dec!(150), 10, dec!(12)));
+    let summary = account(&eng_tx, alice, PerpRequest::Get).await.unwrap();
+    assert_eq!((summary.wallet_balance, summary.position.size), (dec!(100), -10));
+}
@@ -0,0 +1,98 @@
+use rust_decimal_macros::dec;
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::models::{Order, OrderType, Side};
+use rust_matching_engine::publisher::{CoalescingPublisher, DueChannels, PublishIntervals};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+fn intervals() -> PublishIntervals {
+    PublishIntervals { book: Duration::from_millis(50), ticker: Duration::from_millis(200) }
+}
+
+#[test]
+fn test_publisher_flushes_leading_and_trailing_edges() {
+    let mut publisher = CoalescingPublisher::new(&intervals());
+    let t0 = Instant::now();
+    let ms = |n| t0 + Duration::from_millis(n);
+
+    // 変化がなければ何も配信しない
+    assert_eq!(publisher.take_due(t0), DueChannels::default());
+    assert_eq!(publisher.next_deadline(), None);
+
+    // 最初の変化はすぐに配信する
+    publisher.observe(1);
+    assert_eq!(publisher.take_due(t0), DueChannels { book: true, ticker: true });
+    assert_eq!(publisher.next_deadline(), None);
+
+    // 間隔内の変化はまとめて、間隔が空いた時点で配信する
+    publisher.observe(2);
+    publisher.observe(3);
+    assert_eq!(publisher.take_due(ms(10)), DueChannels::default());
+    assert_eq!(publisher.next_deadline(), Some(ms(50)));
+    assert_eq!(publisher.take_due(ms(50)), DueChannels { book: true, ticker: false });
+    // ティッカーは自分の間隔で
+    assert_eq!(publisher.next_deadline(), Some(ms(200)));
+    assert_eq!(publisher.take_due(ms(200)), DueChannels { book: false, ticker: true });
+    assert_eq!(publisher.next_deadline(), None);
+
+    // 同じ sequence を見ても変化とはみなさない
+    publisher.observe(3);
+    assert_eq!(publisher.next_deadline(), None);
+}
+
+async fn place(engine: &mpsc::Sender<EngineMessage>, id: u64, price: rust_decimal::Decimal, side: Side) {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let order = Order { id, price, quantity: 1, side, user_id: None, order_type: OrderType::Limit, ..Default::default() };
+    engine.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_last_update_of_a_burst_is_always_delivered() {
+    let (eng_tx, eng_rx) = mpsc::channel(100);
+    let (db_tx, mut db_rx) = mpsc::channel(1000);
+    let mut feeds = MarketFeeds::new(1000);
+    feeds.intervals = intervals();
+    let mut book_rx = feeds.book.subscribe();
+    let mut ticker_rx = feeds.ticker.subscribe();
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), engine_feeds, MarketData::default()).await;
+    });
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    // 配信間隔より短い間に注文を連続で出し、最後は約定させる
+    for id in 1..=20 {
+        place(&eng_tx, id, dec!(100) + rust_decimal::Decimal::from(id), Side::Sell).await;
+    }
+    place(&eng_tx, 21, dec!(101), Side::Buy).await;
+    let sent_at = Instant::now();
+
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetOrderBook { respond_to: resp_tx }).await.unwrap();
+    let final_sequence = resp_rx.await.unwrap().sequence;
+    assert_eq!(final_sequence, 21);
+
+    // 後続の注文がなくても、最後の状態まで配信される
+    let mut last = 0;
+    while last < final_sequence {
+        let update = tokio::time::timeout(Duration::from_secs(1), book_rx.recv())
+            .await
+            .expect("最後の差分が届かない")
+            .unwrap();
+        assert!(update.prev_sequence <= last, "差分が途切れている");
+        last = update.sequence;
+    }
+    assert!(sent_at.elapsed() < Duration::from_millis(500));
+
+    let mut last_ticker = None;
+    while let Ok(Ok(json)) = tokio::time::timeout(Duration::from_millis(400), ticker_rx.recv()).await {
+        last_ticker = Some(serde_json::from_str::<Value>(&json).unwrap());
+    }
+    let ticker = last_ticker.expect("ティッカーが届かない");
+    assert_eq!(ticker["last_price"], "101");
+    assert_eq!(ticker["best_ask"], "102");
+}
@@ -0,0 +1,214 @@
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::api::{self, AppState};
+use rust_matching_engine::auth::hash_token;
+use rust_matching_engine::engine::{run_matching_engine, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::ratelimit::{BucketConfig, RateKey, RateLimitConfig, RateLimiter};
+use rust_matching_engine::ws::WsConfig;
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+fn small_config() -> RateLimitConfig {
+    RateLimitConfig {
+        user: BucketConfig { burst: 4, refill_per_sec: 2 },
+        ip: BucketConfig { burst: 10, refill_per_sec: 5 },
+        order_weight: 2,
+        cancel_weight: 1,
+        read_weight: 1,
+        ws_connections_per_user: 2,
+        ws_connections_per_ip: 3,
+    }
+}
+
+#[test]
+fn test_bucket_denies_then_refills() {
+    let limiter = RateLimiter::new(small_config());
+    let user = [RateKey::User(Uuid::new_v4())];
+    let t0 = Instant::now();
+
+    let d = limiter.check_at(&user, 2, t0);
+    assert!(d.allowed);
+    assert_eq!((d.limit, d.remaining), (4, 2));
+    assert!(limiter.check_at(&user, 2, t0).allowed);
+
+    // 空になったら、足りない分が回復するまでの時間を返す
+    let d = limiter.check_at(&user, 2, t0);
+    assert!(!d.allowed);
+    assert_eq!(d.remaining, 0);
+    assert_eq!(d.retry_after, Duration::from_secs(1));
+
+    // 1秒で2トークン回復
+    let d = limiter.check_at(&user, 2, t0 + Duration::from_secs(1));
+    assert!(d.allowed);
+    assert_eq!(d.remaining, 0);
+
+    // 回復は容量で頭打ち
+    let d = limiter.check_at(&user, 1, t0 + Duration::from_secs(60));
+    assert_eq!(d.remaining, 3);
+}
+
+#[test]
+fn test_all_buckets_must_have_tokens() {
+    let limiter = RateLimiter::new(small_config());
+    let ip = RateKey::Ip(IpAddr::from([10, 0, 0, 1]));
+    let (alice, bob) = (RateKey::User(Uuid::new_v4()), RateKey::User(Uuid::new_v4()));
+    let t0 = Instant::now();
+
+    assert!(limiter.check_at(&[alice, ip], 4, t0).allowed);
+    // alice が空なので拒否され、IPのトークンも消費されない
+    let d = limiter.check_at(&[alice, ip], 1, t0);
+    assert!(!d.allowed);
+    assert_eq!(d.remaining, 0);
+
+    // 同じIPの別ユーザーは通る（IPは残り6）
+    let d = limiter.check_at(&[bob, ip], 4, t0);
+    assert!(d.allowed);
+    assert_eq!((d.limit, d.remaining), (4, 0));
+    let d = limiter.check_at(&[ip], 2, t0);
+    assert_eq!((d.limit, d.remaining), (10, 0));
+    assert!(!limiter.check_at(&[ip], 1, t0).allowed);
+}
+
+#[test]
+fn test_request_weights() {
+    let limiter = RateLimiter::new(small_config());
+    assert_eq!(limiter.weight(&Method::POST, "/order"), 2);
+    assert_eq!(limiter.weight(&Method::DELETE, "/order/42"), 1);
+    assert_eq!(limiter.weight(&Method::GET, "/trades"), 1);
+}
+
+#[test]
+fn test_ws_connection_limit() {
+    let limiter = Arc::new(RateLimiter::new(small_config()));
+    let user = RateKey::User(Uuid::new_v4());
+
+    let first = limiter.acquire_ws(user).expect("1本目");
+    let _second = limiter.acquire_ws(user).expect("2本目");
+    assert!(limiter.acquire_ws(user).is_none());
+
+    // 切断すると枠が空く
+    drop(first);
+    assert!(limiter.acquire_ws(user).is_some());
+
+    // IPは別の上限
+    let ip = RateKey::Ip(IpAddr::from([10, 0, 0, 1]));
+    let permits: Vec<_> = (0..3).map(|_| limiter.acquire_ws(ip).unwrap()).collect();
+    assert!(limiter.acquire_ws(ip).is_none());
+    drop(permits);
+}
+
+fn test_app(config: RateLimitConfig) -> Router {
+    test_app_with_storage(config, Arc::new(MemoryStorage::new()))
+}
+
+fn test_app_with_storage(config: RateLimitConfig, storage: SharedStorage) -> Router {
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let feeds = MarketFeeds::new(100);
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), engine_feeds, MarketData::default()).await;
+    });
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    let rate_limiter = Arc::new(RateLimiter::new(config));
+    api::router(Arc::new(AppState {
+        sender: eng_tx,
+        storage,
+        feeds,
+        rate_limiter,
+        order_ids: Arc::new(OrderIdGenerator::default()),
+        ws: WsConfig::default(),
+        db_status: Default::default(),
+        assets: Default::default(),
+    }))
+}
+
+fn request_from(method: Method, uri: &str, ip: [u8; 4]) -> Request<Body> {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"price":"100","quantity":1,"side":"Buy"}"#))
+        .unwrap();
+    req.extensions_mut().insert(ConnectInfo(SocketAddr::from((ip, 50000))));
+    req
+}
+
+#[tokio::test]
+async fn test_rest_requests_are_limited_per_ip() {
+    let app = test_app(RateLimitConfig { ip: BucketConfig { burst: 5, refill_per_sec: 1 }, ..small_config() });
+
+    // 全レスポンスに残量ヘッダーが付く
+    let res = app.clone().oneshot(request_from(Method::GET, "/ticker", [10, 0, 0, 1])).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-ratelimit-limit"], "5");
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "4");
+
+    // 発注は重み2（未ログインなので401だが、IPの枠は消費する）
+    for remaining in ["2", "0"] {
+        let res = app.clone().oneshot(request_from(Method::POST, "/order", [10, 0, 0, 1])).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()["x-ratelimit-remaining"], remaining);
+    }
+
+    let res = app.clone().oneshot(request_from(Method::POST, "/order", [10, 0, 0, 1])).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()["retry-after"], "2");
+    assert_eq!(res.headers()["x-ratelimit-remaining"], "0");
+
+    // 別のIPは影響を受けない
+    let res = app.clone().oneshot(request_from(Method::GET, "/ticker", [10, 0, 0, 2])).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_ip_limit_applies_before_api_key_auth() {
+    let app = test_app(RateLimitConfig { ip: BucketConfig { burst: 2, refill_per_sec: 1 }, ..small_config() });
+
+    // 存在しないAPIキーでも、認証の前にIPの枠を消費する
+    for _ in 0..2 {
+        let mut req = request_from(Method::GET, "/balance", [10, 0, 0, 3]);
+        req.headers_mut().insert("x-api-key", "unknown".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // 枠を使い切ったら、APIキーを照会せずに 429 を返す
+    let mut req = request_from(Method::GET, "/balance", [10, 0, 0, 3]);
+    req.headers_mut().insert("x-api-key", "unknown".parse().unwrap());
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_session_requests_are_limited_per_user_across_ips() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let user_id = storage.create_user("alice", "hash").await.unwrap();
+    storage.create_session(&hash_token("alice-token"), user_id, u128::MAX).await.unwrap();
+    let app = test_app_with_storage(small_config(), storage);
+
+    // ユーザーの枠（4）はIPをまたいで共有し、残量は少ないほう（ユーザー）を返す
+    for (i, remaining) in ["3", "2", "1", "0"].into_iter().enumerate() {
+        let mut req = request_from(Method::GET, "/balance", [10, 0, 1, i as u8]);
+        req.headers_mut().insert("authorization", "Bearer alice-token".parse().unwrap());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-ratelimit-limit"], "4");
+        assert_eq!(res.headers()["x-ratelimit-remaining"], remaining);
+    }
+
+    let mut req = request_from(Method::GET, "/balance", [10, 0, 1, 9]);
+    req.headers_mut().insert("authorization", "Bearer alice-token".parse().unwrap());
+    let res = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+}
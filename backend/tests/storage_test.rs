@@ -0,0 +1,452 @@
+use rust_matching_engine::db::{self, SqliteStorage};
+use rust_matching_engine::models::{
+    ApiKey, ApiScope, Asset, Candle, CandleInterval, FixSequence, MarginAccountRecord, MarginAssetRecord, OrderRecord, OrderStatus,
+    OrderType, PerpAccountRecord, Side, Trade,
+};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage, StorageError, TradeQuery};
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use uuid::Uuid;
+
+// 同じテストを両方のバックエンドに対して実行する
+async fn backends() -> Vec<SharedStorage> {
+    let pool = db::connect(":memory:").await.expect("Failed to open sqlite");
+    vec![
+        Arc::new(SqliteStorage::new(pool)),
+        Arc::new(MemoryStorage::new()),
+    ]
+}
+
+fn order_record(id: u64, user_id: Uuid, created_at: u128) -> OrderRecord {
+    OrderRecord {
+        id,
+        user_id,
+        side: Side::Buy,
+        order_type: OrderType::Limit,
+        price: dec!(99.5),
+        quantity: 10,
+        filled_quantity: 0,
+        filled_quote: dec!(0),
+        status: OrderStatus::New,
+        created_at,
+        updated_at: created_at,
+    }
+}
+
+fn trade(id: u64, timestamp: u128, maker_user_id: Option<Uuid>, taker_user_id: Option<Uuid>) -> Trade {
+    Trade {
+        id,
+        maker_id: id * 10,
+        taker_id: id * 10 + 1,
+        price: dec!(100.5),
+        quantity: 5,
+        taker_side: Side::Sell,
+        timestamp,
+        maker_user_id,
+        taker_user_id,
+    }
+}
+
+fn ids(trades: &[Trade]) -> Vec<u64> {
+    trades.iter().map(|t| t.id).collect()
+}
+
+fn candle(open_time: u128, close: rust_decimal::Decimal) -> Candle {
+    Candle {
+        interval: CandleInterval::OneMinute,
+        open_time,
+        open: dec!(100),
+        high: dec!(101),
+        low: dec!(99),
+        close,
+        volume: 10,
+        quote_volume: dec!(1000),
+        trade_count: 2,
+    }
+}
+
+#[tokio::test]
+async fn test_default_user_is_idempotent() {
+    for storage in backends().await {
+        let first = storage.ensure_default_user().await.unwrap();
+        let second = storage.ensure_default_user().await.unwrap();
+        assert_eq!(first, second);
+
+        let balances = storage.get_balances(first).await.unwrap();
+        let usdc = balances.iter().find(|b| b.asset == "USDC").expect("USDC missing");
+        assert_eq!(usdc.available, dec!(10000));
+        assert!(balances.iter().any(|b| b.asset == "BAD"));
+    }
+}
+
+#[tokio::test]
+async fn test_create_user_rejects_duplicate_username() {
+    for storage in backends().await {
+        let alice = storage.create_user("alice", "hash-a").await.unwrap();
+        let err = storage.create_user("alice", "hash-b").await.unwrap_err();
+        assert!(matches!(err, StorageError::Conflict(_)), "got {:?}", err);
+
+        let user = storage.get_user_by_username("alice").await.unwrap().unwrap();
+        assert_eq!(user.id, alice);
+        assert_eq!(user.password_hash.as_deref(), Some("hash-a"));
+        assert!(storage.get_user_by_username("bob").await.unwrap().is_none());
+
+        // 新規ユーザーにも初期残高が付与される
+        let balances = storage.get_balances(alice).await.unwrap();
+        assert_eq!(balances.len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn test_session_expiry_and_delete() {
+    for storage in backends().await {
+        let user_id = storage.create_user("carol", "hash").await.unwrap();
+        storage.create_session("token-hash", user_id, 1_000).await.unwrap();
+
+        assert_eq!(storage.get_session_user("token-hash", 999).await.unwrap(), Some(user_id));
+        // 有効期限を過ぎたら無効
+        assert_eq!(storage.get_session_user("token-hash", 1_000).await.unwrap(), None);
+        assert_eq!(storage.get_session_user("unknown", 0).await.unwrap(), None);
+
+        storage.delete_session("token-hash").await.unwrap();
+        assert_eq!(storage.get_session_user("token-hash", 0).await.unwrap(), None);
+    }
+}
+
+#[tokio::test]
+async fn test_api_key_round_trip_and_revoke() {
+    for storage in backends().await {
+        let owner = Uuid::new_v4();
+        let api_key = ApiKey {
+            key: "k1".to_string(),
+            secret: "s1".to_string(),
+            user_id: owner,
+            label: "bot".to_string(),
+            scopes: vec![ApiScope::Read, ApiScope::Trade],
+            ip_allowlist: vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+            created_at: 1_000,
+            revoked_at: None,
+        };
+        storage.create_api_key(&api_key).await.unwrap();
+        storage.create_api_key(&ApiKey { key: "k2".to_string(), created_at: 2_000, ip_allowlist: vec![], ..api_key.clone() }).await.unwrap();
+
+        assert_eq!(storage.get_api_key("k1").await.unwrap(), Some(api_key.clone()));
+        assert!(storage.get_api_key("missing").await.unwrap().is_none());
+        let keys: Vec<String> = storage.list_api_keys(owner).await.unwrap().into_iter().map(|k| k.key).collect();
+        assert_eq!(keys, vec!["k1", "k2"]);
+
+        // 他人のキーは失効できない
+        assert!(!storage.revoke_api_key(Uuid::new_v4(), "k1", 3_000).await.unwrap());
+        assert!(storage.revoke_api_key(owner, "k1", 3_000).await.unwrap());
+        assert!(!storage.revoke_api_key(owner, "k1", 4_000).await.unwrap());
+        assert_eq!(storage.get_api_key("k1").await.unwrap().unwrap().revoked_at, Some(3_000));
+    }
+}
+
+#[tokio::test]
+async fn test_get_all_balances_covers_every_user() {
+    for storage in backends().await {
+        let a = storage.create_user("dave", "hash").await.unwrap();
+        let b = storage.create_user("erin", "hash").await.unwrap();
+        let all = storage.get_all_balances().await.unwrap();
+        assert_eq!(all.len(), 4);
+        assert!(all.iter().any(|bal| bal.user_id == a));
+        assert!(all.iter().any(|bal| bal.user_id == b));
+    }
+}
+
+#[tokio::test]
+async fn test_update_balance_creates_missing_row() {
+    for storage in backends().await {
+        let user_id = Uuid::new_v4();
+        storage.update_balance(user_id, "BAD", dec!(12.5), dec!(2)).await.unwrap();
+
+        let balances = storage.get_balances(user_id).await.unwrap();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].asset, "BAD");
+        assert_eq!(balances[0].available, dec!(12.5));
+        assert_eq!(balances[0].locked, dec!(2));
+    }
+}
+
+#[tokio::test]
+async fn test_asset_registry_and_unregistered_balances() {
+    for storage in backends().await {
+        let assets = storage.get_assets().await.unwrap();
+        let names: Vec<&str> = assets.iter().map(|a| a.asset.as_str()).collect();
+        assert_eq!(names, ["BAD", "USDC"]);
+        assert_eq!(assets[1].precision, 6);
+
+        // 登録済みなら上書き
+        let eth = Asset { asset: "ETH".to_string(), display_name: "Ether".to_string(), precision: 8 };
+        storage.save_asset(&eth).await.unwrap();
+        storage.save_asset(&Asset { display_name: "Ethereum".to_string(), ..eth }).await.unwrap();
+        let assets = storage.get_assets().await.unwrap();
+        assert_eq!(assets.len(), 3);
+        assert_eq!(assets[1].display_name, "Ethereum");
+
+        // 登録のない資産の残高も持てる（資産名の順）
+        let user_id = storage.create_user("frank", "hash").await.unwrap();
+        storage.update_balance(user_id, "SOL", dec!(1.5), dec!(0)).await.unwrap();
+        let balances = storage.get_balances(user_id).await.unwrap();
+        let names: Vec<&str> = balances.iter().map(|b| b.asset.as_str()).collect();
+        assert_eq!(names, ["BAD", "SOL", "USDC"]);
+    }
+}
+
+#[tokio::test]
+async fn test_trade_round_trip_and_last_id() {
+    for storage in backends().await {
+        assert_eq!(storage.last_trade_id().await.unwrap(), 0);
+
+        let saved = trade(7, 1000, Some(Uuid::new_v4()), None);
+        storage.save_trade(&saved).await.unwrap();
+        storage.save_trade(&trade(3, 900, None, None)).await.unwrap();
+
+        let loaded = storage.get_trades(&TradeQuery { limit: 10, ..Default::default() }).await.unwrap();
+        assert_eq!(ids(&loaded), vec![3, 7]);
+        assert_eq!(loaded[1], saved);
+        assert_eq!(storage.last_trade_id().await.unwrap(), 7);
+    }
+}
+
+#[tokio::test]
+async fn test_trade_pagination() {
+    for storage in backends().await {
+        for id in 1..=10 {
+            storage.save_trade(&trade(id, id as u128 * 1000, None, None)).await.unwrap();
+        }
+
+        // カーソルなしは最新の limit 件（古い順）
+        let latest = storage.get_trades(&TradeQuery { limit: 3, ..Default::default() }).await.unwrap();
+        assert_eq!(ids(&latest), vec![8, 9, 10]);
+
+        // from_id から読み進める
+        let page1 = storage
+            .get_trades(&TradeQuery { from_id: Some(1), limit: 4, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(ids(&page1), vec![1, 2, 3, 4]);
+        let page2 = storage
+            .get_trades(&TradeQuery { from_id: Some(page1[3].id + 1), limit: 4, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(ids(&page2), vec![5, 6, 7, 8]);
+
+        // 時刻の範囲（両端を含む）
+        let ranged = storage
+            .get_trades(&TradeQuery { start_time: Some(3000), end_time: Some(5000), limit: 100, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(ids(&ranged), vec![3, 4, 5]);
+        let before = storage
+            .get_trades(&TradeQuery { end_time: Some(5000), limit: 2, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(ids(&before), vec![4, 5]);
+    }
+}
+
+#[tokio::test]
+async fn test_user_trades_include_maker_and_taker_side() {
+    for storage in backends().await {
+        let user_id = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        storage.save_trade(&trade(1, 1000, Some(user_id), None)).await.unwrap();
+        storage.save_trade(&trade(2, 2000, None, Some(other))).await.unwrap();
+        storage.save_trade(&trade(3, 3000, Some(other), Some(user_id))).await.unwrap();
+        storage.save_trade(&trade(4, 4000, None, None)).await.unwrap();
+
+        let mine = storage
+            .get_trades(&TradeQuery { user_id: Some(user_id), limit: 50, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(ids(&mine), vec![1, 3]);
+
+        let after = storage
+            .get_trades(&TradeQuery { user_id: Some(user_id), from_id: Some(2), limit: 50, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(ids(&after), vec![3]);
+    }
+}
+
+#[tokio::test]
+async fn test_order_upsert_and_lookup() {
+    for storage in backends().await {
+        let user_id = Uuid::new_v4();
+        let mut order = order_record(7, user_id, 1000);
+        storage.save_order(&order).await.unwrap();
+        storage.save_order(&order_record(8, user_id, 2000)).await.unwrap();
+
+        order.apply_fill(dec!(99.25), 3, 1200);
+        order.apply_fill(dec!(99.5), 1, 1500);
+        assert_eq!(order.status, OrderStatus::PartiallyFilled);
+        storage.save_order(&order).await.unwrap();
+
+        let loaded = storage.get_order(7).await.unwrap().expect("order missing");
+        assert_eq!(loaded, order);
+        assert_eq!(loaded.filled_quote, dec!(397.25));
+        assert_eq!(loaded.avg_fill_price(), Some(dec!(99.3125)));
+        assert!(storage.get_order(99).await.unwrap().is_none());
+
+        let orders = storage.get_user_orders(user_id, 10).await.unwrap();
+        assert_eq!(orders.iter().map(|o| o.id).collect::<Vec<_>>(), vec![8, 7]);
+        assert_eq!(storage.get_user_orders(user_id, 1).await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_last_order_id_covers_orders_and_trades() {
+    for storage in backends().await {
+        assert_eq!(storage.last_order_id().await.unwrap(), 0);
+
+        storage.save_order(&order_record(12, Uuid::new_v4(), 1000)).await.unwrap();
+        assert_eq!(storage.last_order_id().await.unwrap(), 12);
+
+        // 注文記録のないシミュレータの注文IDも、約定に残っていれば使用済み扱い
+        storage.save_trade(&trade(5, 1000, None, None)).await.unwrap();
+        assert_eq!(storage.last_order_id().await.unwrap(), 51);
+
+        // 先物の約定の注文IDも同じ採番器から出ている
+        storage.save_perp_trade(&trade(6, 1000, None, None)).await.unwrap();
+        assert_eq!(storage.last_order_id().await.unwrap(), 61);
+    }
+}
+
+#[tokio::test]
+async fn test_perp_trades_are_kept_apart_from_spot_trades() {
+    for storage in backends().await {
+        assert_eq!(storage.last_perp_trade_id().await.unwrap(), 0);
+
+        storage.save_trade(&trade(9, 1000, None, None)).await.unwrap();
+        storage.save_perp_trade(&trade(2, 1000, Some(Uuid::new_v4()), None)).await.unwrap();
+        storage.save_perp_trade(&trade(4, 1000, None, None)).await.unwrap();
+
+        // 約定IDは現物と先物で別々に採番する
+        assert_eq!(storage.last_perp_trade_id().await.unwrap(), 4);
+        assert_eq!(storage.last_trade_id().await.unwrap(), 9);
+        let spot = storage.get_trades(&TradeQuery { limit: 10, ..Default::default() }).await.unwrap();
+        assert_eq!(ids(&spot), vec![9]);
+    }
+}
+
+#[tokio::test]
+async fn test_margin_account_upsert_replaces_assets() {
+    for storage in backends().await {
+        assert!(storage.get_margin_accounts().await.unwrap().is_empty());
+
+        let user_id = Uuid::new_v4();
+        let usdc = MarginAssetRecord {
+            asset: "USDC".to_string(),
+            available: dec!(450.5),
+            locked: dec!(49.5),
+            principal: dec!(400),
+            interest: dec!(0.00000012),
+            bad_debt: dec!(0),
+        };
+        let bad = MarginAssetRecord { asset: "BAD".to_string(), bad_debt: dec!(3), ..Default::default() };
+        let mut account = MarginAccountRecord { user_id, accrued_at: 1_000, liquidated: false, assets: vec![bad, usdc.clone()] };
+        storage.save_margin_account(&account).await.unwrap();
+        assert_eq!(storage.get_margin_accounts().await.unwrap(), vec![account.clone()]);
+
+        // 保存し直すと、なくなった資産の行も消える
+        account.accrued_at = 2_000;
+        account.liquidated = true;
+        account.assets = vec![usdc];
+        storage.save_margin_account(&account).await.unwrap();
+        assert_eq!(storage.get_margin_accounts().await.unwrap(), vec![account]);
+    }
+}
+
+#[tokio::test]
+async fn test_perp_account_upsert() {
+    for storage in backends().await {
+        assert!(storage.get_perp_accounts().await.unwrap().is_empty());
+
+        let mut account = PerpAccountRecord {
+            user_id: Uuid::new_v4(),
+            wallet_balance: dec!(100),
+            size: -10,
+            entry_price: dec!(10.66666667),
+            realized_pnl: dec!(-1.5),
+            funding: dec!(0.5025),
+            bad_debt: dec!(0),
+        };
+        storage.save_perp_account(&account).await.unwrap();
+        account.size = 0;
+        account.bad_debt = dec!(2);
+        storage.save_perp_account(&account).await.unwrap();
+        assert_eq!(storage.get_perp_accounts().await.unwrap(), vec![account]);
+    }
+}
+
+#[tokio::test]
+async fn test_candle_upsert_and_range() {
+    for storage in backends().await {
+        storage.save_candle(&candle(60_000, dec!(100))).await.unwrap();
+        storage.save_candle(&candle(120_000, dec!(100.5))).await.unwrap();
+        storage.save_candle(&candle(180_000, dec!(101))).await.unwrap();
+        // 同じ足を更新
+        storage.save_candle(&candle(120_000, dec!(100.7))).await.unwrap();
+
+        let all = storage.get_candles(CandleInterval::OneMinute, None, None, 100).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[1].close, dec!(100.7));
+
+        let ranged = storage
+            .get_candles(CandleInterval::OneMinute, Some(120_000), Some(180_000), 1)
+            .await
+            .unwrap();
+        assert_eq!(ranged.len(), 1);
+        assert_eq!(ranged[0].open_time, 120_000);
+
+        // from 省略時は最新の limit 本（古い順）
+        let latest = storage.get_candles(CandleInterval::OneMinute, None, None, 2).await.unwrap();
+        assert_eq!(latest.iter().map(|c| c.open_time).collect::<Vec<_>>(), vec![120_000, 180_000]);
+        let before = storage
+            .get_candles(CandleInterval::OneMinute, None, Some(120_000), 5)
+            .await
+            .unwrap();
+        assert_eq!(before.iter().map(|c| c.open_time).collect::<Vec<_>>(), vec![60_000, 120_000]);
+
+        let other = storage.get_candles(CandleInterval::OneHour, None, None, 100).await.unwrap();
+        assert!(other.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_fix_session_sequence_and_message_store() {
+    for storage in backends().await {
+        let session = "FIX.4.4:CLIENT->BADBIT";
+        assert_eq!(storage.get_fix_sequence(session).await.unwrap(), None);
+
+        let seq = FixSequence { next_outgoing: 4, next_incoming: 7 };
+        storage.save_fix_sequence(session, &seq).await.unwrap();
+        assert_eq!(storage.get_fix_sequence(session).await.unwrap(), Some(seq));
+
+        storage.save_fix_message(session, 2, b"second").await.unwrap();
+        storage.save_fix_message(session, 3, b"third").await.unwrap();
+        storage.save_fix_message("FIX.4.4:OTHER->BADBIT", 2, b"other").await.unwrap();
+        // 範囲内のものだけを番号順に返す
+        let stored = storage.get_fix_messages(session, 1, 2).await.unwrap();
+        assert_eq!(stored, vec![(2, b"second".to_vec())]);
+        assert_eq!(storage.get_fix_messages(session, 1, 10).await.unwrap().len(), 2);
+
+        // リセットすると番号もメッセージも消える（他のセッションは残る）
+        storage.reset_fix_session(session).await.unwrap();
+        assert_eq!(storage.get_fix_sequence(session).await.unwrap(), None);
+        assert!(storage.get_fix_messages(session, 1, 10).await.unwrap().is_empty());
+        assert_eq!(storage.get_fix_messages("FIX.4.4:OTHER->BADBIT", 1, 10).await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_unrepresentable_decimal_is_rejected() {
+    for storage in backends().await {
+        let user_id = Uuid::new_v4();
+        let err = storage.update_balance(user_id, "USDC", dec!(1.000000001), dec!(0)).await;
+        assert!(matches!(err, Err(StorageError::Unrepresentable(_))));
+        assert!(storage.get_balances(user_id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::candles::CandleAggregator;
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::models::{Order, OrderType, Side, Trade};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_matching_engine::ticker::{TickerTracker, WINDOW_MS};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+fn trade(price: Decimal, quantity: u64, timestamp: u128) -> Trade {
+    Trade {
+        id: 1,
+        maker_id: 1,
+        taker_id: 2,
+        price,
+        quantity,
+        taker_side: Side::Buy,
+        timestamp,
+        maker_user_id: None,
+        taker_user_id: None,
+    }
+}
+
+const DAY_START: u128 = 10 * WINDOW_MS;
+
+#[test]
+fn test_empty_ticker() {
+    let mut tracker = TickerTracker::new();
+    let t = tracker.snapshot(DAY_START, None, None);
+    assert!(t.last_price.is_none());
+    assert!(t.open_24h.is_none());
+    assert_eq!(t.base_volume_24h, 0);
+    assert_eq!(t.price_change, Decimal::ZERO);
+}
+
+#[test]
+fn test_stats_are_updated_incrementally() {
+    let mut tracker = TickerTracker::new();
+    tracker.on_trades(&[trade(dec!(100), 5, DAY_START), trade(dec!(104), 2, DAY_START + 1_000)]);
+    tracker.on_trades(&[trade(dec!(97), 1, DAY_START + 120_000)]);
+    tracker.on_trades(&[trade(dec!(110), 3, DAY_START + 3_600_000)]);
+
+    let t = tracker.snapshot(DAY_START + 3_600_000, Some(dec!(109.5)), Some(dec!(110.5)));
+    assert_eq!(t.last_price, Some(dec!(110)));
+    assert_eq!(t.best_bid, Some(dec!(109.5)));
+    assert_eq!(t.best_ask, Some(dec!(110.5)));
+    assert_eq!(t.open_24h, Some(dec!(100)));
+    assert_eq!(t.high_24h, Some(dec!(110)));
+    assert_eq!(t.low_24h, Some(dec!(97)));
+    assert_eq!(t.base_volume_24h, 11);
+    assert_eq!(t.quote_volume_24h, dec!(1135)); // 500 + 208 + 97 + 330
+    assert_eq!(t.trade_count_24h, 4);
+    assert_eq!(t.price_change, dec!(10));
+    assert_eq!(t.price_change_percent, dec!(10));
+}
+
+#[test]
+fn test_old_trades_leave_the_window() {
+    let mut tracker = TickerTracker::new();
+    tracker.on_trades(&[trade(dec!(100), 5, DAY_START)]);
+    tracker.on_trades(&[trade(dec!(90), 2, DAY_START + 60_000)]);
+
+    // 最初の1分が24時間の窓から外れた
+    let t = tracker.snapshot(DAY_START + WINDOW_MS + 1, None, None);
+    assert_eq!(t.open_24h, Some(dec!(90)));
+    assert_eq!(t.high_24h, Some(dec!(90)));
+    assert_eq!(t.base_volume_24h, 2);
+    assert_eq!(t.trade_count_24h, 1);
+
+    // 全て外れても最終価格は残る
+    let t = tracker.snapshot(DAY_START + 2 * WINDOW_MS, None, None);
+    assert_eq!(t.last_price, Some(dec!(90)));
+    assert!(t.open_24h.is_none());
+    assert_eq!(t.base_volume_24h, 0);
+    assert_eq!(t.quote_volume_24h, Decimal::ZERO);
+    assert_eq!(t.price_change_percent, Decimal::ZERO);
+}
+
+#[tokio::test]
+async fn test_load_from_saved_candles() {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let mut agg = CandleAggregator::new();
+    for batch in [
+        vec![trade(dec!(200), 1, DAY_START - 60_000)], // 窓の外
+        vec![trade(dec!(100), 4, DAY_START + 60_000)],
+        vec![trade(dec!(102), 6, DAY_START + 120_000)],
+    ] {
+        for candle in agg.apply_trades(&batch) {
+            storage.save_candle(&candle).await.unwrap();
+        }
+    }
+
+    let now = DAY_START + WINDOW_MS;
+    let mut tracker = TickerTracker::load(&storage, now).await.unwrap();
+    let t = tracker.snapshot(now, None, None);
+    assert_eq!(t.last_price, Some(dec!(102)));
+    assert_eq!(t.open_24h, Some(dec!(100)));
+    assert_eq!(t.high_24h, Some(dec!(102)));
+    assert_eq!(t.base_volume_24h, 10);
+    assert_eq!(t.trade_count_24h, 2);
+    assert_eq!(t.price_change_percent, dec!(2));
+}
+
+#[tokio::test]
+async fn test_engine_returns_ticker() {
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), MarketFeeds::new(100), MarketData::default()).await;
+    });
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    for (id, side, price, quantity) in [
+        (1, Side::Sell, dec!(100), 10),
+        (2, Side::Buy, dec!(100), 4),  // 100で4約定
+        (3, Side::Buy, dec!(98), 5),   // 買い板に残る
+    ] {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        eng_tx.send(EngineMessage::PlaceOrder {
+            order: Order { id, price, quantity, side, user_id: None, order_type: OrderType::Limit, ..Default::default() },
+            respond_to: resp_tx,
+        }).await.unwrap();
+        resp_rx.await.unwrap();
+    }
+
+    let (resp_tx, resp_rx) = oneshot::channel();
+    eng_tx.send(EngineMessage::GetTicker { respond_to: resp_tx }).await.unwrap();
+    let t = resp_rx.await.unwrap();
+    assert_eq!(t.last_price, Some(dec!(100)));
+    assert_eq!(t.best_bid, Some(dec!(98)));
+    assert_eq!(t.best_ask, Some(dec!(100)));
+    assert_eq!(t.base_volume_24h, 4);
+    assert_eq!(t.trade_count_24h, 1);
+}
@@ -0,0 +1,417 @@
+use axum::body::{to_bytes, Body};
+use axum::http::Request;
+use axum::Router;
+use futures::{SinkExt, StreamExt};
+use rust_decimal_macros::dec;
+use rust_matching_engine::account::AccountManager;
+use rust_matching_engine::api::{self, AppState};
+use rust_matching_engine::engine::{run_matching_engine, EngineMessage, MarketData, OrderIdGenerator};
+use rust_matching_engine::feeds::MarketFeeds;
+use rust_matching_engine::models::{Order, OrderType, Side};
+use rust_matching_engine::ratelimit::{RateLimitConfig, RateLimiter};
+use rust_matching_engine::storage::{MemoryStorage, SharedStorage};
+use rust_matching_engine::ws::WsConfig;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tower::ServiceExt;
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// 実際にポートを開いてサーバーを起動し、(アドレス, エンジンの送信側, ルーター) を返す
+async fn start_server(ws: WsConfig) -> (SocketAddr, mpsc::Sender<EngineMessage>, Router) {
+    let storage: SharedStorage = Arc::new(MemoryStorage::new());
+    let (db_tx, mut db_rx) = mpsc::channel(100);
+    let (eng_tx, eng_rx) = mpsc::channel(10);
+    let feeds = MarketFeeds::new(100);
+    let engine_feeds = feeds.clone();
+    tokio::spawn(async move {
+        run_matching_engine(eng_rx, db_tx, AccountManager::new(), engine_feeds, MarketData::default()).await;
+    });
+    tokio::spawn(async move { while db_rx.recv().await.is_some() {} });
+
+    let app = api::router(Arc::new(AppState {
+        sender: eng_tx.clone(),
+        storage,
+        feeds,
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        order_ids: Arc::new(OrderIdGenerator::default()),
+        ws,
+        db_status: Default::default(),
+        assets: Default::default(),
+    }));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move {
+        axum::serve(listener, server.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    });
+    (addr, eng_tx, app)
+}
+
+async fn connect(addr: SocketAddr) -> Client {
+    connect_async(format!("ws://{}/ws", addr)).await.unwrap().0
+}
+
+/// 次のテキストメッセージをJSONで読む（Pingなどの制御フレームは飛ばす）
+async fn next_json(ws: &mut Client) -> Value {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(2), ws.next())
+            .await
+            .expect("メッセージが届かない")
+            .unwrap()
+            .unwrap();
+        if let Message::Text(text) = msg {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+async fn request(ws: &mut Client, body: Value) -> Value {
+    ws.send(Message::Text(body.to_string().into())).await.unwrap();
+    next_json(ws).await
+}
+
+/// 購読して、成功の応答を確認する
+async fn subscribe(ws: &mut Client, channel: &str) {
+    let ack = request(ws, json!({ "op": "subscribe", "channel": channel })).await;
+    assert_eq!((ack["type"].as_str(), ack["channel"].as_str()), (Some("subscribed"), Some(channel)));
+}
+
+/// シミュレータと同じく所有者なしの指値を出す
+async fn place(engine: &mpsc::Sender<EngineMessage>, id: u64, price: rust_decimal::Decimal, quantity: u64, side: Side) {
+    // 板の配信は50msに1回なので、間隔を空けて毎回配信されるようにする
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    place_now(engine, id, price, quantity, side).await;
+}
+
+/// 間隔を空けずに指値を出す
+async fn place_now(engine: &mpsc::Sender<EngineMessage>, id: u64, price: rust_decimal::Decimal, quantity: u64, side: Side) {
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let order = Order { id, price, quantity, side, user_id: None, order_type: OrderType::Limit, ..Default::default() };
+    engine.send(EngineMessage::PlaceOrder { order, respond_to: resp_tx }).await.unwrap();
+    resp_rx.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_diff_depth_sends_snapshot_then_sequenced_deltas() {
+    let (addr, engine, _) = start_server(WsConfig::default()).await;
+    place(&engine, 1, dec!(99), 5, Side::Buy).await;
+
+    let mut ws = connect(addr).await;
+    subscribe(&mut ws, "diff_depth").await;
+
+    // 購読直後は現在の板のスナップショット
+    let snapshot = next_json(&mut ws).await;
+    assert_eq!(snapshot["type"], "snapshot");
+    assert_eq!(snapshot["sequence"], 1);
+    assert_eq!(snapshot["bids"][0]["quantity"], 5);
+
+    // 以降は変化した価格帯だけが、sequence が連続する差分で届く
+    place(&engine, 2, dec!(101), 3, Side::Sell).await;
+    let delta = next_json(&mut ws).await;
+    assert_eq!(delta["type"], "delta");
+    assert_eq!((delta["prev_sequence"].as_u64(), delta["sequence"].as_u64()), (Some(1), Some(2)));
+    assert_eq!(delta["bids"], json!([]));
+    assert_eq!(delta["asks"], json!([{ "price": "101", "quantity": 3, "order_count": 1 }]));
+
+    // 約定で消えた価格帯は数量0で届く
+    place(&engine, 3, dec!(99), 5, Side::Sell).await;
+    let delta = next_json(&mut ws).await;
+    assert_eq!(delta["prev_sequence"], 2);
+    assert_eq!(delta["bids"], json!([{ "price": "99", "quantity": 0, "order_count": 0 }]));
+}
+
+#[tokio::test]
+async fn test_subscription_acks_and_errors() {
+    let (addr, _, _) = start_server(WsConfig::default()).await;
+    let mut ws = connect(addr).await;
+
+    // 応答にはリクエストの id と、candles なら時間足が付く
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "candles", "interval": "5m", "id": 7 })).await;
+    assert_eq!(ack, json!({ "type": "subscribed", "channel": "candles", "interval": "5m", "id": 7 }));
+    // 時間足が違えば別の購読
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "candles", "id": 8 })).await;
+    assert_eq!(ack["interval"], "1m");
+
+    let err = request(&mut ws, json!({ "op": "subscribe", "channel": "candles", "interval": "5m", "id": 9 })).await;
+    assert_eq!((err["type"].as_str(), err["channel"].as_str(), err["id"].as_u64()), (Some("error"), Some("candles"), Some(9)));
+
+    let err = request(&mut ws, json!({ "op": "subscribe", "channel": "orders", "id": 10 })).await;
+    assert_eq!((err["type"].as_str(), err["channel"].as_str()), (Some("error"), Some("orders")));
+
+    // user チャネルはログインが必要
+    let err = request(&mut ws, json!({ "op": "subscribe", "channel": "user" })).await;
+    assert_eq!(err["type"], "error");
+    let err = request(&mut ws, json!({ "op": "subscribe", "channel": "user", "token": "invalid" })).await;
+    assert_eq!(err["type"], "error");
+
+    let ack = request(&mut ws, json!({ "op": "unsubscribe", "channel": "candles", "interval": "5m", "id": 11 })).await;
+    assert_eq!(ack, json!({ "type": "unsubscribed", "channel": "candles", "interval": "5m", "id": 11 }));
+    let err = request(&mut ws, json!({ "op": "unsubscribe", "channel": "candles", "interval": "5m" })).await;
+    assert_eq!(err["type"], "error");
+
+    // 解釈できないリクエストでも接続は切らない
+    ws.send(Message::Text("not json".into())).await.unwrap();
+    assert_eq!(next_json(&mut ws).await["type"], "error");
+    assert_eq!(request(&mut ws, json!({ "op": "ping", "id": 12 })).await, json!({ "type": "pong", "id": 12 }));
+}
+
+#[tokio::test]
+async fn test_market_channels_stream_updates() {
+    let (addr, engine, _) = start_server(WsConfig::default()).await;
+    place(&engine, 1, dec!(100), 5, Side::Sell).await;
+
+    let mut ws = connect(addr).await;
+    // depth・ticker は購読直後に現在の状態が届く
+    subscribe(&mut ws, "depth").await;
+    let depth = next_json(&mut ws).await;
+    assert_eq!(depth["type"], "depth");
+    assert_eq!(depth["asks"][0]["cumulative_quantity"], 5);
+    subscribe(&mut ws, "ticker").await;
+    let ticker = next_json(&mut ws).await;
+    assert_eq!((ticker["type"].as_str(), ticker["best_ask"].as_str()), (Some("ticker"), Some("100")));
+    subscribe(&mut ws, "trades").await;
+    subscribe(&mut ws, "candles").await;
+
+    place(&engine, 2, dec!(100), 2, Side::Buy).await;
+    let mut messages = std::collections::HashMap::new();
+    for _ in 0..4 {
+        let msg = next_json(&mut ws).await;
+        messages.insert(msg["type"].as_str().unwrap().to_string(), msg);
+    }
+    assert_eq!(messages["candle"]["interval"], "1m");
+    assert_eq!(messages["candle"]["volume"], 2);
+    assert_eq!(messages["depth"]["asks"][0]["quantity"], 3);
+    assert_eq!(messages["trade"]["quantity"], 2);
+    assert_eq!(messages["trade"]["taker_side"], "Buy");
+    assert_eq!(messages["ticker"]["last_price"], "100");
+
+    // 購読をやめたチャネルは届かなくなる
+    for channel in ["depth", "ticker", "trades"] {
+        assert_eq!(request(&mut ws, json!({ "op": "unsubscribe", "channel": channel })).await["type"], "unsubscribed");
+    }
+    place(&engine, 3, dec!(100), 1, Side::Buy).await;
+    assert_eq!(next_json(&mut ws).await["type"], "candle");
+}
+
+#[tokio::test]
+async fn test_trades_are_streamed_one_by_one_without_throttling() {
+    let (addr, engine, _) = start_server(WsConfig::default()).await;
+    let mut ws = connect(addr).await;
+    subscribe(&mut ws, "trades").await;
+
+    // 板の配信間隔（50ms）より短い間隔で約定させても、全ての約定が1件ずつ届く
+    place_now(&engine, 1, dec!(100), 10, Side::Sell).await;
+    place_now(&engine, 2, dec!(101), 10, Side::Sell).await;
+    place_now(&engine, 3, dec!(101), 12, Side::Buy).await; // 100 と 101 の2件
+    place_now(&engine, 4, dec!(99), 3, Side::Sell).await;
+    place_now(&engine, 5, dec!(101), 1, Side::Buy).await; // 最良の売りは 99
+
+    let mut trades = Vec::new();
+    for _ in 0..3 {
+        trades.push(next_json(&mut ws).await);
+    }
+    let summary: Vec<_> = trades
+        .iter()
+        .map(|t| (t["id"].as_u64().unwrap(), t["price"].as_str().unwrap(), t["quantity"].as_u64().unwrap(), t["taker_side"].as_str().unwrap()))
+        .collect();
+    assert_eq!(summary, [(1, "100", 10, "Buy"), (2, "101", 2, "Buy"), (3, "99", 1, "Buy")]);
+
+    // 公開の約定には注文IDも所有者も含まない
+    let keys: Vec<&str> = trades[0].as_object().unwrap().keys().map(|k| k.as_str()).collect();
+    assert_eq!(keys, ["id", "price", "quantity", "taker_side", "timestamp", "type"]);
+}
+
+/// 次のデータフレームを、JSONならそのまま、MessagePackなら配列の値にして読む
+///
+/// (Binaryで届いたか, 値) を返す
+async fn next_frame(ws: &mut Client) -> (bool, Value) {
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(2), ws.next())
+            .await
+            .expect("メッセージが届かない")
+            .unwrap()
+            .unwrap();
+        match msg {
+            Message::Text(text) => return (false, serde_json::from_str(&text).unwrap()),
+            Message::Binary(bytes) => return (true, rmp_serde::from_slice(&bytes).unwrap()),
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_msgpack_is_negotiated_by_subprotocol_or_subscribe_param() {
+    let (addr, engine, _) = start_server(WsConfig::default()).await;
+    place(&engine, 1, dec!(99.5), 5, Side::Buy).await;
+
+    let mut req = format!("ws://{}/ws", addr).into_client_request().unwrap();
+    req.headers_mut().insert("Sec-WebSocket-Protocol", "badbit.msgpack".parse().unwrap());
+    let (mut ws, response) = connect_async(req).await.unwrap();
+    assert_eq!(response.headers()["sec-websocket-protocol"], "badbit.msgpack");
+
+    // 応答はJSONのまま、配信はサブプロトコルで選んだMessagePackで届く
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "diff_depth" })).await;
+    assert_eq!(ack, json!({ "type": "subscribed", "channel": "diff_depth", "encoding": "msgpack" }));
+    let (binary, snapshot) = next_frame(&mut ws).await;
+    assert!(binary);
+    assert_eq!(snapshot, json!(["snapshot", 1, [[9_950_000_000u64, 5, 1]], []]));
+
+    // 購読ごとの指定が接続の既定より優先される
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "trades", "encoding": "json" })).await;
+    assert_eq!(ack["encoding"], "json");
+    // 形式を選べないチャネルは、既定がMessagePackでもJSONで届き、明示的な指定はエラー
+    let err = request(&mut ws, json!({ "op": "subscribe", "channel": "ticker", "encoding": "msgpack" })).await;
+    assert_eq!(err["type"], "error");
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "ticker" })).await;
+    assert_eq!(ack, json!({ "type": "subscribed", "channel": "ticker" }));
+    let (binary, ticker) = next_frame(&mut ws).await;
+    assert_eq!((binary, ticker["type"].as_str()), (false, Some("ticker")));
+
+    place(&engine, 2, dec!(99.5), 2, Side::Sell).await;
+    let mut frames = std::collections::HashMap::new();
+    for _ in 0..3 {
+        let (binary, msg) = next_frame(&mut ws).await;
+        let kind = if binary { &msg[0] } else { &msg["type"] };
+        frames.insert(kind.as_str().unwrap().to_string(), (binary, msg));
+    }
+    assert!(frames["delta"].0);
+    assert_eq!(frames["delta"].1[3], json!([[9_950_000_000u64, 3, 1]]));
+    assert!(!frames["trade"].0);
+    assert_eq!(frames["trade"].1["price"], "99.5");
+    assert!(!frames["ticker"].0);
+}
+
+/// 登録してログインし、トークンを返す
+async fn login(app: &Router, username: &str) -> String {
+    let creds = json!({ "username": username, "password": "correct horse" });
+    for uri in ["/auth/register", "/auth/login"] {
+        let req = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(creds.to_string()))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        let body: Value = serde_json::from_slice(&to_bytes(res.into_body(), usize::MAX).await.unwrap()).unwrap();
+        if let Some(token) = body["token"].as_str() {
+            return token.to_string();
+        }
+    }
+    panic!("ログインできない");
+}
+
+async fn place_as(app: &Router, token: &str, body: Value) {
+    let req = Request::post("/order")
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    assert!(app.clone().oneshot(req).await.unwrap().status().is_success());
+}
+
+#[tokio::test]
+async fn test_user_channel_only_sends_own_orders() {
+    let (addr, _, app) = start_server(WsConfig::default()).await;
+    let alice = login(&app, "alice").await;
+    let bob = login(&app, "bob").await;
+
+    // 接続時の ?token= でも、subscribe の token でも認証できる
+    let (mut alice_ws, _) = connect_async(format!("ws://{}/ws?token={}", addr, alice)).await.unwrap();
+    subscribe(&mut alice_ws, "user").await;
+    let mut bob_ws = connect(addr).await;
+    let ack = request(&mut bob_ws, json!({ "op": "subscribe", "channel": "user", "token": bob })).await;
+    assert_eq!(ack["type"], "subscribed");
+
+    // 残高のロック → 受付の約定レポート
+    place_as(&app, &alice, json!({ "price": "100", "quantity": 2, "side": "Buy" })).await;
+    let balance = next_json(&mut alice_ws).await;
+    assert_eq!((balance["type"].as_str(), balance["asset"].as_str()), (Some("balance"), Some("USDC")));
+    assert_eq!((balance["available"].as_str(), balance["locked"].as_str()), (Some("9800"), Some("200")));
+    let report = next_json(&mut alice_ws).await;
+    assert_eq!((report["type"].as_str(), report["status"].as_str()), (Some("execution_report"), Some("New")));
+    assert_eq!(report["quantity"], 2);
+
+    place_as(&app, &bob, json!({ "price": "90", "quantity": 1, "side": "Buy" })).await;
+    assert_eq!(next_json(&mut bob_ws).await["type"], "balance");
+    assert_eq!(next_json(&mut bob_ws).await["price"], "90");
+    // bob の注文は alice に届かない
+    assert!(tokio::time::timeout(Duration::from_millis(200), alice_ws.next()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_heartbeat_and_idle_timeout() {
+    let config = WsConfig { ping_interval: Duration::from_millis(50), idle_timeout: Duration::from_millis(300) };
+    let (addr, _, _) = start_server(config).await;
+
+    // 読み続けていればPingに自動でPongを返すので、切断されない
+    let mut ws = connect(addr).await;
+    let mut pings = 0;
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(500);
+    while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, ws.next()).await {
+        assert!(matches!(msg.unwrap(), Message::Ping(_)));
+        pings += 1;
+    }
+    assert!(pings >= 5);
+    assert_eq!(request(&mut ws, json!({ "op": "ping" })).await, json!({ "type": "pong" }));
+
+    // 何も返さないクライアントは idle_timeout 後に切られる
+    // （溜まったPingにPongを返そうとした時点で切断済みのこともあるので、終了だけを確認する）
+    let mut silent = connect(addr).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    loop {
+        let msg = tokio::time::timeout(Duration::from_secs(1), silent.next()).await.expect("切断されない");
+        match msg {
+            Some(Ok(Message::Close(frame))) => assert_eq!(frame.unwrap().reason, "idle timeout"),
+            Some(Ok(_)) => {}
+            Some(Err(_)) | None => break,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_user_channel_resumes_after_reconnect() {
+    let (addr, engine, app) = start_server(WsConfig::default()).await;
+    let alice = login(&app, "alice").await;
+    let url = format!("ws://{}/ws?token={}", addr, alice);
+
+    let (mut ws, _) = connect_async(&url).await.unwrap();
+    subscribe(&mut ws, "user").await;
+    place_as(&app, &alice, json!({ "price": "100", "quantity": 2, "side": "Buy" })).await;
+    assert_eq!(next_json(&mut ws).await["type"], "balance");
+    let report = next_json(&mut ws).await;
+    let token = report["resume_token"].as_str().unwrap().to_string();
+    let order_id = report["id"].as_u64().unwrap();
+    drop(ws);
+
+    // 切断中に一部約定し、残りをキャンセルする
+    place(&engine, 900, dec!(100), 1, Side::Sell).await;
+    let req = Request::delete(format!("/order/{}", order_id))
+        .header("Authorization", format!("Bearer {}", alice))
+        .body(Body::empty())
+        .unwrap();
+    assert!(app.clone().oneshot(req).await.unwrap().status().is_success());
+
+    // 最後に受け取った resume_token から再開すると、取りこぼした分が順に届く
+    let (mut ws, _) = connect_async(&url).await.unwrap();
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "user", "resume": token })).await;
+    assert_eq!(ack["resumed"], true);
+    let mut missed = Vec::new();
+    for _ in 0..5 {
+        let event = next_json(&mut ws).await;
+        missed.push(event["status"].as_str().or(event["asset"].as_str()).unwrap().to_string());
+    }
+    assert_eq!(missed, ["USDC", "BAD", "PartiallyFilled", "USDC", "Cancelled"]);
+    assert!(tokio::time::timeout(Duration::from_millis(200), ws.next()).await.is_err());
+
+    // 再開できないトークンなら resumed: false（RESTで取り直す）
+    let ack = request(&mut ws, json!({ "op": "unsubscribe", "channel": "user" })).await;
+    assert_eq!(ack["type"], "unsubscribed");
+    let ack = request(&mut ws, json!({ "op": "subscribe", "channel": "user", "resume": "0.1" })).await;
+    assert_eq!((ack["type"].as_str(), ack["resumed"].as_bool()), (Some("subscribed"), Some(false)));
+}
@@ -8,9 +8,11 @@ import PriceChart from "@/components/PriceChart";
 import { Settings, ChevronDown } from "lucide-react";
 import { useOrderBook } from "@/hooks/useOrderBook";
 import { useMarketData } from "@/hooks/useMarketData";
+import { useCandles } from "@/hooks/useCandles";
 
 import OpenOrders from "@/components/OpenOrders";
 import AssetsDisplay from "@/components/AssetsDisplay";
+import AuthPanel from "@/components/AuthPanel";
 import { useMyOrders } from "@/hooks/useMyOrders";
 
 import MyTradeHistory from "@/components/MyTradeHistory";
@@ -19,17 +21,12 @@ export default function Home() {
   const { orderBook } = useOrderBook();
   const { trades, marketStats } = useMarketData();
   const { myOrders } = useMyOrders();
+  const { candles } = useCandles("1m");
   const [activeTab, setActiveTab] = useState<"book" | "trades">("book");
   const [activeBottomTab, setActiveBottomTab] = useState<
     "balances" | "orders" | "history"
   >("balances");
 
-  const chartTrades = [...trades].reverse().map((t) => ({
-    price: parseFloat(t.price), // Decimal文字列を数値に変換
-    volume: t.quantity,
-    timestamp: Number(t.timestamp),
-  }));
-
   const {
     currentPrice,
     priceChange,
@@ -86,9 +83,7 @@ export default function Home() {
         </div>
 
         <div className="flex items-center gap-4">
-          <button className="text-xs bg-[#26E8A6] text-black font-bold px-4 py-1.5 rounded hover:bg-[#20c990] transition-colors">
-            Connect Wallet
-          </button>
+          <AuthPanel />
           <Settings className="w-4 h-4 text-zinc-500 cursor-pointer hover:text-white" />
         </div>
       </nav>
@@ -116,7 +111,7 @@ export default function Home() {
 
             {/* Chart Container */}
             <div className="flex-1 min-h-0 w-full">
-              <PriceChart trades={chartTrades} />
+              <PriceChart candles={candles} />
             </div>
           </div>
 
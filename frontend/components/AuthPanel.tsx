@@ -0,0 +1,79 @@
+"use client";
+
+import { useState } from "react";
+import { useAuth } from "@/hooks/useAuth";
+
+export default function AuthPanel() {
+  const { session, error, login, register, logout } = useAuth();
+  const [open, setOpen] = useState(false);
+  const [username, setUsername] = useState("");
+  const [password, setPassword] = useState("");
+
+  if (session) {
+    return (
+      <div className="flex items-center gap-3 text-xs">
+        <span className="text-zinc-300 font-mono">{session.username}</span>
+        <button
+          onClick={logout}
+          className="text-zinc-500 hover:text-white transition-colors"
+        >
+          Logout
+        </button>
+      </div>
+    );
+  }
+
+  const submit = async (mode: "login" | "register") => {
+    const ok =
+      mode === "login"
+        ? await login(username, password)
+        : await register(username, password);
+    if (ok) {
+      setPassword("");
+      setOpen(false);
+    }
+  };
+
+  return (
+    <div className="relative">
+      <button
+        onClick={() => setOpen(!open)}
+        className="text-xs bg-[#26E8A6] text-black font-bold px-4 py-1.5 rounded hover:bg-[#20c990] transition-colors"
+      >
+        Log In
+      </button>
+      {open && (
+        <div className="absolute right-0 mt-2 w-64 bg-[#161a1e] border border-zinc-800 rounded p-3 flex flex-col gap-2 z-50">
+          <input
+            value={username}
+            onChange={(e) => setUsername(e.target.value)}
+            placeholder="Username"
+            className="bg-zinc-900 border border-zinc-800 rounded px-2 py-1 text-xs text-white"
+          />
+          <input
+            type="password"
+            value={password}
+            onChange={(e) => setPassword(e.target.value)}
+            placeholder="Password"
+            className="bg-zinc-900 border border-zinc-800 rounded px-2 py-1 text-xs text-white"
+          />
+          {error && <span className="text-[10px] text-[#F6465D]">{error}</span>}
+          <div className="flex gap-2">
+            <button
+              onClick={() => submit("login")}
+              className="flex-1 text-xs bg-[#26E8A6] text-black font-bold py-1 rounded"
+            >
+              Log In
+            </button>
+            <button
+              onClick={() => submit("register")}
+              className="flex-1 text-xs border border-zinc-700 text-zinc-300 py-1 rounded"
+            >
+              Sign Up
+            </button>
+          </div>
+        </div>
+      )}
+    </div>
+  );
+}
@@ -25,7 +25,7 @@ export default function MyTradeHistory() {
           </tr>
         </thead>
         <tbody>
-          {myTrades.map((trade, i) => {
+          {myTrades.map((trade) => {
             const price = parseFloat(trade.price);
             const value = price * trade.quantity;
             const time = new Date(trade.timestamp).toLocaleTimeString();
@@ -35,7 +35,7 @@ export default function MyTradeHistory() {
 
             return (
               <tr
-                key={i} // trade.idがないのでindex (本来はidが必要)
+                key={trade.id}
                 className="hover:bg-white/5 transition-colors border-b border-white/5 last:border-0"
               >
                 <td className="px-4 py-2 text-right font-mono text-white">
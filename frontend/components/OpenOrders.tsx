@@ -29,7 +29,8 @@ export default function OpenOrders() {
         <tbody>
           {myOrders.map((order) => {
             const price = parseFloat(order.price);
-            const value = price * order.quantity;
+            const remaining = order.quantity - order.filled_quantity;
+            const value = price * remaining;
             const isBuy = order.side === "Buy";
 
             return (
@@ -46,7 +47,7 @@ export default function OpenOrders() {
                   {price.toFixed(2)}
                 </td>
                 <td className="px-4 py-2 text-right font-mono text-zinc-300">
-                  {order.quantity}
+                  {remaining}
                 </td>
                 <td className="px-4 py-2 text-right font-mono text-zinc-400">
                   {value.toFixed(2)}
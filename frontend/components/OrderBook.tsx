@@ -7,27 +7,19 @@ interface Props {
 }
 
 export default function OrderBook({ data }: Props) {
-  // Sort Asks: Lowest Price (Best Ask) at the bottom
-  const sortedAsks = Object.entries(data.asks)
-    .sort((a, b) => parseFloat(b[0]) - parseFloat(a[0])) // Descending (High -> Low)
-    .slice(-7); // Take the last 7 (which are the lowest prices)
+  // Asks arrive Low -> High: take the 7 best and flip so the Best Ask is at the bottom
+  const sortedAsks = data.asks.slice(0, 7).reverse(); // Descending (High -> Low)
 
-  // Sort Bids: Highest Price (Best Bid) at the top
-  const sortedBids = Object.entries(data.bids)
-    .sort((a, b) => parseFloat(b[0]) - parseFloat(a[0])) // Descending (High -> Low)
-    .slice(0, 7);
+  // Bids arrive High -> Low (Best Bid first)
+  const sortedBids = data.bids.slice(0, 7);
 
   // Calculate sizes and find max size for bars
-  const askSizes = sortedAsks.map(([, orders]) =>
-    orders.reduce((acc, o) => acc + o.quantity, 0),
-  );
-  const bidSizes = sortedBids.map(([, orders]) =>
-    orders.reduce((acc, o) => acc + o.quantity, 0),
-  );
+  const askSizes = sortedAsks.map((level) => level.quantity);
+  const bidSizes = sortedBids.map((level) => level.quantity);
   const maxSize = Math.max(...askSizes, ...bidSizes, 1);
 
-  const bestAsk = parseFloat(sortedAsks[sortedAsks.length - 1]?.[0] || "0");
-  const bestBid = parseFloat(sortedBids[0]?.[0] || "0");
+  const bestAsk = parseFloat(sortedAsks[sortedAsks.length - 1]?.price || "0");
+  const bestBid = parseFloat(sortedBids[0]?.price || "0");
   const spread = bestAsk && bestBid ? (bestAsk - bestBid).toFixed(3) : "0.000";
   const spreadPercent =
     bestAsk && bestBid
@@ -65,7 +57,7 @@ export default function OrderBook({ data }: Props) {
       {/* Asks (Sell) - Rendered from Top (High) to Bottom (Low/Best) */}
       <div className="flex-1 overflow-hidden relative">
         <div className="absolute inset-0 flex flex-col justify-end">
-          {sortedAsks.map(([price], index) => {
+          {sortedAsks.map(({ price }, index) => {
             const size = askSizes[index];
             // Accumulate from bottom (best ask) up to current index
             // sortedAsks is High -> Low. Best is at the end.
@@ -118,7 +110,7 @@ export default function OrderBook({ data }: Props) {
 
       {/* Bids (Buy) - Rendered from Top (High/Best) to Bottom (Low) */}
       <div className="flex-1 overflow-hidden">
-        {sortedBids.map(([price], index) => {
+        {sortedBids.map(({ price }, index) => {
           const size = bidSizes[index];
           // Accumulate from top (best bid) down to current index
           // sortedBids is High -> Low. Best is at start (index 0).
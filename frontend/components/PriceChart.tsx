@@ -12,12 +12,13 @@ import {
   HistogramSeries,
   IChartApi,
 } from "lightweight-charts";
+import { Candle } from "@/types";
 
 interface Props {
-  trades: { price: number; volume: number; timestamp: number }[];
+  candles: Candle[];
 }
 
-export default function PriceChart({ trades }: Props) {
+export default function PriceChart({ candles }: Props) {
   const chartContainerRef = useRef<HTMLDivElement>(null);
   const candlestickSeriesRef = useRef<ISeriesApi<"Candlestick">>(null);
   const volumeSeriesRef = useRef<ISeriesApi<"Histogram">>(null);
@@ -97,61 +98,32 @@ export default function PriceChart({ trades }: Props) {
     if (
       !candlestickSeriesRef.current ||
       !volumeSeriesRef.current ||
-      trades.length === 0
+      candles.length === 0
     )
       return;
 
-    const candles: Record<number, CandlestickData<Time>> = {};
-    const volumes: Record<number, HistogramData<Time>> = {};
-
-    const sortedTrades = [...trades].sort((a, b) => a.timestamp - b.timestamp);
-
-    sortedTrades.forEach((t) => {
-      const minute = Math.floor(t.timestamp / 60000) * 60;
-
-      // Aggregate Candles
-      if (!candles[minute]) {
-        candles[minute] = {
-          time: minute as Time,
-          open: t.price,
-          high: t.price,
-          low: t.price,
-          close: t.price,
-        };
-      } else {
-        const c = candles[minute];
-        c.high = Math.max(c.high, t.price);
-        c.low = Math.min(c.low, t.price);
-        c.close = t.price;
-      }
-
-      // Aggregate Volume
-      if (!volumes[minute]) {
-        volumes[minute] = {
-          time: minute as Time,
-          value: t.volume,
-          color: "#26a69a", // Default color, will update based on price action
-        };
-      } else {
-        volumes[minute].value += t.volume;
-      }
-    });
-
-    // Update Volume Colors based on Candle content
-    const candleData = Object.values(candles);
-    const volumeData = Object.values(volumes).map((v) => {
-      const candle = candles[v.time as number];
-      // If candle close >= open, green volume, else red
-      const isUp = candle.close >= candle.open;
-      return {
-        ...v,
-        color: isUp ? "rgba(38, 232, 166, 0.5)" : "rgba(255, 83, 83, 0.5)",
-      };
-    });
+    // サーバーで集計済みの足をそのまま描画する（時刻は秒単位）
+    const candleData: CandlestickData<Time>[] = candles.map((c) => ({
+      time: (c.open_time / 1000) as Time,
+      open: parseFloat(c.open),
+      high: parseFloat(c.high),
+      low: parseFloat(c.low),
+      close: parseFloat(c.close),
+    }));
+
+    // 出来高バーの色は足の陽線/陰線に合わせる
+    const volumeData: HistogramData<Time>[] = candles.map((c) => ({
+      time: (c.open_time / 1000) as Time,
+      value: c.volume,
+      color:
+        parseFloat(c.close) >= parseFloat(c.open)
+          ? "rgba(38, 232, 166, 0.5)"
+          : "rgba(255, 83, 83, 0.5)",
+    }));
 
     candlestickSeriesRef.current.setData(candleData);
     volumeSeriesRef.current.setData(volumeData);
-  }, [trades]);
+  }, [candles]);
 
   return <div ref={chartContainerRef} className="w-full h-full min-h-100" />;
 }
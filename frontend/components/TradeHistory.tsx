@@ -1,9 +1,9 @@
 "use client";
 
-import { Trade } from "@/types";
+import { PublicTrade } from "@/types";
 
 interface Props {
-  trades: Trade[];
+  trades: PublicTrade[];
 }
 
 export default function TradeHistory({ trades }: Props) {
@@ -15,7 +15,7 @@ export default function TradeHistory({ trades }: Props) {
         <span className="text-right">Time</span>
       </div>
       <div className="flex-1 overflow-y-auto custom-scrollbar">
-        {trades.map((trade, i) => {
+        {trades.map((trade) => {
           const date = new Date(Number(trade.timestamp));
           const timeStr = date.toLocaleTimeString([], {
             hour12: false,
@@ -23,12 +23,11 @@ export default function TradeHistory({ trades }: Props) {
             minute: "2-digit",
             second: "2-digit",
           });
-          // Mock side logic logic since we don't have it in Trade yet clearly
-          const isBuy = trade.maker_id < trade.taker_id;
+          const isBuy = trade.taker_side === "Buy";
 
           return (
             <div
-              key={i}
+              key={trade.id}
               className="grid grid-cols-3 px-2 py-0.5 hover:bg-white/5 cursor-pointer"
             >
               <span
@@ -0,0 +1,70 @@
+import { useState, useEffect, useCallback } from "react";
+import { Session, getSession, saveSession, clearSession, authFetch } from "@/lib/auth";
+
+export function useAuth() {
+  const [session, setSession] = useState<Session | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  // 他のコンポーネントでのログイン・ログアウトにも追従する
+  useEffect(() => {
+    const sync = () => setSession(getSession());
+    sync();
+    window.addEventListener("badbit-auth", sync);
+    return () => window.removeEventListener("badbit-auth", sync);
+  }, []);
+
+  const login = useCallback(async (username: string, password: string) => {
+    setError(null);
+    try {
+      const res = await fetch("http://localhost:8000/auth/login", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ username, password }),
+      });
+      const data = await res.json();
+      if (!res.ok) {
+        setError(data.error ?? "ログインに失敗しました");
+        return false;
+      }
+      saveSession(data);
+      return true;
+    } catch (err) {
+      console.error("Login error:", err);
+      setError("サーバー接続エラー");
+      return false;
+    }
+  }, []);
+
+  const register = useCallback(
+    async (username: string, password: string) => {
+      setError(null);
+      try {
+        const res = await fetch("http://localhost:8000/auth/register", {
+          method: "POST",
+          headers: { "Content-Type": "application/json" },
+          body: JSON.stringify({ username, password }),
+        });
+        if (!res.ok) {
+          const data = await res.json();
+          setError(data.error ?? "登録に失敗しました");
+          return false;
+        }
+        return login(username, password);
+      } catch (err) {
+        console.error("Register error:", err);
+        setError("サーバー接続エラー");
+        return false;
+      }
+    },
+    [login],
+  );
+
+  const logout = useCallback(async () => {
+    await authFetch("http://localhost:8000/auth/logout", { method: "POST" }).catch(
+      () => undefined,
+    );
+    clearSession();
+  }, []);
+
+  return { session, error, login, register, logout };
+}
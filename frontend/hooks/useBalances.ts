@@ -1,5 +1,7 @@
 import { useState, useEffect } from "react";
 import { BalanceResponse } from "@/types";
+import { authFetch, getSession } from "@/lib/auth";
+import { subscribeUserEvents } from "@/lib/userStream";
 
 export function useBalances() {
   const [balances, setBalances] = useState<BalanceResponse>({
@@ -11,8 +13,9 @@ export function useBalances() {
 
   useEffect(() => {
     const fetchBalances = async () => {
+      if (!getSession()) return; // 未ログイン
       try {
-        const res = await fetch("http://localhost:8000/balance");
+        const res = await authFetch("http://localhost:8000/balance");
         if (res.ok) {
           const data = await res.json();
           setBalances(data);
@@ -23,8 +26,20 @@ export function useBalances() {
     };
 
     fetchBalances(); // 初回実行
-    const interval = setInterval(fetchBalances, 1000); // 1秒ごとに更新
-    return () => clearInterval(interval);
+
+    // 以降は user チャネルで届く残高変化を反映する
+    return subscribeUserEvents((event) => {
+      if (event.type === "resync") {
+        fetchBalances();
+      } else if (event.type === "balance") {
+        const prefix = event.asset === "USDC" ? "usdc" : "bad";
+        setBalances((prev) => ({
+          ...prev,
+          [`${prefix}_available`]: event.available,
+          [`${prefix}_locked`]: event.locked,
+        }));
+      }
+    });
   }, []);
 
   return balances;
@@ -0,0 +1,62 @@
+import { useState, useEffect } from "react";
+import { Candle, CandleInterval } from "@/types";
+
+// 同じ足（open_time）なら置き換え、新しい足なら末尾に追加する
+function mergeCandle(candles: Candle[], candle: Candle): Candle[] {
+  const last = candles[candles.length - 1];
+  if (last && last.open_time === candle.open_time) {
+    return [...candles.slice(0, -1), candle];
+  }
+  if (last && last.open_time > candle.open_time) {
+    return candles; // 古い更新は無視
+  }
+  return [...candles, candle];
+}
+
+export function useCandles(interval: CandleInterval = "1m") {
+  const [candles, setCandles] = useState<Candle[]>([]);
+
+  useEffect(() => {
+    let cancelled = false;
+
+    // 1. 過去の足をREST APIで取得
+    const fetchCandles = async () => {
+      try {
+        const res = await fetch(
+          `http://localhost:8000/candles?interval=${interval}&limit=500`,
+        );
+        if (res.ok && !cancelled) {
+          const history: Candle[] = await res.json();
+          // 取得中にWebSocketで届いた更新も残す
+          setCandles((current) =>
+            current.reduce(mergeCandle, history),
+          );
+        }
+      } catch (err) {
+        console.error("Candles fetch error:", err);
+      }
+    };
+
+    // 2. 形成中の足をWebSocketで受け取る
+    const ws = new WebSocket(
+      `ws://localhost:8000/ws/candles?interval=${interval}`,
+    );
+    ws.onmessage = (event) => {
+      try {
+        const candle: Candle = JSON.parse(event.data);
+        setCandles((current) => mergeCandle(current, candle));
+      } catch (e) {
+        console.error("Failed to parse candle message:", e);
+      }
+    };
+
+    fetchCandles();
+
+    return () => {
+      cancelled = true;
+      ws.close();
+    };
+  }, [interval]);
+
+  return { candles };
+}
@@ -1,5 +1,14 @@
 import { useState, useEffect } from "react";
-import { Trade } from "@/types";
+import { PublicTrade, Ticker } from "@/types";
+
+const MAX_TRADES = 500;
+
+// 約定IDで重複を除き、新しい順に並べる
+function mergeTrades(current: PublicTrade[], incoming: PublicTrade[]) {
+  const byId = new Map(current.map((t) => [t.id, t]));
+  for (const trade of incoming) byId.set(trade.id, trade);
+  return [...byId.values()].sort((a, b) => b.id - a.id).slice(0, MAX_TRADES);
+}
 
 export interface MarketStats {
   currentPrice: number;
@@ -10,7 +19,7 @@ export interface MarketStats {
 }
 
 export function useMarketData() {
-  const [trades, setTrades] = useState<Trade[]>([]);
+  const [trades, setTrades] = useState<PublicTrade[]>([]);
   const [marketStats, setMarketStats] = useState<MarketStats>({
     currentPrice: 0,
     priceChange: 0,
@@ -19,51 +28,77 @@ export function useMarketData() {
     startPrice: 0,
   });
 
+  // 約定は /ws の trades チャネルで1件ずつ受け取る
   useEffect(() => {
-    const fetchTrades = async () => {
-      try {
-        const tradesRes = await fetch("http://localhost:8000/trades");
+    let ws: WebSocket | null = null;
+    let closed = false;
+
+    const connect = () => {
+      const socket = new WebSocket("ws://localhost:8000/ws");
+      ws = socket;
+
+      socket.onopen = async () => {
+        socket.send(JSON.stringify({ op: "subscribe", channel: "trades" }));
+        // 購読してから履歴を取る（間の約定はIDで重複を除く）
+        try {
+          const res = await fetch("http://localhost:8000/trades");
+          if (res.ok) {
+            const history: PublicTrade[] = await res.json();
+            setTrades((current) => mergeTrades(current, history));
+          }
+        } catch (err) {
+          console.error("Trades fetch error:", err);
+        }
+      };
+
+      socket.onmessage = (event) => {
+        try {
+          const msg = JSON.parse(event.data);
+          if (msg.type === "trade") {
+            setTrades((current) => mergeTrades(current, [msg]));
+          }
+        } catch (e) {
+          console.error("Failed to parse trade message:", e);
+        }
+      };
+
+      socket.onclose = () => {
+        if (!closed) setTimeout(connect, 1000);
+      };
+    };
+
+    connect();
 
-        if (tradesRes.ok) {
-          const newTrades = await tradesRes.json();
-          const sortedTrades = [...newTrades].reverse();
-          setTrades(sortedTrades);
+    return () => {
+      closed = true;
+      ws?.close();
+    };
+  }, []);
 
-          // Calculate Market Stats
-          const currentPrice =
-            sortedTrades.length > 0 ? parseFloat(sortedTrades[0].price) : 0;
-          const now = Date.now();
-          const oneDayAgo = now - 24 * 60 * 60 * 1000;
-          const recentTrades = sortedTrades.filter(
-            (t: Trade) => t.timestamp > oneDayAgo,
-          );
-          const startPrice =
-            recentTrades.length > 0
-              ? parseFloat(recentTrades[recentTrades.length - 1].price)
-              : currentPrice;
-          const priceChange = currentPrice - startPrice;
-          const priceChangePercent =
-            startPrice > 0 ? (priceChange / startPrice) * 100 : 0;
-          const volume24h = recentTrades.reduce(
-            (acc: number, t: Trade) => acc + parseFloat(t.price) * t.quantity,
-            0,
-          );
+  useEffect(() => {
+    const fetchMarketData = async () => {
+      try {
+        const tickerRes = await fetch("http://localhost:8000/ticker");
 
+        // 24時間統計はサーバー側で集計済み
+        if (tickerRes.ok) {
+          const ticker: Ticker = await tickerRes.json();
+          const currentPrice = parseFloat(ticker.last_price ?? "0");
           setMarketStats({
             currentPrice,
-            priceChange,
-            priceChangePercent,
-            volume24h,
-            startPrice,
+            priceChange: parseFloat(ticker.price_change),
+            priceChangePercent: parseFloat(ticker.price_change_percent),
+            volume24h: parseFloat(ticker.quote_volume_24h),
+            startPrice: ticker.open_24h ? parseFloat(ticker.open_24h) : currentPrice,
           });
         }
       } catch (err) {
-        console.error("Trades Sync error:", err);
+        console.error("Market data sync error:", err);
       }
     };
 
-    fetchTrades();
-    const interval = setInterval(fetchTrades, 1000);
+    fetchMarketData();
+    const interval = setInterval(fetchMarketData, 1000);
     return () => clearInterval(interval);
   }, []);
 
@@ -1,35 +1,86 @@
-import { useOrderBook } from "./useOrderBook";
-import { useCallback } from "react";
+import { useState, useEffect, useCallback } from "react";
+import { ExecutionReport, OrderRecord } from "@/types";
+import { authFetch, getSession } from "@/lib/auth";
+import { subscribeUserEvents } from "@/lib/userStream";
 
-export function useMyOrders() {
-  const { orderBook } = useOrderBook();
-
-  // Extract orders that have a user_id (implying they belong to the user, not the simulator)
-  // Flatten calls: bids and asks are Record<price, Order[]>
-  const myBids = Object.values(orderBook.bids)
-    .flat()
-    .filter((o) => o.user_id);
-
-  const myAsks = Object.values(orderBook.asks)
-    .flat()
-    .filter((o) => o.user_id);
+// 約定レポートを注文の記録に戻す（平均約定価格は約定代金から計算する）
+function toRecord(report: ExecutionReport): OrderRecord {
+  return {
+    id: report.id,
+    user_id: report.user_id,
+    side: report.side,
+    order_type: report.order_type,
+    price: report.price,
+    quantity: report.quantity,
+    filled_quantity: report.filled_quantity,
+    filled_quote: report.filled_quote,
+    status: report.status,
+    created_at: report.created_at,
+    updated_at: report.updated_at,
+    avg_fill_price:
+      report.filled_quantity > 0
+        ? String(parseFloat(report.filled_quote) / report.filled_quantity)
+        : null,
+  };
+}
 
-  const myOrders = [...myBids, ...myAsks].sort((a, b) => b.id - a.id); // Newest first
+export function useMyOrders() {
+  const [myOrders, setMyOrders] = useState<OrderRecord[]>([]);
 
-  const cancelOrder = useCallback(async (orderId: number) => {
+  // 板情報には所有者が載らないので、自分の未約定注文はAPIから取得する
+  const fetchOrders = useCallback(async () => {
+    if (!getSession()) {
+      setMyOrders([]);
+      return;
+    }
     try {
-      const res = await fetch(`http://localhost:8000/order/${orderId}`, {
-        method: "DELETE",
-      });
-      if (!res.ok) {
-        throw new Error("Failed to cancel order");
+      const res = await authFetch("http://localhost:8000/orders/open");
+      if (res.ok) {
+        const data: OrderRecord[] = await res.json();
+        // APIは注文ID順なので、新しい順に並べ替える
+        setMyOrders([...data].reverse());
       }
-      // UI update will happen automatically via WebSocket orderbook update
     } catch (err) {
-      console.error("Cancel error:", err);
-      alert("Failed to cancel order");
+      console.error("Open orders fetch error:", err);
     }
   }, []);
 
+  useEffect(() => {
+    fetchOrders();
+
+    // 以降は約定レポートで差分更新する（終わった注文は一覧から外す）
+    return subscribeUserEvents((event) => {
+      if (event.type === "resync") {
+        fetchOrders();
+      } else if (event.type === "execution_report") {
+        const open = event.status === "New" || event.status === "PartiallyFilled";
+        setMyOrders((prev) => {
+          const rest = prev.filter((o) => o.id !== event.id);
+          return open
+            ? [toRecord(event), ...rest].sort((a, b) => b.id - a.id)
+            : rest;
+        });
+      }
+    });
+  }, [fetchOrders]);
+
+  const cancelOrder = useCallback(
+    async (orderId: number) => {
+      try {
+        const res = await authFetch(`http://localhost:8000/order/${orderId}`, {
+          method: "DELETE",
+        });
+        if (!res.ok) {
+          throw new Error("Failed to cancel order");
+        }
+        // 一覧からは Cancelled の約定レポートで外れる
+      } catch (err) {
+        console.error("Cancel error:", err);
+        alert("Failed to cancel order");
+      }
+    },
+    [],
+  );
+
   return { myOrders, cancelOrder };
 }
@@ -1,5 +1,7 @@
 import { useState, useEffect } from "react";
 import { Trade } from "@/types";
+import { authFetch, getSession } from "@/lib/auth";
+import { subscribeUserEvents } from "@/lib/userStream";
 
 export function useMyTrades() {
   const [myTrades, setMyTrades] = useState<Trade[]>([]);
@@ -7,11 +9,16 @@ export function useMyTrades() {
   useEffect(() => {
     // 最初のフェッチ
     const fetchTrades = async () => {
+      if (!getSession()) {
+        setMyTrades([]);
+        return;
+      }
       try {
-        const res = await fetch("http://localhost:8000/my-trades");
+        const res = await authFetch("http://localhost:8000/my-trades");
         if (res.ok) {
-          const data = await res.json();
-          setMyTrades(data);
+          const data: Trade[] = await res.json();
+          // APIは古い順なので、新しい順に並べ替える
+          setMyTrades([...data].reverse());
         }
       } catch (err) {
         console.error("My trades fetch error:", err);
@@ -20,11 +27,15 @@ export function useMyTrades() {
 
     fetchTrades();
 
-    // 定期ポーリング (3秒ごと)
-    // ※本来はWebSocketやSSEでリアルタイム通知を受けるのが理想
-    const interval = setInterval(fetchTrades, 3000);
-
-    return () => clearInterval(interval);
+    // 約定を含む約定レポートが届いたら取り直す
+    return subscribeUserEvents((event) => {
+      if (
+        event.type === "resync" ||
+        (event.type === "execution_report" && event.trade_id !== null)
+      ) {
+        fetchTrades();
+      }
+    });
   }, []);
 
   return { myTrades };
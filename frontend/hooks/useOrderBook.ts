@@ -1,34 +1,111 @@
 import { useState, useEffect } from "react";
-import { OrderBook as OrderBookType } from "@/types";
+import { OrderBook as OrderBookType, PriceLevel } from "@/types";
+
+// /ws の diff_depth チャネルから届くメッセージ（購読直後にsnapshot、以降はdelta）
+type BookMessage =
+  | ({ type: "snapshot" } & OrderBookType)
+  | {
+      type: "delta";
+      prev_sequence: number;
+      sequence: number;
+      bids: PriceLevel[];
+      asks: PriceLevel[];
+    }
+  | { type: "subscribed" | "unsubscribed"; channel: string }
+  | { type: "error"; channel?: string; message: string };
+
+// 差分を適用する（数量0はその価格帯の削除）
+function applyLevels(side: Map<string, PriceLevel>, levels: PriceLevel[]) {
+  for (const level of levels) {
+    if (level.quantity === 0) {
+      side.delete(level.price);
+    } else {
+      side.set(level.price, level);
+    }
+  }
+}
+
+function sortedLevels(side: Map<string, PriceLevel>, descending: boolean) {
+  return [...side.values()].sort((a, b) =>
+    descending
+      ? parseFloat(b.price) - parseFloat(a.price)
+      : parseFloat(a.price) - parseFloat(b.price),
+  );
+}
 
 export function useOrderBook() {
   const [orderBook, setOrderBook] = useState<OrderBookType>({
-    bids: {},
-    asks: {},
+    sequence: 0,
+    bids: [],
+    asks: [],
   });
 
   useEffect(() => {
-    const ws = new WebSocket("ws://localhost:8000/ws");
+    let ws: WebSocket | null = null;
+    let closed = false;
+    const bids = new Map<string, PriceLevel>();
+    const asks = new Map<string, PriceLevel>();
+    let sequence = -1; // スナップショットを受け取るまでは -1
 
-    ws.onopen = () => {
-      console.log("Connected to OrderBook WebSocket");
-    };
+    const connect = () => {
+      ws = new WebSocket("ws://localhost:8000/ws");
 
-    ws.onmessage = (event) => {
-      try {
-        const newBook: OrderBookType = JSON.parse(event.data);
-        setOrderBook(newBook);
-      } catch (e) {
-        console.error("Failed to parse WebSocket message:", e);
-      }
-    };
+      ws.onopen = () => {
+        console.log("Connected to OrderBook WebSocket");
+        ws?.send(JSON.stringify({ op: "subscribe", channel: "diff_depth" }));
+      };
+
+      ws.onmessage = (event) => {
+        try {
+          const msg: BookMessage = JSON.parse(event.data);
+          if (msg.type === "subscribed" || msg.type === "unsubscribed") return;
+          if (msg.type === "error") {
+            console.error("Subscription error:", msg.message);
+            return;
+          }
+          if (msg.type === "snapshot") {
+            bids.clear();
+            asks.clear();
+            applyLevels(bids, msg.bids);
+            applyLevels(asks, msg.asks);
+            sequence = msg.sequence;
+          } else {
+            if (sequence < 0 || msg.sequence <= sequence) return;
+            if (msg.prev_sequence > sequence) {
+              // 取りこぼし: 接続し直してスナップショットから取り直す
+              console.warn("OrderBook gap detected, resyncing...");
+              sequence = -1;
+              ws?.close();
+              return;
+            }
+            applyLevels(bids, msg.bids);
+            applyLevels(asks, msg.asks);
+            sequence = msg.sequence;
+          }
+          setOrderBook({
+            sequence,
+            bids: sortedLevels(bids, true),
+            asks: sortedLevels(asks, false),
+          });
+        } catch (e) {
+          console.error("Failed to parse WebSocket message:", e);
+        }
+      };
 
-    ws.onerror = (error) => {
-      console.error("WebSocket error:", error);
+      ws.onerror = (error) => {
+        console.error("WebSocket error:", error);
+      };
+
+      ws.onclose = () => {
+        if (!closed) setTimeout(connect, 1000);
+      };
     };
 
+    connect();
+
     return () => {
-      ws.close();
+      closed = true;
+      ws?.close();
     };
   }, []);
 
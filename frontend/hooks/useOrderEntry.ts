@@ -1,5 +1,6 @@
 import { useState, useEffect, useCallback } from "react";
 import { Side, Trade, BalanceResponse } from "@/types";
+import { authFetch, getSession } from "@/lib/auth";
 
 export const useOrderEntry = () => {
   const [price, setPrice] = useState("");
@@ -23,8 +24,9 @@ export const useOrderEntry = () => {
   // Balance fetching logic
   useEffect(() => {
     const fetchBalance = async () => {
+      if (!getSession()) return; // 未ログイン
       try {
-        const res = await fetch("http://localhost:8000/balance");
+        const res = await authFetch("http://localhost:8000/balance");
         if (res.ok) {
           const data = await res.json();
           setBalances(data);
@@ -47,7 +49,7 @@ export const useOrderEntry = () => {
     setLastResult(null);
 
     try {
-      const res = await fetch("http://localhost:8000/order", {
+      const res = await authFetch("http://localhost:8000/order", {
         method: "POST",
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({
@@ -98,7 +100,10 @@ export const useOrderEntry = () => {
       } else {
         setLastResult({
           type: "error",
-          message: "❌ 注文失敗: 残高不足の可能性があります",
+          message:
+            res.status === 401
+              ? "❌ ログインしてください"
+              : "❌ 注文失敗: 残高不足の可能性があります",
           trades: [],
         });
       }
@@ -0,0 +1,43 @@
+// ログインセッションの保存と、認証付きfetch
+
+const TOKEN_KEY = "badbit.token";
+const USER_KEY = "badbit.user";
+
+export interface Session {
+  token: string;
+  user_id: string;
+  username: string;
+}
+
+export function getSession(): Session | null {
+  if (typeof window === "undefined") return null;
+  const token = localStorage.getItem(TOKEN_KEY);
+  const user = localStorage.getItem(USER_KEY);
+  if (!token || !user) return null;
+  return { token, ...JSON.parse(user) };
+}
+
+export function saveSession(session: Session) {
+  localStorage.setItem(TOKEN_KEY, session.token);
+  localStorage.setItem(
+    USER_KEY,
+    JSON.stringify({ user_id: session.user_id, username: session.username }),
+  );
+  window.dispatchEvent(new Event("badbit-auth"));
+}
+
+export function clearSession() {
+  localStorage.removeItem(TOKEN_KEY);
+  localStorage.removeItem(USER_KEY);
+  window.dispatchEvent(new Event("badbit-auth"));
+}
+
+/// Authorizationヘッダーを付けてfetchする（401ならセッションを破棄）
+export async function authFetch(url: string, init: RequestInit = {}) {
+  const session = getSession();
+  const headers = new Headers(init.headers);
+  if (session) headers.set("Authorization", `Bearer ${session.token}`);
+  const res = await fetch(url, { ...init, headers });
+  if (res.status === 401 && session) clearSession();
+  return res;
+}
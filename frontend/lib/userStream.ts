@@ -0,0 +1,81 @@
+// /ws の user チャネル（約定レポート・残高変化）を1本の接続で共有する
+
+import { getSession } from "@/lib/auth";
+import { UserEvent } from "@/types";
+
+// "resync" は取りこぼしを取り戻せなかった（初回接続を含む）ので、RESTで取り直す合図
+export type UserStreamEvent = UserEvent | { type: "resync" };
+type Listener = (event: UserStreamEvent) => void;
+
+const listeners = new Set<Listener>();
+let ws: WebSocket | null = null;
+let resumeToken: string | null = null; // 最後に受け取ったイベントの resume_token
+
+function emit(event: UserStreamEvent) {
+  listeners.forEach((listener) => listener(event));
+}
+
+function connect() {
+  const session = getSession();
+  if (!session || listeners.size === 0 || ws) return;
+
+  const socket = new WebSocket(`ws://localhost:8000/ws?token=${session.token}`);
+  ws = socket;
+
+  socket.onopen = () => {
+    socket.send(
+      JSON.stringify({
+        op: "subscribe",
+        channel: "user",
+        ...(resumeToken ? { resume: resumeToken } : {}),
+      }),
+    );
+  };
+
+  socket.onmessage = (event) => {
+    try {
+      const msg = JSON.parse(event.data);
+      if (msg.type === "subscribed") {
+        if (msg.resumed !== true) emit({ type: "resync" });
+      } else if (msg.type === "execution_report" || msg.type === "balance") {
+        resumeToken = msg.resume_token;
+        emit(msg);
+      } else if (msg.type === "error") {
+        console.error("User stream error:", msg.message);
+      }
+    } catch (e) {
+      console.error("Failed to parse user stream message:", e);
+    }
+  };
+
+  socket.onclose = () => {
+    if (ws !== socket) return; // 自分で閉じた
+    ws = null;
+    setTimeout(connect, 1000);
+  };
+}
+
+function disconnect() {
+  const socket = ws;
+  ws = null;
+  socket?.close();
+}
+
+// ログイン・ログアウトしたら接続し直す（別ユーザーの resume_token は使わない）
+if (typeof window !== "undefined") {
+  window.addEventListener("badbit-auth", () => {
+    disconnect();
+    resumeToken = null;
+    connect();
+  });
+}
+
+/// 自分宛てのイベントを購読する（戻り値で購読解除）
+export function subscribeUserEvents(listener: Listener): () => void {
+  listeners.add(listener);
+  connect();
+  return () => {
+    listeners.delete(listener);
+    if (listeners.size === 0) disconnect();
+  };
+}
@@ -8,16 +8,60 @@ export interface Order {
   user_id?: string;
 }
 
+export type OrderStatus =
+  | "New"
+  | "PartiallyFilled"
+  | "Filled"
+  | "Cancelled"
+  | "Rejected"
+  | "Expired";
+
+// GET /orders/open などが返す自分の注文
+export interface OrderRecord {
+  id: number;
+  user_id: string;
+  side: Side;
+  order_type: "Limit" | "Market";
+  price: string; // Decimal string
+  quantity: number; // 発注時の数量
+  filled_quantity: number;
+  filled_quote: string;
+  status: OrderStatus;
+  created_at: number;
+  updated_at: number;
+  avg_fill_price: string | null;
+}
+
+// 板の1価格帯（所有者は含まない）
+export interface PriceLevel {
+  price: string; // Decimal string
+  quantity: number; // この価格の合計数量
+  order_count: number;
+}
+
+// bids は高い順、asks は安い順（最良気配が先頭）
 export interface OrderBook {
-  bids: Record<string, Order[]>;
-  asks: Record<string, Order[]>;
+  sequence: number; // 板が変化するたびに増える番号
+  bids: PriceLevel[];
+  asks: PriceLevel[];
 }
 
 export interface Trade {
+  id: number;
   maker_id: number;
   taker_id: number;
   price: string;
   quantity: number;
+  taker_side: Side;
+  timestamp: number;
+}
+
+// /ws の trades チャネルで1件ずつ届く公開の約定（注文IDは含まない）
+export interface PublicTrade {
+  id: number;
+  price: string;
+  quantity: number;
+  taker_side: Side;
   timestamp: number;
 }
 
@@ -27,3 +71,52 @@ export interface BalanceResponse {
   bad_available: string;
   bad_locked: string;
 }
+
+export type CandleInterval = "1m" | "5m" | "15m" | "1h" | "1d";
+
+export interface Candle {
+  interval: CandleInterval;
+  open_time: number; // ミリ秒
+  open: string;
+  high: string;
+  low: string;
+  close: string;
+  volume: number;
+  quote_volume: string;
+  trade_count: number;
+}
+
+export interface Ticker {
+  last_price: string | null;
+  best_bid: string | null;
+  best_ask: string | null;
+  open_24h: string | null;
+  high_24h: string | null;
+  low_24h: string | null;
+  base_volume_24h: number;
+  quote_volume_24h: string;
+  price_change: string;
+  price_change_percent: string;
+  trade_count_24h: number;
+  timestamp: number; // ミリ秒
+}
+
+// /ws の user チャネル: 注文の状態が変わるたびに届く約定レポート
+export interface ExecutionReport extends Omit<OrderRecord, "avg_fill_price"> {
+  type: "execution_report";
+  resume_token: string; // 再接続時に subscribe の resume に渡すと続きから届く
+  trade_id: number | null; // 約定によるものなら、その約定
+  last_price: string | null;
+  last_quantity: number | null;
+}
+
+// /ws の user チャネル: 残高の変化（変化後の値）
+export interface BalanceEvent {
+  type: "balance";
+  resume_token: string;
+  asset: "USDC" | "BAD";
+  available: string;
+  locked: string;
+}
+
+export type UserEvent = ExecutionReport | BalanceEvent;